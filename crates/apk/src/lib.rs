@@ -1,11 +1,25 @@
 use std::fs::{self, File};
-use std::path::Path;
-use zip::ZipArchive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use anyhow::{Result, anyhow};
 use serde::{Serialize, Deserialize};
 use std::io::Read;
 use axmldecoder::Node;
 
+pub mod resources;
+pub mod signature;
+
+pub use signature::SignatureInfo;
+pub use zip::ZipArchive;
+
+/// ELF `e_machine` values of the Android ABIs
+pub const EM_386: u16 = 3;
+pub const EM_ARM: u16 = 40;
+pub const EM_X86_64: u16 = 62;
+pub const EM_AARCH64: u16 = 183;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Abi {
     Arm64V8a,
@@ -33,6 +47,68 @@ impl Abi {
             Abi::X86 => "x86",
         }
     }
+
+    /// Target triple of the NDK toolchain (and emulator) for this ABI
+    pub fn target_triple(&self) -> &'static str {
+        match self {
+            Abi::Arm64V8a => "aarch64-linux-android",
+            Abi::ArmV7a => "armv7a-linux-androideabi",
+            Abi::X86_64 => "x86_64-linux-android",
+            Abi::X86 => "i686-linux-android",
+        }
+    }
+
+    /// ELF `e_machine` of native libraries built for this ABI
+    pub fn elf_machine(&self) -> u16 {
+        match self {
+            Abi::Arm64V8a => EM_AARCH64,
+            Abi::ArmV7a => EM_ARM,
+            Abi::X86_64 => EM_X86_64,
+            Abi::X86 => EM_386,
+        }
+    }
+
+    /// ABI of the machine rad runs on (`std::env::consts::ARCH`)
+    pub fn host() -> Option<Self> {
+        match std::env::consts::ARCH {
+            "aarch64" => Some(Abi::Arm64V8a),
+            "arm" => Some(Abi::ArmV7a),
+            "x86_64" => Some(Abi::X86_64),
+            "x86" => Some(Abi::X86),
+            _ => None,
+        }
+    }
+
+    /// ABIs a CPU of this ABI runs natively, preferred first
+    pub fn compatible(&self) -> &'static [Abi] {
+        match self {
+            Abi::Arm64V8a => &[Abi::Arm64V8a, Abi::ArmV7a],
+            Abi::ArmV7a => &[Abi::ArmV7a],
+            Abi::X86_64 => &[Abi::X86_64, Abi::X86],
+            Abi::X86 => &[Abi::X86],
+        }
+    }
+
+    /// Pick the ABI to install from the ones an APK ships (x86_64 first)
+    ///
+    /// Ignores the host; see [`preferred_abi`].
+    pub fn best_abi(supported: &[Abi]) -> Option<Abi> {
+        supported
+            .iter()
+            .find(|a| matches!(a, Abi::X86_64))
+            .or_else(|| supported.first())
+            .cloned()
+    }
+}
+
+/// Pick the ABI to install on a `host` machine from the ones an APK ships:
+/// the host's own ABI, else one it runs natively (armeabi-v7a on arm64, x86
+/// on x86_64). `None` if the APK has nothing the host can run.
+pub fn preferred_abi(available: &[Abi], host: Abi) -> Option<Abi> {
+    host.compatible()
+        .iter()
+        .find(|abi| available.contains(abi))
+        .cloned()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -48,38 +124,168 @@ pub struct AppManifest {
     pub version_code: Option<i32>,
     pub version_name: Option<String>,
     pub main_activity: Option<String>,
+    /// `<activity>` components declared by the application
+    pub activities: Vec<ActivityInfo>,
+    /// `<service>` components declared by the application
+    pub services: Vec<ComponentInfo>,
+    /// `<receiver>` components declared by the application
+    pub receivers: Vec<ComponentInfo>,
+    /// `<provider>` components declared by the application
+    pub providers: Vec<ProviderInfo>,
+    /// Requested permissions (`<uses-permission>` and
+    /// `<uses-permission-sdk-23>`), deduplicated in manifest order
+    pub permissions: Vec<String>,
+    /// `<uses-sdk android:minSdkVersion>`
+    pub min_sdk_version: Option<i32>,
+    /// `<uses-sdk android:targetSdkVersion>`
+    pub target_sdk_version: Option<i32>,
+    /// `<application android:debuggable>`: a debug build, which any process
+    /// can attach a debugger to or run code as (`run-as`)
+    pub debuggable: bool,
+    /// `<application android:testOnly>`: only installable with `pm install -t`
+    pub test_only: bool,
+}
+
+/// A component (`<service>`, `<receiver>`) declared in the manifest
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ComponentInfo {
+    /// Class name (android:name), as written in the manifest
+    pub name: String,
+    /// android:exported, if declared
+    pub exported: Option<bool>,
+    /// `<intent-filter>`s declared on the component
+    pub intent_filters: Vec<IntentFilter>,
+}
+
+impl ComponentInfo {
+    /// Whether other apps can start the component: android:exported, or
+    /// when it isn't declared, whether it has intent filters (the default
+    /// before Android 12 made the attribute mandatory for those)
+    pub fn is_exported(&self) -> bool {
+        self.exported.unwrap_or(!self.intent_filters.is_empty())
+    }
+
+    /// Every action of the component's intent filters, deduplicated in order
+    pub fn actions(&self) -> Vec<&str> {
+        let mut actions: Vec<&str> = Vec::new();
+        for action in self.intent_filters.iter().flat_map(|f| &f.actions) {
+            if !actions.contains(&action.as_str()) {
+                actions.push(action);
+            }
+        }
+        actions
+    }
+}
+
+/// An `<activity>` declared in the manifest
+pub type ActivityInfo = ComponentInfo;
+
+/// An `<intent-filter>` declared on a component
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct IntentFilter {
+    /// `<action android:name>` values
+    pub actions: Vec<String>,
+    /// `<category android:name>` values
+    pub categories: Vec<String>,
+    /// `<data android:scheme>` values
+    pub data_schemes: Vec<String>,
+}
+
+impl IntentFilter {
+    /// Basic intent matching: the action must be listed, and the category
+    /// (if any) must be listed too
+    pub fn matches(&self, action: &str, category: Option<&str>) -> bool {
+        self.actions.iter().any(|a| a == action)
+            && category.is_none_or(|c| self.categories.iter().any(|fc| fc == c))
+    }
+}
+
+impl AppManifest {
+    /// Find the first activity with an intent filter matching `action` and `category`
+    pub fn resolve_activity(&self, action: &str, category: Option<&str>) -> Option<&ActivityInfo> {
+        self.activities.iter().find(|activity| {
+            activity
+                .intent_filters
+                .iter()
+                .any(|filter| filter.matches(action, category))
+        })
+    }
+}
+
+/// A `<provider>` declared in the manifest
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProviderInfo {
+    /// Class name (android:name), as written in the manifest
+    pub name: String,
+    /// Content authorities (android:authorities, split on ';')
+    pub authorities: Vec<String>,
+    /// android:exported, if declared
+    pub exported: Option<bool>,
+}
+
+/// What `extract_libs` does with a library whose ELF machine doesn't match the ABI
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ElfCheck {
+    /// Don't look at the extracted libraries
+    Off,
+    /// Log a warning per mismatching library
+    #[default]
+    Warn,
+    /// Fail the extraction
+    Error,
 }
 
 pub struct ApkInspector {
     pub path: std::path::PathBuf,
+    elf_check: ElfCheck,
 }
 
 impl ApkInspector {
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
         Self {
             path: path.as_ref().to_path_buf(),
+            elf_check: ElfCheck::default(),
         }
     }
 
+    /// Choose how mispackaged native libraries are handled by `extract_libs`
+    pub fn with_elf_check(mut self, elf_check: ElfCheck) -> Self {
+        self.elf_check = elf_check;
+        self
+    }
+
+    /// Run `f` on the opened APK
+    ///
+    /// Lets callers that need several things from one APK read them all
+    /// without reopening the file for each.
+    pub fn with_archive<T>(&self, f: impl FnOnce(&mut ZipArchive<File>) -> Result<T>) -> Result<T> {
+        let file = File::open(&self.path)
+            .map_err(|e| anyhow!("Failed to open {}: {}", self.path.display(), e))?;
+        let mut archive = ZipArchive::new(file)
+            .map_err(|e| anyhow!("{} is not a valid APK: {}", self.path.display(), e))?;
+        f(&mut archive)
+    }
+
     pub fn inspect(&self) -> Result<ApkInfo> {
-        let file = File::open(&self.path)?;
-        let mut archive = ZipArchive::new(file)?;
+        self.with_archive(|archive| self.inspect_archive(archive))
+    }
 
+    /// [`inspect`](Self::inspect) on an archive opened by
+    /// [`with_archive`](Self::with_archive)
+    pub fn inspect_archive(&self, archive: &mut ZipArchive<File>) -> Result<ApkInfo> {
         let mut abis = std::collections::HashSet::new();
-        
-        for i in 0..archive.len() {
-            let file = archive.by_index(i)?;
-            let name = file.name();
-            
-            if name.starts_with("lib/") {
-                let parts: Vec<&str> = name.split('/').collect();
-                if let Some(abi) = parts.get(1).and_then(|s| Abi::from_str_opt(s)) {
-                    abis.insert(abi);
-                }
+
+        // Entry names come from the central directory, so this doesn't touch
+        // the entries themselves; only the manifest gets decompressed
+        for name in archive.file_names() {
+            if let Some(rest) = name.strip_prefix("lib/")
+                && let Some(abi) = rest.split('/').next().and_then(Abi::from_str_opt)
+            {
+                abis.insert(abi);
             }
         }
 
-        let package_name = self.extract_package_name(&mut archive)?;
+        let package_name = self.extract_package_name(archive)?;
 
         Ok(ApkInfo {
             package_name,
@@ -88,14 +294,7 @@ impl ApkInspector {
     }
 
     fn extract_package_name(&self, archive: &mut ZipArchive<File>) -> Result<String> {
-        let mut manifest_file = archive.by_name("AndroidManifest.xml")
-            .map_err(|_| anyhow!("AndroidManifest.xml not found in APK"))?;
-        
-        let mut buffer = Vec::new();
-        manifest_file.read_to_end(&mut buffer)?;
-
-        let doc = axmldecoder::parse(&buffer)
-            .map_err(|e| anyhow!("Failed to decode AXML: {:?}", e))?;
+        let doc = read_manifest(archive)?;
 
         match doc.get_root() {
             Some(Node::Element(root)) if root.get_tag() == "manifest" => {
@@ -110,9 +309,19 @@ impl ApkInspector {
     }
 
     pub fn extract_libs(&self, target_dir: &Path, abi: &Abi) -> Result<()> {
-        let file = File::open(&self.path)?;
-        let mut archive = ZipArchive::new(file)?;
+        self.with_archive(|archive| self.extract_libs_archive(archive, target_dir, abi))
+    }
+
+    /// [`extract_libs`](Self::extract_libs) on an archive opened by
+    /// [`with_archive`](Self::with_archive)
+    pub fn extract_libs_archive(
+        &self,
+        archive: &mut ZipArchive<File>,
+        target_dir: &Path,
+        abi: &Abi,
+    ) -> Result<()> {
         let abi_prefix = format!("lib/{}/", abi.as_str());
+        let mut extracted = Vec::new();
 
         for i in 0..archive.len() {
             let mut file = archive.by_index(i)?;
@@ -120,6 +329,13 @@ impl ApkInspector {
             
             if name.starts_with(&abi_prefix) && name.ends_with(".so") {
                 let rel_path = name.strip_prefix(&abi_prefix).unwrap();
+                // Skip entries that would escape target_dir (zip slip)
+                if Path::new(rel_path)
+                    .components()
+                    .any(|c| !matches!(c, std::path::Component::Normal(_)))
+                {
+                    continue;
+                }
                 let out_path = target_dir.join(rel_path);
                 
                 if let Some(parent) = out_path.parent() {
@@ -128,31 +344,96 @@ impl ApkInspector {
                 
                 let mut outfile = File::create(&out_path)?;
                 std::io::copy(&mut file, &mut outfile)?;
+                extracted.push(out_path);
             }
         }
 
+        self.check_lib_machines(&extracted, abi)
+    }
+
+    /// Compare each extracted library's ELF machine with the one `abi` needs
+    fn check_lib_machines(&self, libs: &[PathBuf], abi: &Abi) -> Result<()> {
+        if self.elf_check == ElfCheck::Off {
+            return Ok(());
+        }
+
+        let mut mismatches = Vec::new();
+        for lib in libs {
+            let problem = match read_elf_machine(lib)? {
+                Some(machine) if machine == abi.elf_machine() => continue,
+                Some(machine) => format!(
+                    "{} is built for ELF machine {}, expected {} ({})",
+                    lib.display(),
+                    machine,
+                    abi.elf_machine(),
+                    abi.target_triple()
+                ),
+                None => format!("{} is not an ELF file", lib.display()),
+            };
+            if self.elf_check == ElfCheck::Warn {
+                tracing::warn!("Mispackaged native library: {}", problem);
+            }
+            mismatches.push(problem);
+        }
+
+        if self.elf_check == ElfCheck::Error && !mismatches.is_empty() {
+            return Err(anyhow!(
+                "APK ships native libraries that don't match {}:\n  {}",
+                abi.as_str(),
+                mismatches.join("\n  ")
+            ));
+        }
         Ok(())
     }
 
+    /// Check the APK Signature Scheme v2 content digests (not the signers'
+    /// signatures, so this doesn't authenticate the APK, see [`signature`]);
+    /// APKs with just a JAR signature give [`SignatureInfo::V1Only`],
+    /// unsigned ones an error
+    pub fn verify_signature(&self) -> Result<SignatureInfo> {
+        signature::verify(&self.path)
+    }
+
+    /// Extract the application icon (`<application android:icon>`) to `out`
+    ///
+    /// The highest-density PNG/WebP among the icon's `mipmap`/`drawable`
+    /// files is used; if there are only adaptive-icon XMLs, the bitmap of
+    /// their foreground layer. If `out` is a directory the icon is written
+    /// inside it under its name in the APK. Returns the written path.
+    pub fn extract_icon(&self, out: &Path) -> Result<PathBuf> {
+        self.with_archive(|archive| extract_icon(archive, out))
+    }
+
+    /// Every `<activity>` (and `<activity-alias>`) declared in the manifest,
+    /// e.g. to pick a launch target other than the MAIN activity
+    pub fn list_activities(&self) -> Result<Vec<ActivityInfo>> {
+        Ok(self.parse_manifest()?.activities)
+    }
+
     /// Parse AndroidManifest.xml and extract package name, version, and main activity
     pub fn parse_manifest(&self) -> Result<AppManifest> {
-        let file = File::open(&self.path)?;
-        let mut archive = ZipArchive::new(file)?;
-        
-        let mut manifest_file = archive.by_name("AndroidManifest.xml")
-            .map_err(|_| anyhow!("AndroidManifest.xml not found in APK"))?;
-        
-        let mut buffer = Vec::new();
-        manifest_file.read_to_end(&mut buffer)?;
-
-        let doc = axmldecoder::parse(&buffer)
-            .map_err(|e| anyhow!("Failed to decode AXML: {:?}", e))?;
+        self.with_archive(|archive| self.parse_manifest_archive(archive))
+    }
+
+    /// [`parse_manifest`](Self::parse_manifest) on an archive opened by
+    /// [`with_archive`](Self::with_archive)
+    pub fn parse_manifest_archive(&self, archive: &mut ZipArchive<File>) -> Result<AppManifest> {
+        let doc = read_manifest(archive)?;
 
         let mut manifest = AppManifest {
             package_name: String::new(),
             version_code: None,
             version_name: None,
             main_activity: None,
+            activities: Vec::new(),
+            services: Vec::new(),
+            receivers: Vec::new(),
+            providers: Vec::new(),
+            permissions: Vec::new(),
+            min_sdk_version: None,
+            target_sdk_version: None,
+            debuggable: false,
+            test_only: false,
         };
 
         // Parse manifest tag attributes
@@ -182,12 +463,214 @@ impl ApkInspector {
             
             // Find main activity
             manifest.main_activity = find_main_activity(root);
+
+            // Find services, receivers and providers
+            collect_components(root, &mut manifest);
+
+            manifest.permissions = collect_permissions(root);
+
+            // SDK levels (<uses-sdk>)
+            for child in root.get_children() {
+                if let Node::Element(uses_sdk) = child
+                    && uses_sdk.get_tag() == "uses-sdk"
+                {
+                    let attrs = uses_sdk.get_attributes();
+                    manifest.min_sdk_version = attrs
+                        .get("android:minSdkVersion")
+                        .and_then(|v| parse_sdk_version(v));
+                    manifest.target_sdk_version = attrs
+                        .get("android:targetSdkVersion")
+                        .and_then(|v| parse_sdk_version(v));
+                }
+                if let Node::Element(application) = child
+                    && application.get_tag() == "application"
+                {
+                    let attrs = application.get_attributes();
+                    let flag = |name: &str| attrs.get(name).and_then(|v| parse_bool_attr(v));
+                    manifest.debuggable = flag("android:debuggable").unwrap_or(false);
+                    manifest.test_only = flag("android:testOnly").unwrap_or(false);
+                }
+            }
         }
 
         Ok(manifest)
     }
 }
 
+/// Write the application icon to `out`, see [`ApkInspector::extract_icon`]
+fn extract_icon(archive: &mut ZipArchive<File>, out: &Path) -> Result<PathBuf> {
+    let doc = read_manifest(archive)?;
+    let icon = match doc.get_root() {
+        Some(Node::Element(root)) => root
+            .get_children()
+            .iter()
+            .find_map(|child| match child {
+                Node::Element(app) if app.get_tag() == "application" => {
+                    app.get_attributes().get("android:icon").cloned()
+                }
+                _ => None,
+            }),
+        _ => None,
+    }
+    .ok_or_else(|| anyhow!("The manifest does not declare an application icon"))?;
+    let icon = resources::parse_reference(&icon)
+        .ok_or_else(|| anyhow!("Application icon is not a resource: {}", icon))?;
+
+    let table = resources::ResourceTable::parse(&read_zip_entry(archive, "resources.arsc")?)?;
+    let files = table.resolve_files(icon);
+    let entry = match best_icon_bitmap(&files) {
+        Some(path) => path.to_string(),
+        None => adaptive_icon_foreground(archive, &table, &files)?.ok_or_else(|| {
+            anyhow!("No PNG/WebP found for icon resource {:#010x}", icon)
+        })?,
+    };
+
+    let out = if out.is_dir() {
+        out.join(Path::new(&entry).file_name().unwrap_or_default())
+    } else {
+        out.to_path_buf()
+    };
+    fs::write(&out, read_zip_entry(archive, &entry)?)?;
+    Ok(out)
+}
+
+/// Decode AndroidManifest.xml
+fn read_manifest(archive: &mut ZipArchive<File>) -> Result<axmldecoder::XmlDocument> {
+    let buffer = read_zip_entry(archive, "AndroidManifest.xml")?;
+    axmldecoder::parse(&buffer).map_err(|e| anyhow!("Failed to decode AXML: {:?}", e))
+}
+
+fn read_zip_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<Vec<u8>> {
+    let mut entry = archive
+        .by_name(name)
+        .map_err(|_| anyhow!("{} not found in APK", name))?;
+    let mut buffer = Vec::new();
+    entry.read_to_end(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// The highest-density PNG/WebP among a resource's files
+fn best_icon_bitmap(files: &[(u16, String)]) -> Option<&str> {
+    // Unqualified resources are mdpi; nodpi ones aren't meant to be scaled up
+    let rank = |density: u16| match density {
+        0 => 160,
+        resources::DENSITY_NONE => 1,
+        d => d,
+    };
+    files
+        .iter()
+        .filter(|(density, path)| {
+            *density != resources::DENSITY_ANY
+                && (path.ends_with(".png") || path.ends_with(".webp"))
+                && !path.ends_with(".9.png")
+        })
+        .max_by_key(|(density, _)| rank(*density))
+        .map(|(_, path)| path.as_str())
+}
+
+/// The foreground bitmap of the first `<adaptive-icon>` XML among `files`
+fn adaptive_icon_foreground(
+    archive: &mut ZipArchive<File>,
+    table: &resources::ResourceTable,
+    files: &[(u16, String)],
+) -> Result<Option<String>> {
+    for (_, path) in files.iter().filter(|(_, path)| path.ends_with(".xml")) {
+        let Ok(doc) = axmldecoder::parse(&read_zip_entry(archive, path)?) else {
+            continue;
+        };
+        let Some(Node::Element(root)) = doc.get_root() else {
+            continue;
+        };
+        if root.get_tag() != "adaptive-icon" {
+            continue;
+        }
+        let foreground = root.get_children().iter().find_map(|child| match child {
+            Node::Element(layer) if layer.get_tag() == "foreground" => layer
+                .get_attributes()
+                .get("android:drawable")
+                .and_then(|d| resources::parse_reference(d)),
+            _ => None,
+        });
+        if let Some(foreground) = foreground
+            && let Some(bitmap) = best_icon_bitmap(&table.resolve_files(foreground))
+        {
+            return Ok(Some(bitmap.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+/// Inspect every `*.apk` directly inside `dir` on up to `parallelism` threads
+///
+/// Results are sorted by path. A file that fails to inspect gets an `Err`
+/// entry without affecting the others; if `dir` itself can't be read, the
+/// result is a single `Err` entry for `dir`.
+pub fn inspect_dir(dir: &Path, parallelism: usize) -> Vec<(PathBuf, Result<ApkInfo>)> {
+    let mut apks: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "apk") && path.is_file())
+            .collect(),
+        Err(e) => return vec![(dir.to_path_buf(), Err(e.into()))],
+    };
+    apks.sort();
+
+    let next = AtomicUsize::new(0);
+    let workers = parallelism.clamp(1, apks.len().max(1));
+
+    let mut results: Vec<(usize, Result<ApkInfo>)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = apks.get(index) else {
+                            break;
+                        };
+                        done.push((index, ApkInspector::new(path).inspect()));
+                    }
+                    done
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_default())
+            .collect()
+    });
+    results.sort_by_key(|(index, _)| *index);
+
+    results
+        .into_iter()
+        .map(|(index, result)| (apks[index].clone(), result))
+        .collect()
+}
+
+/// `e_machine` from an ELF header, or None if `header` isn't one
+///
+/// Only the first 20 bytes are needed; the field is read with the byte order
+/// given in `e_ident[EI_DATA]`.
+pub fn elf_machine(header: &[u8]) -> Option<u16> {
+    if header.len() < 20 || !header.starts_with(ELF_MAGIC) {
+        return None;
+    }
+    let bytes = [header[18], header[19]];
+    match header[5] {
+        1 => Some(u16::from_le_bytes(bytes)),
+        2 => Some(u16::from_be_bytes(bytes)),
+        _ => None,
+    }
+}
+
+/// [`elf_machine`] of a file on disk
+pub fn read_elf_machine(path: &Path) -> Result<Option<u16>> {
+    let mut header = Vec::with_capacity(20);
+    File::open(path)?.take(20).read_to_end(&mut header)?;
+    Ok(elf_machine(&header))
+}
+
 /// Recursively search for the activity with MAIN action intent filter
 fn find_main_activity(element: &axmldecoder::Element) -> Option<String> {
     // Search for <application> tag
@@ -212,6 +695,109 @@ fn find_main_activity(element: &axmldecoder::Element) -> Option<String> {
     None
 }
 
+/// Collect `<activity>`, `<service>`, `<receiver>` and `<provider>` children of `<application>`
+fn collect_components(element: &axmldecoder::Element, manifest: &mut AppManifest) {
+    for child in element.get_children() {
+        if let Node::Element(app) = child
+            && app.get_tag() == "application" {
+                for component_node in app.get_children() {
+                    let Node::Element(component) = component_node else {
+                        continue;
+                    };
+                    let attrs = component.get_attributes();
+                    let Some(name) = attrs.get("android:name") else {
+                        continue;
+                    };
+                    let exported = attrs.get("android:exported").and_then(|v| parse_bool_attr(v));
+                    let info = || ComponentInfo {
+                        name: name.to_string(),
+                        exported,
+                        intent_filters: parse_intent_filters(component),
+                    };
+
+                    match component.get_tag() {
+                        "activity" | "activity-alias" => manifest.activities.push(info()),
+                        "service" => manifest.services.push(info()),
+                        "receiver" => manifest.receivers.push(info()),
+                        "provider" => manifest.providers.push(ProviderInfo {
+                            name: name.to_string(),
+                            authorities: attrs
+                                .get("android:authorities")
+                                .map(|a| a.split(';').map(|s| s.to_string()).collect())
+                                .unwrap_or_default(),
+                            exported,
+                        }),
+                        _ => {}
+                    }
+                }
+            }
+    }
+}
+
+/// An API level from a decoded `<uses-sdk>` attribute
+///
+/// Compiled manifests store these as integers, which axmldecoder renders in
+/// decimal, or as `0x` followed by the *decimal* value for hex-typed ones.
+/// Manifests that keep them as strings hold the plain number, or a preview
+/// codename (e.g. "Tiramisu"), which has no level and gives None.
+pub fn parse_sdk_version(value: &str) -> Option<i32> {
+    let value = value.trim();
+    value.strip_prefix("0x").unwrap_or(value).parse().ok()
+}
+
+/// A boolean attribute value: `true`/`false`, or the raw integer of an AXML
+/// boolean (0 is false, anything else, usually `0xffffffff`, true)
+pub fn parse_bool_attr(value: &str) -> Option<bool> {
+    match value.trim() {
+        "true" => Some(true),
+        "false" => Some(false),
+        value => {
+            let int = value.strip_prefix("0x").unwrap_or(value);
+            int.parse::<i64>().ok().map(|i| i != 0)
+        }
+    }
+}
+
+/// `android:name` of the `<uses-permission>` (and `-sdk-23`) children of `<manifest>`
+fn collect_permissions(manifest: &axmldecoder::Element) -> Vec<String> {
+    let mut permissions: Vec<String> = Vec::new();
+    for child in manifest.get_children() {
+        if let Node::Element(element) = child
+            && matches!(element.get_tag(), "uses-permission" | "uses-permission-sdk-23")
+            && let Some(name) = element.get_attributes().get("android:name")
+            && !permissions.contains(name)
+        {
+            permissions.push(name.to_string());
+        }
+    }
+    permissions
+}
+
+/// Parse the `<intent-filter>` children of a component element
+fn parse_intent_filters(component: &axmldecoder::Element) -> Vec<IntentFilter> {
+    let mut filters = Vec::new();
+    for child in component.get_children() {
+        if let Node::Element(intent_filter) = child
+            && intent_filter.get_tag() == "intent-filter" {
+                let mut filter = IntentFilter::default();
+                for entry_node in intent_filter.get_children() {
+                    let Node::Element(entry) = entry_node else {
+                        continue;
+                    };
+                    let attrs = entry.get_attributes();
+                    match entry.get_tag() {
+                        "action" => filter.actions.extend(attrs.get("android:name").cloned()),
+                        "category" => filter.categories.extend(attrs.get("android:name").cloned()),
+                        "data" => filter.data_schemes.extend(attrs.get("android:scheme").cloned()),
+                        _ => {}
+                    }
+                }
+                filters.push(filter);
+            }
+    }
+    filters
+}
+
 /// Check if an activity element has an intent-filter with action.MAIN
 fn has_main_intent_filter(activity: &axmldecoder::Element) -> bool {
     for child in activity.get_children() {
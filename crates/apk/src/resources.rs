@@ -0,0 +1,329 @@
+//! Minimal `resources.arsc` reader.
+//!
+//! The compiled resource table is a tree of chunks, each starting with
+//! `u16 type | u16 header size | u32 size`:
+//!
+//! ```text
+//! TABLE
+//! ├── STRING_POOL          values (file paths, strings)
+//! └── PACKAGE              id 0x7f for the app
+//!     ├── STRING_POOL      type names
+//!     ├── STRING_POOL      entry names
+//!     ├── TYPE_SPEC ...
+//!     └── TYPE ...         one per (type, configuration), entries by index
+//! ```
+//!
+//! A resource ID is `0xPPTTEEEE` (package, type, entry). Only what's needed
+//! to find the files behind a resource is kept: plain string and reference
+//! values, with the density of the configuration they're defined for. Bags
+//! (styles, arrays) are skipped.
+
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow};
+
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_TABLE_TYPE: u16 = 0x0002;
+const RES_TABLE_PACKAGE_TYPE: u16 = 0x0200;
+const RES_TABLE_TYPE_TYPE: u16 = 0x0201;
+
+const UTF8_FLAG: u32 = 1 << 8;
+const NO_ENTRY: u32 = 0xffff_ffff;
+
+/// `ResTable_type` flags
+const TYPE_FLAG_SPARSE: u8 = 0x01;
+const TYPE_FLAG_OFFSET16: u8 = 0x02;
+
+/// `ResTable_entry` flags
+const ENTRY_FLAG_COMPLEX: u16 = 0x0001;
+const ENTRY_FLAG_COMPACT: u16 = 0x0008;
+
+/// `Res_value` data types
+const TYPE_REFERENCE: u8 = 0x01;
+const TYPE_STRING: u8 = 0x03;
+
+/// Configuration density for resources that scale to any density (adaptive
+/// icons, vector drawables)
+pub const DENSITY_ANY: u16 = 0xfffe;
+/// Configuration density for resources that must not be scaled
+pub const DENSITY_NONE: u16 = 0xffff;
+
+/// A value a resource has in one configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourceValue {
+    /// A string; for drawables and mipmaps, the file path inside the APK
+    String(String),
+    /// Another resource (an alias)
+    Reference(u32),
+}
+
+/// One configuration's value of a resource
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceEntry {
+    /// Screen density of the configuration (dpi, 0 if unspecified, or
+    /// [`DENSITY_ANY`]/[`DENSITY_NONE`])
+    pub density: u16,
+    pub value: ResourceValue,
+}
+
+/// Resource values from a `resources.arsc`, by resource ID
+#[derive(Debug, Default)]
+pub struct ResourceTable {
+    entries: HashMap<u32, Vec<ResourceEntry>>,
+}
+
+impl ResourceTable {
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let table = Chunk::at(data, 0)?;
+        if table.kind != RES_TABLE_TYPE {
+            return Err(anyhow!("resources.arsc does not start with a table chunk"));
+        }
+
+        let mut strings = None;
+        let mut table_out = Self::default();
+        for chunk in table.children(data)? {
+            match chunk.kind {
+                RES_STRING_POOL_TYPE if strings.is_none() => {
+                    strings = Some(StringPool::parse(data, &chunk)?);
+                }
+                RES_TABLE_PACKAGE_TYPE => {
+                    let strings = strings
+                        .as_ref()
+                        .ok_or_else(|| anyhow!("Package chunk before the value string pool"))?;
+                    table_out.parse_package(data, &chunk, strings)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(table_out)
+    }
+
+    /// Values of resource `id` in every configuration that defines it
+    pub fn get(&self, id: u32) -> &[ResourceEntry] {
+        self.entries.get(&id).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// File paths behind resource `id` with their densities, following
+    /// aliases
+    pub fn resolve_files(&self, id: u32) -> Vec<(u16, String)> {
+        let mut files = Vec::new();
+        self.collect_files(id, &mut files, 0);
+        files
+    }
+
+    fn collect_files(&self, id: u32, files: &mut Vec<(u16, String)>, depth: usize) {
+        // Reference cycles are invalid, but don't loop on a broken table
+        if depth > 8 {
+            return;
+        }
+        for entry in self.get(id) {
+            match &entry.value {
+                ResourceValue::String(path) => files.push((entry.density, path.clone())),
+                ResourceValue::Reference(target) => self.collect_files(*target, files, depth + 1),
+            }
+        }
+    }
+
+    fn parse_package(&mut self, data: &[u8], package: &Chunk, strings: &StringPool) -> Result<()> {
+        let package_id = read_u32(data, package.start + 8)?;
+        for chunk in package.children(data)? {
+            if chunk.kind == RES_TABLE_TYPE_TYPE {
+                self.parse_type(data, &chunk, package_id, strings)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_type(
+        &mut self,
+        data: &[u8],
+        chunk: &Chunk,
+        package_id: u32,
+        strings: &StringPool,
+    ) -> Result<()> {
+        let start = chunk.start;
+        let type_id = u32::from(read_u8(data, start + 8)?);
+        let flags = read_u8(data, start + 9)?;
+        let entry_count = read_u32(data, start + 12)? as usize;
+        let entries_start = start + read_u32(data, start + 16)? as usize;
+        // ResTable_config: u32 size, u16 mcc, u16 mnc, char[2] language,
+        // char[2] country, u8 orientation, u8 touchscreen, u16 density
+        let density = read_u16(data, start + 20 + 14)?;
+
+        let offsets = start + chunk.header_size;
+        let mut indexed = Vec::with_capacity(entry_count);
+        for i in 0..entry_count {
+            if flags & TYPE_FLAG_SPARSE != 0 {
+                let index = u32::from(read_u16(data, offsets + i * 4)?);
+                let offset = u32::from(read_u16(data, offsets + i * 4 + 2)?) * 4;
+                indexed.push((index, offset));
+            } else if flags & TYPE_FLAG_OFFSET16 != 0 {
+                let offset = read_u16(data, offsets + i * 2)?;
+                if offset != 0xffff {
+                    indexed.push((i as u32, u32::from(offset) * 4));
+                }
+            } else {
+                let offset = read_u32(data, offsets + i * 4)?;
+                if offset != NO_ENTRY {
+                    indexed.push((i as u32, offset));
+                }
+            }
+        }
+
+        for (index, offset) in indexed {
+            let entry = entries_start + offset as usize;
+            let entry_flags = read_u16(data, entry + 2)?;
+            let (data_type, value) = if entry_flags & ENTRY_FLAG_COMPACT != 0 {
+                // u16 key | u16 flags (data type in the high byte) | u32 data
+                ((entry_flags >> 8) as u8, read_u32(data, entry + 4)?)
+            } else if entry_flags & ENTRY_FLAG_COMPLEX != 0 {
+                continue;
+            } else {
+                // u16 size | u16 flags | u32 key | Res_value
+                let size = usize::from(read_u16(data, entry)?);
+                let res_value = entry + size;
+                (
+                    read_u8(data, res_value + 3)?,
+                    read_u32(data, res_value + 4)?,
+                )
+            };
+
+            let value = match data_type {
+                TYPE_STRING => match strings.get(data, value as usize) {
+                    Some(s) => ResourceValue::String(s),
+                    None => continue,
+                },
+                TYPE_REFERENCE => ResourceValue::Reference(value),
+                _ => continue,
+            };
+            let id = (package_id << 24) | (type_id << 16) | index;
+            self.entries
+                .entry(id)
+                .or_default()
+                .push(ResourceEntry { density, value });
+        }
+        Ok(())
+    }
+}
+
+/// Resource ID of an attribute value as axmldecoder renders references
+/// (`ResourceValueType::Reference/2131230916`), or `@0x7f0800c4`
+pub fn parse_reference(value: &str) -> Option<u32> {
+    if let Some(id) = value.strip_prefix("ResourceValueType::Reference/") {
+        return id.parse().ok();
+    }
+    let hex = value
+        .strip_prefix("@0x")
+        .or_else(|| value.strip_prefix("@"))?;
+    u32::from_str_radix(hex, 16).ok()
+}
+
+struct Chunk {
+    start: usize,
+    kind: u16,
+    header_size: usize,
+    size: usize,
+}
+
+impl Chunk {
+    fn at(data: &[u8], start: usize) -> Result<Self> {
+        let chunk = Self {
+            start,
+            kind: read_u16(data, start)?,
+            header_size: usize::from(read_u16(data, start + 2)?),
+            size: read_u32(data, start + 4)? as usize,
+        };
+        if chunk.header_size < 8
+            || chunk.size < chunk.header_size
+            || start + chunk.size > data.len()
+        {
+            return Err(anyhow!("Malformed resource chunk at offset {}", start));
+        }
+        Ok(chunk)
+    }
+
+    /// Chunks following this chunk's header
+    fn children(&self, data: &[u8]) -> Result<Vec<Chunk>> {
+        let end = self.start + self.size;
+        let mut offset = self.start + self.header_size;
+        let mut children = Vec::new();
+        while offset + 8 <= end {
+            let child = Chunk::at(data, offset)?;
+            offset += child.size;
+            children.push(child);
+        }
+        Ok(children)
+    }
+}
+
+struct StringPool {
+    count: usize,
+    utf8: bool,
+    offsets: usize,
+    strings: usize,
+}
+
+impl StringPool {
+    fn parse(data: &[u8], chunk: &Chunk) -> Result<Self> {
+        Ok(Self {
+            count: read_u32(data, chunk.start + 8)? as usize,
+            utf8: read_u32(data, chunk.start + 16)? & UTF8_FLAG != 0,
+            offsets: chunk.start + chunk.header_size,
+            strings: chunk.start + read_u32(data, chunk.start + 20)? as usize,
+        })
+    }
+
+    fn get(&self, data: &[u8], index: usize) -> Option<String> {
+        if index >= self.count {
+            return None;
+        }
+        let start = self.strings + read_u32(data, self.offsets + index * 4).ok()? as usize;
+        if self.utf8 {
+            // UTF-16 length, then UTF-8 length, each 1 or 2 bytes
+            let (_, skip) = utf8_length(data, start)?;
+            let (len, skip2) = utf8_length(data, start + skip)?;
+            let bytes = data.get(start + skip + skip2..start + skip + skip2 + len)?;
+            Some(String::from_utf8_lossy(bytes).into_owned())
+        } else {
+            let first = read_u16(data, start).ok()?;
+            let (len, skip) = if first & 0x8000 != 0 {
+                let second = read_u16(data, start + 2).ok()?;
+                ((usize::from(first & 0x7fff) << 16) | usize::from(second), 4)
+            } else {
+                (usize::from(first), 2)
+            };
+            let units = (0..len)
+                .map(|i| read_u16(data, start + skip + i * 2).ok())
+                .collect::<Option<Vec<u16>>>()?;
+            Some(String::from_utf16_lossy(&units))
+        }
+    }
+}
+
+fn utf8_length(data: &[u8], offset: usize) -> Option<(usize, usize)> {
+    let first = *data.get(offset)?;
+    if first & 0x80 != 0 {
+        let second = *data.get(offset + 1)?;
+        Some(((usize::from(first & 0x7f) << 8) | usize::from(second), 2))
+    } else {
+        Some((usize::from(first), 1))
+    }
+}
+
+fn read_u8(data: &[u8], offset: usize) -> Result<u8> {
+    data.get(offset)
+        .copied()
+        .ok_or_else(|| anyhow!("Truncated resource table"))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow!("Truncated resource table"))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow!("Truncated resource table"))
+}
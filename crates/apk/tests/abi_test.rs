@@ -0,0 +1,131 @@
+use apk::{
+    elf_machine, preferred_abi, read_elf_machine, Abi, ApkInspector, ElfCheck, EM_386, EM_AARCH64, EM_ARM, EM_X86_64,
+};
+
+/// First 20 bytes of an ELF header for `machine`
+fn elf_header(machine: u16, little_endian: bool) -> Vec<u8> {
+    let mut header = vec![0u8; 20];
+    header[..4].copy_from_slice(b"\x7fELF");
+    header[4] = 2; // ELFCLASS64
+    header[5] = if little_endian { 1 } else { 2 };
+    header[6] = 1; // EV_CURRENT
+    let machine = if little_endian {
+        machine.to_le_bytes()
+    } else {
+        machine.to_be_bytes()
+    };
+    header[18..20].copy_from_slice(&machine);
+    header
+}
+
+#[test]
+fn test_preferred_abi_matches_host() {
+    let all = [Abi::ArmV7a, Abi::Arm64V8a, Abi::X86, Abi::X86_64];
+    assert_eq!(preferred_abi(&all, Abi::Arm64V8a), Some(Abi::Arm64V8a));
+    assert_eq!(preferred_abi(&all, Abi::X86_64), Some(Abi::X86_64));
+
+    // 32-bit fallbacks
+    assert_eq!(preferred_abi(&[Abi::ArmV7a, Abi::X86], Abi::Arm64V8a), Some(Abi::ArmV7a));
+    assert_eq!(preferred_abi(&[Abi::ArmV7a, Abi::X86], Abi::X86_64), Some(Abi::X86));
+
+    // Nothing runnable
+    assert_eq!(preferred_abi(&[Abi::Arm64V8a], Abi::X86_64), None);
+    assert_eq!(preferred_abi(&[Abi::Arm64V8a], Abi::ArmV7a), None);
+    assert_eq!(preferred_abi(&[], Abi::X86_64), None);
+}
+
+#[test]
+fn test_abi_target_triple_and_machine() {
+    let expected = [
+        (Abi::Arm64V8a, "aarch64-linux-android", EM_AARCH64),
+        (Abi::ArmV7a, "armv7a-linux-androideabi", EM_ARM),
+        (Abi::X86_64, "x86_64-linux-android", EM_X86_64),
+        (Abi::X86, "i686-linux-android", EM_386),
+    ];
+    for (abi, triple, machine) in expected {
+        assert_eq!(abi.target_triple(), triple);
+        assert_eq!(abi.elf_machine(), machine);
+    }
+}
+
+#[test]
+fn test_elf_machine_from_header() {
+    assert_eq!(elf_machine(&elf_header(EM_AARCH64, true)), Some(183));
+    assert_eq!(elf_machine(&elf_header(EM_ARM, false)), Some(40));
+    assert_eq!(elf_machine(b"PK\x03\x04 not an elf file"), None);
+    assert_eq!(elf_machine(&elf_header(EM_X86_64, true)[..10]), None);
+}
+
+#[test]
+fn test_read_elf_machine_from_file() {
+    let path = std::env::temp_dir().join(format!("rad-elf-{}.so", std::process::id()));
+    let mut data = elf_header(EM_X86_64, true);
+    data.extend_from_slice(&[0u8; 44]);
+    std::fs::write(&path, data).unwrap();
+
+    assert_eq!(read_elf_machine(&path).unwrap(), Some(EM_X86_64));
+
+    let _ = std::fs::remove_file(path);
+}
+
+/// APK containing `lib/arm64-v8a/<name>` entries with the given contents
+fn make_apk_with_libs(label: &str, libs: &[(&str, Vec<u8>)]) -> std::path::PathBuf {
+    use std::io::Write;
+
+    let path = std::env::temp_dir().join(format!("rad-libs-{label}-{}.apk", std::process::id()));
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+    for (name, data) in libs {
+        zip.start_file(
+            format!("lib/arm64-v8a/{name}"),
+            zip::write::FileOptions::default(),
+        )
+        .unwrap();
+        zip.write_all(data).unwrap();
+    }
+    zip.finish().unwrap();
+    path
+}
+
+#[test]
+fn test_extract_libs_accepts_matching_machine() {
+    let apk = make_apk_with_libs("good", &[("libgood.so", elf_header(EM_AARCH64, true))]);
+    let out = std::env::temp_dir().join(format!("rad-libs-good-out-{}", std::process::id()));
+
+    ApkInspector::new(&apk)
+        .with_elf_check(ElfCheck::Error)
+        .extract_libs(&out, &Abi::Arm64V8a)
+        .expect("matching library should extract");
+    assert!(out.join("libgood.so").is_file());
+
+    let _ = std::fs::remove_dir_all(out);
+    let _ = std::fs::remove_file(apk);
+}
+
+#[test]
+fn test_extract_libs_wrong_machine_warns_or_errors() {
+    let apk = make_apk_with_libs(
+        "bad",
+        &[
+            ("libgood.so", elf_header(EM_AARCH64, true)),
+            ("libbad.so", elf_header(EM_X86_64, true)),
+        ],
+    );
+    let out = std::env::temp_dir().join(format!("rad-libs-bad-out-{}", std::process::id()));
+
+    // The default only warns
+    ApkInspector::new(&apk)
+        .extract_libs(&out, &Abi::Arm64V8a)
+        .expect("warn mode should not fail");
+    assert!(out.join("libbad.so").is_file());
+
+    let err = ApkInspector::new(&apk)
+        .with_elf_check(ElfCheck::Error)
+        .extract_libs(&out, &Abi::Arm64V8a)
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("libbad.so"), "{}", err);
+    assert!(!err.contains("libgood.so"), "{}", err);
+
+    let _ = std::fs::remove_dir_all(out);
+    let _ = std::fs::remove_file(apk);
+}
@@ -1,4 +1,4 @@
-use apk::{ApkInspector};
+use apk::{inspect_dir, parse_bool_attr, parse_sdk_version, ApkInspector};
 
 /// Test parsing AndroidManifest.xml from a real APK
 /// Uses F-Droid APK which has proper AXML format
@@ -35,6 +35,221 @@ fn test_parse_manifest_main_activity() {
     );
 }
 
+/// Test parsing services, receivers and providers from manifest
+#[test]
+fn test_parse_manifest_components() {
+    let inspector = ApkInspector::new("test_data/real.apk");
+    let manifest = inspector.parse_manifest().expect("Failed to parse manifest");
+
+    assert_eq!(manifest.services.len(), 20);
+    assert_eq!(manifest.receivers.len(), 15);
+    assert_eq!(manifest.providers.len(), 4);
+
+    assert!(manifest
+        .services
+        .iter()
+        .any(|s| s.name == "org.fdroid.fdroid.UpdateService" && s.exported == Some(false)));
+
+    let file_provider = manifest
+        .providers
+        .iter()
+        .find(|p| p.name == "androidx.core.content.FileProvider")
+        .expect("FileProvider should be declared");
+    assert_eq!(file_provider.authorities, vec!["org.fdroid.fdroid.installer"]);
+}
+
+/// Test listing every activity with its exported flag and actions
+#[test]
+fn test_list_activities() {
+    let inspector = ApkInspector::new("test_data/real.apk");
+    let activities = inspector.list_activities().expect("Failed to list activities");
+
+    assert_eq!(activities.len(), 22);
+
+    let find = |name: &str| {
+        activities
+            .iter()
+            .find(|a| a.name == name)
+            .unwrap_or_else(|| panic!("{} should be listed", name))
+    };
+    // Explicitly exported, no filters
+    let details = find("org.fdroid.fdroid.views.AppDetailsActivity");
+    assert_eq!(details.exported, Some(true));
+    assert!(details.is_exported());
+    assert!(details.actions().is_empty());
+    // Exported implicitly through its intent filters
+    let main = find("org.fdroid.fdroid.views.main.MainActivity");
+    assert_eq!(main.exported, None);
+    assert!(main.is_exported());
+    assert_eq!(
+        main.actions(),
+        vec![
+            "android.intent.action.MAIN",
+            "android.intent.action.VIEW",
+            "android.intent.action.SEARCH",
+            "android.nfc.action.NDEF_DISCOVERED",
+        ]
+    );
+    assert!(!find("org.fdroid.fdroid.panic.ExitActivity").is_exported());
+}
+
+/// Test parsing requested permissions from manifest
+#[test]
+fn test_parse_manifest_permissions() {
+    let inspector = ApkInspector::new("test_data/real.apk");
+    let manifest = inspector.parse_manifest().expect("Failed to parse manifest");
+
+    assert_eq!(manifest.permissions.len(), 18);
+    assert_eq!(
+        &manifest.permissions[..3],
+        &[
+            "android.permission.INTERNET",
+            "android.permission.ACCESS_NETWORK_STATE",
+            "android.permission.ACCESS_WIFI_STATE",
+        ]
+    );
+    // Declared with <uses-permission-sdk-23>
+    assert!(manifest
+        .permissions
+        .contains(&"android.permission.ACCESS_COARSE_LOCATION".to_string()));
+
+    let mut unique = manifest.permissions.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), manifest.permissions.len());
+}
+
+/// Test parsing SDK levels from <uses-sdk>
+#[test]
+fn test_parse_manifest_sdk_versions() {
+    let inspector = ApkInspector::new("test_data/real.apk");
+    let manifest = inspector.parse_manifest().expect("Failed to parse manifest");
+
+    assert_eq!(manifest.min_sdk_version, Some(23));
+    assert_eq!(manifest.target_sdk_version, Some(25));
+}
+
+#[test]
+fn test_parse_sdk_version_string_and_numeric() {
+    // Integer-typed values, decimal and hex (rendered "0x" + decimal)
+    assert_eq!(parse_sdk_version("21"), Some(21));
+    assert_eq!(parse_sdk_version("0x34"), Some(34));
+    // String-typed values
+    assert_eq!(parse_sdk_version(" 28 "), Some(28));
+    assert_eq!(parse_sdk_version("Tiramisu"), None);
+    assert_eq!(parse_sdk_version(""), None);
+}
+
+/// Test resolving a deep-link (VIEW + BROWSABLE) entry point
+#[test]
+fn test_resolve_activity_view_browsable() {
+    let inspector = ApkInspector::new("test_data/real.apk");
+    let manifest = inspector.parse_manifest().expect("Failed to parse manifest");
+
+    let activity = manifest
+        .resolve_activity(
+            "android.intent.action.VIEW",
+            Some("android.intent.category.BROWSABLE"),
+        )
+        .expect("VIEW+BROWSABLE should resolve");
+    assert_eq!(activity.name, "org.fdroid.fdroid.views.main.MainActivity");
+
+    let filter = activity
+        .intent_filters
+        .iter()
+        .find(|f| f.data_schemes.iter().any(|s| s == "fdroidrepos"))
+        .expect("fdroidrepos deep link filter should be parsed");
+    assert!(filter.actions.iter().any(|a| a == "android.intent.action.VIEW"));
+}
+
+/// Test that an action no activity declares doesn't resolve
+#[test]
+fn test_resolve_activity_non_matching_action() {
+    let inspector = ApkInspector::new("test_data/real.apk");
+    let manifest = inspector.parse_manifest().expect("Failed to parse manifest");
+
+    assert!(manifest
+        .resolve_activity("android.intent.action.SEND", None)
+        .is_none());
+    assert!(manifest
+        .resolve_activity(
+            "android.intent.action.SEARCH",
+            Some("android.intent.category.BROWSABLE")
+        )
+        .is_none());
+}
+
+/// Test bulk inspection over a directory with one broken APK
+#[test]
+fn test_inspect_dir_keeps_going_on_invalid_apk() {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let dir = std::env::temp_dir().join(format!("rad-inspect-dir-{}", nanos));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::copy("test_data/real.apk", dir.join("a.apk")).unwrap();
+    std::fs::copy("test_data/real.apk", dir.join("c.apk")).unwrap();
+    std::fs::write(dir.join("b.apk"), b"PK\x03\x04 not really a zip").unwrap();
+    std::fs::write(dir.join("notes.txt"), b"ignored").unwrap();
+
+    let results = inspect_dir(&dir, 4);
+
+    let names: Vec<_> = results
+        .iter()
+        .map(|(path, _)| path.file_name().unwrap().to_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["a.apk", "b.apk", "c.apk"]);
+    assert_eq!(results[0].1.as_ref().unwrap().package_name, "org.fdroid.fdroid");
+    assert!(results[1].1.is_err());
+    assert_eq!(results[2].1.as_ref().unwrap().package_name, "org.fdroid.fdroid");
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+/// Several reads share one opened archive
+#[test]
+fn test_with_archive() {
+    let inspector = ApkInspector::new("test_data/real.apk");
+    let (info, manifest) = inspector
+        .with_archive(|archive| {
+            Ok((
+                inspector.inspect_archive(archive)?,
+                inspector.parse_manifest_archive(archive)?,
+            ))
+        })
+        .expect("Failed to read APK");
+    assert_eq!(info.package_name, "org.fdroid.fdroid");
+    assert_eq!(manifest.package_name, info.package_name);
+
+    let err = ApkInspector::new("test_data/missing.apk")
+        .with_archive(|archive| Ok(archive.len()))
+        .unwrap_err();
+    assert!(err.to_string().contains("missing.apk"), "{}", err);
+}
+
+/// F-Droid's release build is neither debuggable nor test-only
+#[test]
+fn test_parse_manifest_debug_flags() {
+    let inspector = ApkInspector::new("test_data/real.apk");
+    let manifest = inspector.parse_manifest().expect("Failed to parse manifest");
+
+    assert!(!manifest.debuggable);
+    assert!(!manifest.test_only);
+}
+
+#[test]
+fn test_parse_bool_attr() {
+    assert_eq!(parse_bool_attr("true"), Some(true));
+    assert_eq!(parse_bool_attr("false"), Some(false));
+    assert_eq!(parse_bool_attr("1"), Some(true));
+    assert_eq!(parse_bool_attr("0"), Some(false));
+    assert_eq!(parse_bool_attr("-1"), Some(true));
+    assert_eq!(parse_bool_attr("0x4294967295"), Some(true));
+    assert_eq!(parse_bool_attr("0x0"), Some(false));
+    assert_eq!(parse_bool_attr("yes"), None);
+}
+
 /// This test requires a real APK with valid binary AXML.
 /// To test manually, download any APK from F-Droid and place it at test_data/real.apk
 /// 
@@ -0,0 +1,76 @@
+use apk::ApkInspector;
+use apk::resources::{DENSITY_ANY, ResourceTable, ResourceValue, parse_reference};
+use std::io::Read;
+
+/// F-Droid's `@mipmap/ic_launcher`
+const ICON_ID: u32 = 0x7f08_00c4;
+
+fn read_entry(name: &str) -> Vec<u8> {
+    let file = std::fs::File::open("test_data/real.apk").unwrap();
+    let mut archive = zip::ZipArchive::new(file).unwrap();
+    let mut buffer = Vec::new();
+    archive
+        .by_name(name)
+        .unwrap()
+        .read_to_end(&mut buffer)
+        .unwrap();
+    buffer
+}
+
+#[test]
+fn test_resolve_icon_resource() {
+    let table = ResourceTable::parse(&read_entry("resources.arsc")).unwrap();
+    let files = table.resolve_files(ICON_ID);
+
+    let densities: Vec<u16> = files.iter().map(|(density, _)| *density).collect();
+    assert_eq!(densities, vec![120, 160, 240, 320, 480, 640, DENSITY_ANY]);
+    assert_eq!(
+        table.get(ICON_ID)[5].value,
+        ResourceValue::String("res/I8.png".to_string())
+    );
+    assert!(table.get(0x7f08_ffff).is_empty());
+}
+
+#[test]
+fn test_parse_reference() {
+    assert_eq!(
+        parse_reference("ResourceValueType::Reference/2131230916"),
+        Some(ICON_ID)
+    );
+    assert_eq!(parse_reference("@0x7f0800c4"), Some(ICON_ID));
+    assert_eq!(parse_reference("@7f0800c4"), Some(ICON_ID));
+    assert_eq!(parse_reference("true"), None);
+}
+
+#[test]
+fn test_extract_icon_highest_density() {
+    let dir = std::env::temp_dir().join(format!("rad-icon-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let inspector = ApkInspector::new("test_data/real.apk");
+    let path = inspector
+        .extract_icon(&dir)
+        .expect("Failed to extract icon");
+    assert_eq!(path, dir.join("I8.png"));
+    let icon = std::fs::read(&path).unwrap();
+    assert!(icon.starts_with(b"\x89PNG"));
+    assert_eq!(icon, read_entry("res/I8.png"));
+
+    // A file path is used as is
+    let out = dir.join("fdroid.png");
+    assert_eq!(inspector.extract_icon(&out).unwrap(), out);
+    assert_eq!(std::fs::read(&out).unwrap(), icon);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_extract_icon_without_resources() {
+    let out = std::env::temp_dir().join(format!("rad-icon-none-{}.png", std::process::id()));
+    assert!(
+        ApkInspector::new("test_data/test.apk")
+            .extract_icon(&out)
+            .is_err()
+    );
+    assert!(!out.exists());
+}
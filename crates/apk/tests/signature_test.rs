@@ -0,0 +1,89 @@
+use apk::{ApkInspector, SignatureInfo};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// F-Droid's published signing certificate fingerprint
+const FDROID_CERT_SHA256: &str = "43238d512c1e5eb2d6569f4a3afbf5523418b82e0a3ed1552770abb9a9c9ccab";
+
+fn temp_apk(label: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rad-sig-{}-{}.apk", label, std::process::id()))
+}
+
+#[test]
+fn test_verify_v2_signature() {
+    let inspector = ApkInspector::new("test_data/real.apk");
+    let info = inspector
+        .verify_signature()
+        .expect("Failed to verify signature");
+
+    assert_eq!(
+        info,
+        SignatureInfo::V2 {
+            certificate_sha256: FDROID_CERT_SHA256.to_string(),
+            digests_match: true,
+        }
+    );
+}
+
+#[test]
+fn test_verify_detects_tampered_entry() {
+    let mut data = std::fs::read("test_data/real.apk").unwrap();
+    // A byte inside the first entry's data (after its local header)
+    data[1000] ^= 0xff;
+    let path = temp_apk("tampered");
+    std::fs::write(&path, &data).unwrap();
+
+    let info = ApkInspector::new(&path).verify_signature().unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(
+        info,
+        SignatureInfo::V2 {
+            certificate_sha256: FDROID_CERT_SHA256.to_string(),
+            digests_match: false,
+        }
+    );
+}
+
+#[test]
+fn test_verify_v1_only_and_unsigned() {
+    let path = temp_apk("v1");
+    {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        let options = zip::write::FileOptions::default();
+        for (name, content) in [
+            ("AndroidManifest.xml", &b""[..]),
+            ("META-INF/MANIFEST.MF", b"Manifest-Version: 1.0\r\n"),
+            ("META-INF/CERT.SF", b"Signature-Version: 1.0\r\n"),
+            ("META-INF/CERT.RSA", b"\x30\x00"),
+        ] {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+    let info = ApkInspector::new(&path).verify_signature();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(info.unwrap(), SignatureInfo::V1Only);
+
+    // test.apk has no signature at all
+    let err = ApkInspector::new("test_data/test.apk")
+        .verify_signature()
+        .unwrap_err();
+    assert!(err.to_string().contains("not signed"), "{}", err);
+}
+
+#[test]
+fn test_verify_rejects_truncated_eocd() {
+    // Files shorter than an EOCD record that start with its signature
+    for len in 4..22 {
+        let mut data = b"PK\x05\x06".to_vec();
+        data.resize(len, 0);
+        let path = temp_apk(&format!("short-{}", len));
+        std::fs::write(&path, &data).unwrap();
+
+        let result = ApkInspector::new(&path).verify_signature();
+        let _ = std::fs::remove_file(&path);
+        assert!(result.is_err(), "{} bytes", len);
+    }
+}
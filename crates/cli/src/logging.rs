@@ -0,0 +1,194 @@
+//! Log output setup: human-readable by default, or JSON Lines for log pipelines.
+//!
+//! The JSON layout follows `tracing_subscriber::fmt().json()`: one object per
+//! line with `timestamp`, `level`, `fields` (including `message`) and `target`.
+
+use std::fmt;
+
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Human,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    /// The `EnvFilter` directive enabling this level for every target
+    pub fn directive(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+}
+
+/// `RUST_LOG` directives plus a global default at `level`
+pub fn env_filter(level: LogLevel) -> EnvFilter {
+    let directive = level
+        .directive()
+        .parse()
+        .expect("log level directives are valid");
+    EnvFilter::from_default_env().add_directive(directive)
+}
+
+/// Install the global subscriber; call once, before any command runs
+pub fn init(format: LogFormat, level: LogLevel) {
+    let builder = tracing_subscriber::fmt().with_env_filter(env_filter(level));
+    match format {
+        LogFormat::Human => builder.init(),
+        LogFormat::Json => builder.with_ansi(false).event_format(JsonLines).init(),
+    }
+}
+
+/// Formats each event as a single JSON object
+struct JsonLines;
+
+impl<S, N> FormatEvent<S, N> for JsonLines
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        _ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+
+        let mut fields = Map::new();
+        event.record(&mut JsonVisitor(&mut fields));
+
+        let metadata = event.metadata();
+        let mut line = Map::new();
+        line.insert("timestamp".to_string(), Value::String(timestamp));
+        line.insert(
+            "level".to_string(),
+            Value::String(metadata.level().to_string()),
+        );
+        line.insert("fields".to_string(), Value::Object(fields));
+        line.insert(
+            "target".to_string(),
+            Value::String(metadata.target().to_string()),
+        );
+
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0
+            .insert(field.name().to_string(), Value::String(value.to_string()));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.insert(
+            field.name().to_string(),
+            Value::String(format!("{:?}", value)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_level_maps_to_filter_directive() {
+        assert_eq!(LogLevel::Error.directive(), "error");
+        assert_eq!(LogLevel::Warn.directive(), "warn");
+        assert_eq!(LogLevel::Info.directive(), "info");
+        assert_eq!(LogLevel::Debug.directive(), "debug");
+        assert_eq!(LogLevel::Trace.directive(), "trace");
+    }
+
+    #[test]
+    fn json_format_writes_one_object_per_line() {
+        use std::sync::{Arc, Mutex};
+
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let sink = buf.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .event_format(JsonLines)
+            .with_writer(move || SharedBuf(sink.clone()))
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(pid = 42, "container started");
+        });
+
+        let output = String::from_utf8(buf.lock().unwrap().clone()).unwrap();
+        let line: Value = serde_json::from_str(output.trim_end()).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["fields"]["message"], "container started");
+        assert_eq!(line["fields"]["pid"], 42);
+        assert!(line["timestamp"].is_string());
+    }
+
+    struct SharedBuf(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuf {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(data);
+            Ok(data.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn env_filter_enables_chosen_level() {
+        assert_eq!(
+            env_filter(LogLevel::Debug).max_level_hint(),
+            Some(tracing::level_filters::LevelFilter::DEBUG)
+        );
+        assert_eq!(
+            env_filter(LogLevel::Warn).max_level_hint(),
+            Some(tracing::level_filters::LevelFilter::WARN)
+        );
+    }
+}
@@ -1,20 +1,36 @@
+mod logging;
+mod shell;
+mod signals;
+
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 use nix::unistd::Pid;
-use tracing_subscriber::EnvFilter;
 
-use apk::ApkInspector;
-use core::container::Container;
+use apk::{Abi, ApkInspector};
+use core::config::{LimitsConfig, RunConfig};
+use core::container::{Container, LogcatBuffer, LogcatOpts};
 use core::doctor;
 use core::image::{ImagePaths, MountPoints};
+use core::net::NetworkMode;
 use core::prefix::Prefix;
+use core::registry::ContainerTable;
+
+use crate::logging::{LogFormat, LogLevel};
+use crate::shell::ShellSession;
+use crate::signals::ShutdownSignal;
 
 #[derive(Parser)]
 #[command(name = "run-android-app")]
 #[command(about = "A rootless runner for Android apps on Linux", long_about = None)]
 struct Cli {
+    /// Log output format
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Human)]
+    log_format: LogFormat,
+    /// Minimum log level (RUST_LOG directives still apply)
+    #[arg(long, global = true, value_enum, default_value_t = LogLevel::Info)]
+    log_level: LogLevel,
     #[command(subcommand)]
     command: Commands,
 }
@@ -27,19 +43,59 @@ enum Commands {
     Setup,
     /// Run an Android application (.apk)
     Run {
-        /// Path to the APK file
-        apk_path: String,
+        /// APK file, or a base APK and its splits (files or a directory)
+        #[arg(required = true, num_args = 1..)]
+        apk_paths: Vec<PathBuf>,
         /// Force execution even if doctor finds issues
         #[arg(long)]
         force: bool,
-        /// Wait timeout for Android boot (seconds)
-        #[arg(long, default_value = "120")]
-        boot_timeout: u64,
+        /// Wait timeout for Android boot (seconds, default 120)
+        #[arg(long)]
+        boot_timeout: Option<u64>,
+        /// Force the native ABI to install (e.g. arm64-v8a)
+        #[arg(long)]
+        abi: Option<String>,
+        /// Validate and print every action without mounting or booting anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Leave the container running after launching the app and return
+        #[arg(long)]
+        detach: bool,
+        /// Extra container environment variable (KEY=VALUE, repeatable)
+        #[arg(long = "env", value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
+        /// Refuse to run an APK whose minSdkVersion is above the image's API level
+        #[arg(long)]
+        strict_sdk: bool,
+        /// Cap the container's memory (bytes, or with a K/M/G suffix, e.g. 512M)
+        #[arg(long, value_parser = parse_size)]
+        memory_max: Option<u64>,
+        /// Cap the container's CPU time, in percent of one CPU (200 = two CPUs)
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        cpu_max: Option<u32>,
+        /// Run as a separate instance with its own app data (e.g. --instance work)
+        #[arg(long, value_parser = parse_instance)]
+        instance: Option<String>,
+        /// Network mode: host (default), none, or slirp (NAT through slirp4netns)
+        #[arg(long)]
+        network: Option<NetworkMode>,
+        /// Init binary inside the container, instead of probing the image
+        #[arg(long)]
+        init: Option<String>,
+        /// Argument passed to init (repeatable, e.g. --init-arg=--second-stage)
+        #[arg(long = "init-arg", allow_hyphen_values = true)]
+        init_args: Vec<String>,
     },
     /// Open an interactive shell inside the container
     Shell {
         /// Package name (used for prefix directory)
         package: String,
+        /// Instance to enter or start (see `run --instance`)
+        #[arg(long, value_parser = parse_instance)]
+        instance: Option<String>,
+        /// Extra container environment variable (KEY=VALUE, repeatable)
+        #[arg(long = "env", value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
     },
     /// Reset the environment for a specific package
     Reset {
@@ -50,23 +106,59 @@ enum Commands {
     Logs {
         /// Package name
         package: String,
-        /// Follow log output
+        /// Follow the Android logcat of the running container
         #[arg(short, long)]
         follow: bool,
+        /// Show Android logcat from the running container instead of app.log
+        #[arg(long)]
+        logcat: bool,
+        /// Logcat buffer to read (main, system, crash, events, radio, all)
+        #[arg(long, requires = "logcat")]
+        buffer: Vec<String>,
+        /// Only show the most recent N logcat lines
+        #[arg(long, requires = "logcat")]
+        lines: Option<usize>,
     },
     /// Stop a running container
     Stop {
         /// Package name
         package: String,
+        /// Instance to stop (see `run --instance`)
+        #[arg(long, value_parser = parse_instance)]
+        instance: Option<String>,
+        /// Seconds to wait for a clean shutdown before killing the container
+        #[arg(long)]
+        timeout: Option<u64>,
+    },
+    /// Restart a container, keeping its prefix and app data
+    Restart {
+        /// Package name
+        package: String,
+        /// Instance to restart (see `run --instance`)
+        #[arg(long, value_parser = parse_instance)]
+        instance: Option<String>,
+    },
+    /// List running containers
+    #[command(alias = "list")]
+    Ps,
+    /// List the snapshots of a package's prefix
+    Snapshots {
+        /// Package name
+        package: String,
+    },
+    /// Delete a package's prefix, stopping its container first
+    Rm {
+        /// Package name
+        package: String,
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
     },
 }
 
 fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env().add_directive(tracing::Level::INFO.into()))
-        .init();
-
     let cli = Cli::parse();
+    logging::init(cli.log_format, cli.log_level);
 
     match cli.command {
         Commands::Doctor => {
@@ -76,14 +168,54 @@ fn main() -> Result<()> {
             run_setup()?;
         }
         Commands::Run {
-            apk_path,
+            apk_paths,
             force,
             boot_timeout,
+            abi,
+            dry_run,
+            detach,
+            env,
+            strict_sdk,
+            memory_max,
+            cpu_max,
+            instance,
+            network,
+            init,
+            init_args,
         } => {
-            run_app(&apk_path, force, boot_timeout)?;
+            let flags = RunConfig {
+                boot_timeout,
+                abi,
+                env: env.into_iter().collect(),
+                limits: LimitsConfig {
+                    memory_max_bytes: memory_max,
+                    cpu_max_percent: cpu_max,
+                },
+                network,
+                init_path: init,
+                init_args,
+                ..Default::default()
+            };
+            run_app(
+                &apk_paths,
+                force,
+                flags,
+                dry_run,
+                detach,
+                strict_sdk,
+                instance.as_deref(),
+            )?;
         }
-        Commands::Shell { package } => {
-            run_shell(&package)?;
+        Commands::Shell {
+            package,
+            instance,
+            env,
+        } => {
+            let flags = RunConfig {
+                env: env.into_iter().collect(),
+                ..Default::default()
+            };
+            run_shell(&package, instance.as_deref(), flags)?;
         }
         Commands::Reset { package } => {
             let prefix = get_prefix(&package)?;
@@ -93,10 +225,16 @@ fn main() -> Result<()> {
         }
         Commands::Logs {
             package,
-            follow: _,
-        } => {
+            follow,
+            logcat,
+            buffer,
+            lines,
+        } if logcat || follow => {
+            show_logcat(&package, follow, &buffer, lines)?;
+        }
+        Commands::Logs { package, .. } => {
             let prefix = get_prefix(&package)?;
-            let log_file = prefix.root.join("logs/app.log");
+            let log_file = prefix.log_path();
             if !log_file.exists() {
                 println!("No logs found for {}.", package);
                 return Ok(());
@@ -105,8 +243,24 @@ fn main() -> Result<()> {
             let content = std::fs::read_to_string(log_file)?;
             println!("{}", content);
         }
-        Commands::Stop { package } => {
-            stop_container(&package)?;
+        Commands::Stop {
+            package,
+            instance,
+            timeout,
+        } => {
+            stop_container(&package, instance.as_deref(), timeout)?;
+        }
+        Commands::Restart { package, instance } => {
+            restart_container(&package, instance.as_deref())?;
+        }
+        Commands::Ps => {
+            list_containers()?;
+        }
+        Commands::Snapshots { package } => {
+            list_snapshots(&package)?;
+        }
+        Commands::Rm { package, yes } => {
+            remove_prefix(&package, yes)?;
         }
     }
 
@@ -126,6 +280,9 @@ fn run_doctor() {
             if let Some(fix) = &issue.fix {
                 println!("  Fix: {}", fix);
             }
+            if let Some(command) = &issue.fix_command {
+                println!("  Run as root:\n\n      {}\n", command);
+            }
         }
     }
 
@@ -147,8 +304,36 @@ fn run_doctor() {
 
     // Check for images
     match ImagePaths::default_location() {
+        Ok(paths) if !paths.pending_decompression().is_empty() => {
+            let names: Vec<String> = paths
+                .pending_decompression()
+                .iter()
+                .map(|(compressed, _)| compressed.display().to_string())
+                .collect();
+            match paths.validate_decompressed() {
+                Ok(()) => println!(
+                    "[OK] Waydroid Images: {} will be decompressed on first run",
+                    names.join(", ")
+                ),
+                Err(e) => {
+                    println!("[FAIL] Waydroid Images: {}", e);
+                    println!("  Fix: Run 'run-android-app setup' to download images");
+                    all_ok = false;
+                }
+            }
+        }
         Ok(paths) => match paths.validate() {
-            Ok(()) => println!("[OK] Waydroid Images: system.img and vendor.img found"),
+            Ok(()) => {
+                println!("[OK] Waydroid Images: system.img and vendor.img found");
+                if let Some(issue) = doctor::check_image_checksums(&paths) {
+                    let mark = if issue.status { "OK" } else { "FAIL" };
+                    println!("[{}] {}: {}", mark, issue.name, issue.description);
+                    if let Some(fix) = issue.fix.as_ref().filter(|_| !issue.status) {
+                        println!("  Fix: {}", fix);
+                        all_ok = false;
+                    }
+                }
+            }
             Err(e) => {
                 println!("[FAIL] Waydroid Images: {}", e);
                 println!("  Fix: Run 'run-android-app setup' to download images");
@@ -172,6 +357,9 @@ fn run_setup() -> Result<()> {
     println!("Setting up Waydroid LineageOS images...");
     println!("This will run scripts/setup-image.sh to download ~1 GB of images.");
 
+    // Refuse early instead of failing halfway through a ~1 GB download
+    core::image::preflight_setup(&core::image::default_cache_dir()?)?;
+
     let script = find_setup_script()?;
 
     let status = std::process::Command::new("bash")
@@ -191,7 +379,39 @@ fn run_setup() -> Result<()> {
     Ok(())
 }
 
-fn run_app(apk_path: &str, force: bool, boot_timeout: u64) -> Result<()> {
+/// APK files named on the command line, with directories expanded to the
+/// `.apk` files inside them
+fn expand_apk_paths(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut apks = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut found: Vec<PathBuf> = std::fs::read_dir(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|p| p.is_file() && p.extension().is_some_and(|ext| ext == "apk"))
+                .collect();
+            if found.is_empty() {
+                anyhow::bail!("No .apk files in {}", path.display());
+            }
+            found.sort();
+            apks.extend(found);
+        } else {
+            apks.push(path.clone());
+        }
+    }
+    Ok(apks)
+}
+
+/// `run` with settings from the prefix's config.toml, overridden by `flags`
+fn run_app(
+    apk_paths: &[PathBuf],
+    force: bool,
+    flags: RunConfig,
+    dry_run: bool,
+    detach: bool,
+    strict_sdk: bool,
+    instance: Option<&str>,
+) -> Result<()> {
     // Doctor check
     if !force {
         let issues = doctor::run_doctor();
@@ -206,11 +426,23 @@ fn run_app(apk_path: &str, force: bool, boot_timeout: u64) -> Result<()> {
         }
     }
 
+    let apks = expand_apk_paths(apk_paths)?;
+    let apks: Vec<&Path> = apks.iter().map(PathBuf::as_path).collect();
+    let apks = core::container::order_apk_set(&apks)?;
+    let apk_path = apks[0];
+    if apks.len() > 1 {
+        println!("Split APK set: {} files", apks.len());
+    }
+
     // Inspect APK - use parse_manifest() to get main_activity
-    println!("Inspecting APK: {}", apk_path);
+    println!("Inspecting APK: {}", apk_path.display());
     let inspector = ApkInspector::new(apk_path);
-    let info = inspector.inspect()?;
-    let manifest = inspector.parse_manifest()?;
+    let (info, manifest) = inspector.with_archive(|archive| {
+        Ok((
+            inspector.inspect_archive(archive)?,
+            inspector.parse_manifest_archive(archive)?,
+        ))
+    })?;
 
     println!("APK Metadata:");
     println!("  Package: {}", info.package_name);
@@ -224,42 +456,125 @@ fn run_app(apk_path: &str, force: bool, boot_timeout: u64) -> Result<()> {
     if let Some(ref activity) = manifest.main_activity {
         println!("  Main activity: {}", activity);
     }
+    let sdk = |level: Option<i32>| level.map_or("unset".to_string(), |l| l.to_string());
+    println!(
+        "  SDK: min {}, target {}",
+        sdk(manifest.min_sdk_version),
+        sdk(manifest.target_sdk_version)
+    );
+    if manifest.test_only {
+        println!("  Test-only build (android:testOnly)");
+    }
+
+    // Flags win over the per-package config file
+    let prefix = get_prefix(&info.package_name)?;
+    let config = RunConfig::load(&prefix.config_path())?.merge(flags);
+
+    // Validate a forced ABI against what the APK actually ships
+    let forced_abi = match config.abi.as_deref() {
+        Some(name) => {
+            let abi = Abi::from_str_opt(name).with_context(|| {
+                format!(
+                    "Unknown ABI '{}' (expected arm64-v8a, armeabi-v7a, x86_64 or x86)",
+                    name
+                )
+            })?;
+            core::prefix::select_abi(&info, Some(&abi))?;
+            println!("  Forced ABI: {}", abi.as_str());
+            Some(abi)
+        }
+        None => None,
+    };
 
     // Locate images
     let images = ImagePaths::default_location()?;
-    images.validate()?;
+    prepare_images(&images, dry_run)?;
 
     // Set up prefix
-    let prefix = get_prefix(&info.package_name)?;
-    prefix.initialize()?;
-    println!("Prefix initialized at: {}", prefix.root.display());
+    if dry_run {
+        println!("[dry-run] Would initialize prefix at: {}", prefix.root.display());
+    } else {
+        prepare_prefix(&prefix)?;
+        println!("Prefix initialized at: {}", prefix.root.display());
+    }
 
     // Set up container mount points
-    let mounts = MountPoints::for_prefix(&prefix.root);
-    let pid_file = prefix.root.join(".container_pid");
-    let mut container = Container::new(images, mounts).with_pid_file(pid_file);
+    let key = registry_key(&info.package_name, instance);
+    let mut container =
+        config.apply(container_handle(&prefix, images, instance).with_dry_run(dry_run));
 
     // Start container (rootless)
     println!("\nStarting rootless Android container...");
     container.start()?;
+    if !dry_run {
+        register_container(&key, container.init_pid);
+    }
+
+    // From here on a Ctrl+C must stop the container instead of killing us;
+    // it is checked between the setup steps below
+    let shutdown = ShutdownSignal::install()?;
 
     // Wait for boot
     println!("Waiting for Android system to boot...");
-    match container.wait_for_boot(boot_timeout) {
+    match container.wait_for_boot_until(config.boot_timeout(), || shutdown.pending()) {
         Ok(()) => println!("Android system booted!"),
+        Err(_) if shutdown.pending() => {}
         Err(e) => {
             println!("[WARN] Boot wait issue: {}. Continuing anyway...", e);
         }
     }
+    if stop_if_interrupted(&shutdown, &mut container, &key)? {
+        return Ok(());
+    }
+
+    // Compare the APK's minimum API level with the image's
+    if !dry_run
+        && let Some(min_sdk) = manifest.min_sdk_version
+        && let Ok(Some(image_sdk)) = container.get_property("ro.build.version.sdk")
+        && let Ok(image_sdk) = image_sdk.parse::<i32>()
+        && min_sdk > image_sdk
+    {
+        if strict_sdk {
+            container.stop()?;
+            unregister_container(&key);
+            anyhow::bail!(
+                "{} needs API level {} but the image is API level {}",
+                info.package_name,
+                min_sdk,
+                image_sdk
+            );
+        }
+        println!(
+            "[WARN] {} needs API level {} but the image is API level {}; it may not install or run.",
+            info.package_name, min_sdk, image_sdk
+        );
+    }
+
+    if manifest.debuggable {
+        println!(
+            "[NOTE] {} is a debuggable build: other processes in the container can attach a \
+             debugger to it and run code as the app (run-as), reading its private data. \
+             Only use debug builds you trust, for development.",
+            info.package_name
+        );
+    }
 
     // Install APK
     println!("Installing APK...");
-    match container.install_apk(Path::new(apk_path)) {
+    let installed = if apks.len() > 1 {
+        container.install_apk_set_with_abi(&apks, forced_abi.as_ref())
+    } else {
+        container.install_apk_with_abi(apk_path, forced_abi.as_ref())
+    };
+    match installed {
         Ok(()) => println!("APK installed."),
         Err(e) => {
             println!("[WARN] APK install issue: {}", e);
         }
     }
+    if stop_if_interrupted(&shutdown, &mut container, &key)? {
+        return Ok(());
+    }
 
     // Try to launch the main activity
     if let Some(activity) = &manifest.main_activity {
@@ -275,67 +590,84 @@ fn run_app(apk_path: &str, force: bool, boot_timeout: u64) -> Result<()> {
             info.package_name
         );
     }
+    if stop_if_interrupted(&shutdown, &mut container, &key)? {
+        return Ok(());
+    }
+
+    if dry_run {
+        println!("\n[dry-run] All preconditions validated; nothing was mounted or started.");
+        return Ok(());
+    }
+
+    if detach {
+        // The PID file and registry entry stay behind for a later shell/stop
+        if !container.is_booted() {
+            println!("\n[WARN] Android hasn't reported boot completion; the app may not be up yet.");
+        }
+        container.detach();
+        let target = match instance {
+            Some(id) => format!("{} --instance {}", info.package_name, id),
+            None => info.package_name.clone(),
+        };
+        println!(
+            "\nContainer left running. Use 'shell {0}' to interact or 'stop {0}' to stop it.",
+            target
+        );
+        return Ok(());
+    }
 
     // Keep running until Ctrl+C
-    println!("\nContainer is running. Press Ctrl+C to stop.");
-    wait_for_signal();
+    println!("\nContainer is running. Press Ctrl+C to stop (twice to force).");
+    shutdown.wait();
 
     // Stop container
-    container.stop()?;
-    println!("Container stopped.");
+    let forced = container.stop()?;
+    unregister_container(&key);
+    report_stopped(forced);
 
     Ok(())
 }
 
-fn run_shell(package: &str) -> Result<()> {
+fn run_shell(package: &str, instance: Option<&str>, flags: RunConfig) -> Result<()> {
     let prefix = get_prefix(package)?;
+    let config = RunConfig::load(&prefix.config_path())?.merge(flags);
     let images = ImagePaths::default_location()?;
+    let container = config.apply(container_handle(&prefix, images.clone(), instance));
+    let key = registry_key(package, instance);
 
-    // Check if container is already running (look for init PID file)
-    let pid_file = prefix.root.join(".container_pid");
-    if pid_file.exists() {
-        let pid_str = std::fs::read_to_string(&pid_file)?;
-        if let Ok(pid) = pid_str.trim().parse::<u32>() {
-            let pid_nix = Pid::from_raw(pid as i32);
-            if nix::sys::signal::kill(pid_nix, None).is_ok() {
-                println!("Entering running container (PID {})...", pid);
-                // nsenter into our own user namespace doesn't need root
-                let status = std::process::Command::new("nsenter")
-                    .arg("-t")
-                    .arg(pid.to_string())
-                    .arg("--user")
-                    .arg("--mount")
-                    .arg("--uts")
-                    .arg("--ipc")
-                    .arg("--pid")
-                    .arg("--")
-                    .arg("/system/bin/sh")
-                    .status()?;
-                if !status.success() {
-                    println!("[WARN] Shell exited with: {:?}", status.code());
-                }
-                return Ok(());
-            }
+    // Adopt a running container (look for init PID file), or start our own
+    let running_pid = container
+        .pid_file()
+        .and_then(|pid_file| std::fs::read_to_string(pid_file).ok())
+        .and_then(|pid| pid.trim().parse::<u32>().ok())
+        .filter(|pid| nix::sys::signal::kill(Pid::from_raw(*pid as i32), None).is_ok());
+    let session = match running_pid {
+        Some(pid) => {
+            println!("Entering running container (PID {})...", pid);
+            ShellSession::adopt(container, pid)
         }
-    }
+        None => {
+            println!("Starting container for shell access...");
+            prepare_images(&images, false)?;
+            prepare_prefix(&prefix)?;
 
-    // No running container, start one and enter shell
-    println!("Starting container for shell access...");
-    images.validate()?;
-    prefix.initialize()?;
+            let mut container = container;
+            container.start()?;
+            register_container(&key, container.init_pid);
 
-    let mounts = MountPoints::for_prefix(&prefix.root);
-    let mut container =
-        Container::new(images, mounts).with_pid_file(prefix.root.join(".container_pid"));
-    container.start()?;
+            // Give it a moment for basic services
+            std::thread::sleep(std::time::Duration::from_secs(2));
+            println!("Entering shell...");
+            ShellSession::started(container)
+        }
+    };
 
-    // Give it a moment for basic services
-    std::thread::sleep(std::time::Duration::from_secs(2));
+    // The shell handles Ctrl+C; a closed terminal ends it and we clean up below
+    signals::defer_to_child()?;
 
-    // Enter shell
-    println!("Entering shell...");
-    if let Some(pid) = container.init_pid {
-        let _status = std::process::Command::new("nsenter")
+    if let Some(pid) = session.init_pid() {
+        // nsenter into our own user namespace doesn't need root
+        let status = std::process::Command::new("nsenter")
             .arg("-t")
             .arg(pid.to_string())
             .arg("--user")
@@ -346,35 +678,408 @@ fn run_shell(package: &str) -> Result<()> {
             .arg("--")
             .arg("/system/bin/sh")
             .status()?;
+        if !status.success() {
+            println!("[WARN] Shell exited with: {:?}", status.code());
+        }
     }
 
-    // Stop container when shell exits
-    container.stop()?;
+    // Stop the container when the shell exits, if we started it
+    let started_by_us = session.started_by_us();
+    if session.end()? {
+        unregister_container(&key);
+    }
+    if !started_by_us {
+        println!("Container left running (started by another invocation).");
+    }
 
     Ok(())
 }
 
-fn stop_container(package: &str) -> Result<()> {
+fn stop_container(package: &str, instance: Option<&str>, timeout: Option<u64>) -> Result<()> {
     let prefix = get_prefix(package)?;
     let images = ImagePaths::default_location()?;
-    let mounts = MountPoints::for_prefix(&prefix.root);
-
-    let pid_file = prefix.root.join(".container_pid");
-    let mut container =
-        Container::new(images, mounts).with_pid_file(pid_file.clone());
+    let config = RunConfig::load(&prefix.config_path())?;
+    let mut container = container_handle(&prefix, images, instance);
+    if let Some(secs) = timeout.or(config.stop_timeout) {
+        container = container.with_stop_timeout(std::time::Duration::from_secs(secs));
+    }
+    let key = registry_key(package, instance);
 
-    if pid_file.exists() {
-        let pid_str = std::fs::read_to_string(&pid_file)?;
+    if let Some(pid_file) = container.pid_file()
+        && pid_file.exists()
+    {
+        let pid_str = std::fs::read_to_string(pid_file)?;
         if let Ok(pid) = pid_str.trim().parse::<u32>() {
             container.init_pid = Some(pid);
         }
     }
 
-    container.stop()?;
+    // Fall back to the tool-wide table if the PID file is gone
+    if container.init_pid.is_none()
+        && let Ok(table) = ContainerTable::default_location()
+        && let Ok(Some(entry)) = table.get(&key)
+    {
+        container.init_pid = Some(entry.pid);
+    }
+
+    let forced = container.stop()?;
+    unregister_container(&key);
+    report_stopped(forced);
+    Ok(())
+}
+
+/// Stop the container if Ctrl+C arrived while `run` was still setting it up
+fn stop_if_interrupted(
+    shutdown: &ShutdownSignal,
+    container: &mut Container,
+    key: &str,
+) -> Result<bool> {
+    if !shutdown.pending() {
+        return Ok(false);
+    }
+    println!("\nInterrupted, stopping the container...");
+    let forced = container.stop()?;
+    unregister_container(key);
+    report_stopped(forced);
+    Ok(true)
+}
+
+fn report_stopped(forced: bool) {
+    if forced {
+        println!("[WARN] The app did not shut down cleanly (killed after the grace period).");
+    }
     println!("Container stopped.");
+}
+
+fn restart_container(package: &str, instance: Option<&str>) -> Result<()> {
+    // Same settings as `run`, from the package's config file
+    let prefix = get_prefix(package)?;
+    let config = RunConfig::load(&prefix.config_path())?;
+    let images = ImagePaths::default_location()?;
+    let mut container = config.apply(container_handle(&prefix, images, instance));
+    let key = registry_key(package, instance);
+
+    // Pick up the running container, if there is one, so it is torn down first
+    if let Some(pid_file) = container.pid_file()
+        && pid_file.exists()
+    {
+        let pid_str = std::fs::read_to_string(pid_file)?;
+        if let Ok(pid) = pid_str.trim().parse::<u32>() {
+            container.init_pid = Some(pid);
+        }
+    }
+
+    println!("Restarting container for {}...", key);
+    container.restart()?;
+    register_container(&key, container.init_pid);
+    let shutdown = ShutdownSignal::install()?;
+
+    println!("\nContainer is running. Press Ctrl+C to stop (twice to force).");
+    shutdown.wait();
+
+    let forced = container.stop()?;
+    unregister_container(&key);
+    report_stopped(forced);
+    Ok(())
+}
+
+fn list_containers() -> Result<()> {
+    let table = ContainerTable::default_location()?;
+    let entries = table.list()?;
+
+    if entries.is_empty() {
+        println!("No running containers.");
+        return Ok(());
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    println!("{:<40} {:>8} {:>10}", "PACKAGE", "PID", "UPTIME");
+    for (package, entry) in entries {
+        let uptime = now.saturating_sub(entry.started_at);
+        println!("{:<40} {:>8} {:>9}s", package, entry.pid, uptime);
+    }
+    Ok(())
+}
+
+fn list_snapshots(package: &str) -> Result<()> {
+    let prefix = get_prefix(package)?;
+    let snapshots = prefix.list_snapshots()?;
+
+    if snapshots.is_empty() {
+        println!("No snapshots for {}.", package);
+        return Ok(());
+    }
+
+    println!("{:<30} {:>10} {:>12}", "NAME", "SIZE", "CREATED");
+    for snapshot in snapshots {
+        let created = snapshot
+            .created
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        println!(
+            "{:<30} {:>10} {:>12}",
+            snapshot.name,
+            format_size(snapshot.size_bytes),
+            created
+        );
+    }
     Ok(())
 }
 
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn remove_prefix(package: &str, yes: bool) -> Result<()> {
+    let prefix = get_prefix(package)?;
+    if !prefix.root.exists() {
+        println!("No prefix for {}.", package);
+        return Ok(());
+    }
+
+    let question = format!(
+        "Delete {} and all its app data and snapshots?",
+        prefix.root.display()
+    );
+    if !yes && !confirm(&question, false)? {
+        println!("Aborted.");
+        return Ok(());
+    }
+
+    let registered = ContainerTable::default_location()
+        .and_then(|t| t.get(package))
+        .ok()
+        .flatten()
+        .is_some();
+    if prefix.pid_file().exists() || registered {
+        stop_container(package, None, None)?;
+    }
+
+    // A container that died without cleaning up can leave FUSE mounts behind
+    let images = ImagePaths::default_location()?;
+    Container::new(images.clone(), MountPoints::for_prefix(&prefix.root)).clean_stale_mounts()?;
+
+    // Same for every instance
+    let instances = std::fs::read_dir(prefix.root.join(core::container::INSTANCES_DIR))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok());
+    for id in instances {
+        let container = container_handle(&prefix, images.clone(), Some(&id));
+        if container.pid_file().is_some_and(Path::exists) {
+            stop_container(package, Some(&id), None)?;
+        }
+        container.clean_stale_mounts()?;
+    }
+
+    prefix.delete(false)?;
+    println!("Prefix for {} deleted.", package);
+    Ok(())
+}
+
+/// A (not yet started) handle on the container of `prefix`, or on one of its
+/// instances
+fn container_handle(prefix: &Prefix, images: ImagePaths, instance: Option<&str>) -> Container {
+    let container = Container::new(images, MountPoints::for_prefix(&prefix.root))
+        .with_pid_file(prefix.pid_file());
+    match instance {
+        Some(id) => container.with_instance(id),
+        None => container,
+    }
+}
+
+/// Registry name of a container: the package, plus `@<id>` for an instance
+fn registry_key(package: &str, instance: Option<&str>) -> String {
+    match instance {
+        Some(id) => format!("{}@{}", package, id),
+        None => package.to_string(),
+    }
+}
+
+/// Record a started container in the tool-wide table (best-effort)
+fn register_container(package: &str, pid: Option<u32>) {
+    let Some(pid) = pid else { return };
+    if let Err(e) = ContainerTable::default_location().and_then(|t| t.add(package, pid)) {
+        println!("[WARN] Failed to record container in registry: {}", e);
+    }
+}
+
+/// Drop a stopped container from the tool-wide table (best-effort)
+fn unregister_container(package: &str) {
+    if let Err(e) = ContainerTable::default_location().and_then(|t| t.remove(package)) {
+        println!("[WARN] Failed to update container registry: {}", e);
+    }
+}
+
+fn show_logcat(
+    package: &str,
+    follow: bool,
+    buffers: &[String],
+    lines: Option<usize>,
+) -> Result<()> {
+    let prefix = get_prefix(package)?;
+    let images = ImagePaths::default_location()?;
+    let mounts = MountPoints::for_prefix(&prefix.root);
+
+    let pid_file = prefix.pid_file();
+    let pid = std::fs::read_to_string(&pid_file)
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok())
+        .filter(|pid| nix::sys::signal::kill(Pid::from_raw(*pid as i32), None).is_ok())
+        .with_context(|| format!("No running container for {}", package))?;
+
+    let opts = LogcatOpts {
+        buffers: buffers
+            .iter()
+            .map(|b| parse_logcat_buffer(b))
+            .collect::<Result<_>>()?,
+        lines,
+    };
+
+    let mut container = Container::new(images, mounts);
+    container.init_pid = Some(pid);
+
+    let result = if follow {
+        container.logcat_lines(opts, true).and_then(|lines| {
+            // logcat has its own process group, so Ctrl+C doesn't reach it:
+            // end it on a signal, which ends the loop below
+            let group = Pid::from_raw(lines.process_group() as i32);
+            let shutdown = ShutdownSignal::install()?;
+            std::thread::spawn(move || {
+                shutdown.wait();
+                let _ = nix::sys::signal::killpg(group, nix::sys::signal::Signal::SIGTERM);
+            });
+
+            use std::io::Write;
+            let mut stdout = std::io::stdout().lock();
+            for line in lines {
+                // e.g. piped into `head`
+                if writeln!(stdout, "{}", line).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        })
+    } else {
+        container.logcat(opts).map(|output| print!("{}", output))
+    };
+
+    // We only attached to the container; don't let Drop stop it
+    container.init_pid = None;
+    result
+}
+
+fn parse_logcat_buffer(name: &str) -> Result<LogcatBuffer> {
+    Ok(match name {
+        "main" => LogcatBuffer::Main,
+        "system" => LogcatBuffer::System,
+        "crash" => LogcatBuffer::Crash,
+        "events" => LogcatBuffer::Events,
+        "radio" => LogcatBuffer::Radio,
+        "all" => LogcatBuffer::All,
+        _ => anyhow::bail!("Unknown logcat buffer: {}", name),
+    })
+}
+
+/// Decompress `.img.xz` images if needed, then validate the images
+fn prepare_images(images: &ImagePaths, dry_run: bool) -> Result<()> {
+    let pending = images.pending_decompression();
+    if !pending.is_empty() {
+        if dry_run {
+            for (compressed, image) in &pending {
+                println!(
+                    "[dry-run] Would decompress {} to {}",
+                    compressed.display(),
+                    image.display()
+                );
+            }
+            return Ok(());
+        }
+        println!("Decompressing images (first run, this may take a few minutes)...");
+        images.decompress_pending()?;
+    }
+    images.validate()
+}
+
+/// Initialize a new prefix, or check an existing one and offer to repair it
+fn prepare_prefix(prefix: &Prefix) -> Result<()> {
+    if !prefix.root.exists() {
+        return prefix.initialize();
+    }
+
+    if let Err(e) = prefix.validate() {
+        println!("[WARN] Prefix check failed: {}", e);
+        if !confirm("Re-initialize the prefix? App data is kept.", true)? {
+            anyhow::bail!("Prefix {} is not usable", prefix.root.display());
+        }
+        prefix.repair()?;
+        prefix.validate()?;
+    }
+    Ok(())
+}
+
+/// Parse a `KEY=VALUE` flag
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", s)),
+    }
+}
+
+/// An instance ID (see `Container::with_instance`)
+fn parse_instance(s: &str) -> Result<String, String> {
+    core::container::validate_instance_id(s).map_err(|e| e.to_string())?;
+    Ok(s.to_string())
+}
+
+/// A byte count with an optional binary K/M/G suffix
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match s.trim().to_ascii_uppercase() {
+        s if s.ends_with('K') => (s[..s.len() - 1].to_string(), 1u64 << 10),
+        s if s.ends_with('M') => (s[..s.len() - 1].to_string(), 1 << 20),
+        s if s.ends_with('G') => (s[..s.len() - 1].to_string(), 1 << 30),
+        s => (s, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("expected a size like 512M or 2G, got '{}'", s))
+}
+
+/// Ask a yes/no question on stdin; an empty answer picks `default`
+fn confirm(question: &str, default: bool) -> Result<bool> {
+    use std::io::Write;
+
+    print!("{} {} ", question, if default { "[Y/n]" } else { "[y/N]" });
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(match answer.trim().to_lowercase().as_str() {
+        "" => default,
+        "y" | "yes" => true,
+        _ => false,
+    })
+}
+
 fn get_prefix(package: &str) -> Result<Prefix> {
     let prefix_path = std::env::current_dir()?.join("prefixes").join(package);
     Ok(Prefix::new(prefix_path))
@@ -402,29 +1107,3 @@ fn find_setup_script() -> Result<PathBuf> {
          Make sure you're running from the project root directory."
     )
 }
-
-/// Wait for SIGINT/SIGTERM using a simple signal flag
-fn wait_for_signal() {
-    use std::sync::atomic::{AtomicBool, Ordering};
-
-    let _running = AtomicBool::new(true);
-
-    // Register signal handler (best-effort)
-    unsafe {
-        nix::libc::signal(nix::libc::SIGINT, signal_handler as nix::libc::sighandler_t);
-        nix::libc::signal(nix::libc::SIGTERM, signal_handler as nix::libc::sighandler_t);
-    }
-
-    // Store global flag pointer for signal handler
-    SIGNAL_FLAG.store(true, Ordering::SeqCst);
-
-    while SIGNAL_FLAG.load(Ordering::SeqCst) {
-        std::thread::sleep(std::time::Duration::from_millis(500));
-    }
-}
-
-static SIGNAL_FLAG: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
-
-extern "C" fn signal_handler(_sig: i32) {
-    SIGNAL_FLAG.store(false, std::sync::atomic::Ordering::SeqCst);
-}
@@ -0,0 +1,121 @@
+//! Interactive `shell` sessions.
+//!
+//! A session either adopts a container that was already running or starts
+//! one itself. Only in the second case does ending the session (normally,
+//! through an error, or because the terminal went away) stop the container.
+
+use anyhow::Result;
+use core::container::Container;
+
+pub struct ShellSession {
+    /// `None` once the session has ended
+    container: Option<Container>,
+    started_by_us: bool,
+}
+
+impl ShellSession {
+    /// Enter a container some other invocation started, with init `pid`
+    pub fn adopt(mut container: Container, pid: u32) -> Self {
+        container.init_pid = Some(pid);
+        Self {
+            container: Some(container),
+            started_by_us: false,
+        }
+    }
+
+    /// Enter a container this invocation has just started
+    pub fn started(container: Container) -> Self {
+        Self {
+            container: Some(container),
+            started_by_us: true,
+        }
+    }
+
+    pub fn started_by_us(&self) -> bool {
+        self.started_by_us
+    }
+
+    pub fn init_pid(&self) -> Option<u32> {
+        self.container.as_ref().and_then(|c| c.init_pid)
+    }
+
+    /// Stop the container if this session started it, otherwise leave it running
+    ///
+    /// Returns whether the container was stopped.
+    pub fn end(mut self) -> Result<bool> {
+        let Some(mut container) = self.container.take() else {
+            return Ok(false);
+        };
+        if self.started_by_us {
+            container.stop()?;
+            Ok(true)
+        } else {
+            container.detach();
+            Ok(false)
+        }
+    }
+}
+
+impl Drop for ShellSession {
+    fn drop(&mut self) {
+        // A session we started is stopped by the container's own drop; an
+        // adopted one must survive us
+        if !self.started_by_us
+            && let Some(container) = self.container.take()
+        {
+            container.detach();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::image::{ImagePaths, MountPoints};
+    use std::path::{Path, PathBuf};
+    use std::process::{Child, Command};
+
+    fn fake_container() -> Container {
+        let images = ImagePaths {
+            system_img: PathBuf::from("/tmp/nonexistent/system.img"),
+            vendor_img: PathBuf::from("/tmp/nonexistent/vendor.img"),
+        };
+        Container::new(
+            images,
+            MountPoints::for_prefix(Path::new("/tmp/nonexistent-prefix")),
+        )
+    }
+
+    fn still_running(child: &mut Child) -> bool {
+        matches!(child.try_wait(), Ok(None))
+    }
+
+    #[test]
+    fn adopted_session_leaves_container_running() {
+        let mut init = Command::new("sleep").arg("30").spawn().unwrap();
+
+        let session = ShellSession::adopt(fake_container(), init.id());
+        assert!(!session.started_by_us());
+        assert!(!session.end().unwrap());
+        assert!(still_running(&mut init));
+
+        // Nor when the session is dropped without ending (e.g. on an error)
+        drop(ShellSession::adopt(fake_container(), init.id()));
+        assert!(still_running(&mut init));
+
+        let _ = init.kill();
+        let _ = init.wait();
+    }
+
+    #[test]
+    fn started_session_stops_container() {
+        let mut init = Command::new("sleep").arg("30").spawn().unwrap();
+        let mut container = fake_container();
+        container.init_pid = Some(init.id());
+
+        let session = ShellSession::started(container);
+        assert!(session.started_by_us());
+        assert!(session.end().unwrap());
+        assert!(!still_running(&mut init));
+    }
+}
@@ -0,0 +1,165 @@
+//! Ctrl+C / SIGTERM handling for long-running commands.
+//!
+//! Signals are delivered through a self-pipe: the handler only bumps a
+//! counter and writes one byte, and the main thread blocks reading the pipe.
+//! The first signal asks for a clean shutdown; a second one force-exits so a
+//! hung stop can always be interrupted.
+
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+/// Exit status used when a second signal forces the process down
+const FORCE_EXIT_CODE: i32 = 130;
+
+/// Signals received since the handlers were installed
+static SIGNAL_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Write end of the self-pipe (-1 until installed)
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// Read end of the self-pipe, created by the first `ShutdownSignal::install`
+static SIGNAL_READ: OnceLock<OwnedFd> = OnceLock::new();
+
+#[derive(Debug, PartialEq, Eq)]
+enum SignalAction {
+    /// First signal: stop the container cleanly
+    Shutdown,
+    /// Repeated signal: exit immediately
+    ForceExit,
+}
+
+/// Record a signal in `count` and decide what to do about it
+fn on_signal(count: &AtomicUsize) -> SignalAction {
+    if count.fetch_add(1, Ordering::SeqCst) == 0 {
+        SignalAction::Shutdown
+    } else {
+        SignalAction::ForceExit
+    }
+}
+
+extern "C" fn signal_handler(_sig: libc::c_int) {
+    match on_signal(&SIGNAL_COUNT) {
+        SignalAction::Shutdown => {
+            let fd = SIGNAL_PIPE.load(Ordering::SeqCst);
+            if fd >= 0 {
+                // Only async-signal-safe calls in here
+                unsafe {
+                    libc::write(fd, [1u8].as_ptr().cast(), 1);
+                }
+            }
+        }
+        SignalAction::ForceExit => unsafe { libc::_exit(FORCE_EXIT_CODE) },
+    }
+}
+
+extern "C" fn ignore_in_parent(_sig: libc::c_int) {}
+
+/// Let an interactive child own the terminal's signals
+///
+/// Ctrl+C, Ctrl+\ and a hangup go to the whole foreground process group; with
+/// these handlers we survive them and get to clean up once the child exits.
+/// Unlike `SIG_IGN`, handlers are reset on exec, so the child still sees the
+/// signals.
+pub fn defer_to_child() -> Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(ignore_in_parent),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for signal in [Signal::SIGINT, Signal::SIGQUIT, Signal::SIGHUP] {
+        unsafe { sigaction(signal, &action) }
+            .with_context(|| format!("Failed to install {} handler", signal))?;
+    }
+    Ok(())
+}
+
+/// Installed SIGINT/SIGTERM handlers; [`wait`](Self::wait) blocks until one fires
+pub struct ShutdownSignal {
+    read_fd: BorrowedFd<'static>,
+}
+
+impl ShutdownSignal {
+    /// Install the handlers. Signals arriving before [`wait`](Self::wait) are not lost.
+    ///
+    /// The self-pipe is created on the first call and shared by later ones.
+    pub fn install() -> Result<Self> {
+        let read_fd = match SIGNAL_READ.get() {
+            Some(fd) => fd,
+            None => {
+                let (read_fd, write_fd) =
+                    nix::unistd::pipe().context("Failed to create signal pipe")?;
+                // The write end stays open for the lifetime of the process
+                SIGNAL_PIPE.store(write_fd, Ordering::SeqCst);
+                SIGNAL_READ.get_or_init(|| unsafe { OwnedFd::from_raw_fd(read_fd) })
+            }
+        };
+
+        let action = SigAction::new(
+            SigHandler::Handler(signal_handler),
+            SaFlags::SA_RESTART,
+            SigSet::empty(),
+        );
+        for signal in [Signal::SIGINT, Signal::SIGTERM] {
+            unsafe { sigaction(signal, &action) }
+                .with_context(|| format!("Failed to install {} handler", signal))?;
+        }
+
+        Ok(Self {
+            read_fd: read_fd.as_fd(),
+        })
+    }
+
+    /// Whether SIGINT or SIGTERM has been received, without blocking
+    pub fn pending(&self) -> bool {
+        SIGNAL_COUNT.load(Ordering::SeqCst) > 0
+    }
+
+    /// Block until SIGINT or SIGTERM is received
+    pub fn wait(&self) {
+        let mut buf = [0u8; 1];
+        loop {
+            match nix::unistd::read(self.read_fd.as_raw_fd(), &mut buf) {
+                Err(Errno::EINTR) => continue,
+                _ => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_signal_requests_shutdown() {
+        let count = AtomicUsize::new(0);
+
+        assert_eq!(on_signal(&count), SignalAction::Shutdown);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn second_signal_forces_exit() {
+        let count = AtomicUsize::new(0);
+
+        on_signal(&count);
+        assert_eq!(on_signal(&count), SignalAction::ForceExit);
+        assert_eq!(on_signal(&count), SignalAction::ForceExit);
+    }
+
+    #[test]
+    fn install_reuses_the_pipe() {
+        let first = ShutdownSignal::install().unwrap();
+        let write_fd = SIGNAL_PIPE.load(Ordering::SeqCst);
+        let second = ShutdownSignal::install().unwrap();
+
+        assert_eq!(SIGNAL_PIPE.load(Ordering::SeqCst), write_fd);
+        assert_eq!(first.read_fd.as_raw_fd(), second.read_fd.as_raw_fd());
+    }
+}
@@ -0,0 +1,233 @@
+//! Selection of the APEX modules to bind under `/apex`.
+//!
+//! Android binaries are symlinked into `/apex/<module>/...`, which apexd
+//! normally populates. We have no apexd, so the flattened modules shipped in
+//! `/system/apex/<dir>/` are bind-mounted over `/apex/<module>/` instead.
+//! `.apex`/`.capex` archives are compressed images that can't be mounted
+//! without apexd; they are reported and skipped. Symlinks are never followed,
+//! so an entry can't redirect a bind mount to somewhere else in the image.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// File names of the manifest in a flattened module, newest first
+const APEX_MANIFESTS: &[&str] = &["apex_manifest.pb", "apex_manifest.json"];
+
+/// A flattened module to bind-mount
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApexModule {
+    /// Module name, i.e. the directory under `/apex`
+    pub name: String,
+    /// Directory name under `/system/apex`
+    pub dir_name: String,
+}
+
+/// Result of scanning `/system/apex`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ApexScan {
+    /// Flattened modules, sorted by name
+    pub modules: Vec<ApexModule>,
+    /// Packaged `.apex`/`.capex` archives that were not selected
+    pub archives: Vec<PathBuf>,
+    /// Entries that are neither (symlinks, stray files), with the reason
+    pub skipped: Vec<(PathBuf, &'static str)>,
+}
+
+/// Scan a `/system/apex` directory and pick the modules to bind
+///
+/// A directory named after the manifest's module name takes precedence over
+/// a differently named one (e.g. Android 10's `com.android.runtime.release`)
+/// providing the same module.
+pub fn scan(system_apex_dir: &Path) -> Result<ApexScan> {
+    let mut scan = ApexScan::default();
+
+    let entries = std::fs::read_dir(system_apex_dir)
+        .with_context(|| format!("Failed to read {}", system_apex_dir.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let dir_name = entry.file_name().to_string_lossy().into_owned();
+        // DirEntry::file_type does not follow symlinks
+        let file_type = entry.file_type()?;
+
+        if file_type.is_symlink() {
+            scan.skipped.push((path, "symlink"));
+        } else if file_type.is_dir() {
+            let name = manifest_name(&path).unwrap_or_else(|| dir_name.clone());
+            scan.modules.push(ApexModule { name, dir_name });
+        } else if dir_name.ends_with(".apex") || dir_name.ends_with(".capex") {
+            scan.archives.push(path);
+        } else {
+            scan.skipped.push((path, "not an APEX module"));
+        }
+    }
+
+    // One directory per module name, preferring the exact match
+    scan.modules.sort_by(|a, b| {
+        a.name
+            .cmp(&b.name)
+            .then((a.dir_name != a.name).cmp(&(b.dir_name != b.name)))
+            .then(a.dir_name.cmp(&b.dir_name))
+    });
+    scan.modules.dedup_by(|dup, kept| {
+        if dup.name == kept.name {
+            scan.skipped
+                .push((system_apex_dir.join(&dup.dir_name), "duplicate module name"));
+            true
+        } else {
+            false
+        }
+    });
+    scan.archives.sort();
+
+    Ok(scan)
+}
+
+/// Module name from the manifest of a flattened module, if it has a usable one
+fn manifest_name(module_dir: &Path) -> Option<String> {
+    APEX_MANIFESTS.iter().find_map(|file| {
+        let data = std::fs::read(module_dir.join(file)).ok()?;
+        let name = if file.ends_with(".pb") {
+            protobuf_name(&data)?
+        } else {
+            let manifest: serde_json::Value = serde_json::from_slice(&data).ok()?;
+            manifest.get("name")?.as_str()?.to_string()
+        };
+        is_valid_name(&name).then_some(name)
+    })
+}
+
+/// Field 1 (`name`) of an `ApexManifest` protobuf message
+fn protobuf_name(data: &[u8]) -> Option<String> {
+    let mut pos = 0;
+    while pos < data.len() {
+        let key = read_varint(data, &mut pos)?;
+        match key & 0x7 {
+            // Varint
+            0 => {
+                read_varint(data, &mut pos)?;
+            }
+            // Length-delimited
+            2 => {
+                let len = read_varint(data, &mut pos)? as usize;
+                let bytes = data.get(pos..pos.checked_add(len)?)?;
+                if key >> 3 == 1 {
+                    return String::from_utf8(bytes.to_vec()).ok();
+                }
+                pos += len;
+            }
+            // 64-bit
+            1 => pos += 8,
+            // 32-bit
+            5 => pos += 4,
+            _ => return None,
+        }
+    }
+    None
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Module names become a path component under `/apex`
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_selects_flattened_dirs_only() {
+        let root = std::env::temp_dir().join(format!("rad-apex-scan-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let apex = root.join("system/apex");
+        std::fs::create_dir_all(apex.join("com.android.art")).unwrap();
+        std::fs::create_dir_all(apex.join("com.android.i18n")).unwrap();
+        // Android 10 style: the directory name differs from the module name
+        std::fs::create_dir_all(apex.join("com.android.runtime.release")).unwrap();
+        std::fs::write(
+            apex.join("com.android.runtime.release/apex_manifest.json"),
+            r#"{"name": "com.android.runtime", "version": 1}"#,
+        )
+        .unwrap();
+        // Protobuf manifest: field 1 = "com.android.conscrypt", field 2 = 1
+        let mut pb = vec![0x0a, 21];
+        pb.extend_from_slice(b"com.android.conscrypt");
+        pb.extend_from_slice(&[0x10, 0x01]);
+        std::fs::create_dir_all(apex.join("conscrypt")).unwrap();
+        std::fs::write(apex.join("conscrypt/apex_manifest.pb"), pb).unwrap();
+        std::fs::write(apex.join("com.android.tzdata.apex"), b"PK").unwrap();
+        std::fs::write(apex.join("com.android.adbd.capex"), b"PK").unwrap();
+        std::fs::write(apex.join("README"), b"").unwrap();
+        std::os::unix::fs::symlink("/etc", apex.join("com.android.evil")).unwrap();
+        std::os::unix::fs::symlink("com.android.art", apex.join("com.android.art.link")).unwrap();
+
+        let scan = scan(&apex).unwrap();
+
+        let selected: Vec<(&str, &str)> = scan
+            .modules
+            .iter()
+            .map(|m| (m.name.as_str(), m.dir_name.as_str()))
+            .collect();
+        assert_eq!(
+            selected,
+            vec![
+                ("com.android.art", "com.android.art"),
+                ("com.android.conscrypt", "conscrypt"),
+                ("com.android.i18n", "com.android.i18n"),
+                ("com.android.runtime", "com.android.runtime.release"),
+            ]
+        );
+        assert_eq!(
+            scan.archives,
+            vec![
+                apex.join("com.android.adbd.capex"),
+                apex.join("com.android.tzdata.apex"),
+            ]
+        );
+        assert_eq!(scan.skipped.len(), 3);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn scan_prefers_dir_matching_module_name() {
+        let apex = std::env::temp_dir().join(format!("rad-apex-dup-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&apex);
+        for dir in ["com.android.runtime", "com.android.runtime.debug"] {
+            std::fs::create_dir_all(apex.join(dir)).unwrap();
+            std::fs::write(
+                apex.join(dir).join("apex_manifest.json"),
+                r#"{"name": "com.android.runtime"}"#,
+            )
+            .unwrap();
+        }
+
+        let scan = scan(&apex).unwrap();
+
+        assert_eq!(scan.modules.len(), 1);
+        assert_eq!(scan.modules[0].dir_name, "com.android.runtime");
+        assert_eq!(
+            scan.skipped,
+            vec![(
+                apex.join("com.android.runtime.debug"),
+                "duplicate module name"
+            )]
+        );
+
+        let _ = std::fs::remove_dir_all(apex);
+    }
+}
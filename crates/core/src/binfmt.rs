@@ -0,0 +1,244 @@
+//! binfmt_misc lookup for running foreign-architecture binaries.
+//!
+//! An arm64 system image on an x86_64 host only boots if an emulator such as
+//! `qemu-user` is registered with binfmt_misc; otherwise exec'ing init fails
+//! with ENOEXEC. Registrations live in `/proc/sys/fs/binfmt_misc/<name>`:
+//!
+//! ```text
+//! enabled
+//! interpreter /usr/bin/qemu-aarch64-static
+//! flags: F
+//! offset 0
+//! magic 7f454c460201010000000000000000000200b700
+//! mask ffffffffffffff00fffffffffffffffffeffffff
+//! ```
+//!
+//! For ELF handlers the magic covers the header up to `e_machine` (bytes
+//! 18-19), which tells which architecture the handler runs.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use apk::{EM_386, EM_AARCH64, EM_ARM, EM_X86_64};
+
+pub const BINFMT_MISC_DIR: &str = "/proc/sys/fs/binfmt_misc";
+
+/// Offset of `e_machine` in an ELF header
+const E_MACHINE_OFFSET: usize = 18;
+
+/// A magic-based binfmt_misc registration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinfmtEntry {
+    pub name: String,
+    pub enabled: bool,
+    pub interpreter: PathBuf,
+    pub offset: usize,
+    pub magic: Vec<u8>,
+    pub mask: Vec<u8>,
+}
+
+impl BinfmtEntry {
+    /// Parse a registration file; None for extension-based entries and for
+    /// the `register`/`status` control files
+    pub fn parse(name: &str, content: &str) -> Option<Self> {
+        let mut enabled = false;
+        let mut interpreter = None;
+        let mut offset = 0;
+        let mut magic = None;
+        let mut mask = Vec::new();
+
+        for line in content.lines() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "enabled" => enabled = true,
+                "disabled" => enabled = false,
+                "interpreter" => interpreter = Some(PathBuf::from(value)),
+                "offset" => offset = value.parse().ok()?,
+                "magic" => magic = Some(decode_hex(value)?),
+                "mask" => mask = decode_hex(value)?,
+                _ => {}
+            }
+        }
+
+        Some(Self {
+            name: name.to_string(),
+            enabled,
+            interpreter: interpreter?,
+            offset,
+            magic: magic?,
+            mask,
+        })
+    }
+
+    /// The ELF `e_machine` this entry handles, if it matches ELF binaries
+    pub fn elf_machine(&self) -> Option<u16> {
+        let byte = |i: usize| -> Option<u8> {
+            let index = i.checked_sub(self.offset)?;
+            let mask = self.mask.get(index).copied().unwrap_or(0xff);
+            Some(self.magic.get(index)? & mask)
+        };
+
+        let header: Option<Vec<u8>> = (0..E_MACHINE_OFFSET + 2).map(byte).collect();
+        let header = header?;
+        if !header.starts_with(b"\x7fELF") {
+            return None;
+        }
+        let bytes = [header[E_MACHINE_OFFSET], header[E_MACHINE_OFFSET + 1]];
+        match header[5] {
+            1 => Some(u16::from_le_bytes(bytes)),
+            2 => Some(u16::from_be_bytes(bytes)),
+            _ => None,
+        }
+    }
+}
+
+/// `e_machine` of the host, and of binaries it runs without emulation
+pub fn native_machines() -> &'static [u16] {
+    match std::env::consts::ARCH {
+        "x86_64" => &[EM_X86_64, EM_386],
+        "x86" => &[EM_386],
+        "aarch64" => &[EM_AARCH64, EM_ARM],
+        "arm" => &[EM_ARM],
+        _ => &[],
+    }
+}
+
+/// Enabled registrations in `dir` handling ELF `machine`
+pub fn handlers_for(dir: &Path, machine: u16) -> Result<Vec<BinfmtEntry>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut handlers = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Ok(content) = std::fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        if let Some(parsed) = BinfmtEntry::parse(&name, &content)
+            && parsed.enabled
+            && parsed.elf_machine() == Some(machine)
+        {
+            handlers.push(parsed);
+        }
+    }
+    Ok(handlers)
+}
+
+/// Fail with instructions if ELF `machine` binaries can't be exec'd on this host
+pub fn ensure_runnable(machine: u16) -> Result<()> {
+    if native_machines().contains(&machine) {
+        return Ok(());
+    }
+    if let Some(handler) = handlers_for(Path::new(BINFMT_MISC_DIR), machine)?.first() {
+        tracing::debug!(
+            "{} binaries run through binfmt_misc handler {} ({})",
+            machine_name(machine),
+            handler.name,
+            handler.interpreter.display()
+        );
+        return Ok(());
+    }
+
+    anyhow::bail!(
+        "The image needs {} binaries, but this is a {} host and no binfmt_misc \
+         handler for {} is registered (exec would fail with ENOEXEC).\n{}",
+        machine_name(machine),
+        std::env::consts::ARCH,
+        machine_name(machine),
+        REGISTER_HINT
+    )
+}
+
+/// How to register qemu-user handlers on common distros
+pub const REGISTER_HINT: &str = "Install qemu-user with binfmt support, e.g. \
+     `pacman -S qemu-user-static qemu-user-static-binfmt` or \
+     `apt install qemu-user-static binfmt-support`, then check \
+     /proc/sys/fs/binfmt_misc/ for a qemu-<arch> entry.";
+
+pub fn machine_name(machine: u16) -> &'static str {
+    match machine {
+        EM_AARCH64 => "aarch64",
+        EM_ARM => "arm",
+        EM_X86_64 => "x86_64",
+        EM_386 => "x86",
+        _ => "unknown",
+    }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim();
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QEMU_AARCH64: &str = "enabled
+interpreter /usr/bin/qemu-aarch64-static
+flags: F
+offset 0
+magic 7f454c460201010000000000000000000200b700
+mask ffffffffffffff00fffffffffffffffffeffffff
+";
+
+    #[test]
+    fn parses_qemu_registration() {
+        let entry = BinfmtEntry::parse("qemu-aarch64", QEMU_AARCH64).unwrap();
+        assert!(entry.enabled);
+        assert_eq!(
+            entry.interpreter,
+            PathBuf::from("/usr/bin/qemu-aarch64-static")
+        );
+        assert_eq!(entry.elf_machine(), Some(EM_AARCH64));
+
+        let arm = QEMU_AARCH64
+            .replace("qemu-aarch64", "qemu-arm")
+            .replace("magic 7f454c460201", "magic 7f454c460101")
+            .replace("0200b700", "02002800");
+        assert_eq!(
+            BinfmtEntry::parse("qemu-arm", &arm).unwrap().elf_machine(),
+            Some(EM_ARM)
+        );
+    }
+
+    #[test]
+    fn ignores_non_elf_and_control_files() {
+        let wine = "enabled\ninterpreter /usr/bin/wine\nflags: \noffset 0\nmagic 4d5a\n";
+        let entry = BinfmtEntry::parse("wine", wine).unwrap();
+        assert_eq!(entry.elf_machine(), None);
+
+        assert_eq!(BinfmtEntry::parse("status", "enabled\n"), None);
+        let disabled = QEMU_AARCH64.replace("enabled", "disabled");
+        assert!(
+            !BinfmtEntry::parse("qemu-aarch64", &disabled)
+                .unwrap()
+                .enabled
+        );
+    }
+
+    #[test]
+    fn handlers_for_scans_directory() {
+        let dir = std::env::temp_dir().join(format!("rad-binfmt-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("qemu-aarch64"), QEMU_AARCH64).unwrap();
+        std::fs::write(dir.join("status"), "enabled\n").unwrap();
+
+        let handlers = handlers_for(&dir, EM_AARCH64).unwrap();
+        assert_eq!(handlers.len(), 1);
+        assert_eq!(handlers[0].name, "qemu-aarch64");
+        assert!(handlers_for(&dir, EM_ARM).unwrap().is_empty());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,218 @@
+//! cgroup v2 resource limits for containers.
+//!
+//! Each limited container gets `/sys/fs/cgroup/rad/<name>`. The `rad`
+//! cgroup must be writable by the user and have the `memory`/`cpu`
+//! controllers available, i.e. be delegated (e.g. by systemd, or created by
+//! root with `chown`).
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+/// Mount point of the unified cgroup hierarchy
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Parent of the per-container cgroups, under [`CGROUP_ROOT`]
+pub const RAD_CGROUP: &str = "rad";
+
+/// `cpu.max` period: the quota is a share of every 100ms
+const CPU_PERIOD_US: u64 = 100_000;
+
+/// Limits applied to everything running in a container
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// `memory.max`: the OOM killer runs inside the container past this
+    pub memory_max_bytes: Option<u64>,
+    /// `cpu.max` as a percentage of one CPU (200 = two full CPUs)
+    pub cpu_max_percent: Option<u32>,
+}
+
+impl ResourceLimits {
+    pub fn is_unlimited(&self) -> bool {
+        self.memory_max_bytes.is_none() && self.cpu_max_percent.is_none()
+    }
+
+    /// Controllers the limits need
+    fn controllers(&self) -> Vec<&'static str> {
+        let mut controllers = Vec::new();
+        if self.memory_max_bytes.is_some() {
+            controllers.push("memory");
+        }
+        if self.cpu_max_percent.is_some() {
+            controllers.push("cpu");
+        }
+        controllers
+    }
+
+    /// `cpu.max` value (`<quota> <period>` in microseconds)
+    fn cpu_max(&self) -> Option<String> {
+        self.cpu_max_percent
+            .map(|percent| format!("{} {}", u64::from(percent) * CPU_PERIOD_US / 100, CPU_PERIOD_US))
+    }
+}
+
+/// A container's cgroup
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// The cgroup for container `name` under `root`, whether or not it exists
+    pub fn for_name(root: &Path, name: &str) -> Result<Self> {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            anyhow::bail!("Invalid cgroup name: {:?}", name);
+        }
+        Ok(Self {
+            path: root.join(RAD_CGROUP).join(name),
+        })
+    }
+
+    /// Create the cgroup for container `name` under `root` and apply `limits`
+    pub fn create(root: &Path, name: &str, limits: &ResourceLimits) -> Result<Self> {
+        let cgroup = Self::for_name(root, name)?;
+        let parent = root.join(RAD_CGROUP);
+        fs::create_dir_all(&parent).with_context(|| {
+            format!(
+                "Failed to create {}; it must be delegated to this user",
+                parent.display()
+            )
+        })?;
+
+        // Controllers only show up in a child once its parent enables them
+        let available = fs::read_to_string(parent.join("cgroup.controllers")).unwrap_or_default();
+        for controller in limits.controllers() {
+            if !available.split_whitespace().any(|c| c == controller) {
+                anyhow::bail!(
+                    "The {} cgroup controller is not delegated to {}",
+                    controller,
+                    parent.display()
+                );
+            }
+            write_file(&parent.join("cgroup.subtree_control"), &format!("+{}", controller))?;
+        }
+
+        match fs::create_dir(&cgroup.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to create {}", cgroup.path.display()));
+            }
+        }
+
+        if let Some(bytes) = limits.memory_max_bytes {
+            write_file(&cgroup.path.join("memory.max"), &bytes.to_string())?;
+        }
+        if let Some(cpu_max) = limits.cpu_max() {
+            write_file(&cgroup.path.join("cpu.max"), &cpu_max)?;
+        }
+        Ok(cgroup)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.is_dir()
+    }
+
+    /// `cgroup.procs`, opened for writing: writing `0` to it moves the
+    /// writing process (e.g. a child before exec)
+    pub fn procs_file(&self) -> Result<fs::File> {
+        let path = self.path.join("cgroup.procs");
+        fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))
+    }
+
+    /// Move process `pid` into the cgroup
+    pub fn add_process(&self, pid: u32) -> Result<()> {
+        write_file(&self.path.join("cgroup.procs"), &pid.to_string())
+    }
+
+    /// Remove the cgroup, waiting briefly for killed processes to leave it
+    pub fn remove(self) -> Result<()> {
+        let mut attempts = 0;
+        loop {
+            match fs::remove_dir(&self.path) {
+                Ok(()) => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+                Err(e) if e.raw_os_error() == Some(nix::libc::EBUSY) && attempts < 20 => {
+                    attempts += 1;
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to remove {}", self.path.display()));
+                }
+            }
+        }
+    }
+}
+
+fn write_file(path: &Path, value: &str) -> Result<()> {
+    fs::write(path, value).with_context(|| format!("Failed to write {:?} to {}", value, path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_root(label: &str, controllers: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("rad-cgroup-{}-{}", label, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join(RAD_CGROUP)).unwrap();
+        fs::write(root.join(RAD_CGROUP).join("cgroup.controllers"), controllers).unwrap();
+        root
+    }
+
+    #[test]
+    fn cpu_max_is_a_share_of_the_period() {
+        let limits = |percent| ResourceLimits {
+            cpu_max_percent: Some(percent),
+            ..Default::default()
+        };
+        assert_eq!(limits(50).cpu_max().as_deref(), Some("50000 100000"));
+        assert_eq!(limits(200).cpu_max().as_deref(), Some("200000 100000"));
+        assert_eq!(ResourceLimits::default().cpu_max(), None);
+        assert!(ResourceLimits::default().is_unlimited());
+    }
+
+    #[test]
+    fn create_writes_limits() {
+        let root = fake_root("create", "cpuset cpu io memory pids\n");
+        let limits = ResourceLimits {
+            memory_max_bytes: Some(512 * 1024 * 1024),
+            cpu_max_percent: Some(150),
+        };
+
+        let cgroup = Cgroup::create(&root, "com.example.app", &limits).unwrap();
+        assert_eq!(cgroup.path(), root.join("rad/com.example.app"));
+        let read = |file: &str| fs::read_to_string(cgroup.path().join(file)).unwrap();
+        assert_eq!(read("memory.max"), "536870912");
+        assert_eq!(read("cpu.max"), "150000 100000");
+
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn create_requires_delegated_controllers() {
+        let root = fake_root("undelegated", "pids\n");
+        let limits = ResourceLimits {
+            memory_max_bytes: Some(1 << 30),
+            ..Default::default()
+        };
+
+        let err = Cgroup::create(&root, "app", &limits).unwrap_err().to_string();
+        assert!(err.contains("memory cgroup controller is not delegated"), "{}", err);
+        assert!(Cgroup::for_name(&root, "../escape").is_err());
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
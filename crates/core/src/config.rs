@@ -0,0 +1,271 @@
+//! Per-package run settings persisted in `<prefix>/config.toml`.
+//!
+//! Every field is optional; command-line flags override the file (see
+//! [`RunConfig::merge`]) and unset values fall back to the built-in defaults.
+//!
+//! ```toml
+//! boot_timeout = 180
+//! stop_timeout = 10
+//! abi = "arm64-v8a"
+//! network = "slirp"
+//! init_args = ["--second-stage"]
+//!
+//! [display]
+//! width = 1080
+//! height = 1920
+//! density = 420
+//!
+//! [env]
+//! ANDROID_LOG_TAGS = "*:v"
+//!
+//! [limits]
+//! memory_max_bytes = 536870912
+//! cpu_max_percent = 100
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::cgroup::ResourceLimits;
+use crate::container::Container;
+use crate::net::NetworkMode;
+
+/// Name of the config file in the prefix root
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// Seconds to wait for `sys.boot_completed` unless configured otherwise
+pub const DEFAULT_BOOT_TIMEOUT: u64 = 120;
+
+/// cgroup limits, see `Container::with_limits`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub memory_max_bytes: Option<u64>,
+    pub cpu_max_percent: Option<u32>,
+}
+
+/// Display size and density, see `Container::with_display`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisplayConfig {
+    pub width: u32,
+    pub height: u32,
+    pub density: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RunConfig {
+    /// Seconds to wait for Android to boot
+    pub boot_timeout: Option<u64>,
+    /// Seconds to wait for namespace setup before init is exec'd
+    pub start_timeout: Option<u64>,
+    /// Seconds init gets to exit after SIGTERM before it is killed
+    pub stop_timeout: Option<u64>,
+    /// Native ABI to install, e.g. "arm64-v8a"
+    pub abi: Option<String>,
+    /// Init binary inside the container, instead of probing the image
+    pub init_path: Option<String>,
+    /// Arguments passed to init
+    pub init_args: Vec<String>,
+    pub display: Option<DisplayConfig>,
+    /// Host network, or an isolated one ("none"/"slirp")
+    pub network: Option<NetworkMode>,
+    /// Extra environment for the container
+    pub env: BTreeMap<String, String>,
+    /// Memory and CPU caps
+    pub limits: LimitsConfig,
+}
+
+impl RunConfig {
+    /// Load `path`, or the defaults if it doesn't exist
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        toml::from_str(&content).with_context(|| format!("Invalid config {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = toml::to_string_pretty(self)?;
+        std::fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Layer `overrides` (typically from flags) over this config: set values
+    /// replace ours, env vars are merged with theirs winning
+    pub fn merge(mut self, overrides: RunConfig) -> Self {
+        self.boot_timeout = overrides.boot_timeout.or(self.boot_timeout);
+        self.start_timeout = overrides.start_timeout.or(self.start_timeout);
+        self.stop_timeout = overrides.stop_timeout.or(self.stop_timeout);
+        self.abi = overrides.abi.or(self.abi);
+        self.init_path = overrides.init_path.or(self.init_path);
+        if !overrides.init_args.is_empty() {
+            self.init_args = overrides.init_args;
+        }
+        self.display = overrides.display.or(self.display);
+        self.network = overrides.network.or(self.network);
+        self.env.extend(overrides.env);
+        self.limits.memory_max_bytes = overrides
+            .limits
+            .memory_max_bytes
+            .or(self.limits.memory_max_bytes);
+        self.limits.cpu_max_percent = overrides.limits.cpu_max_percent.or(self.limits.cpu_max_percent);
+        self
+    }
+
+    pub fn boot_timeout(&self) -> u64 {
+        self.boot_timeout.unwrap_or(DEFAULT_BOOT_TIMEOUT)
+    }
+
+    /// Configure `container` with the settings that apply before it starts
+    pub fn apply(&self, mut container: Container) -> Container {
+        if let Some(secs) = self.start_timeout {
+            container = container.with_start_timeout(Duration::from_secs(secs));
+        }
+        if let Some(secs) = self.stop_timeout {
+            container = container.with_stop_timeout(Duration::from_secs(secs));
+        }
+        if let Some(display) = self.display {
+            container = container.with_display(display.width, display.height, display.density);
+        }
+        if self.init_path.is_some() || !self.init_args.is_empty() {
+            container = container.with_init(self.init_path.clone(), self.init_args.clone());
+        }
+        if let Some(mode) = self.network {
+            container = container.with_networking(mode);
+        }
+        let limits = ResourceLimits {
+            memory_max_bytes: self.limits.memory_max_bytes,
+            cpu_max_percent: self.limits.cpu_max_percent,
+        };
+        if !limits.is_unlimited() {
+            container = container.with_limits(limits);
+        }
+        if !self.env.is_empty() {
+            container = container.with_env(
+                self.env
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+            );
+        }
+        container
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(label: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("rad-config-{}-{}.toml", label, std::process::id()))
+    }
+
+    #[test]
+    fn loads_sample_config() {
+        let path = temp_path("sample");
+        std::fs::write(
+            &path,
+            r#"
+boot_timeout = 180
+stop_timeout = 10
+abi = "arm64-v8a"
+network = "slirp"
+
+[display]
+width = 1080
+height = 1920
+density = 420
+
+[env]
+ANDROID_LOG_TAGS = "*:v"
+
+[limits]
+memory_max_bytes = 536870912
+"#,
+        )
+        .unwrap();
+
+        let config = RunConfig::load(&path).unwrap();
+        assert_eq!(config.boot_timeout(), 180);
+        assert_eq!(config.start_timeout, None);
+        assert_eq!(config.stop_timeout, Some(10));
+        assert_eq!(config.abi.as_deref(), Some("arm64-v8a"));
+        assert_eq!(config.network, Some(NetworkMode::Slirp));
+        assert_eq!(
+            config.display,
+            Some(DisplayConfig {
+                width: 1080,
+                height: 1920,
+                density: 420
+            })
+        );
+        assert_eq!(
+            config.env.get("ANDROID_LOG_TAGS").map(String::as_str),
+            Some("*:v")
+        );
+        assert_eq!(
+            config.limits,
+            LimitsConfig {
+                memory_max_bytes: Some(536870912),
+                cpu_max_percent: None
+            }
+        );
+
+        // Round-trips through save
+        config.save(&path).unwrap();
+        assert_eq!(RunConfig::load(&path).unwrap(), config);
+
+        std::fs::write(&path, "boot_timout = 5\n").unwrap();
+        assert!(RunConfig::load(&path).is_err(), "typos are reported");
+
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn flags_override_file() {
+        let mut file = RunConfig {
+            boot_timeout: Some(180),
+            abi: Some("arm64-v8a".to_string()),
+            ..Default::default()
+        };
+        file.env.insert("A".to_string(), "file".to_string());
+        file.env.insert("B".to_string(), "file".to_string());
+        file.limits.memory_max_bytes = Some(1 << 30);
+        file.limits.cpu_max_percent = Some(100);
+        file.init_args = vec!["--file".to_string()];
+
+        let mut flags = RunConfig {
+            abi: Some("x86_64".to_string()),
+            init_path: Some("/system/bin/init".to_string()),
+            init_args: vec!["--second-stage".to_string()],
+            ..Default::default()
+        };
+        flags.limits.cpu_max_percent = Some(50);
+        flags.env.insert("B".to_string(), "flag".to_string());
+
+        let merged = file.merge(flags);
+        assert_eq!(merged.boot_timeout(), 180);
+        assert_eq!(merged.abi.as_deref(), Some("x86_64"));
+        assert_eq!(merged.env["A"], "file");
+        assert_eq!(merged.env["B"], "flag");
+        assert_eq!(merged.limits.memory_max_bytes, Some(1 << 30));
+        assert_eq!(merged.limits.cpu_max_percent, Some(50));
+        assert_eq!(merged.init_path.as_deref(), Some("/system/bin/init"));
+        assert_eq!(merged.init_args, ["--second-stage"]);
+    }
+
+    #[test]
+    fn missing_file_gives_defaults() {
+        let config = RunConfig::load(&temp_path("missing")).unwrap();
+        assert_eq!(config, RunConfig::default());
+        assert_eq!(config.boot_timeout(), DEFAULT_BOOT_TIMEOUT);
+    }
+}
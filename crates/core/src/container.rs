@@ -3,22 +3,353 @@
 //! This module manages the full lifecycle of an Android container WITHOUT sudo:
 //! - Mount system.img and vendor.img via fuse2fs (userspace FUSE)
 //! - Fork child process
-//! - Enter user + mount + PID + IPC + UTS namespaces (via nix crate)
+//! - Enter user + mount + PID + IPC + UTS (+ network) namespaces (via nix crate)
 //! - Set up overlayfs rootfs inside the namespace
 //! - Boot Android init as PID 1 inside the container
-//! - Execute commands inside running container (nsenter via setns)
+//! - Execute commands inside running container (setns, or nsenter as a fallback)
 //! - Stop the container and clean up FUSE mounts
 
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::io::{BufRead, BufReader};
+use std::os::fd::RawFd;
+use std::process::{Child, Command};
+use std::time::Duration;
 
 use anyhow::{Context, Result};
+use apk::Abi;
 use nix::sys::signal::Signal;
 use nix::sys::wait::{waitpid, WaitPidFlag};
 use nix::unistd::Pid;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
+use crate::apex;
+use crate::cgroup::{CGROUP_ROOT, Cgroup, ResourceLimits};
+use crate::error::CoreError;
 use crate::image::{ImagePaths, MountPoints};
+use crate::net::{NetworkMode, SLIRP_PID_FILE, Slirp};
+use crate::overlay;
+use crate::paths;
+use crate::snapshot;
+use crate::zygote;
+
+/// Default bound on the namespace/overlay setup before init is exec'd
+const DEFAULT_START_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default grace period `stop` gives init between SIGTERM and SIGKILL
+pub const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often `stop` checks whether init has exited during the grace period
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How long to wait for a SIGKILLed namespace to empty out
+const NAMESPACE_KILL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Printed by the setup script right before it execs init
+const READY_MARKER: &str = "RAD_INIT_READY";
+
+/// Output of the setup script and init, in the prefix's `logs` dir;
+/// truncated on every start
+pub const BOOT_LOG_FILE: &str = "container.log";
+
+/// Directory in the prefix holding one state dir per instance (see
+/// `Container::with_instance`)
+pub const INSTANCES_DIR: &str = "instances";
+
+/// Lines of the boot log quoted when init dies
+const BOOT_LOG_TAIL_LINES: usize = 20;
+
+/// How often `wait_for_ready` re-reads the boot log
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Essentials added to the zygote Android environment for every container
+/// (unless overridden with `with_env`). The host dirs in `PATH` are needed by
+/// the setup script, which runs host `mount`/`mknod` before the chroot.
+const DEFAULT_INIT_ENV: &[(&str, &str)] = &[(
+    "PATH",
+    "/system/bin:/system/xbin:/vendor/bin:/bin:/usr/bin",
+)];
+
+/// Boot-time property overrides, read by init on debuggable (userdebug/eng)
+/// builds such as the Waydroid images; relative to the overlay upper dir
+const LOCAL_PROP_FILE: &str = "data/local.prop";
+
+/// First line of a `local.prop` we generated, so we only ever remove our own
+const LOCAL_PROP_HEADER: &str = "# Generated by rad; rewritten on every start";
+
+/// Locations of adbd: the APEX module (Android 11+) or the system partition
+const ADBD_PATHS: &[&str] = &["/apex/com.android.adbd/bin/adbd", "/system/bin/adbd"];
+
+/// `statfs` magics of filesystems overlayfs can't use as an unprivileged
+/// upperdir, with a short reason
+const UNSUPPORTED_UPPER_FS: &[(i64, &str)] = &[
+    (0x0102_1994, "tmpfs (no user xattrs before Linux 6.6)"),
+    (0x794c_7630, "overlayfs (nested overlay upperdir)"),
+    (0x6573_5546, "FUSE"),
+    (0x6969, "NFS"),
+    (0x517b, "SMB"),
+    (0xff53_4d42, "CIFS"),
+    (0xfe53_4d42, "SMB2"),
+    (0xf15f, "eCryptfs"),
+];
+
+/// How long `install_apk` waits for the package manager service
+const PACKAGE_SERVICE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Android log buffer selectable with `logcat -b`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogcatBuffer {
+    Main,
+    System,
+    Crash,
+    Events,
+    Radio,
+    All,
+}
+
+impl LogcatBuffer {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogcatBuffer::Main => "main",
+            LogcatBuffer::System => "system",
+            LogcatBuffer::Crash => "crash",
+            LogcatBuffer::Events => "events",
+            LogcatBuffer::Radio => "radio",
+            LogcatBuffer::All => "all",
+        }
+    }
+}
+
+/// Options for reading logcat from the container
+#[derive(Debug, Clone, Default)]
+pub struct LogcatOpts {
+    /// Buffers to read (empty = logcat's default set)
+    pub buffers: Vec<LogcatBuffer>,
+    /// Only print the most recent N lines
+    pub lines: Option<usize>,
+}
+
+impl LogcatOpts {
+    /// Translate the options into `logcat` arguments
+    ///
+    /// `dump` adds `-d` so logcat exits after printing instead of following.
+    pub fn to_args(&self, dump: bool) -> Vec<String> {
+        let mut args = Vec::new();
+        if dump {
+            args.push("-d".to_string());
+        }
+        for buffer in &self.buffers {
+            args.push("-b".to_string());
+            args.push(buffer.as_str().to_string());
+        }
+        if let Some(lines) = self.lines {
+            args.push("-t".to_string());
+            args.push(lines.to_string());
+        }
+        args
+    }
+}
+
+/// Lines printed by a `logcat` in the container (see
+/// [`Container::logcat_lines`]), as they arrive
+///
+/// logcat runs in its own process group, which dropping this kills, taking
+/// along the processes the namespace join forked.
+pub struct LogcatLines {
+    child: Child,
+    lines: std::io::Lines<BufReader<std::process::ChildStdout>>,
+}
+
+impl LogcatLines {
+    /// `child` must lead its own process group, with stdout piped
+    fn new(mut child: Child) -> Result<Self> {
+        let stdout = child.stdout.take().context("logcat stdout is not piped")?;
+        Ok(Self {
+            child,
+            lines: BufReader::new(stdout).lines(),
+        })
+    }
+
+    /// The process group logcat runs in, e.g. to signal it from another thread
+    pub fn process_group(&self) -> u32 {
+        self.child.id()
+    }
+}
+
+impl Iterator for LogcatLines {
+    type Item = String;
+
+    /// The next line; `None` once logcat exits (or its output can't be read)
+    fn next(&mut self) -> Option<String> {
+        self.lines.next()?.ok()
+    }
+}
+
+impl Drop for LogcatLines {
+    fn drop(&mut self) {
+        // The leader isn't reaped yet, so its PID still names our group
+        let _ = nix::sys::signal::killpg(Pid::from_raw(self.child.id() as i32), Signal::SIGKILL);
+        let _ = self.child.wait();
+    }
+}
+
+/// Where the overlay module exposes its parameters; one file per option the
+/// running kernel understands
+const OVERLAY_PARAMS_DIR: &str = "/sys/module/overlay/parameters";
+
+/// Optional overlayfs features for the container rootfs
+///
+/// All off by default: that's the set that mounts everywhere, including
+/// inside unprivileged user namespaces on older kernels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OverlayOpts {
+    /// Copy up only metadata on chmod/chown (`metacopy=on`)
+    pub metacopy: bool,
+    /// Allow renaming directories that exist in the lower layer (`redirect_dir=on`)
+    pub redirect_dir: bool,
+    /// Index copied-up inodes to keep hardlinks intact (`index=on`)
+    pub index: bool,
+}
+
+impl OverlayOpts {
+    /// The `-o` option string for an overlay mount with these features
+    pub fn mount_options(&self, lower: &Path, upper: &Path, work: &Path) -> String {
+        let mut opts = format!(
+            "lowerdir={},upperdir={},workdir={}",
+            lower.display(),
+            upper.display(),
+            work.display()
+        );
+        for (enabled, name) in self.flags() {
+            if enabled {
+                opts.push_str(&format!(",{}=on", name));
+            }
+        }
+        opts
+    }
+
+    /// Drop the features the running kernel doesn't know about
+    pub fn supported(&self) -> Self {
+        self.supported_with(|name| Path::new(OVERLAY_PARAMS_DIR).join(name).exists())
+    }
+
+    fn supported_with<F>(&self, has_param: F) -> Self
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut opts = *self;
+        for (flag, name) in [
+            (&mut opts.metacopy, "metacopy"),
+            (&mut opts.redirect_dir, "redirect_dir"),
+            (&mut opts.index, "index"),
+        ] {
+            if *flag && !has_param(name) {
+                warn!("Kernel overlayfs has no {} support, not enabling it", name);
+                *flag = false;
+            }
+        }
+        opts
+    }
+
+    fn flags(&self) -> [(bool, &'static str); 3] {
+        [
+            (self.metacopy, "metacopy"),
+            (self.redirect_dir, "redirect_dir"),
+            (self.index, "index"),
+        ]
+    }
+}
+
+/// A host path bind-mounted into the container rootfs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindMount {
+    /// Path on the host
+    pub host: PathBuf,
+    /// Absolute path inside the container
+    pub container_dest: String,
+    /// Remount read-only after binding
+    pub read_only: bool,
+}
+
+/// Where shared storage is bind-mounted inside the container
+pub const SHARED_STORAGE_MOUNT: &str = "/sdcard";
+
+/// Paths symlinked to [`SHARED_STORAGE_MOUNT`], where Android expects the
+/// primary external storage
+pub const SHARED_STORAGE_LINKS: &[&str] = &["/storage/emulated/0"];
+
+/// Permission bits that let non-root Android uids read shared storage
+///
+/// `unshare_command` uses `--map-root-user`, which maps only container root to
+/// the host user owning the directory. Every other container uid, the media
+/// user included, is then checked against the "other" bits.
+const SHARED_STORAGE_MODE: u32 = 0o755;
+
+/// A host directory used as the app's external storage (see
+/// `Container::with_shared_storage`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedStorage {
+    /// Directory on the host
+    pub host_dir: PathBuf,
+    /// Size cap in bytes; enforced by backing the directory with an ext4 image
+    pub size_cap: Option<u64>,
+}
+
+impl SharedStorage {
+    /// ext4 image backing a capped storage, `<host_dir>.img`, or None for a
+    /// plain directory
+    ///
+    /// The image is FUSE-mounted over `host_dir` while the container runs, so
+    /// the host still sees the files at `host_dir`.
+    pub fn image_path(&self) -> Option<PathBuf> {
+        self.size_cap?;
+        let mut name = self.host_dir.as_os_str().to_owned();
+        name.push(".img");
+        Some(PathBuf::from(name))
+    }
+}
+
+/// Stages of [`Container::start_with_progress`], in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartPhase {
+    /// Checking images, bind mounts and the prefix
+    ValidatingImages,
+    /// FUSE-mounting system.img
+    MountingSystem,
+    /// FUSE-mounting vendor.img
+    MountingVendor,
+    /// Selecting APEX modules and preparing their mount points
+    PreparingApex,
+    /// Creating the namespaces and exec'ing init
+    LaunchingInit,
+}
+
+/// How a container's init exited, as reported by [`Container::wait`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// Exited normally with this code
+    Exited(i32),
+    /// Killed by this signal
+    Signaled(Signal),
+    /// Gone, but not our child, so its status couldn't be collected
+    Unknown,
+}
+
+/// How often `Container::wait_for_prop` re-reads the property
+const PROP_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often `Container::wait_for_service` re-runs `service check`
+const SERVICE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Upper bound on a single probe (`getprop`, `service check`); the waits
+/// also clamp it to the time left, so a wedged container can't stall a wait
+/// past its own timeout
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often `exec_command_timeout` checks whether its command exited
+const EXEC_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How often `Container::wait` checks on an init it can't reap
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(200);
 
 /// State of a running container (fully rootless)
 #[derive(Debug)]
@@ -37,6 +368,43 @@ pub struct Container {
     overlay_mounted: bool,
     /// PID file path for persisting container PID
     pid_file: Option<PathBuf>,
+    /// Validate and log every operation without performing it
+    dry_run: bool,
+    /// Upper bound on namespace setup before init is exec'd
+    start_timeout: Duration,
+    /// Grace period between SIGTERM and SIGKILL in `stop`
+    stop_timeout: Duration,
+    /// Path (inside the container) of a custom entrypoint exec'd instead of
+    /// the detected init
+    init_path: Option<String>,
+    /// Arguments passed to init (or to the custom entrypoint)
+    init_args: Vec<String>,
+    /// Extra environment for the container, overriding the defaults
+    env: Vec<(String, String)>,
+    /// Optional overlayfs features for the rootfs mount
+    overlay_opts: OverlayOpts,
+    /// Extra host directories/files bind-mounted into the rootfs
+    binds: Vec<BindMount>,
+    /// System properties set at boot (through `/data/local.prop`)
+    boot_props: Vec<(String, String)>,
+    /// Flattened APEX modules bound under /apex (found by `prepare_apex_dirs`)
+    apex_modules: Vec<apex::ApexModule>,
+    /// Host directory bound at /sdcard (and linked from /storage/emulated/0)
+    shared_storage: Option<SharedStorage>,
+    /// Whether a size-capped shared storage image is currently FUSE-mounted
+    storage_mounted: bool,
+    /// Host fds passed to init as (host fd, fd number in the container)
+    inherited_fds: Vec<(RawFd, RawFd)>,
+    /// cgroup v2 limits for everything in the container
+    limits: ResourceLimits,
+    /// The container's cgroup, while it runs with `limits`
+    cgroup: Option<Cgroup>,
+    /// Host network, or an own network namespace (optionally NAT'd)
+    network: NetworkMode,
+    /// slirp4netns serving the container's network namespace
+    slirp: Option<Slirp>,
+    /// Instance ID, for running several containers from one prefix
+    instance: Option<String>,
 }
 
 impl Container {
@@ -50,6 +418,24 @@ impl Container {
             vendor_mounted: false,
             overlay_mounted: false,
             pid_file: None,
+            dry_run: false,
+            start_timeout: DEFAULT_START_TIMEOUT,
+            stop_timeout: DEFAULT_STOP_TIMEOUT,
+            init_path: None,
+            init_args: Vec::new(),
+            env: Vec::new(),
+            overlay_opts: OverlayOpts::default(),
+            binds: Vec::new(),
+            boot_props: Vec::new(),
+            apex_modules: Vec::new(),
+            shared_storage: None,
+            storage_mounted: false,
+            inherited_fds: Vec::new(),
+            limits: ResourceLimits::default(),
+            cgroup: None,
+            network: NetworkMode::default(),
+            slirp: None,
+            instance: None,
         }
     }
 
@@ -59,24 +445,247 @@ impl Container {
         self
     }
 
+    /// Run as instance `id` of the prefix, isolated from its other instances
+    ///
+    /// The PID file (if already set), the image mount points and the overlay
+    /// upper/work dirs move to `<prefix>/instances/<id>/`, so each instance
+    /// has its own Android data; the prefix's config and snapshots are
+    /// shared. The cgroup and boot log are per instance too. `id` is checked
+    /// when the container starts (see [`validate_instance_id`]).
+    pub fn with_instance(mut self, id: &str) -> Self {
+        let dir = self.prefix_root().join(INSTANCES_DIR).join(id);
+        let instance_mounts = MountPoints::for_prefix(&dir);
+        self.mounts.system_mount = instance_mounts.system_mount;
+        self.mounts.vendor_mount = instance_mounts.vendor_mount;
+        self.mounts.overlay_upper = instance_mounts.overlay_upper;
+        self.mounts.overlay_work = instance_mounts.overlay_work;
+        if let Some(pid_file) = &self.pid_file
+            && let Some(name) = pid_file.file_name()
+        {
+            self.pid_file = Some(dir.join(name));
+        }
+        self.instance = Some(id.to_string());
+        self
+    }
+
+    /// Where the init PID is persisted, if anywhere
+    pub fn pid_file(&self) -> Option<&Path> {
+        self.pid_file.as_deref()
+    }
+
+    /// Only validate preconditions and log the operations `start`,
+    /// `install_apk` and `launch_app` would perform
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Set how long `start` waits for namespace setup before giving up
+    pub fn with_start_timeout(mut self, timeout: Duration) -> Self {
+        self.start_timeout = timeout;
+        self
+    }
+
+    /// Set how long `stop` waits for init to exit after SIGTERM before
+    /// killing it
+    pub fn with_stop_timeout(mut self, grace: Duration) -> Self {
+        self.stop_timeout = grace;
+        self
+    }
+
+    /// Exec a custom command instead of Android init after the mounts are set up
+    ///
+    /// Useful for debugging, e.g. booting straight into `/system/bin/sh`.
+    pub fn with_init_command(self, cmd: String, args: Vec<String>) -> Self {
+        self.with_init(Some(cmd), args)
+    }
+
+    /// Exec `path` (a path inside the container) as init with `args`, e.g.
+    /// `--second-stage`
+    ///
+    /// With `None` the init binary is still looked up in the system image
+    /// (`/init`, `/system/bin/init`, `/bin/init`) and only gets `args`.
+    pub fn with_init(mut self, path: Option<String>, args: Vec<String>) -> Self {
+        self.init_path = path;
+        self.init_args = args;
+        self
+    }
+
+    /// Add environment variables for the container (later values win, and
+    /// caller values override the built-in defaults)
+    pub fn with_env(mut self, vars: Vec<(String, String)>) -> Self {
+        self.env.extend(vars);
+        self
+    }
+
+    /// Pass host fd `fd` to init as fd `target_fd`, e.g. a connected Wayland
+    /// socket, and point `WAYLAND_SOCKET` at it
+    ///
+    /// The fd must stay open until the container has started. Targets must
+    /// not collide with another inherited host fd.
+    pub fn with_inherited_fd(mut self, fd: RawFd, target_fd: RawFd) -> Self {
+        self.inherited_fds.push((fd, target_fd));
+        self.env
+            .push(("WAYLAND_SOCKET".to_string(), target_fd.to_string()));
+        self
+    }
+
+    /// Cap the container's memory and CPU with a cgroup
+    /// (`/sys/fs/cgroup/rad/<prefix name>`), created on start and removed on
+    /// stop. Starting fails if the cgroup can't be set up.
+    pub fn with_limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Choose how the container is networked
+    ///
+    /// `None` and `Slirp` give the container its own network namespace, so
+    /// host ports aren't visible to apps; `Slirp` also needs `slirp4netns`.
+    pub fn with_networking(mut self, mode: NetworkMode) -> Self {
+        self.network = mode;
+        self
+    }
+
+    /// Enable overlayfs features for the rootfs (those the kernel lacks are skipped)
+    pub fn with_overlay_opts(mut self, opts: OverlayOpts) -> Self {
+        self.overlay_opts = opts;
+        self
+    }
+
+    /// Render at `width`x`height` pixels and `density` dpi, e.g. to match the
+    /// host window
+    ///
+    /// Sets `ro.sf.lcd_density` and the Waydroid display size properties at
+    /// boot; they can't be changed once Android is up.
+    pub fn with_display(mut self, width: u32, height: u32, density: u32) -> Self {
+        for (key, value) in display_props(width, height, density) {
+            self.boot_props.retain(|(k, _)| *k != key);
+            self.boot_props.push((key, value));
+        }
+        self
+    }
+
+    /// Bind-mount `host` at `container_dest` inside the container once the
+    /// overlay is up, e.g. a directory of shared assets
+    ///
+    /// `container_dest` must stay within the rootfs; this is checked by `start`.
+    pub fn with_bind(mut self, host: PathBuf, container_dest: String, read_only: bool) -> Self {
+        self.binds.push(BindMount {
+            host,
+            container_dest,
+            read_only,
+        });
+        self
+    }
+
+    /// Use `host_dir` as the app's external storage, bound at `/sdcard`, with
+    /// `/storage/emulated/0` symlinked to it
+    ///
+    /// The directory is created on start if needed, readable by Android's
+    /// media user; an existing directory keeps its mode, with a warning if
+    /// the media user can't read it. Sharing is bidirectional: files dropped in
+    /// `host_dir` show up in the container and anything the app writes lands
+    /// directly on the host, with no size limit (see
+    /// `with_shared_storage_cap` to bound it). As `host_dir` lives outside the
+    /// overlay, its contents survive restarts, resets and snapshot restores.
+    pub fn with_shared_storage(mut self, host_dir: PathBuf) -> Self {
+        self.shared_storage = Some(SharedStorage {
+            host_dir,
+            size_cap: None,
+        });
+        self
+    }
+
+    /// Like `with_shared_storage`, but stop untrusted apps from filling the
+    /// host disk by backing the storage with a `max_bytes` ext4 image
+    ///
+    /// The image (`<host_dir>.img`) is created on first start, seeded with the
+    /// current contents of `host_dir`, and FUSE-mounted read-write over
+    /// `host_dir` while the container runs. Later changes to the cap don't
+    /// resize an existing image.
+    pub fn with_shared_storage_cap(mut self, host_dir: PathBuf, max_bytes: u64) -> Self {
+        self.shared_storage = Some(SharedStorage {
+            host_dir,
+            size_cap: Some(max_bytes),
+        });
+        self
+    }
+
     /// Start the container: FUSE-mount images, fork, enter namespaces, boot init
     ///
     /// This is fully rootless - no sudo required.
     /// Requires: fuse2fs, user namespaces enabled, overlayfs support.
     pub fn start(&mut self) -> Result<()> {
+        self.start_with_progress(|_| {})
+    }
+
+    /// [`start`](Self::start) on tokio's blocking pool, for async callers
+    ///
+    /// The container is moved to the blocking task while it starts and put
+    /// back when it's done; if this future is dropped early the task still
+    /// runs to completion and the dropped container is stopped.
+    pub async fn start_async(&mut self) -> Result<()> {
+        let placeholder = Container::new(self.images.clone(), self.mounts.clone());
+        let mut container = std::mem::replace(self, placeholder);
+
+        let (container, result) = tokio::task::spawn_blocking(move || {
+            let result = container.start();
+            (container, result)
+        })
+        .await
+        .context("Container start task panicked")?;
+
+        *self = container;
+        result
+    }
+
+    /// [`start`](Self::start), calling `progress` as each stage begins
+    ///
+    /// If a stage fails, the phases after it are never reported.
+    pub fn start_with_progress<F>(&mut self, mut progress: F) -> Result<()>
+    where
+        F: FnMut(StartPhase),
+    {
         info!("Starting rootless Android container...");
 
         // Validate images exist
-        self.images.validate()?;
+        progress(StartPhase::ValidatingImages);
+        let pending = self.images.pending_decompression();
+        if self.dry_run && !pending.is_empty() {
+            for (compressed, image) in &pending {
+                info!(
+                    "[dry-run] Would decompress {} to {}",
+                    compressed.display(),
+                    image.display()
+                );
+            }
+        } else {
+            self.images.decompress_pending()?;
+            self.images.validate()?;
+        }
+
+        if let Some(id) = &self.instance {
+            validate_instance_id(id)?;
+        }
+
+        // Validate extra bind mounts
+        self.bind_mount_script()?;
+
+        if self.dry_run {
+            return self.dry_run_start();
+        }
 
         // Create mount point directories
         self.mounts.ensure_dirs()?;
 
         // Prepare overlay upper/work directories
         self.prepare_prefix_dirs()?;
+        self.check_upper_fs()?;
+        self.write_boot_props()?;
 
         // Step 1: FUSE-mount system.img and vendor.img (userspace, no root)
-        self.fuse_mount_images()?;
+        self.fuse_mount_images(&mut progress)?;
 
         // Step 2: Pre-create APEX dirs in overlay upper layer.
         // The system image's /apex/ dir is owned by root:root with 0755 perms,
@@ -84,13 +693,30 @@ impl Container {
         // mkdir inside the overlayfs merged /apex/ would fail with Permission denied.
         // By pre-creating these dirs in the upper layer (which we own), overlayfs
         // will show our writable dirs instead of the unwritable lower ones.
+        progress(StartPhase::PreparingApex);
         self.prepare_apex_dirs()?;
 
         // Step 3: Generate linker config to suppress Android linker warnings
         self.generate_linkerconfig()?;
 
-        // Step 4: Fork + enter namespaces + overlayfs + chroot + exec init
-        self.launch_init()?;
+        // Step 4: cgroup for the resource limits, joined by init before exec
+        if !self.limits.is_unlimited() {
+            let cgroup = Cgroup::create(Path::new(CGROUP_ROOT), &self.cgroup_name()?, &self.limits)
+                .context("Failed to set up the container's resource limits")?;
+            info!("Applied resource limits in {}", cgroup.path().display());
+            self.cgroup = Some(cgroup);
+        }
+
+        // Step 5: Fork + enter namespaces + overlayfs + chroot + exec init
+        progress(StartPhase::LaunchingInit);
+        if let Err(e) = self.launch_init() {
+            // Tear down the mounts, cgroup and any init left now rather than
+            // leaving them to Drop
+            if let Err(cleanup) = self.stop() {
+                warn!("Cleanup after failed start failed: {:#}", cleanup);
+            }
+            return Err(e);
+        }
 
         info!(
             "Container started successfully (init PID: {:?})",
@@ -107,583 +733,3588 @@ impl Container {
         Ok(())
     }
 
-    /// Stop the container: kill init, unmount FUSE
-    pub fn stop(&mut self) -> Result<()> {
+    /// Stop the container: terminate init, unmount FUSE
+    ///
+    /// Init gets SIGTERM and up to the stop timeout (see
+    /// [`with_stop_timeout`](Self::with_stop_timeout)) to exit before it is
+    /// killed. Returns whether that SIGKILL was needed.
+    pub fn stop(&mut self) -> Result<bool> {
         info!("Stopping Android container...");
 
-        // Kill init process
-        if let Some(pid) = self.init_pid.take() {
-            info!("Killing init process (PID {})", pid);
-            let pid = Pid::from_raw(pid as i32);
-            // Send SIGTERM first
-            if nix::sys::signal::kill(pid, Signal::SIGTERM).is_ok() {
-                // Wait a bit for graceful shutdown
-                std::thread::sleep(std::time::Duration::from_secs(2));
-                // Force kill if still alive
-                let _ = nix::sys::signal::kill(pid, Signal::SIGKILL);
-                let _ = waitpid(pid, Some(WaitPidFlag::WNOHANG));
-            }
+        let forced = self.terminate_init();
+        self.init_pid = None;
+
+        // slirp4netns outlives the namespace it served; a detached
+        // container's is only known by its PID file
+        let slirp_pid_file = self.slirp_pid_file();
+        let stopped = match self.slirp.take() {
+            Some(slirp) => slirp.stop(),
+            None => match slirp_pid_file
+                .as_deref()
+                .and_then(|path| std::fs::read_to_string(path).ok())
+                .and_then(|pid| pid.trim().parse().ok())
+            {
+                Some(pid) => Slirp::stop_pid(pid),
+                None => Ok(()),
+            },
+        };
+        if let Err(e) = stopped {
+            warn!("Failed to stop slirp4netns: {:#}", e);
+        }
+        if let Some(path) = slirp_pid_file {
+            let _ = std::fs::remove_file(path);
         }
 
         // Unmount FUSE mounts (no sudo needed - fusermount -u)
         self.fuse_unmount_all()?;
 
+        // Remove the cgroup, including one left by a detached handle
+        let cgroup = self.cgroup.take().or_else(|| {
+            let name = self.cgroup_name().ok()?;
+            Cgroup::for_name(Path::new(CGROUP_ROOT), &name)
+                .ok()
+                .filter(Cgroup::exists)
+        });
+        if let Some(cgroup) = cgroup
+            && let Err(e) = cgroup.remove()
+        {
+            warn!("Failed to remove cgroup: {:#}", e);
+        }
+
         // Clean up PID file
         if let Some(pid_file) = &self.pid_file {
             let _ = std::fs::remove_file(pid_file);
         }
 
         info!("Container stopped");
-        Ok(())
+        Ok(forced)
     }
 
-    /// Execute a command inside the running container using nsenter
+    /// SIGTERM init and wait out the grace period, then SIGKILL it if it
+    /// is still running. Returns whether the SIGKILL was sent.
     ///
-    /// Note: nsenter into your own user namespace doesn't require root.
-    /// We use the external `nsenter` binary for simplicity.
-    pub fn exec_command(&self, command: &str, args: &[&str]) -> Result<std::process::Output> {
-        let init_pid = self
-            .init_pid
-            .context("Container is not running (no init PID)")?;
-
-        info!("Executing in container: {} {:?}", command, args);
+    /// `init_pid` is the outer unshare process, which doesn't pass signals
+    /// on, so SIGTERM goes to the namespace's own PID 1; unshare exits once
+    /// that does.
+    fn terminate_init(&self) -> bool {
+        let Some(raw) = self.init_pid else {
+            return false;
+        };
+        let pid = Pid::from_raw(raw as i32);
+        let target = namespace_init(pid).unwrap_or(pid);
+        info!("Terminating init process (PID {}, outer PID {})", target, raw);
+        if nix::sys::signal::kill(target, Signal::SIGTERM).is_err() {
+            return false;
+        }
 
-        let output = Command::new("nsenter")
-            .arg("-t")
-            .arg(init_pid.to_string())
-            .arg("--user")
-            .arg("--mount")
-            .arg("--uts")
-            .arg("--ipc")
-            .arg("--pid")
-            .arg("--")
-            .arg(command)
-            .args(args)
-            .output()
-            .context("Failed to execute nsenter")?;
+        let deadline = std::time::Instant::now() + self.stop_timeout;
+        loop {
+            // Reap it if it's our child, so it doesn't linger as a zombie
+            let _ = waitpid(pid, Some(WaitPidFlag::WNOHANG));
+            if !self.is_running() {
+                return false;
+            }
+            if std::time::Instant::now() >= deadline {
+                break;
+            }
+            std::thread::sleep(STOP_POLL_INTERVAL);
+        }
 
-        Ok(output)
+        warn!(
+            "Init (PID {}) still running {:?} after SIGTERM; killing it",
+            target, self.stop_timeout
+        );
+        kill_namespace(pid);
+        let _ = waitpid(pid, None);
+        true
     }
 
-    /// Install an APK into the running container
-    pub fn install_apk(&self, apk_path: &Path) -> Result<()> {
-        let _init_pid = self.init_pid.context("Container is not running")?;
-
-        info!("Installing APK: {}", apk_path.display());
-
-        // Copy APK into the container's /data directory via the overlay upper layer
-        let container_apk_dir = self.mounts.overlay_upper.join("data/local/tmp");
-        std::fs::create_dir_all(&container_apk_dir)?;
-        let container_apk = container_apk_dir.join("install.apk");
-        std::fs::copy(apk_path, &container_apk)
-            .context("Failed to copy APK into container overlay")?;
+    /// Give up this handle without stopping the container, returning the init PID
+    ///
+    /// The PID file is (re)written first, then the tracked PID and mount flags
+    /// are cleared so `Drop` does nothing. From here on nothing stops the
+    /// container or unmounts its images automatically: the caller is
+    /// responsible for cleanup, by adopting the PID from the PID file into a
+    /// new handle (`init_pid` plus `with_pid_file`) and calling `stop` on it.
+    pub fn detach(mut self) -> Option<u32> {
+        let pid = self.init_pid.take();
+        if let Some(pid) = pid {
+            if let Some(pid_file) = &self.pid_file
+                && let Err(e) = std::fs::write(pid_file, pid.to_string())
+            {
+                warn!("Failed to write PID file: {}", e);
+            }
+            info!("Detached from container (init PID {})", pid);
+        }
+        self.system_mounted = false;
+        self.vendor_mounted = false;
+        self.overlay_mounted = false;
+        self.storage_mounted = false;
+        self.cgroup = None;
+        // Dropping the handle leaves slirp4netns running with the container;
+        // `stop` finds it again through its PID file
+        self.slirp = None;
+        pid
+    }
 
-        // Use pm install inside the container
-        let output =
-            self.exec_command("pm", &["install", "-r", "/data/local/tmp/install.apk"])?;
+    /// Save the overlay upper layer (all writable state) as snapshot `name`
+    ///
+    /// The container must be stopped, so the layer isn't changing underneath
+    /// the copy. Fails if the snapshot already exists.
+    pub fn snapshot(&self, name: &str) -> Result<()> {
+        snapshot::validate_name(name)?;
+        self.ensure_stopped("snapshot")?;
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            warn!("pm install output: {}{}", stdout, stderr);
-            anyhow::bail!("pm install failed: {}{}", stdout, stderr);
+        let target = self.mounts.snapshots.join(name);
+        if target.exists() {
+            anyhow::bail!("Snapshot '{}' already exists at {}", name, target.display());
         }
+        std::fs::create_dir_all(&self.mounts.snapshots)?;
 
-        info!("APK installed successfully");
+        info!("Saving snapshot '{}' to {}", name, target.display());
+        if let Err(e) = snapshot::copy_tree(&self.mounts.overlay_upper, &target) {
+            let _ = std::fs::remove_dir_all(&target);
+            return Err(e).with_context(|| format!("Failed to save snapshot '{}'", name));
+        }
         Ok(())
     }
 
-    /// Launch an Android app by package name
-    pub fn launch_app(&self, package: &str, activity: &str) -> Result<()> {
-        info!("Launching {}/{}", package, activity);
-
-        let component = format!("{}/{}", package, activity);
-        let output = self.exec_command("am", &["start", "-n", &component])?;
+    /// Replace the overlay upper layer with snapshot `name`
+    ///
+    /// Changes made since the snapshot are lost. The container must be stopped.
+    pub fn restore_snapshot(&self, name: &str) -> Result<()> {
+        snapshot::validate_name(name)?;
+        self.ensure_stopped("restore a snapshot of")?;
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            anyhow::bail!("am start failed: {}{}", stdout, stderr);
+        let source = self.mounts.snapshots.join(name);
+        if !source.is_dir() {
+            anyhow::bail!("Snapshot '{}' not found in {}", name, self.mounts.snapshots.display());
         }
 
-        info!("App launched");
-        Ok(())
-    }
+        info!("Restoring snapshot '{}'", name);
+        snapshot::restore_tree(&source, &self.mounts.overlay_upper)
+            .with_context(|| format!("Failed to restore snapshot '{}'", name))?;
 
-    /// Check if the container init process is still running
-    pub fn is_running(&self) -> bool {
-        if let Some(pid) = self.init_pid {
-            let pid = Pid::from_raw(pid as i32);
-            nix::sys::signal::kill(pid, None).is_ok()
-        } else {
-            false
+        // Stale overlayfs bookkeeping must not outlive the layer it was for
+        if self.mounts.overlay_work.exists() {
+            std::fs::remove_dir_all(&self.mounts.overlay_work)?;
+            std::fs::create_dir_all(&self.mounts.overlay_work)?;
         }
+        Ok(())
     }
 
-    /// Wait for the Android system to boot (poll for sys.boot_completed)
-    pub fn wait_for_boot(&self, timeout_secs: u64) -> Result<()> {
-        info!(
-            "Waiting for Android system to boot (timeout: {}s)...",
-            timeout_secs
-        );
-
-        let start = std::time::Instant::now();
-        let timeout = std::time::Duration::from_secs(timeout_secs);
-
-        loop {
-            if start.elapsed() > timeout {
-                anyhow::bail!(
-                    "Timeout waiting for Android system to boot after {}s",
-                    timeout_secs
-                );
-            }
+    /// Delete `container_path` (e.g. `/system/app/Browser` or app data) from
+    /// the container's filesystem
+    ///
+    /// Works on the overlay upper layer, so the container must be stopped.
+    /// Paths that come from the system image are masked with a whiteout,
+    /// since the image itself is read-only.
+    pub fn delete_path(&self, container_path: &str) -> Result<()> {
+        self.ensure_stopped("delete files from")?;
 
-            if !self.is_running() {
-                anyhow::bail!("Container init process died during boot");
-            }
+        // The image is normally unmounted while stopped; without it, assume
+        // the path may be there
+        let lower = self
+            .mounts
+            .system_mount
+            .join("system")
+            .is_dir()
+            .then_some(self.mounts.system_mount.as_path());
+        overlay::remove(&self.mounts.overlay_upper, lower, container_path)
+            .with_context(|| format!("Failed to delete {}", container_path))
+    }
 
-            // Check if system has booted
-            match self.exec_command("getprop", &["sys.boot_completed"]) {
-                Ok(output) => {
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    if stdout.trim() == "1" {
-                        info!("Android system boot completed!");
-                        return Ok(());
-                    }
-                }
-                Err(_) => {
-                    // getprop may not be available yet, keep waiting
-                }
-            }
+    /// Fail if this handle, or another invocation per the PID file, is running the container
+    fn ensure_stopped(&self, action: &str) -> Result<()> {
+        let recorded_pid = self
+            .pid_file
+            .as_ref()
+            .and_then(|f| std::fs::read_to_string(f).ok())
+            .and_then(|pid| pid.trim().parse::<i32>().ok())
+            .filter(|pid| nix::sys::signal::kill(Pid::from_raw(*pid), None).is_ok());
 
-            std::thread::sleep(std::time::Duration::from_secs(2));
+        if self.is_running() || self.system_mounted || self.vendor_mounted || recorded_pid.is_some() {
+            anyhow::bail!("Cannot {} a running container; stop it first", action);
         }
+        Ok(())
     }
 
-    // --- Private methods ---
+    /// Stop the container if it is running, clean up stale mounts and start it again
+    ///
+    /// The prefix and overlay upper layer are left alone, so app data survives.
+    /// Restarting a stopped container is the same as starting it.
+    pub fn restart(&mut self) -> Result<()> {
+        info!("Restarting Android container...");
 
-    fn prepare_prefix_dirs(&self) -> Result<()> {
-        let data_dir = self.mounts.overlay_upper.join("data");
-        std::fs::create_dir_all(&data_dir)?;
-        std::fs::create_dir_all(data_dir.join("app"))?;
-        std::fs::create_dir_all(data_dir.join("data"))?;
-        std::fs::create_dir_all(data_dir.join("local/tmp"))?;
-        Ok(())
+        if self.init_pid.is_some() || self.system_mounted || self.vendor_mounted {
+            self.stop()?;
+        }
+
+        if !self.dry_run {
+            self.clean_stale_mounts()?;
+        }
+
+        self.start()
     }
 
-    /// Pre-create APEX module directories in the overlay upper layer.
+    /// Execute a command inside the running container
     ///
-    /// Android binaries (sh, linker64, app_process64, etc.) are symlinked to
-    /// /apex/<module>/... but the /apex/ directory in the system image is empty.
-    /// The actual APEX module contents live at /system/apex/<module>/.
+    /// The namespaces are joined natively with setns; if that fails (e.g. a
+    /// permission error), the external `nsenter` binary is tried instead.
+    /// Entering your own user namespace doesn't require root either way.
+    pub fn exec_command(&self, command: &str, args: &[&str]) -> Result<std::process::Output> {
+        info!("Executing in container: {} {:?}", command, args);
+        self.run_in_container(command, args, |cmd| cmd.output())
+    }
+
+    /// Like [`exec_command`](Self::exec_command), but kill the command (and
+    /// its process group) if it runs longer than `timeout`
     ///
-    /// Inside the user namespace, the fuse2fs-mounted /apex/ dir is owned by
-    /// nobody (unmapped root), making mkdir fail. We pre-create the dirs in the
-    /// overlay upper layer (which we own) so they appear writable in the merged view.
-    fn prepare_apex_dirs(&self) -> Result<()> {
-        let system_apex_dir = self.mounts.system_mount.join("system/apex");
-        if !system_apex_dir.exists() {
-            info!("No /system/apex directory found in system image, skipping APEX prep");
-            return Ok(());
-        }
+    /// A timeout is reported as [`CoreError::TimedOut`] in the error chain.
+    pub fn exec_command_timeout(
+        &self,
+        command: &str,
+        args: &[&str],
+        timeout: Duration,
+    ) -> Result<std::process::Output> {
+        debug!("Executing in container (timeout {:?}): {} {:?}", timeout, command, args);
+        self.run_in_container(command, args, |cmd| output_with_timeout(cmd, timeout))?
+            .ok_or_else(|| {
+                CoreError::TimedOut {
+                    command: std::iter::once(command)
+                        .chain(args.iter().copied())
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    timeout,
+                }
+                .into()
+            })
+    }
 
-        let upper_apex_dir = self.mounts.overlay_upper.join("apex");
-        std::fs::create_dir_all(&upper_apex_dir)?;
+    /// Dump the Android logcat buffers (`logcat -d`)
+    pub fn logcat(&self, opts: LogcatOpts) -> Result<String> {
+        let args = opts.to_args(true);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let output = self.exec_command("logcat", &args)?;
 
-        let mut count = 0;
-        for entry in std::fs::read_dir(&system_apex_dir)
-            .context("Failed to read /system/apex directory")?
-        {
-            let entry = entry?;
-            if entry.file_type()?.is_dir() {
-                let name = entry.file_name();
-                let target = upper_apex_dir.join(&name);
-                std::fs::create_dir_all(&target)?;
-                count += 1;
-            }
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("logcat failed: {}", stderr.trim());
         }
 
-        info!(
-            "Pre-created {} APEX directories in overlay upper layer",
-            count
-        );
-        Ok(())
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
     }
 
-    /// Generate a minimal linker configuration to suppress Android linker warnings.
+    /// Run `logcat` in the container and iterate over its lines as they are
+    /// printed: the buffers' current contents and then, with `follow`, new
+    /// messages until logcat is killed or the container stops
     ///
-    /// The Android dynamic linker (linker64) looks for /linkerconfig/ld.config.txt
-    /// on boot. Without it, every binary invocation prints a warning.
-    fn generate_linkerconfig(&self) -> Result<()> {
-        let linkerconfig_dir = self.mounts.overlay_upper.join("linkerconfig");
-        std::fs::create_dir_all(&linkerconfig_dir)?;
-
-        let config = "\
-dir.system = /system/bin/
-dir.system = /system/xbin/
-dir.vendor = /vendor/bin/
-
-[system]
-namespace.default.search.paths = /system/lib64:/system/lib:/apex/com.android.art/lib64:/apex/com.android.runtime/lib64
-namespace.default.permitted.paths = /system:/apex:/vendor:/data
-
-[vendor]
-namespace.default.search.paths = /vendor/lib64:/vendor/lib:/system/lib64:/system/lib
-namespace.default.permitted.paths = /system:/apex:/vendor:/data
-";
+    /// Dropping the iterator kills logcat.
+    pub fn logcat_lines(&self, opts: LogcatOpts, follow: bool) -> Result<LogcatLines> {
+        let args = opts.to_args(!follow);
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
 
-        std::fs::write(linkerconfig_dir.join("ld.config.txt"), config)
-            .context("Failed to write linker config")?;
+        info!("Reading logcat from container: {:?}", args);
 
-        info!("Generated /linkerconfig/ld.config.txt");
-        Ok(())
+        let child = self
+            .run_in_container("logcat", &args, |cmd| {
+                std::os::unix::process::CommandExt::process_group(cmd, 0)
+                    .stdin(std::process::Stdio::null())
+                    .stdout(std::process::Stdio::piped())
+                    .spawn()
+            })
+            .context("Failed to spawn logcat")?;
+        LogcatLines::new(child)
     }
 
-    /// Mount images using fuse2fs (userspace, no root needed)
-    fn fuse_mount_images(&mut self) -> Result<()> {
-        // Clean up any stale mounts from previous runs
-        let _ = fusermount_unmount(&self.mounts.system_mount);
-        let _ = fusermount_unmount(&self.mounts.vendor_mount);
+    /// ABIs the booted image supports, most preferred first
+    ///
+    /// Reads `ro.product.cpu.abilist`; entries rad has no `Abi` for (such as
+    /// the legacy `armeabi`) are left out.
+    pub fn supported_abis(&self) -> Result<Vec<Abi>> {
+        let abilist = self.get_property("ro.product.cpu.abilist")?.unwrap_or_default();
+        let abis = parse_abilist(&abilist);
+        if abis.is_empty() {
+            anyhow::bail!(
+                "Android image reports no supported ABIs (ro.product.cpu.abilist = '{}')",
+                abilist
+            );
+        }
+        Ok(abis)
+    }
 
-        // Mount system.img via fuse2fs
-        info!(
-            "FUSE-mounting system.img at {}...",
-            self.mounts.system_mount.display()
-        );
-        fuse2fs_mount(&self.images.system_img, &self.mounts.system_mount, true)?;
-        self.system_mounted = true;
-        info!("system.img FUSE-mounted (read-only)");
+    /// Read a system property with `getprop`, trailing newline trimmed;
+    /// `None` if it is unset (getprop prints an empty line)
+    ///
+    /// getprop only reads shared memory, so one still running after a few
+    /// seconds means the container is wedged: it is killed and reported as
+    /// [`CoreError::TimedOut`].
+    pub fn get_property(&self, key: &str) -> Result<Option<String>> {
+        self.get_property_timeout(key, PROBE_TIMEOUT)
+    }
 
-        // Mount vendor.img via fuse2fs
-        info!(
-            "FUSE-mounting vendor.img at {}...",
-            self.mounts.vendor_mount.display()
-        );
-        match fuse2fs_mount(&self.images.vendor_img, &self.mounts.vendor_mount, true) {
-            Ok(()) => {
-                self.vendor_mounted = true;
-                info!("vendor.img FUSE-mounted (read-only)");
-            }
-            Err(e) => {
-                // Clean up system mount on failure
-                let _ = fusermount_unmount(&self.mounts.system_mount);
-                self.system_mounted = false;
-                return Err(e).context("Failed to FUSE-mount vendor.img");
-            }
+    /// [`get_property`](Self::get_property), giving up after `timeout`
+    fn get_property_timeout(&self, key: &str, timeout: Duration) -> Result<Option<String>> {
+        let output = self.exec_command_timeout("getprop", &[key], timeout)?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("getprop {} failed: {}", key, stderr.trim());
         }
+        Ok(getprop_value(&String::from_utf8_lossy(&output.stdout)))
+    }
 
+    /// Set a system property with `setprop`
+    pub fn set_property(&self, key: &str, value: &str) -> Result<()> {
+        let output = self.exec_command("setprop", &[key, value])?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("setprop {} {} failed: {}", key, value, stderr.trim());
+        }
         Ok(())
     }
 
-    /// Launch init inside namespaces using unshare (no sudo)
-    fn launch_init(&mut self) -> Result<()> {
-        info!("Launching Android init inside rootless namespaces...");
+    /// Install an APK into the running container
+    pub fn install_apk(&self, apk_path: &Path) -> Result<()> {
+        self.install_apk_with_abi(apk_path, None)
+    }
 
-        // Check for init binary in the FUSE-mounted system
-        let init_path = if self.mounts.system_mount.join("init").exists() {
-            "/init"
-        } else if self.mounts.system_mount.join("system/bin/init").exists() {
-            "/system/bin/init"
-        } else if self.mounts.system_mount.join("bin/init").exists() {
-            "/bin/init"
-        } else {
-            anyhow::bail!(
-                "No init binary found in system image. Checked:\n  \
-                 {}/init\n  {}/system/bin/init\n  {}/bin/init",
-                self.mounts.system_mount.display(),
-                self.mounts.system_mount.display(),
-                self.mounts.system_mount.display()
+    /// Install an APK, forcing the package manager to use `abi` (`pm install --abi`)
+    pub fn install_apk_with_abi(&self, apk_path: &Path, abi: Option<&Abi>) -> Result<()> {
+        if self.dry_run {
+            if !apk_path.is_file() {
+                anyhow::bail!("APK not found: {}", apk_path.display());
+            }
+            info!(
+                "[dry-run] Would copy {} to {} and run: pm {}",
+                apk_path.display(),
+                self.mounts
+                    .overlay_upper
+                    .join("data/local/tmp/install.apk")
+                    .display(),
+                pm_install_args(abi).join(" ")
             );
-        };
+            return Ok(());
+        }
 
-        info!("Using init: {}", init_path);
+        let _init_pid = self.init_pid.context("Container is not running")?;
 
-        // Use unprivileged unshare to create namespaces and run init
-        // unshare --user --map-root-user --pid --fork --mount-proc --uts --ipc --mount
-        //   -- sh -c "set up overlayfs + chroot + exec init"
-        //
-        // We use a shell wrapper inside the namespace to:
-        // 1. Mount overlayfs (system as lower, overlay upper/work)
-        // 2. Bind-mount vendor into rootfs/vendor
-        // 3. Create /data directories
-        // 4. Mount /proc, /dev, /tmp
-        // 5. chroot into rootfs
-        // 6. exec init
+        info!("Installing APK: {}", apk_path.display());
 
-        let rootfs = &self.mounts.rootfs;
-        let system_mount = &self.mounts.system_mount;
-        let vendor_mount = &self.mounts.vendor_mount;
-        let overlay_upper = &self.mounts.overlay_upper;
-        let overlay_work = &self.mounts.overlay_work;
+        // Needed afterwards to check the package actually landed
+        let package = apk::ApkInspector::new(apk_path)
+            .inspect()
+            .context("Failed to read package name from APK")?
+            .package_name;
 
-        // Build the shell script that runs inside the namespace
-        let setup_script = format!(
-            r#"
-set -e
+        // pm talks to the "package" service, which comes up late on slow boots
+        self.wait_for_service("package", PACKAGE_SERVICE_TIMEOUT)?;
 
-# Mount overlayfs: system as lower, prefix overlay as upper
-# APEX dirs are pre-created in the upper layer (see prepare_apex_dirs)
-mount -t overlay overlay \
-    -o lowerdir={system},upperdir={upper},workdir={work} \
-    {rootfs}
+        // Copy APK into the container's /data directory via the overlay upper layer
+        let container_apk_dir =
+            paths::resolve_within(&self.mounts.overlay_upper, "data/local/tmp")?;
+        std::fs::create_dir_all(&container_apk_dir)?;
+        let container_apk = container_apk_dir.join("install.apk");
+        std::fs::copy(apk_path, &container_apk)
+            .context("Failed to copy APK into container overlay")?;
 
-# Bind-mount vendor into rootfs
-mkdir -p {rootfs}/vendor
-mount --bind {vendor} {rootfs}/vendor
+        // Use pm install inside the container
+        let output = self.exec_command("pm", &pm_install_args(abi))?;
 
-# APEX bind mounts: Android binaries (sh, linker64, etc.) are symlinked
-# to /apex/<module>/... but the /apex/ directory in the image is empty.
-# The actual APEX module contents live at /system/apex/<module>/.
-# The target dirs in /apex/ were pre-created in the overlay upper layer.
-if [ -d "{rootfs}/system/apex" ]; then
-    for apex_dir in {rootfs}/system/apex/*/; do
-        apex_name=$(basename "$apex_dir")
-        if [ -d "$apex_dir" ] && [ -d "{rootfs}/apex/$apex_name" ]; then
-            mount --bind "$apex_dir" "{rootfs}/apex/$apex_name"
-        fi
-    done
-fi
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            warn!("pm install output: {}{}", stdout, stderr);
+            anyhow::bail!("pm install failed: {}{}", stdout, stderr);
+        }
 
-# Create essential directories in rootfs
-mkdir -p {rootfs}/data/app {rootfs}/data/data {rootfs}/data/local/tmp \
-         {rootfs}/data/system {rootfs}/data/misc {rootfs}/data/dalvik-cache \
-         {rootfs}/proc {rootfs}/sys {rootfs}/dev {rootfs}/tmp
+        let paths = self.package_paths(&package)?;
+        if paths.is_empty() {
+            anyhow::bail!(
+                "pm install reported success, but package {} is not installed. \
+                 The APK may have been rejected after parsing; check 'logs --logcat' for PackageManager errors.",
+                package
+            );
+        }
 
-# Mount proc/dev/tmp inside rootfs
-mount -t proc proc {rootfs}/proc || true
-mount -t tmpfs tmpfs {rootfs}/dev || true
-mount -t tmpfs tmpfs {rootfs}/tmp || true
+        info!("APK installed successfully ({})", paths.join(", "));
+        Ok(())
+    }
 
-# Create basic /dev nodes (mknod works as "root" in user ns)
-mknod -m 666 {rootfs}/dev/null c 1 3 2>/dev/null || true
-mknod -m 666 {rootfs}/dev/zero c 1 5 2>/dev/null || true
-mknod -m 666 {rootfs}/dev/random c 1 8 2>/dev/null || true
-mknod -m 666 {rootfs}/dev/urandom c 1 9 2>/dev/null || true
+    /// Install a base APK and its splits (`split_config.*.apk`) as one package
+    ///
+    /// Uses a `pm install-create`/`install-write`/`install-commit` session,
+    /// writing the base APK first; the session is abandoned if a write fails.
+    /// The staged copies are removed once the session is committed or
+    /// abandoned.
+    pub fn install_apk_set(&self, apks: &[&Path]) -> Result<()> {
+        self.install_apk_set_with_abi(apks, None)
+    }
 
-# Pivot root and exec init
-cd {rootfs}
-exec chroot {rootfs} {init} \
-    </dev/null >/dev/null 2>&1
-"#,
-            system = system_mount.display(),
-            vendor = vendor_mount.display(),
-            upper = overlay_upper.display(),
-            work = overlay_work.display(),
-            rootfs = rootfs.display(),
-            init = init_path,
-        );
+    /// [`install_apk_set`](Self::install_apk_set), forcing the package manager
+    /// to use `abi`
+    pub fn install_apk_set_with_abi(&self, apks: &[&Path], abi: Option<&Abi>) -> Result<()> {
+        let apks = order_apk_set(apks)?;
+        if let [single] = apks[..] {
+            return self.install_apk_with_abi(single, abi);
+        }
 
-        let child = Command::new("unshare")
-            .arg("--user")
-            .arg("--map-root-user")
-            .arg("--pid")
-            .arg("--fork")
-            .arg("--mount-proc")
-            .arg("--uts")
-            .arg("--ipc")
-            .arg("--mount")
-            .arg("--")
-            .arg("sh")
-            .arg("-c")
-            .arg(&setup_script)
-            .env("ANDROID_ROOT", "/system")
-            .env("ANDROID_DATA", "/data")
-            .env("PATH", "/system/bin:/system/xbin:/vendor/bin:/bin:/usr/bin")
-            .stdin(std::process::Stdio::null())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .spawn()
-            .context("Failed to spawn container via unshare (is unshare available?)")?;
+        if self.dry_run {
+            for apk in &apks {
+                if !apk.is_file() {
+                    anyhow::bail!("APK not found: {}", apk.display());
+                }
+            }
+            info!(
+                "[dry-run] Would copy {} APKs to {} and install them in one pm session",
+                apks.len(),
+                self.mounts
+                    .overlay_upper
+                    .join(INSTALL_SET_DIR.trim_start_matches('/'))
+                    .display()
+            );
+            return Ok(());
+        }
 
-        let pid = child.id();
-        self.init_pid = Some(pid);
-        self.overlay_mounted = true;
-        info!("Init process spawned (outer PID: {})", pid);
+        let _init_pid = self.init_pid.context("Container is not running")?;
+        let package = apk::ApkInspector::new(apks[0])
+            .inspect()
+            .context("Failed to read package name from the base APK")?
+            .package_name;
+        info!("Installing {} as {} APKs", package, apks.len());
 
-        // Give init a moment to start
-        std::thread::sleep(std::time::Duration::from_millis(500));
+        self.wait_for_service("package", PACKAGE_SERVICE_TIMEOUT)?;
 
-        // Check it's still alive
-        if !self.is_running() {
-            let output = child.wait_with_output()?;
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let stderr = String::from_utf8_lossy(&output.stderr);
+        // Copy the set into the container via the overlay upper layer
+        let set_dir = paths::resolve_within(
+            &self.mounts.overlay_upper,
+            INSTALL_SET_DIR.trim_start_matches('/'),
+        )?;
+        let _ = std::fs::remove_dir_all(&set_dir);
+        std::fs::create_dir_all(&set_dir)?;
+        let result = self.stage_and_install_set(&apks, &set_dir, abi);
+        if let Err(e) = std::fs::remove_dir_all(&set_dir) {
+            warn!("Failed to remove staged APKs {}: {}", set_dir.display(), e);
+        }
+        result?;
+
+        let paths = self.package_paths(&package)?;
+        if paths.is_empty() {
             anyhow::bail!(
-                "Init process died immediately.\nstdout: {}\nstderr: {}",
-                stdout,
-                stderr
+                "pm install-commit reported success, but package {} is not installed",
+                package
             );
         }
-
+        info!("APK set installed successfully ({})", paths.join(", "));
         Ok(())
     }
 
-    /// Unmount all FUSE mounts (no sudo needed)
-    fn fuse_unmount_all(&mut self) -> Result<()> {
-        let mut errors = Vec::new();
+    /// Copy `apks` into `set_dir` (the overlay upper side of
+    /// `INSTALL_SET_DIR`) and install them in one pm session, abandoning the
+    /// session if any write fails
+    fn stage_and_install_set(
+        &self,
+        apks: &[&Path],
+        set_dir: &Path,
+        abi: Option<&Abi>,
+    ) -> Result<()> {
+        let mut entries = Vec::new();
+        for (index, apk) in apks.iter().enumerate() {
+            let name = apk
+                .file_name()
+                .with_context(|| format!("Not an APK file: {}", apk.display()))?
+                .to_string_lossy()
+                .into_owned();
+            let file = format!("{}-{}", index, name);
+            std::fs::copy(apk, set_dir.join(&file))
+                .with_context(|| format!("Failed to copy {} into container overlay", name))?;
+            entries.push((name, format!("{}/{}", INSTALL_SET_DIR, file)));
+        }
 
-        // Note: overlayfs and bind mounts inside the namespace are cleaned up
-        // automatically when the namespace (init process) dies.
+        let output = self.exec_command("pm", &pm_install_create_args(abi))?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let session = parse_install_session(&stdout).with_context(|| {
+            format!(
+                "pm install-create failed: {}{}",
+                stdout,
+                String::from_utf8_lossy(&output.stderr)
+            )
+        })?;
+        let session = session.to_string();
 
-        // Unmount vendor.img FUSE
-        if self.vendor_mounted {
-            if let Err(e) = fusermount_unmount(&self.mounts.vendor_mount) {
-                errors.push(format!("vendor.img FUSE: {}", e));
-            } else {
-                self.vendor_mounted = false;
+        let written = entries.iter().try_for_each(|(name, path)| {
+            let output = self
+                .exec_command("pm", &["install-write", &session, name, path])
+                .with_context(|| format!("pm install-write {} failed", name))?;
+            if !output.status.success() {
+                anyhow::bail!(
+                    "pm install-write {} failed: {}{}",
+                    name,
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+            Ok(())
+        });
+        if let Err(e) = written {
+            if let Err(abandon) = self.exec_command("pm", &["install-abandon", &session]) {
+                warn!("pm install-abandon {} failed: {}", session, abandon);
             }
+            return Err(e.context(format!("Install session {} abandoned", session)));
         }
 
-        // Unmount system.img FUSE
-        if self.system_mounted {
-            if let Err(e) = fusermount_unmount(&self.mounts.system_mount) {
-                errors.push(format!("system.img FUSE: {}", e));
-            } else {
-                self.system_mounted = false;
-            }
+        let output = self.exec_command("pm", &["install-commit", &session])?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !output.status.success() || !stdout.contains("Success") {
+            anyhow::bail!(
+                "pm install-commit failed: {}{}",
+                stdout,
+                String::from_utf8_lossy(&output.stderr)
+            );
         }
+        Ok(())
+    }
 
-        self.overlay_mounted = false;
+    /// APK paths of an installed package (`pm path`), empty if it isn't installed
+    pub fn package_paths(&self, package: &str) -> Result<Vec<String>> {
+        // pm path exits non-zero for unknown packages, so only its output matters
+        let output = self.exec_command("pm", &["path", package])?;
+        Ok(parse_pm_path(&String::from_utf8_lossy(&output.stdout)))
+    }
 
-        if !errors.is_empty() {
-            warn!("Some unmount operations failed: {:?}", errors);
+    /// Launch an Android app by package name
+    pub fn launch_app(&self, package: &str, activity: &str) -> Result<()> {
+        info!("Launching {}/{}", package, activity);
+
+        let component = format!("{}/{}", package, activity);
+        if self.dry_run {
+            info!("[dry-run] Would run: am start -n {}", component);
+            return Ok(());
+        }
+
+        let output = self.exec_command("am", &["start", "-n", &component])?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            anyhow::bail!("am start failed: {}{}", stdout, stderr);
         }
 
+        info!("App launched");
         Ok(())
     }
-}
 
-impl Drop for Container {
-    fn drop(&mut self) {
-        if (self.init_pid.is_some() || self.system_mounted || self.vendor_mounted)
-            && let Err(e) = self.stop()
-        {
-            error!("Failed to stop container during drop: {}", e);
+    /// Start adbd listening on `tcp_port` so the host can `adb connect localhost:<port>`
+    ///
+    /// With host networking (the default) the container shares the host
+    /// network namespace, so the port is reachable from the host without any
+    /// forwarding. adbd is restarted if it was already running.
+    pub fn enable_adb(&self, tcp_port: u16) -> Result<()> {
+        let commands = adb_enable_commands(tcp_port);
+        if self.dry_run {
+            for (command, args) in &commands {
+                info!("[dry-run] Would run: {} {}", command, args.join(" "));
+            }
+            return Ok(());
         }
-    }
-}
 
-// --- Helper functions ---
+        let (check, check_args) = &commands[0];
+        let check_args: Vec<&str> = check_args.iter().map(String::as_str).collect();
+        if !self.exec_command(check, &check_args)?.status.success() {
+            anyhow::bail!(
+                "adbd not found in the container image (looked in {})",
+                ADBD_PATHS.join(", ")
+            );
+        }
+
+        for (command, args) in &commands[1..] {
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            let output = self.exec_command(command, &args)?;
+            if !output.status.success() {
+                anyhow::bail!(
+                    "{} {} failed: {}",
+                    command,
+                    args.join(" "),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+        }
+
+        info!("adbd listening; connect with: adb connect localhost:{}", tcp_port);
+        Ok(())
+    }
+
+    /// Stop an app via `am force-stop`, falling back to [`kill_package`](Self::kill_package)
+    ///
+    /// Does nothing (with a warning) if the app isn't running.
+    pub fn force_stop(&self, package: &str) -> Result<()> {
+        if self.package_pids(package)?.is_empty() {
+            warn!("{} is not running, nothing to stop", package);
+            return Ok(());
+        }
+
+        info!("Force-stopping {}", package);
+        let output = self.exec_command("am", &force_stop_args(package))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            warn!("am force-stop failed ({}), killing processes instead", stderr.trim());
+            return self.kill_package(package);
+        }
+
+        Ok(())
+    }
+
+    /// SIGKILL every process belonging to `package` inside the container
+    ///
+    /// Does nothing (with a warning) if the app isn't running.
+    pub fn kill_package(&self, package: &str) -> Result<()> {
+        let pids = self.package_pids(package)?;
+        if pids.is_empty() {
+            warn!("{} is not running, nothing to kill", package);
+            return Ok(());
+        }
+
+        // PIDs are namespace-local, so the kill has to run inside the container
+        for pid in pids {
+            info!("Killing {} (PID {} in container)", package, pid);
+            let pid = pid.to_string();
+            let output = self.exec_command("kill", &["-9", &pid])?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                warn!("kill -9 {} failed: {}", pid, stderr.trim());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check if the container init process is still running
+    pub fn is_running(&self) -> bool {
+        self.init_pid
+            .is_some_and(|pid| process_alive(Pid::from_raw(pid as i32)))
+    }
+
+    /// Block until the container's init exits on its own
+    ///
+    /// `init_pid` is the outer `unshare` process: with `--fork` it waits for
+    /// the real init (PID 1 inside the namespace) and exits with its status,
+    /// re-raising the signal if init was killed, so reaping it reports how
+    /// init itself ended. A container adopted from the PID file isn't our
+    /// child and can't be reaped; then `/proc` is polled until the process is
+    /// gone and the status is `Unknown`.
+    pub fn wait(&self) -> Result<ExitStatus> {
+        let pid = self.init_pid.context("Container is not running")?;
+        let pid = Pid::from_raw(pid as i32);
+
+        loop {
+            match waitpid(pid, None) {
+                Ok(nix::sys::wait::WaitStatus::Exited(_, code)) => {
+                    return Ok(ExitStatus::Exited(code));
+                }
+                Ok(nix::sys::wait::WaitStatus::Signaled(_, signal, _)) => {
+                    return Ok(ExitStatus::Signaled(signal));
+                }
+                // Stopped/continued: still alive
+                Ok(_) | Err(nix::errno::Errno::EINTR) => continue,
+                Err(nix::errno::Errno::ECHILD) => break,
+                Err(e) => return Err(e).context("Failed to wait for init"),
+            }
+        }
+
+        debug!("Init (PID {}) is not our child, polling for its exit", pid);
+        while process_alive(pid) {
+            std::thread::sleep(WAIT_POLL_INTERVAL);
+        }
+        Ok(ExitStatus::Unknown)
+    }
+
+    /// Wait for the Android system to boot (poll for sys.boot_completed)
+    pub fn wait_for_boot(&self, timeout_secs: u64) -> Result<()> {
+        self.wait_for_boot_until(timeout_secs, || false)
+    }
+
+    /// [`wait_for_boot`](Self::wait_for_boot), giving up early once
+    /// `interrupted` returns true (checked between polls), e.g. on Ctrl+C
+    pub fn wait_for_boot_until(
+        &self,
+        timeout_secs: u64,
+        interrupted: impl FnMut() -> bool,
+    ) -> Result<()> {
+        if self.dry_run {
+            info!("[dry-run] Would wait up to {}s for sys.boot_completed", timeout_secs);
+            return Ok(());
+        }
+
+        info!(
+            "Waiting for Android system to boot (timeout: {}s)...",
+            timeout_secs
+        );
+
+        if let Err(e) = self.wait_for_prop_until(
+            "sys.boot_completed",
+            "1",
+            Duration::from_secs(timeout_secs),
+            interrupted,
+        ) {
+            if !self.is_running()
+                && let Some(tail) = self.boot_log_tail(BOOT_LOG_TAIL_LINES)
+            {
+                return Err(e.context(format!(
+                    "Android system did not finish booting. Last lines of {}:\n{}",
+                    self.boot_log_path().display(),
+                    tail
+                )));
+            }
+            return Err(e.context("Android system did not finish booting"));
+        }
+        info!("Android system boot completed!");
+        Ok(())
+    }
+
+    /// [`wait_for_boot`](Self::wait_for_boot) on tokio's blocking pool
+    pub async fn wait_for_boot_async(&self, timeout_secs: u64) -> Result<()> {
+        // A handle on the same init; detached afterwards so it never stops it
+        let mut handle = Container::new(self.images.clone(), self.mounts.clone());
+        handle.init_pid = self.init_pid;
+        handle.dry_run = self.dry_run;
+
+        tokio::task::spawn_blocking(move || {
+            let result = handle.wait_for_boot(timeout_secs);
+            handle.detach();
+            result
+        })
+        .await
+        .context("Boot wait task panicked")?
+    }
+
+    /// Whether Android reports `sys.boot_completed=1` right now
+    ///
+    /// A single getprop: never sleeps or retries, and a failed query counts
+    /// as not booted. See `wait_for_boot` to block until it is.
+    pub fn is_booted(&self) -> bool {
+        boot_completed(self.get_property("sys.boot_completed"))
+    }
+
+    /// Wait until property `key` equals `expected`, e.g. `init.svc.zygote` ==
+    /// `running`
+    ///
+    /// Fails on timeout (reporting the last value seen) or if init dies.
+    /// Each `getprop` is killed after a few seconds at most, and never later
+    /// than the deadline, so a hung probe can't hold the wait past `timeout`.
+    pub fn wait_for_prop(&self, key: &str, expected: &str, timeout: Duration) -> Result<()> {
+        self.wait_for_prop_until(key, expected, timeout, || false)
+    }
+
+    /// [`wait_for_prop`](Self::wait_for_prop), also failing once
+    /// `interrupted` returns true
+    fn wait_for_prop_until(
+        &self,
+        key: &str,
+        expected: &str,
+        timeout: Duration,
+        interrupted: impl FnMut() -> bool,
+    ) -> Result<()> {
+        debug!("Waiting for {}={} (timeout: {:?})", key, expected, timeout);
+        let deadline = std::time::Instant::now() + timeout;
+        poll_prop(
+            key,
+            expected,
+            timeout,
+            PROP_POLL_INTERVAL,
+            || {
+                let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+                self.get_property_timeout(key, PROBE_TIMEOUT.min(remaining))
+            },
+            || self.is_running(),
+            interrupted,
+        )
+    }
+
+    /// Wait until a system service is published (polls `service check <name>`)
+    ///
+    /// Each probe is killed after a few seconds at most, and never later than
+    /// the deadline, so a wedged servicemanager can't hold the wait past
+    /// `timeout`.
+    pub fn wait_for_service(&self, name: &str, timeout: Duration) -> Result<()> {
+        info!("Waiting for service '{}' (timeout: {:?})...", name, timeout);
+
+        poll_service(
+            name,
+            timeout,
+            SERVICE_POLL_INTERVAL,
+            |remaining| {
+                let output = self.exec_command_timeout(
+                    "service",
+                    &["check", name],
+                    PROBE_TIMEOUT.min(remaining),
+                )?;
+                Ok(parse_service_check(&String::from_utf8_lossy(&output.stdout)))
+            },
+            || self.is_running(),
+        )?;
+        info!("Service '{}' is published", name);
+        Ok(())
+    }
+
+    // --- Private methods ---
+
+    /// Log what `start` would do, validating what can be checked without mounting
+    fn dry_run_start(&mut self) -> Result<()> {
+        if !self.limits.is_unlimited() {
+            info!(
+                "[dry-run] Would create cgroup {}/{}/{} with {:?}",
+                CGROUP_ROOT,
+                crate::cgroup::RAD_CGROUP,
+                self.cgroup_name()?,
+                self.limits
+            );
+        }
+        let mounts = &self.mounts;
+        info!(
+            "[dry-run] Images validated: {}, {}",
+            self.images.system_img.display(),
+            self.images.vendor_img.display()
+        );
+        info!(
+            "[dry-run] Would create mount dirs under {} and overlay dirs {}, {}",
+            mounts.rootfs.display(),
+            mounts.overlay_upper.display(),
+            mounts.overlay_work.display()
+        );
+        info!(
+            "[dry-run] Would FUSE-mount {} at {} (read-only)",
+            self.images.system_img.display(),
+            mounts.system_mount.display()
+        );
+        info!(
+            "[dry-run] Would FUSE-mount {} at {} (read-only)",
+            self.images.vendor_img.display(),
+            mounts.vendor_mount.display()
+        );
+
+        // The init binary can only be located once system.img is mounted;
+        // check now if a previous run left it mounted.
+        if let Some(init_path) = &self.init_path {
+            info!("[dry-run] Would exec custom init: {} {:?}", init_path, self.init_args);
+        } else if mounts.system_mount.join("system").is_dir() {
+            let init_path = find_init(&mounts.system_mount)?;
+            info!("[dry-run] Found init: {} {:?}", init_path, self.init_args);
+        } else {
+            info!("[dry-run] system.img is not mounted; init lookup deferred to a real start");
+            if !self.init_args.is_empty() {
+                info!("[dry-run] Would pass init arguments: {:?}", self.init_args);
+            }
+        }
+
+        if !self.boot_props.is_empty() {
+            info!(
+                "[dry-run] Would write boot properties to {}: {:?}",
+                mounts.overlay_upper.join(LOCAL_PROP_FILE).display(),
+                self.boot_props
+            );
+        }
+        info!(
+            "[dry-run] Would generate {} and pre-create APEX dirs",
+            mounts.overlay_upper.join("linkerconfig/ld.config.txt").display()
+        );
+        info!(
+            "[dry-run] Would exec init via: unshare --user --map-root-user --pid --fork --kill-child=SIGKILL --mount-proc --uts --ipc --mount{} -- sh -c <setup script>",
+            if self.network.isolated() { " --net" } else { "" }
+        );
+        if self.network == NetworkMode::Slirp {
+            info!(
+                "[dry-run] Would attach {} to init's network namespace (device {})",
+                crate::net::SLIRP4NETNS,
+                crate::net::TAP_DEVICE
+            );
+        }
+        Ok(())
+    }
+
+    /// Container-local PIDs of the processes belonging to `package`
+    fn package_pids(&self, package: &str) -> Result<Vec<u32>> {
+        let output = self.exec_command("ps", &["-A", "-o", "PID,NAME"])?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("ps failed: {}", stderr.trim());
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(stdout
+            .lines()
+            .filter_map(|line| parse_ps_line(line, package))
+            .collect())
+    }
+
+    /// Run `command` inside the container through `run` (e.g. `Command::output`),
+    /// natively via setns first and through `nsenter` if joining fails
+    fn run_in_container<T>(
+        &self,
+        command: &str,
+        args: &[&str],
+        run: impl Fn(&mut Command) -> std::io::Result<T>,
+    ) -> Result<T> {
+        let init_pid = self
+            .init_pid
+            .context("Container is not running (no init PID)")?;
+
+        let (value, path) = exec_with_fallback(
+            || run(&mut setns_command(init_pid, self.joins_netns(init_pid), command, args)?),
+            || {
+                let mut cmd = self.nsenter_command(command, args)?;
+                run(&mut cmd).context("Failed to execute nsenter")
+            },
+        )?;
+        debug!("Ran {} in the container via {:?}", command, path);
+        Ok(value)
+    }
+
+    /// Whether commands run in the container must join its network namespace
+    ///
+    /// Decided from the running container rather than `network`, which a
+    /// handle re-attached by PID (`shell`, `stop`) doesn't know.
+    fn joins_netns(&self, init_pid: u32) -> bool {
+        crate::net::has_own_netns(init_pid).unwrap_or(self.network.isolated())
+    }
+
+    /// Where the slirp4netns PID is recorded: next to the PID file
+    fn slirp_pid_file(&self) -> Option<PathBuf> {
+        self.pid_file
+            .as_ref()
+            .map(|pid_file| pid_file.with_file_name(SLIRP_PID_FILE))
+    }
+
+    /// Build an `nsenter` command that runs `command` inside the container
+    fn nsenter_command(&self, command: &str, args: &[&str]) -> Result<Command> {
+        let init_pid = self
+            .init_pid
+            .context("Container is not running (no init PID)")?;
+
+        let mut cmd = Command::new("nsenter");
+        cmd.arg("-t")
+            .arg(init_pid.to_string())
+            .arg("--user")
+            .arg("--mount")
+            .arg("--uts")
+            .arg("--ipc")
+            .arg("--pid");
+        if self.joins_netns(init_pid) {
+            cmd.arg("--net");
+        }
+        cmd.arg("--").arg(command).args(args);
+
+        Ok(cmd)
+    }
+
+    fn prepare_prefix_dirs(&self) -> Result<()> {
+        let data_dir = self.mounts.overlay_upper.join("data");
+        std::fs::create_dir_all(&data_dir)?;
+        std::fs::create_dir_all(data_dir.join("app"))?;
+        std::fs::create_dir_all(data_dir.join("data"))?;
+        std::fs::create_dir_all(data_dir.join("local/tmp"))?;
+        Ok(())
+    }
+
+    /// Write the boot properties to `/data/local.prop` in the overlay, or
+    /// remove a previously generated file if there are none
+    fn write_boot_props(&self) -> Result<()> {
+        let path = self.mounts.overlay_upper.join(LOCAL_PROP_FILE);
+
+        if self.boot_props.is_empty() {
+            let ours = std::fs::read_to_string(&path)
+                .map(|content| content.starts_with(LOCAL_PROP_HEADER))
+                .unwrap_or(false);
+            if ours {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Failed to remove {}", path.display()))?;
+            }
+            return Ok(());
+        }
+
+        // init ignores a local.prop that is group/world writable
+        std::fs::write(&path, local_prop_content(&self.boot_props))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        std::fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(0o644))?;
+        info!("Wrote {} boot properties to {}", self.boot_props.len(), path.display());
+        Ok(())
+    }
+
+    /// Refuse to start if the overlay upper dir is on a filesystem overlayfs
+    /// rejects, instead of failing later with a bare mount error
+    fn check_upper_fs(&self) -> Result<()> {
+        let upper = &self.mounts.overlay_upper;
+        let stat = nix::sys::statfs::statfs(upper)
+            .with_context(|| format!("Failed to statfs {}", upper.display()))?;
+
+        if let Some(reason) = unsupported_upper_fs(stat.filesystem_type().0 as i64) {
+            anyhow::bail!(
+                "The overlay upper dir {} is on {}, which overlayfs can't use as an upperdir.\n\
+                 Move the prefix to a local ext4, xfs or btrfs filesystem (e.g. under $HOME).",
+                upper.display(),
+                reason
+            );
+        }
+        Ok(())
+    }
+
+    /// Pick the APEX modules to bind and pre-create their mount points in the
+    /// overlay upper layer.
+    ///
+    /// Android binaries (sh, linker64, app_process64, etc.) are symlinked to
+    /// /apex/<module>/... but the /apex/ directory in the system image is empty.
+    /// The actual APEX module contents live at /system/apex/<module>/.
+    ///
+    /// Inside the user namespace, the fuse2fs-mounted /apex/ dir is owned by
+    /// nobody (unmapped root), making mkdir fail. We pre-create the dirs in the
+    /// overlay upper layer (which we own) so they appear writable in the merged view.
+    fn prepare_apex_dirs(&mut self) -> Result<()> {
+        self.apex_modules.clear();
+
+        let system_apex_dir = self.mounts.system_mount.join("system/apex");
+        if !system_apex_dir.is_dir() {
+            info!("No /system/apex directory found in system image, skipping APEX prep");
+            return Ok(());
+        }
+
+        let scan = apex::scan(&system_apex_dir)?;
+        for archive in &scan.archives {
+            warn!(
+                "Skipping packaged APEX {} (only flattened modules can be mounted)",
+                archive.display()
+            );
+        }
+        for (path, reason) in &scan.skipped {
+            debug!("Skipping {} in /system/apex: {}", path.display(), reason);
+        }
+
+        let upper_apex_dir = self.mounts.overlay_upper.join("apex");
+        std::fs::create_dir_all(&upper_apex_dir)?;
+        for module in &scan.modules {
+            std::fs::create_dir_all(upper_apex_dir.join(&module.name))?;
+            info!(
+                "APEX /system/apex/{} -> /apex/{}",
+                module.dir_name, module.name
+            );
+        }
+
+        info!(
+            "Pre-created {} APEX directories in overlay upper layer",
+            scan.modules.len()
+        );
+        self.apex_modules = scan.modules;
+        Ok(())
+    }
+
+    /// Bind mounts for the modules chosen by `prepare_apex_dirs`
+    fn apex_mount_script(&self) -> String {
+        let rootfs = &self.mounts.rootfs;
+        self.apex_modules
+            .iter()
+            .map(|module| {
+                let source = rootfs.join("system/apex").join(&module.dir_name);
+                let target = rootfs.join("apex").join(&module.name);
+                format!(
+                    "mount --bind {} {}\n",
+                    sh_quote(&source.to_string_lossy()),
+                    sh_quote(&target.to_string_lossy())
+                )
+            })
+            .collect()
+    }
+
+    /// Generate a minimal linker configuration to suppress Android linker warnings.
+    ///
+    /// The Android dynamic linker (linker64) looks for /linkerconfig/ld.config.txt
+    /// on boot. Without it, every binary invocation prints a warning.
+    fn generate_linkerconfig(&self) -> Result<()> {
+        let linkerconfig_dir = self.mounts.overlay_upper.join("linkerconfig");
+        std::fs::create_dir_all(&linkerconfig_dir)?;
+
+        let config = "\
+dir.system = /system/bin/
+dir.system = /system/xbin/
+dir.vendor = /vendor/bin/
+
+[system]
+namespace.default.search.paths = /system/lib64:/system/lib:/apex/com.android.art/lib64:/apex/com.android.runtime/lib64
+namespace.default.permitted.paths = /system:/apex:/vendor:/data
+
+[vendor]
+namespace.default.search.paths = /vendor/lib64:/vendor/lib:/system/lib64:/system/lib
+namespace.default.permitted.paths = /system:/apex:/vendor:/data
+";
+
+        std::fs::write(linkerconfig_dir.join("ld.config.txt"), config)
+            .context("Failed to write linker config")?;
+
+        info!("Generated /linkerconfig/ld.config.txt");
+        Ok(())
+    }
+
+    /// Mount images using fuse2fs (userspace, no root needed)
+    fn fuse_mount_images(&mut self, progress: &mut dyn FnMut(StartPhase)) -> Result<()> {
+        // Clean up any stale mounts from previous runs
+        let _ = fusermount_unmount(&self.mounts.system_mount);
+        let _ = fusermount_unmount(&self.mounts.vendor_mount);
+
+        // Mount system.img via fuse2fs
+        progress(StartPhase::MountingSystem);
+        info!(
+            "FUSE-mounting system.img at {}...",
+            self.mounts.system_mount.display()
+        );
+        fuse2fs_mount(&self.images.system_img, &self.mounts.system_mount, true)?;
+        self.system_mounted = true;
+        if let Err(e) = ensure_populated(&self.images.system_img, &self.mounts.system_mount) {
+            let _ = fusermount_unmount(&self.mounts.system_mount);
+            self.system_mounted = false;
+            return Err(e);
+        }
+        info!("system.img FUSE-mounted (read-only)");
+
+        // Mount vendor.img via fuse2fs
+        progress(StartPhase::MountingVendor);
+        info!(
+            "FUSE-mounting vendor.img at {}...",
+            self.mounts.vendor_mount.display()
+        );
+        let vendor = fuse2fs_mount(&self.images.vendor_img, &self.mounts.vendor_mount, true)
+            .and_then(|()| {
+                let populated = ensure_populated(&self.images.vendor_img, &self.mounts.vendor_mount);
+                if populated.is_err() {
+                    let _ = fusermount_unmount(&self.mounts.vendor_mount);
+                }
+                populated
+            });
+        match vendor {
+            Ok(()) => {
+                self.vendor_mounted = true;
+                info!("vendor.img FUSE-mounted (read-only)");
+            }
+            Err(e) => {
+                // Clean up system mount on failure
+                let _ = fusermount_unmount(&self.mounts.system_mount);
+                self.system_mounted = false;
+                return Err(e).context("Failed to FUSE-mount vendor.img");
+            }
+        }
+
+        if let Err(e) = self.mount_shared_storage() {
+            let _ = self.fuse_unmount_all();
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Create the shared storage directory and, for a capped storage, mount
+    /// its image over it
+    fn mount_shared_storage(&mut self) -> Result<()> {
+        let Some(storage) = &self.shared_storage else {
+            return Ok(());
+        };
+        // Only a directory rad creates gets its mode changed; the user's own
+        // (e.g. ~/Android) is left as is
+        let created = !storage.host_dir.exists();
+        std::fs::create_dir_all(&storage.host_dir).with_context(|| {
+            format!("Failed to create shared storage {}", storage.host_dir.display())
+        })?;
+        if created {
+            make_media_readable(&storage.host_dir)?;
+        } else {
+            warn_unless_media_readable(&storage.host_dir);
+        }
+
+        let (Some(image), Some(size_cap)) = (storage.image_path(), storage.size_cap) else {
+            return Ok(());
+        };
+        if !image.exists() {
+            info!(
+                "Creating {} MiB shared storage image {}...",
+                size_cap / (1024 * 1024),
+                image.display()
+            );
+            create_storage_image(&image, size_cap, &storage.host_dir)?;
+        }
+
+        let _ = fusermount_unmount(&storage.host_dir);
+        fuse2fs_mount(&image, &storage.host_dir, false)
+            .context("Failed to FUSE-mount the shared storage image")?;
+        self.storage_mounted = true;
+        // The image root, which rad created, replaces the directory
+        make_media_readable(&storage.host_dir)?;
+        info!(
+            "Shared storage image FUSE-mounted at {}",
+            storage.host_dir.display()
+        );
+        Ok(())
+    }
+
+    /// Launch init inside namespaces using unshare (no sudo)
+    fn launch_init(&mut self) -> Result<()> {
+        info!("Launching Android init inside rootless namespaces...");
+
+        // A foreign-arch image only runs through binfmt_misc; say so up front
+        // instead of letting exec fail with ENOEXEC
+        if let Some(machine) = image_machine(&self.mounts.system_mount) {
+            crate::binfmt::ensure_runnable(machine)?;
+        }
+
+        // Use unprivileged unshare to create namespaces and run init
+        // unshare --user --map-root-user --pid --fork --kill-child=SIGKILL --mount-proc
+        //   --uts --ipc --mount
+        //   -- sh -c "set up overlayfs + chroot + exec init"
+        //
+        // We use a shell wrapper inside the namespace to:
+        // 1. Mount overlayfs (system as lower, overlay upper/work)
+        // 2. Bind-mount vendor into rootfs/vendor
+        // 3. Create /data directories
+        // 4. Mount /proc, /dev, /tmp
+        // 5. chroot into rootfs
+        // 6. exec init
+
+        let setup_script = self.build_init_script()?;
+
+        let mut cmd = self.unshare_command(&setup_script);
+        if let Some(cgroup) = &self.cgroup {
+            // Join before unshare forks, so init and all its children are in
+            let procs = cgroup.procs_file()?;
+            // SAFETY: write is async-signal-safe
+            unsafe {
+                std::os::unix::process::CommandExt::pre_exec(&mut cmd, move || {
+                    std::io::Write::write_all(&mut &procs, b"0")
+                });
+            }
+        }
+        let boot_log = self.boot_log_path();
+        if let Some(dir) = boot_log.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let log = std::fs::File::create(&boot_log)
+            .with_context(|| format!("Failed to create {}", boot_log.display()))?;
+        let mut child = cmd
+            .stdin(std::process::Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .spawn()
+            .context("Failed to spawn container via unshare (is unshare available?)")?;
+
+        let pid = child.id();
+        info!("Init process spawned (outer PID: {}), logging to {}", pid, boot_log.display());
+
+        // Wait (bounded) for the setup script to reach exec of init
+        wait_for_ready(&mut child, &boot_log, self.start_timeout)?;
+
+        self.init_pid = Some(pid);
+        self.overlay_mounted = true;
+
+        // The outer unshare process is in the new network namespace by now
+        if self.network == NetworkMode::Slirp {
+            let slirp = Slirp::spawn(pid)?;
+            if let Some(path) = self.slirp_pid_file()
+                && let Err(e) = std::fs::write(&path, slirp.pid().to_string())
+            {
+                warn!("Failed to write {}: {}", path.display(), e);
+            }
+            self.slirp = Some(slirp);
+        }
+
+        // Give init a moment to start
+        std::thread::sleep(std::time::Duration::from_millis(500));
+
+        // Check it's still alive
+        if !self.is_running() {
+            let _ = child.wait();
+            let log = std::fs::read_to_string(&boot_log).unwrap_or_default();
+            return Err(startup_failure(
+                format!(
+                    "Init process died immediately. Last lines of {}:\n{}",
+                    boot_log.display(),
+                    last_lines(&log, BOOT_LOG_TAIL_LINES)
+                ),
+                &log,
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The `unshare` invocation that creates the namespaces and runs `setup_script`
+    fn unshare_command(&self, setup_script: &str) -> Command {
+        let mut cmd = Command::new("unshare");
+        cmd.arg("--user")
+            .arg("--map-root-user")
+            .arg("--pid")
+            .arg("--fork")
+            // If unshare itself dies, take the namespace down with it
+            .arg("--kill-child=SIGKILL")
+            .arg("--mount-proc")
+            .arg("--uts")
+            .arg("--ipc")
+            .arg("--mount");
+        if self.network.isolated() {
+            cmd.arg("--net");
+        }
+        cmd.arg("--")
+            .arg("sh")
+            .arg("-c")
+            .arg(setup_script)
+            .envs(self.init_env());
+
+        if !self.inherited_fds.is_empty() {
+            let fds = self.inherited_fds.clone();
+            // SAFETY: dup2 and fcntl are async-signal-safe
+            unsafe {
+                std::os::unix::process::CommandExt::pre_exec(&mut cmd, move || {
+                    inherit_fds(&fds)
+                });
+            }
+        }
+        cmd
+    }
+
+    /// Where the output of the setup script and init goes
+    /// (`<prefix>/logs/container.log`, or under the instance dir), kept after
+    /// the container stops
+    pub fn boot_log_path(&self) -> PathBuf {
+        let dir = match &self.instance {
+            Some(id) => self.prefix_root().join(INSTANCES_DIR).join(id),
+            None => self.prefix_root().to_path_buf(),
+        };
+        dir.join("logs").join(BOOT_LOG_FILE)
+    }
+
+    /// The prefix directory, which holds the rootfs mount point
+    fn prefix_root(&self) -> &Path {
+        self.mounts.rootfs.parent().unwrap_or(&self.mounts.rootfs)
+    }
+
+    /// The last `n` lines of the boot log, if there is one
+    fn boot_log_tail(&self, n: usize) -> Option<String> {
+        let log = std::fs::read_to_string(self.boot_log_path()).ok()?;
+        Some(last_lines(&log, n))
+    }
+
+    /// The cgroup is named after the prefix directory (the package name),
+    /// plus `@<id>` for an instance
+    fn cgroup_name(&self) -> Result<String> {
+        let name = self
+            .mounts
+            .rootfs
+            .parent()
+            .and_then(Path::file_name)
+            .map(|name| name.to_string_lossy().into_owned())
+            .context("Cannot name the cgroup: rootfs has no prefix directory")?;
+        Ok(match &self.instance {
+            Some(id) => format!("{}@{}", name, id),
+            None => name,
+        })
+    }
+
+    /// Environment for the container: the zygote Android environment plus
+    /// [`DEFAULT_INIT_ENV`], overridden by `with_env` vars
+    fn init_env(&self) -> Vec<(String, String)> {
+        let mut env = zygote::build_android_env(&self.mounts.rootfs);
+
+        let essentials = DEFAULT_INIT_ENV
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()));
+        for (key, value) in essentials.chain(self.env.iter().cloned()) {
+            match env.iter_mut().find(|(k, _)| *k == key) {
+                Some(entry) => entry.1 = value,
+                None => env.push((key, value)),
+            }
+        }
+        env
+    }
+
+    /// Command line exec'd as PID 1, quoted for the setup script: the custom
+    /// entrypoint, or the init binary found in the FUSE-mounted system, then
+    /// the init arguments
+    fn init_target(&self) -> Result<String> {
+        let init = match &self.init_path {
+            Some(path) => path.as_str(),
+            None => find_init(&self.mounts.system_mount)?,
+        };
+        Ok(std::iter::once(init)
+            .chain(self.init_args.iter().map(String::as_str))
+            .map(sh_quote)
+            .collect::<Vec<_>>()
+            .join(" "))
+    }
+
+    /// Build the shell script that runs inside the namespace and execs init
+    ///
+    /// Only reads the configuration (and looks up init in the system mount
+    /// unless a custom init command is set), so it can be checked without
+    /// starting anything. Fails if a bind mount is invalid or init is missing.
+    pub fn build_init_script(&self) -> Result<String> {
+        let init = self.init_target()?;
+        info!("Using init: {}", init);
+
+        // Every host path is single-quoted: prefixes may contain spaces or
+        // shell metacharacters
+        let at = |rel: &str| sh_quote(&self.mounts.rootfs.join(rel).to_string_lossy());
+        let rootfs = sh_quote(&self.mounts.rootfs.to_string_lossy());
+        let binds = self.bind_mount_script()? + &self.shared_storage_script();
+        let apex = self.apex_mount_script();
+        let vendor_mount = sh_quote(&self.mounts.vendor_mount.to_string_lossy());
+        let overlay_options = self.overlay_opts.supported().mount_options(
+            &self.mounts.system_mount,
+            &self.mounts.overlay_upper,
+            &self.mounts.overlay_work,
+        );
+
+        Ok(format!(
+            r#"
+set -e
+
+# Mount overlayfs: system as lower, prefix overlay as upper
+# APEX dirs are pre-created in the upper layer (see prepare_apex_dirs)
+mount -t overlay overlay \
+    -o {overlay_options} \
+    {rootfs}
+
+# Bind-mount vendor into rootfs
+mkdir -p {rootfs_vendor}
+mount --bind {vendor} {rootfs_vendor}
+
+# APEX bind mounts: Android binaries (sh, linker64, etc.) are symlinked
+# to /apex/<module>/... but the /apex/ directory in the image is empty.
+# The flattened modules in /system/apex/ were selected by prepare_apex_dirs,
+# which also pre-created their targets in the overlay upper layer.
+{apex}
+# Create essential directories in rootfs
+mkdir -p {data_app} {data_data} {data_local_tmp} \
+         {data_system} {data_misc} {data_dalvik_cache} \
+         {proc} {sys} {dev} {tmp}
+
+# Mount proc/dev/tmp inside rootfs
+mount -t proc proc {proc} || true
+mount -t tmpfs tmpfs {dev} || true
+mount -t tmpfs tmpfs {tmp} || true
+
+# Create basic /dev nodes (mknod works as "root" in user ns)
+mknod -m 666 {dev_null} c 1 3 2>/dev/null || true
+mknod -m 666 {dev_zero} c 1 5 2>/dev/null || true
+mknod -m 666 {dev_random} c 1 8 2>/dev/null || true
+mknod -m 666 {dev_urandom} c 1 9 2>/dev/null || true
+{binds}
+# Tell the host setup finished; init's own output goes to the boot log too
+echo {ready}
+
+# Pivot root and exec init
+cd {rootfs}
+exec chroot {rootfs} {init} \
+    </dev/null
+"#,
+            vendor = vendor_mount,
+            overlay_options = sh_quote(&overlay_options),
+            rootfs = rootfs,
+            rootfs_vendor = at("vendor"),
+            data_app = at("data/app"),
+            data_data = at("data/data"),
+            data_local_tmp = at("data/local/tmp"),
+            data_system = at("data/system"),
+            data_misc = at("data/misc"),
+            data_dalvik_cache = at("data/dalvik-cache"),
+            proc = at("proc"),
+            sys = at("sys"),
+            dev = at("dev"),
+            tmp = at("tmp"),
+            dev_null = at("dev/null"),
+            dev_zero = at("dev/zero"),
+            dev_random = at("dev/random"),
+            dev_urandom = at("dev/urandom"),
+            init = init,
+            ready = READY_MARKER,
+            binds = binds,
+            apex = apex,
+        ))
+    }
+
+    /// Shell commands for the `with_bind` mounts, run after the overlay is up
+    ///
+    /// Fails if a host path is missing or a destination would leave the rootfs.
+    fn bind_mount_script(&self) -> Result<String> {
+        let mut script = String::new();
+        for bind in &self.binds {
+            if !bind.host.exists() {
+                anyhow::bail!("Bind mount source not found: {}", bind.host.display());
+            }
+            let rel = paths::normalize_relative(&bind.container_dest).with_context(|| {
+                format!("Bind mount destination escapes the rootfs: {}", bind.container_dest)
+            })?;
+            if rel.as_os_str().is_empty() {
+                anyhow::bail!("Bind mount destination cannot be the rootfs itself");
+            }
+
+            let host = sh_quote(&bind.host.to_string_lossy());
+            let dest = sh_quote(&self.mounts.rootfs.join(&rel).to_string_lossy());
+            script.push_str(&format!("\n# Bind {} into the container\n", bind.container_dest));
+            if bind.host.is_dir() {
+                script.push_str(&format!("mkdir -p {}\n", dest));
+            } else {
+                script.push_str(&format!("mkdir -p \"$(dirname {dest})\" && touch {dest}\n"));
+            }
+            script.push_str(&format!("mount --bind {} {}\n", host, dest));
+            if bind.read_only {
+                script.push_str(&format!("mount -o remount,ro,bind {}\n", dest));
+            }
+        }
+        Ok(script)
+    }
+
+    /// Shell commands binding the shared storage at `SHARED_STORAGE_MOUNT`
+    /// and linking each of `SHARED_STORAGE_LINKS` to it
+    ///
+    /// In the image `/sdcard` is usually a symlink into `/storage`; it is
+    /// replaced by a directory first, as an absolute link would resolve
+    /// against the host before the chroot. A link path that is a non-empty
+    /// directory can't be replaced, so it gets a bind mount instead.
+    fn shared_storage_script(&self) -> String {
+        let Some(storage) = &self.shared_storage else {
+            return String::new();
+        };
+
+        let host = sh_quote(&storage.host_dir.to_string_lossy());
+        let at = |target: &str| self.mounts.rootfs.join(&target[1..]);
+        let dest = sh_quote(&at(SHARED_STORAGE_MOUNT).to_string_lossy());
+        let mut script = format!(
+            "\n# Shared storage\n\
+             if [ -L {dest} ]; then rm {dest}; fi\nmkdir -p {dest}\nmount --bind {host} {dest}\n"
+        );
+        for link in SHARED_STORAGE_LINKS {
+            let path = at(link);
+            let parent = sh_quote(&path.parent().unwrap_or(&path).to_string_lossy());
+            let path = sh_quote(&path.to_string_lossy());
+            let target = sh_quote(SHARED_STORAGE_MOUNT);
+            script.push_str(&format!(
+                "if [ -L {path} ]; then rm {path}; elif [ -d {path} ]; then rmdir {path} 2>/dev/null || true; fi\n\
+                 if [ -e {path} ]; then mount --bind {host} {path}; else mkdir -p {parent}; ln -s {target} {path}; fi\n"
+            ));
+        }
+        script
+    }
+
+    /// Unmount FUSE mounts left behind by a container that died without
+    /// cleaning up (we no longer track them, but fuse2fs would refuse to mount
+    /// over them)
+    pub fn clean_stale_mounts(&self) -> Result<()> {
+        let mounted = match sandbox::mountinfo::read_self() {
+            Ok(mounts) => mounts,
+            Err(e) => {
+                warn!("Cannot read mountinfo, skipping stale mount cleanup: {}", e);
+                return Ok(());
+            }
+        };
+
+        for mount_point in [&self.mounts.system_mount, &self.mounts.vendor_mount] {
+            if mounted.iter().any(|m| &m.mount_point == mount_point) {
+                info!("Unmounting stale mount {}", mount_point.display());
+                fusermount_unmount(mount_point)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unmount all FUSE mounts (no sudo needed)
+    fn fuse_unmount_all(&mut self) -> Result<()> {
+        let mut errors = Vec::new();
+
+        // Note: overlayfs and bind mounts inside the namespace are cleaned up
+        // automatically when the namespace (init process) dies.
+
+        // Unmount the shared storage image
+        if self.storage_mounted
+            && let Some(storage) = &self.shared_storage
+        {
+            if let Err(e) = fusermount_unmount(&storage.host_dir) {
+                errors.push(format!("shared storage FUSE: {}", e));
+            } else {
+                self.storage_mounted = false;
+            }
+        }
+
+        // Unmount vendor.img FUSE
+        if self.vendor_mounted {
+            if let Err(e) = fusermount_unmount(&self.mounts.vendor_mount) {
+                errors.push(format!("vendor.img FUSE: {}", e));
+            } else {
+                self.vendor_mounted = false;
+            }
+        }
+
+        // Unmount system.img FUSE
+        if self.system_mounted {
+            if let Err(e) = fusermount_unmount(&self.mounts.system_mount) {
+                errors.push(format!("system.img FUSE: {}", e));
+            } else {
+                self.system_mounted = false;
+            }
+        }
+
+        self.overlay_mounted = false;
+
+        if !errors.is_empty() {
+            warn!("Some unmount operations failed: {:?}", errors);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Container {
+    fn drop(&mut self) {
+        if (self.init_pid.is_some()
+            || self.system_mounted
+            || self.vendor_mounted
+            || self.storage_mounted)
+            && let Err(e) = self.stop()
+        {
+            error!("Failed to stop container during drop: {}", e);
+        }
+    }
+}
+
+// --- Helper functions ---
+
+/// Add the [`SHARED_STORAGE_MODE`] bits to `dir`, keeping any others
+fn make_media_readable(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = std::fs::metadata(dir)
+        .with_context(|| format!("Failed to stat {}", dir.display()))?
+        .permissions()
+        .mode();
+    if mode & SHARED_STORAGE_MODE != SHARED_STORAGE_MODE {
+        std::fs::set_permissions(
+            dir,
+            std::fs::Permissions::from_mode((mode | SHARED_STORAGE_MODE) & 0o7777),
+        )
+        .with_context(|| format!("Failed to make {} readable", dir.display()))?;
+    }
+    Ok(())
+}
+
+/// Warn if `dir` lacks the [`SHARED_STORAGE_MODE`] bits, without changing it
+fn warn_unless_media_readable(dir: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+
+    let Ok(metadata) = std::fs::metadata(dir) else {
+        return;
+    };
+    let mode = metadata.permissions().mode() & 0o7777;
+    if mode & SHARED_STORAGE_MODE != SHARED_STORAGE_MODE {
+        warn!(
+            "Shared storage {} has mode {:o}, so Android's media user can't read it; \
+             run 'chmod {:o} {}' to fix this",
+            dir.display(),
+            mode,
+            mode | SHARED_STORAGE_MODE,
+            dir.display()
+        );
+    }
+}
+
+/// Run `cmd` in its own process group like `Command::output`, killing the
+/// whole group once `timeout` has passed; `None` if it was killed
+///
+/// The group takes along the processes forked by the setns hook or nsenter.
+fn output_with_timeout(
+    cmd: &mut Command,
+    timeout: Duration,
+) -> std::io::Result<Option<std::process::Output>> {
+    use std::io::Read;
+    use std::os::unix::process::CommandExt;
+    use std::process::Stdio;
+
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+
+    // Drain both pipes while waiting, so a chatty command can't block on a
+    // full pipe
+    let drain = |pipe: Option<Box<dyn Read + Send>>| {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            buf
+        })
+    };
+    let stdout = drain(child.stdout.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+    let stderr = drain(child.stderr.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+
+    let deadline = std::time::Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if std::time::Instant::now() >= deadline {
+            let _ = nix::sys::signal::killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
+            let _ = child.wait();
+            // The drain threads finish once the killed group closes the pipes
+            return Ok(None);
+        }
+        std::thread::sleep(EXEC_POLL_INTERVAL);
+    };
+
+    Ok(Some(std::process::Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    }))
+}
+
+/// Property value from `getprop <key>` output; getprop prints an empty line
+/// for unset properties
+fn getprop_value(stdout: &str) -> Option<String> {
+    let value = stdout.trim_end_matches(['\n', '\r']);
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Make each `(fd, target)` available as `target` across exec: dup'd there
+/// (dup2 leaves the copy without FD_CLOEXEC), or FD_CLOEXEC cleared if it
+/// already has that number
+///
+/// Runs between fork and exec.
+fn inherit_fds(fds: &[(RawFd, RawFd)]) -> std::io::Result<()> {
+    use nix::fcntl::{FcntlArg, FdFlag, fcntl};
+
+    for &(fd, target) in fds {
+        if fd == target {
+            let flags = FdFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFD)?);
+            fcntl(fd, FcntlArg::F_SETFD(flags - FdFlag::FD_CLOEXEC))?;
+        } else {
+            nix::unistd::dup2(fd, target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Map a `getprop sys.boot_completed` result to booted or not
+fn boot_completed(value: Result<Option<String>>) -> bool {
+    matches!(value, Ok(Some(v)) if v == "1")
+}
+
+/// How `Container::exec_command` entered the container
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecPath {
+    /// setns(2) from a pre_exec hook
+    Setns,
+    /// The external `nsenter` binary
+    Nsenter,
+}
+
+/// Namespaces joined by exec, in order: the user namespace first, as it
+/// grants the capabilities needed to join the others
+const EXEC_NAMESPACES: &[(&str, nix::sched::CloneFlags)] = &[
+    ("user", nix::sched::CloneFlags::CLONE_NEWUSER),
+    ("net", nix::sched::CloneFlags::CLONE_NEWNET),
+    ("mnt", nix::sched::CloneFlags::CLONE_NEWNS),
+    ("uts", nix::sched::CloneFlags::CLONE_NEWUTS),
+    ("ipc", nix::sched::CloneFlags::CLONE_NEWIPC),
+    ("pid", nix::sched::CloneFlags::CLONE_NEWPID),
+];
+
+/// Try `native`, falling back to `nsenter` when it fails in a way that
+/// points at setns (permissions, an unsupported namespace)
+///
+/// A missing command (ENOENT) is reported as is: nsenter couldn't run it either.
+fn exec_with_fallback<T>(
+    native: impl FnOnce() -> std::io::Result<T>,
+    nsenter: impl FnOnce() -> Result<T>,
+) -> Result<(T, ExecPath)> {
+    match native() {
+        Ok(value) => Ok((value, ExecPath::Setns)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Err(e).context("Failed to execute command in the container")
+        }
+        Err(e) => {
+            warn!("Joining the container namespaces failed ({}), falling back to nsenter", e);
+            nsenter().map(|value| (value, ExecPath::Nsenter))
+        }
+    }
+}
+
+/// `command` set up to join the namespaces of `pid` before it execs
+///
+/// Like nsenter, the hook forks once more after joining, since a new PID
+/// namespace only applies to children; the intermediate process waits and
+/// exits with the command's status.
+///
+/// The network namespace is only joined when the container has its own
+/// (`net`): the host one belongs to the initial user namespace, which the
+/// container's user namespace can't join back into.
+fn setns_command(pid: u32, net: bool, command: &str, args: &[&str]) -> std::io::Result<Command> {
+    let mut namespaces = Vec::new();
+    for (name, flag) in EXEC_NAMESPACES {
+        if *flag == nix::sched::CloneFlags::CLONE_NEWNET && !net {
+            continue;
+        }
+        let file = std::fs::File::open(format!("/proc/{}/ns/{}", pid, name))?;
+        namespaces.push((std::os::fd::OwnedFd::from(file), *flag));
+    }
+
+    let mut cmd = Command::new(command);
+    cmd.args(args);
+    // SAFETY: the hook only makes async-signal-safe calls (setns, fork,
+    // waitpid, kill, _exit)
+    unsafe {
+        std::os::unix::process::CommandExt::pre_exec(&mut cmd, move || {
+            for (fd, flag) in &namespaces {
+                nix::sched::setns(fd, *flag)?;
+            }
+            match nix::unistd::fork()? {
+                nix::unistd::ForkResult::Child => Ok(()),
+                nix::unistd::ForkResult::Parent { child } => {
+                    let code = loop {
+                        match waitpid(child, None) {
+                            Ok(nix::sys::wait::WaitStatus::Exited(_, code)) => break code,
+                            Ok(nix::sys::wait::WaitStatus::Signaled(_, signal, _)) => {
+                                let _ = nix::sys::signal::kill(nix::unistd::getpid(), signal);
+                                break 128 + signal as i32;
+                            }
+                            Ok(_) | Err(nix::errno::Errno::EINTR) => continue,
+                            Err(_) => break 1,
+                        }
+                    };
+                    nix::libc::_exit(code)
+                }
+            }
+        });
+    }
+    Ok(cmd)
+}
+
+/// Poll `getprop` every `interval` until it returns `expected`
+///
+/// Errors from `getprop` just mean it isn't available yet (early boot), so
+/// they're retried like an unset property. Gives up as soon as `interrupted`
+/// returns true.
+fn poll_prop(
+    key: &str,
+    expected: &str,
+    timeout: Duration,
+    interval: Duration,
+    mut getprop: impl FnMut() -> Result<Option<String>>,
+    mut is_running: impl FnMut() -> bool,
+    mut interrupted: impl FnMut() -> bool,
+) -> Result<()> {
+    let start = std::time::Instant::now();
+    let mut last_seen = None;
+
+    loop {
+        if interrupted() {
+            anyhow::bail!("Interrupted while waiting for {}", key);
+        }
+        if !is_running() {
+            anyhow::bail!("Container init process died while waiting for {}", key);
+        }
+
+        if let Ok(value) = getprop() {
+            if value.as_deref() == Some(expected) {
+                return Ok(());
+            }
+            last_seen = value;
+        }
+
+        if start.elapsed() >= timeout {
+            anyhow::bail!(
+                "Timeout after {:?} waiting for {}={} (last value: {})",
+                timeout,
+                key,
+                expected,
+                last_seen.as_deref().map_or("<unset>".to_string(), |v| format!("{:?}", v))
+            );
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// Parse a comma-separated `ro.product.cpu.abilist`, skipping unknown ABIs
+fn parse_abilist(abilist: &str) -> Vec<Abi> {
+    let mut abis: Vec<Abi> = Vec::new();
+    for abi in abilist.trim().split(',').filter_map(|a| Abi::from_str_opt(a.trim())) {
+        if !abis.contains(&abi) {
+            abis.push(abi);
+        }
+    }
+    abis
+}
 
 /// Mount an ext4 image using fuse2fs (no root required)
-fn fuse2fs_mount(image: &Path, mount_point: &Path, read_only: bool) -> Result<()> {
+pub(crate) fn fuse2fs_mount(image: &Path, mount_point: &Path, read_only: bool) -> Result<()> {
     let mut cmd = Command::new("fuse2fs");
     cmd.arg(image);
     cmd.arg(mount_point);
 
-    if read_only {
-        cmd.arg("-o").arg("ro,fakeroot");
-    } else {
-        cmd.arg("-o").arg("fakeroot");
+    if read_only {
+        cmd.arg("-o").arg("ro,fakeroot");
+    } else {
+        cmd.arg("-o").arg("fakeroot");
+    }
+
+    let output = cmd
+        .output()
+        .context("Failed to execute fuse2fs. Is it installed? (pacman -S fuse2fs)")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!(
+            "fuse2fs failed for {}: {}",
+            image.display(),
+            stderr.trim()
+        );
+    }
+
+    Ok(())
+}
+
+/// Whether `path` is a directory with at least one entry
+///
+/// fuse2fs can report success while the mount point stays empty, when its
+/// FUSE daemon dies right after mounting.
+pub(crate) fn mount_is_populated(path: &Path) -> bool {
+    std::fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_some())
+}
+
+/// Fail with a clear message if the mount of `image` at `mount_point` came up
+/// empty (see [`mount_is_populated`])
+fn ensure_populated(image: &Path, mount_point: &Path) -> Result<()> {
+    if !mount_is_populated(mount_point) {
+        anyhow::bail!(
+            "fuse2fs reported success for {} but {} is empty; the FUSE daemon \
+             probably crashed (check dmesg, or try mounting it by hand with \
+             `fuse2fs -o ro,fakeroot {} {}`)",
+            image.display(),
+            mount_point.display(),
+            image.display(),
+            mount_point.display()
+        );
+    }
+    Ok(())
+}
+
+/// Create an ext4 image of `size` bytes at `image`, seeded with the files in `seed`
+pub(crate) fn create_storage_image(image: &Path, size: u64, seed: &Path) -> Result<()> {
+    let output = Command::new("mkfs.ext4")
+        .args(["-q", "-F", "-d"])
+        .arg(seed)
+        .arg(image)
+        .arg(format!("{}k", size / 1024))
+        .output()
+        .context("Failed to execute mkfs.ext4. Is e2fsprogs installed?")?;
+
+    if !output.status.success() {
+        let _ = std::fs::remove_file(image);
+        anyhow::bail!(
+            "mkfs.ext4 failed for {}: {}",
+            image.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Unmount a FUSE mount using fusermount (no root required)
+pub(crate) fn fusermount_unmount(mount_point: &Path) -> Result<()> {
+    // Try fusermount3 first (newer), fall back to fusermount
+    let result = Command::new("fusermount3")
+        .arg("-u")
+        .arg(mount_point)
+        .output();
+
+    let output = match result {
+        Ok(o) if o.status.success() => return Ok(()),
+        _ => {
+            // Fall back to fusermount
+            Command::new("fusermount")
+                .arg("-u")
+                .arg(mount_point)
+                .output()
+                .with_context(|| {
+                    format!(
+                        "Failed to execute fusermount -u {}",
+                        mount_point.display()
+                    )
+                })?
+        }
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // Try lazy unmount as last resort
+        let lazy_result = Command::new("fusermount")
+            .arg("-uz")
+            .arg(mount_point)
+            .output();
+
+        if let Ok(lazy_out) = lazy_result
+            && lazy_out.status.success()
+        {
+            warn!(
+                "Used lazy unmount for {} (busy mount)",
+                mount_point.display()
+            );
+            return Ok(());
+        }
+
+        anyhow::bail!(
+            "fusermount -u failed for {}: {}",
+            mount_point.display(),
+            stderr.trim()
+        );
+    }
+
+    Ok(())
+}
+
+/// Whether `pid` exists and isn't a zombie (a zombie has exited, it just
+/// hasn't been reaped by its parent yet)
+fn process_alive(pid: Pid) -> bool {
+    match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        // The state follows the parenthesized command name, which may itself
+        // contain spaces or parentheses
+        Ok(stat) => stat
+            .rsplit_once(')')
+            .and_then(|(_, rest)| rest.split_whitespace().next())
+            .is_some_and(|state| state != "Z" && state != "X"),
+        Err(_) => false,
+    }
+}
+
+/// The namespace's PID 1 under the outer unshare process `pid` (its only
+/// child)
+fn namespace_init(pid: Pid) -> Option<Pid> {
+    // Without CONFIG_PROC_CHILDREN there is no `children` file; fall back to
+    // looking for the process whose parent is `pid`
+    let children = std::fs::read_to_string(format!("/proc/{0}/task/{0}/children", pid))
+        .ok()
+        .and_then(|list| list.split_whitespace().next()?.parse().ok());
+    children.or_else(|| {
+        std::fs::read_dir("/proc").ok()?.flatten().find_map(|entry| {
+            let child: i32 = entry.file_name().to_str()?.parse().ok()?;
+            let stat = std::fs::read_to_string(entry.path().join("stat")).ok()?;
+            // Fields after the command name: state, ppid, ...
+            let ppid: i32 = stat.rsplit_once(')')?.1.split_whitespace().nth(1)?.parse().ok()?;
+            (ppid == pid.as_raw()).then_some(child)
+        })
+    })
+    .map(Pid::from_raw)
+}
+
+/// SIGKILL the namespace under the outer unshare process `pid`, then
+/// unshare itself
+///
+/// Killing the namespace's PID 1 makes the kernel kill everything else in
+/// it; PID 1 only finishes exiting once they are gone, which is waited for
+/// (bounded). The caller reaps `pid`.
+fn kill_namespace(pid: Pid) {
+    if let Some(init) = namespace_init(pid) {
+        let _ = nix::sys::signal::kill(init, Signal::SIGKILL);
+        let deadline = std::time::Instant::now() + NAMESPACE_KILL_TIMEOUT;
+        while process_alive(init) && std::time::Instant::now() < deadline {
+            std::thread::sleep(STOP_POLL_INTERVAL);
+        }
+    }
+    let _ = nix::sys::signal::kill(pid, Signal::SIGKILL);
+}
+
+/// Run `check` every `interval` until it reports the service as published
+///
+/// `check` gets the time left before `timeout` to bound its probe; a failed
+/// or timed-out probe counts as not published yet.
+fn poll_service(
+    name: &str,
+    timeout: Duration,
+    interval: Duration,
+    mut check: impl FnMut(Duration) -> Result<bool>,
+    mut is_running: impl FnMut() -> bool,
+) -> Result<()> {
+    let start = std::time::Instant::now();
+
+    loop {
+        let remaining = timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            anyhow::bail!(
+                "Timeout waiting for service '{}' after {:?}",
+                name,
+                timeout
+            );
+        }
+
+        if !is_running() {
+            anyhow::bail!("Container init process died while waiting for '{}'", name);
+        }
+
+        if let Ok(true) = check(remaining) {
+            return Ok(());
+        }
+
+        std::thread::sleep(interval.min(timeout.saturating_sub(start.elapsed())));
+    }
+}
+
+/// Parse `service check` output: "Service <name>: found" / "Service <name>: not found"
+fn parse_service_check(output: &str) -> bool {
+    output
+        .lines()
+        .any(|line| line.trim_end().ends_with(": found"))
+}
+
+/// The error for a container that died during startup: `message`, with a
+/// [`CoreError`] underneath if its `log` shows a known cause
+fn startup_failure(message: String, log: &str) -> anyhow::Error {
+    match CoreError::classify_startup_failure(log) {
+        Some(cause) => anyhow::Error::new(cause).context(message),
+        None => anyhow::anyhow!(message),
+    }
+}
+
+/// Check an instance ID is usable as a directory and cgroup name
+pub fn validate_instance_id(id: &str) -> Result<()> {
+    let valid = !id.is_empty()
+        && !id.starts_with('.')
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        anyhow::bail!(
+            "Invalid instance ID {:?}: use letters, digits, '-', '_' and '.' (not leading)",
+            id
+        );
+    }
+    Ok(())
+}
+
+/// The last `n` lines of `text`
+fn last_lines(text: &str, n: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    lines[lines.len().saturating_sub(n)..].join("\n")
+}
+
+/// Wait until `child` writes [`READY_MARKER`] to its output, the boot log at
+/// `log`; returns what it wrote before the marker
+///
+/// Kills the child and returns an error with the output so far if the marker
+/// doesn't show up within `timeout`, or if the child exits first.
+fn wait_for_ready(child: &mut Child, log: &Path, timeout: Duration) -> Result<String> {
+    let deadline = std::time::Instant::now() + timeout;
+    let read_log = || std::fs::read_to_string(log).unwrap_or_default();
+
+    loop {
+        // Check for an exit first, so the log read after it is complete
+        let exited = child.try_wait()?;
+        let output = read_log();
+        if let Some(pos) = output.lines().position(|line| line.trim() == READY_MARKER) {
+            let before: Vec<&str> = output.lines().take(pos).collect();
+            return Ok(before.iter().map(|line| format!("{}\n", line)).collect());
+        }
+
+        if let Some(status) = exited {
+            return Err(startup_failure(
+                format!("Init process died before starting ({}).\noutput: {}", status, output),
+                &output,
+            ));
+        }
+        if std::time::Instant::now() >= deadline {
+            // Killing unshare alone would orphan the namespace
+            kill_namespace(Pid::from_raw(child.id() as i32));
+            let _ = child.wait();
+            anyhow::bail!(
+                "Container setup did not finish within {:?}; killed it.\noutput: {}",
+                timeout,
+                read_log()
+            );
+        }
+        std::thread::sleep(READY_POLL_INTERVAL);
+    }
+}
+
+/// Quote a string for safe interpolation into a POSIX shell command
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Locate the init binary inside a mounted system image
+fn find_init(system_mount: &Path) -> Result<&'static str> {
+    for init_path in ["/init", "/system/bin/init", "/bin/init"] {
+        if system_mount.join(&init_path[1..]).exists() {
+            return Ok(init_path);
+        }
+    }
+
+    anyhow::bail!(
+        "No init binary found in system image. Checked:\n  \
+         {}/init\n  {}/system/bin/init\n  {}/bin/init",
+        system_mount.display(),
+        system_mount.display(),
+        system_mount.display()
+    );
+}
+
+/// ELF machine of the image's init, or None if it can't be determined
+///
+/// `/init` is often an absolute symlink into the image, so the real binary
+/// under `system/bin` is checked first.
+fn image_machine(system_mount: &Path) -> Option<u16> {
+    ["system/bin/init", "init", "bin/init"]
+        .iter()
+        .map(|path| system_mount.join(path))
+        .filter(|path| path.symlink_metadata().is_ok_and(|m| m.is_file()))
+        .find_map(|path| apk::read_elf_machine(&path).ok().flatten())
+}
+
+/// Arguments for `pm` to install the staged APK
+fn pm_install_args(abi: Option<&Abi>) -> Vec<&'static str> {
+    let mut args = vec!["install", "-r"];
+    if let Some(abi) = abi {
+        args.push("--abi");
+        args.push(abi.as_str());
+    }
+    args.push("/data/local/tmp/install.apk");
+    args
+}
+
+/// Where `install_apk_set` stages the APKs inside the container
+const INSTALL_SET_DIR: &str = "/data/local/tmp/install-set";
+
+/// `pm install-create` arguments, replacing an existing install
+fn pm_install_create_args(abi: Option<&Abi>) -> Vec<&'static str> {
+    let mut args = vec!["install-create", "-r"];
+    if let Some(abi) = abi {
+        args.push("--abi");
+        args.push(abi.as_str());
+    }
+    args
+}
+
+/// Session ID from `Success: created install session [1234]`
+fn parse_install_session(output: &str) -> Option<u32> {
+    let (_, rest) = output.split_once("install session [")?;
+    rest.split_once(']')?.0.parse().ok()
+}
+
+/// Order an APK set with the base APK first
+///
+/// Splits are named `split_*.apk` (bundletool's `split_config.x86_64.apk`
+/// etc.); exactly one other file must be left as the base. Single files are
+/// returned as is.
+pub fn order_apk_set<'a>(apks: &[&'a Path]) -> Result<Vec<&'a Path>> {
+    if apks.len() <= 1 {
+        return match apks.first() {
+            Some(apk) => Ok(vec![*apk]),
+            None => anyhow::bail!("No APKs to install"),
+        };
+    }
+
+    let is_split = |apk: &Path| {
+        apk.file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("split_"))
+    };
+    let (bases, splits): (Vec<&Path>, Vec<&Path>) = apks.iter().partition(|apk| !is_split(apk));
+    match bases[..] {
+        [base] => Ok(std::iter::once(base).chain(splits).collect()),
+        [] => anyhow::bail!("No base APK among the split APKs"),
+        _ => anyhow::bail!(
+            "Several base APKs in the set: {}",
+            bases
+                .iter()
+                .map(|b| b.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// APK paths from `pm path` output (`package:/data/app/.../base.apk` per line)
+fn parse_pm_path(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("package:"))
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Commands `enable_adb` runs in order: check adbd exists, set the TCP port,
+/// then have init (re)start the adbd service
+fn adb_enable_commands(tcp_port: u16) -> Vec<(&'static str, Vec<String>)> {
+    let check = ADBD_PATHS
+        .iter()
+        .map(|path| format!("[ -x {} ]", path))
+        .collect::<Vec<_>>()
+        .join(" || ");
+
+    vec![
+        ("sh", vec!["-c".to_string(), check]),
+        (
+            "setprop",
+            vec!["service.adb.tcp.port".to_string(), tcp_port.to_string()],
+        ),
+        (
+            "setprop",
+            vec!["ctl.restart".to_string(), "adbd".to_string()],
+        ),
+    ]
+}
+
+/// Properties describing a `width`x`height` display at `density` dpi
+fn display_props(width: u32, height: u32, density: u32) -> Vec<(String, String)> {
+    vec![
+        ("ro.sf.lcd_density".to_string(), density.to_string()),
+        ("persist.waydroid.width".to_string(), width.to_string()),
+        ("persist.waydroid.height".to_string(), height.to_string()),
+    ]
+}
+
+/// `local.prop` contents for `props`
+fn local_prop_content(props: &[(String, String)]) -> String {
+    let mut content = format!("{}\n", LOCAL_PROP_HEADER);
+    for (key, value) in props {
+        content.push_str(&format!("{}={}\n", key, value));
+    }
+    content
+}
+
+/// Why a filesystem with `statfs` magic `f_type` can't be an overlay upperdir,
+/// or None if it can
+fn unsupported_upper_fs(f_type: i64) -> Option<&'static str> {
+    // f_type is a signed long, so 32-bit magics may come back sign-extended
+    let magic = f_type & 0xffff_ffff;
+    UNSUPPORTED_UPPER_FS
+        .iter()
+        .find(|(m, _)| *m == magic)
+        .map(|(_, reason)| *reason)
+}
+
+/// Arguments for `am` to force-stop a package
+fn force_stop_args(package: &str) -> [&str; 2] {
+    ["force-stop", package]
+}
+
+/// Parse a `ps -o PID,NAME` line, returning the PID if it belongs to `package`
+///
+/// Matches the main process and its `package:suffix` sub-processes.
+fn parse_ps_line(line: &str, package: &str) -> Option<u32> {
+    let mut fields = line.split_whitespace();
+    let pid = fields.next()?.parse().ok()?;
+    let name = fields.next_back()?;
+
+    let matches = name == package
+        || name
+            .strip_prefix(package)
+            .is_some_and(|rest| rest.starts_with(':'));
+    matches.then_some(pid)
+}
+
+/// Check if fuse2fs is available on the system
+pub fn check_fuse2fs() -> bool {
+    Command::new("fuse2fs")
+        .arg("--help")
+        .output()
+        .map(|o| o.status.success() || !o.stderr.is_empty())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{ImagePaths, MountPoints};
+
+    /// Images that don't exist, so nothing actually gets mounted
+    fn test_images() -> ImagePaths {
+        ImagePaths {
+            system_img: PathBuf::from("/tmp/nonexistent/system.img"),
+            vendor_img: PathBuf::from("/tmp/nonexistent/vendor.img"),
+        }
+    }
+
+    /// A container on [`test_images`] with its prefix at `prefix`
+    fn test_container_at(prefix: &Path) -> Container {
+        Container::new(test_images(), MountPoints::for_prefix(prefix))
+    }
+
+    /// A container on [`test_images`] in a prefix that doesn't exist
+    fn test_container() -> Container {
+        test_container_at(Path::new("/tmp/nonexistent-prefix"))
+    }
+
+    #[test]
+    fn container_new_not_running() {
+        let container = test_container();
+
+        assert!(!container.is_running());
+        assert!(container.init_pid.is_none());
+    }
+
+    #[test]
+    fn container_start_fails_without_images() {
+        let mut container = test_container();
+
+        let result = container.start();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn dry_run_reports_same_validation_error_without_mounting() {
+        let prefix = std::env::temp_dir().join(format!("rad-dry-run-{}", std::process::id()));
+
+        let real_err = test_container_at(&prefix).start().unwrap_err();
+        let dry_err = test_container_at(&prefix)
+            .with_dry_run(true)
+            .start()
+            .unwrap_err();
+
+        assert_eq!(dry_err.to_string(), real_err.to_string());
+        assert!(!prefix.exists(), "dry-run must not create mount directories");
+    }
+
+    #[test]
+    fn dry_run_install_and_launch_do_not_exec() {
+        let container = test_container().with_dry_run(true);
+
+        assert!(container.launch_app("com.example", ".Main").is_ok());
+        assert!(container.install_apk(Path::new("/tmp/nonexistent/app.apk")).is_err());
+        assert!(!Path::new("/tmp/nonexistent-prefix").exists());
+    }
+
+    #[test]
+    fn restart_from_stopped_behaves_like_start() {
+        let start_err = test_container().start().unwrap_err();
+        let restart_err = test_container().restart().unwrap_err();
+
+        assert_eq!(restart_err.to_string(), start_err.to_string());
+    }
+
+    #[test]
+    fn restart_from_running_tears_down_first() {
+        let mut container = test_container();
+
+        // Stand-in for a running init
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        container.init_pid = Some(child.id());
+
+        // The fresh start fails on the missing images, after the old init is gone
+        assert!(container.restart().is_err());
+        assert!(container.init_pid.is_none());
+        assert!(!matches!(child.try_wait(), Ok(None)), "old init still running");
+        let _ = child.kill();
+    }
+
+    #[test]
+    fn start_with_progress_stops_reporting_at_failed_stage() {
+        let mut phases = Vec::new();
+
+        let result = test_container().start_with_progress(|phase| phases.push(phase));
+
+        assert!(result.is_err());
+        assert_eq!(phases, vec![StartPhase::ValidatingImages]);
+    }
+
+    #[test]
+    fn snapshot_and_restore_upper_layer() {
+        let root = std::env::temp_dir().join(format!("rad-snapshot-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let container = test_container_at(&root);
+        let upper = container.mounts.overlay_upper.clone();
+        std::fs::create_dir_all(upper.join("data/data/com.example")).unwrap();
+        std::fs::write(upper.join("data/data/com.example/state"), b"original").unwrap();
+        std::os::unix::fs::symlink("state", upper.join("data/data/com.example/link")).unwrap();
+
+        container.snapshot("clean").unwrap();
+        assert!(container.snapshot("clean").is_err(), "snapshot names are unique");
+
+        std::fs::write(upper.join("data/data/com.example/state"), b"modified").unwrap();
+        std::fs::remove_file(upper.join("data/data/com.example/link")).unwrap();
+        std::fs::write(upper.join("data/new-file"), b"added later").unwrap();
+
+        container.restore_snapshot("clean").unwrap();
+
+        assert_eq!(
+            std::fs::read(upper.join("data/data/com.example/state")).unwrap(),
+            b"original"
+        );
+        assert_eq!(
+            std::fs::read_link(upper.join("data/data/com.example/link")).unwrap(),
+            PathBuf::from("state")
+        );
+        assert!(!upper.join("data/new-file").exists());
+        // The snapshot itself survives a restore
+        assert!(root.join(".snapshots/clean/data/data/com.example/state").is_file());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn snapshot_refuses_running_container() {
+        let mut container = test_container();
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        container.init_pid = Some(child.id());
+
+        let err = container.snapshot("running").unwrap_err();
+        assert!(err.to_string().contains("stop it first"));
+        assert!(container.restore_snapshot("running").is_err());
+
+        let _ = child.kill();
+        let _ = child.wait();
+        container.detach();
+    }
+
+    #[test]
+    fn detach_keeps_process_alive_on_drop() {
+        let pid_file = std::env::temp_dir().join(format!("rad-detach-{}.pid", std::process::id()));
+        let mut container = test_container().with_pid_file(pid_file.clone());
+
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        container.init_pid = Some(child.id());
+        container.system_mounted = true;
+
+        // Consumes the handle; its drop must neither kill nor unmount
+        assert_eq!(container.detach(), Some(child.id()));
+
+        assert!(matches!(child.try_wait(), Ok(None)), "init was killed on drop");
+        assert_eq!(
+            std::fs::read_to_string(&pid_file).unwrap(),
+            child.id().to_string()
+        );
+        let _ = child.kill();
+        let _ = child.wait();
+        let _ = std::fs::remove_file(pid_file);
+    }
+
+    #[test]
+    fn wait_returns_exit_status_of_init() {
+        let mut container = test_container();
+
+        // Reaped through the container, like a real init
+        let pid = Command::new("sh").args(["-c", "sleep 0.2; exit 3"]).spawn().unwrap().id();
+        container.init_pid = Some(pid);
+        assert_eq!(container.wait().unwrap(), ExitStatus::Exited(3));
+
+        let pid = Command::new("sleep").arg("30").spawn().unwrap().id();
+        container.init_pid = Some(pid);
+        nix::sys::signal::kill(Pid::from_raw(pid as i32), Signal::SIGKILL).unwrap();
+        assert_eq!(
+            container.wait().unwrap(),
+            ExitStatus::Signaled(Signal::SIGKILL)
+        );
+
+        container.init_pid = None;
+        assert!(container.wait().is_err());
+    }
+
+    #[test]
+    fn mount_is_populated_needs_an_entry() {
+        let dir = std::env::temp_dir().join(format!("rad-populated-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(!mount_is_populated(&dir), "missing dir");
+
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(!mount_is_populated(&dir), "empty dir");
+        let err = ensure_populated(Path::new("/images/system.img"), &dir).unwrap_err();
+        assert!(err.to_string().contains("is empty"), "{}", err);
+
+        std::fs::create_dir(dir.join("system")).unwrap();
+        assert!(mount_is_populated(&dir));
+        assert!(ensure_populated(Path::new("/images/system.img"), &dir).is_ok());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    #[ignore = "Requires unprivileged user namespaces"]
+    fn stop_kills_only_after_grace_period() {
+        let mut container = test_container().with_stop_timeout(Duration::from_millis(500));
+
+        // Launch `script` as the namespace's PID 1, like init, and wait for it
+        let launch = |container: &Container, script: &str| {
+            let outer = container
+                .unshare_command(script)
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .spawn()
+                .unwrap()
+                .id();
+            let outer = Pid::from_raw(outer as i32);
+            let deadline = std::time::Instant::now() + Duration::from_secs(5);
+            let init = loop {
+                if let Some(init) = namespace_init(outer) {
+                    break init;
+                }
+                assert!(std::time::Instant::now() < deadline, "namespace init never started");
+                std::thread::sleep(Duration::from_millis(20));
+            };
+            // Let the shell install its trap
+            std::thread::sleep(Duration::from_millis(200));
+            (outer.as_raw() as u32, init)
+        };
+
+        // Exits on SIGTERM: no SIGKILL, and no waiting out the grace period
+        let (outer, init) = launch(&container, "trap 'exit 0' TERM; while :; do sleep 0.05; done");
+        container.init_pid = Some(outer);
+        let start = std::time::Instant::now();
+        assert!(!container.stop().unwrap());
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert!(!process_alive(init));
+        assert!(!process_alive(Pid::from_raw(outer as i32)));
+        assert!(container.init_pid.is_none());
+
+        // Ignores SIGTERM: killed, along with the whole namespace, once the
+        // grace period is over
+        let (outer, init) = launch(&container, "trap '' TERM; while :; do sleep 0.05; done");
+        container.init_pid = Some(outer);
+        let start = std::time::Instant::now();
+        assert!(container.stop().unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        assert!(!process_alive(init));
+        assert!(!process_alive(Pid::from_raw(outer as i32)));
+    }
+
+    #[test]
+    fn bind_mount_script_binds_and_remounts_read_only() {
+        let host = std::env::temp_dir();
+        let container = test_container_at(Path::new("/tmp/prefix"))
+            .with_bind(host.clone(), "/data/media/shared".to_string(), true)
+            .with_bind(host.clone(), "/sdcard/rw".to_string(), false);
+
+        let script = container.bind_mount_script().unwrap();
+        let host = host.display();
+
+        assert!(script.contains("mkdir -p '/tmp/prefix/rootfs/data/media/shared'"));
+        assert!(script.contains(&format!(
+            "mount --bind '{host}' '/tmp/prefix/rootfs/data/media/shared'\n\
+             mount -o remount,ro,bind '/tmp/prefix/rootfs/data/media/shared'"
+        )));
+        assert!(script.contains(&format!("mount --bind '{host}' '/tmp/prefix/rootfs/sdcard/rw'")));
+        assert!(!script.contains("remount,ro,bind '/tmp/prefix/rootfs/sdcard/rw'"));
+    }
+
+    #[test]
+    fn shared_storage_binds_sdcard_and_links_emulated_storage() {
+        let container = test_container_at(Path::new("/tmp/prefix"));
+        assert_eq!(container.shared_storage_script(), "");
+
+        let container = container.with_shared_storage(PathBuf::from("/home/u/Android"));
+        let script = container.shared_storage_script();
+        assert!(script.contains(
+            "if [ -L '/tmp/prefix/rootfs/sdcard' ]; then rm '/tmp/prefix/rootfs/sdcard'; fi\n\
+             mkdir -p '/tmp/prefix/rootfs/sdcard'\n\
+             mount --bind '/home/u/Android' '/tmp/prefix/rootfs/sdcard'\n"
+        ));
+        let link = "'/tmp/prefix/rootfs/storage/emulated/0'";
+        assert!(script.contains(&format!(
+            "mkdir -p '/tmp/prefix/rootfs/storage/emulated'; ln -s '/sdcard' {link}"
+        )));
+        assert!(script.contains(&format!("if [ -e {link} ]; then mount --bind '/home/u/Android' {link}")));
+    }
+
+    #[test]
+    fn shared_storage_dir_is_made_media_readable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("rad-sdcard-mode-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o700)).unwrap();
+
+        make_media_readable(&dir).unwrap();
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(mode, 0o755);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn existing_shared_storage_dir_keeps_its_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = std::env::temp_dir().join(format!("rad-sdcard-keep-{}", std::process::id()));
+        let existing = root.join("existing");
+        let created = root.join("created");
+        std::fs::create_dir_all(&existing).unwrap();
+        std::fs::set_permissions(&existing, std::fs::Permissions::from_mode(0o700)).unwrap();
+        let mode = |dir: &Path| std::fs::metadata(dir).unwrap().permissions().mode() & 0o7777;
+
+        let mut container = test_container().with_shared_storage(existing.clone());
+        container.mount_shared_storage().unwrap();
+        assert_eq!(mode(&existing), 0o700);
+
+        let mut container = test_container().with_shared_storage(created.clone());
+        container.mount_shared_storage().unwrap();
+        assert_eq!(mode(&created) & 0o755, 0o755);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn shared_storage_cap_selects_image_backing() {
+        let plain = SharedStorage {
+            host_dir: PathBuf::from("/home/u/Android"),
+            size_cap: None,
+        };
+        assert_eq!(plain.image_path(), None);
+
+        let capped = SharedStorage {
+            size_cap: Some(512 * 1024 * 1024),
+            ..plain
+        };
+        assert_eq!(capped.image_path(), Some(PathBuf::from("/home/u/Android.img")));
+
+        // The container still binds the directory the image is mounted over
+        let container = test_container_at(Path::new("/tmp/prefix"))
+            .with_shared_storage_cap(PathBuf::from("/home/u/Android"), 512 * 1024 * 1024);
+        assert_eq!(container.shared_storage, Some(capped));
+        assert!(container
+            .shared_storage_script()
+            .contains("mount --bind '/home/u/Android' '/tmp/prefix/rootfs/sdcard'"));
+    }
+
+    #[test]
+    fn bind_mount_outside_rootfs_is_rejected() {
+        let escaping = test_container_at(Path::new("/tmp/prefix")).with_bind(
+            std::env::temp_dir(),
+            "/data/../../etc".to_string(),
+            true,
+        );
+        assert!(escaping.bind_mount_script().is_err());
+
+        let missing_host = test_container_at(Path::new("/tmp/prefix")).with_bind(
+            PathBuf::from("/nonexistent/host/dir"),
+            "/data/shared".to_string(),
+            true,
+        );
+        assert!(missing_host.bind_mount_script().is_err());
+    }
+
+    /// Run `script` with its output going to a fresh log file
+    fn spawn_sh(script: &str, label: &str) -> (Child, PathBuf) {
+        let log = std::env::temp_dir().join(format!("rad-boot-{}-{}.log", label, std::process::id()));
+        let file = std::fs::File::create(&log).unwrap();
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(script)
+            .stdout(file.try_clone().unwrap())
+            .stderr(file)
+            .spawn()
+            .unwrap();
+        (child, log)
+    }
+
+    #[test]
+    fn wait_for_ready_times_out_and_kills_child() {
+        let (mut child, log) = spawn_sh("echo mounting; echo stuck >&2; sleep 30", "timeout");
+
+        let start = std::time::Instant::now();
+        let err = wait_for_ready(&mut child, &log, Duration::from_millis(300)).unwrap_err();
+
+        assert!(start.elapsed() < Duration::from_secs(10));
+        let message = err.to_string();
+        assert!(message.contains("did not finish"), "{}", message);
+        assert!(message.contains("mounting"), "{}", message);
+        assert!(message.contains("stuck"), "{}", message);
+        // The child was killed and reaped
+        assert!(child.try_wait().unwrap().is_some());
+        let _ = std::fs::remove_file(log);
+    }
+
+    #[test]
+    #[ignore = "Requires unprivileged user namespaces"]
+    fn wait_for_ready_timeout_kills_the_namespace() {
+        let log = std::env::temp_dir().join(format!("rad-boot-ns-{}.log", std::process::id()));
+        let file = std::fs::File::create(&log).unwrap();
+        let mut child = test_container()
+            .unshare_command("echo mounting; while :; do sleep 0.05; done")
+            .stdout(file.try_clone().unwrap())
+            .stderr(file)
+            .spawn()
+            .unwrap();
+        let outer = Pid::from_raw(child.id() as i32);
+        std::thread::sleep(Duration::from_millis(200));
+        let init = namespace_init(outer).expect("namespace init not started");
+
+        assert!(wait_for_ready(&mut child, &log, Duration::from_millis(300)).is_err());
+        assert!(!process_alive(init), "namespace init orphaned");
+        assert!(child.try_wait().unwrap().is_some());
+        let _ = std::fs::remove_file(log);
+    }
+
+    #[test]
+    fn wait_for_ready_returns_on_marker() {
+        let (mut child, log) = spawn_sh(
+            &format!("echo setup; echo {}; echo init output; sleep 1", READY_MARKER),
+            "marker",
+        );
+
+        let output = wait_for_ready(&mut child, &log, Duration::from_secs(10)).unwrap();
+
+        assert_eq!(output, "setup\n");
+        let _ = child.kill();
+        let _ = child.wait();
+        // Everything stays in the log, including what init prints afterwards
+        let content = std::fs::read_to_string(&log).unwrap();
+        assert!(content.starts_with(&format!("setup\n{}\n", READY_MARKER)), "{}", content);
+        let _ = std::fs::remove_file(log);
+    }
+
+    #[test]
+    fn wait_for_ready_reports_early_exit() {
+        let (mut child, log) = spawn_sh("echo 'mount: permission denied' >&2; exit 1", "exit");
+
+        let err = wait_for_ready(&mut child, &log, Duration::from_secs(10)).unwrap_err();
+
+        assert!(err.to_string().contains("died before starting"));
+        assert_eq!(
+            err.downcast_ref::<CoreError>(),
+            Some(&CoreError::OverlayInUserNsUnsupported)
+        );
+        let _ = std::fs::remove_file(log);
+    }
+
+    #[test]
+    fn with_instance_moves_state_under_instance_dir() {
+        let container = test_container_at(Path::new("/tmp/prefix/com.example"))
+            .with_pid_file(PathBuf::from("/tmp/prefix/com.example/.container_pid"))
+            .with_instance("second");
+
+        let dir = Path::new("/tmp/prefix/com.example/instances/second");
+        assert_eq!(container.pid_file(), Some(dir.join(".container_pid").as_path()));
+        assert_eq!(container.mounts.overlay_upper, dir.join(".overlay/upper"));
+        assert_eq!(container.mounts.overlay_work, dir.join(".overlay/work"));
+        assert_eq!(container.mounts.system_mount, dir.join(".mounts/system"));
+        // The rootfs only exists inside the container's mount namespace
+        assert_eq!(container.mounts.rootfs, Path::new("/tmp/prefix/com.example/rootfs"));
+        assert_eq!(container.cgroup_name().unwrap(), "com.example@second");
+        assert_eq!(container.boot_log_path(), dir.join("logs/container.log"));
+
+        assert!(validate_instance_id("work-2").is_ok());
+        for bad in ["", "..", ".hidden", "a/b", "a b"] {
+            assert!(validate_instance_id(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn boot_log_lives_in_prefix_logs() {
+        let container = test_container_at(Path::new("/tmp/prefix"));
+        assert_eq!(
+            container.boot_log_path(),
+            PathBuf::from("/tmp/prefix/logs/container.log")
+        );
+
+        let log: String = (1..=30).map(|i| format!("line {}\n", i)).collect();
+        let tail = last_lines(&log, BOOT_LOG_TAIL_LINES);
+        assert!(tail.starts_with("line 11\n"), "{}", tail);
+        assert!(tail.ends_with("line 30"), "{}", tail);
+        assert_eq!(last_lines("only\n", 20), "only");
     }
 
-    let output = cmd
-        .output()
-        .context("Failed to execute fuse2fs. Is it installed? (pacman -S fuse2fs)")?;
+    #[test]
+    fn getprop_value_trims_newline() {
+        assert_eq!(getprop_value("1\n").as_deref(), Some("1"));
+        assert_eq!(
+            getprop_value("arm64-v8a,armeabi-v7a\r\n").as_deref(),
+            Some("arm64-v8a,armeabi-v7a")
+        );
+        // Inner whitespace is part of the value
+        assert_eq!(getprop_value("a b\n").as_deref(), Some("a b"));
+    }
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!(
-            "fuse2fs failed for {}: {}",
-            image.display(),
-            stderr.trim()
+    #[test]
+    fn getprop_value_unset_is_none() {
+        assert_eq!(getprop_value("\n"), None);
+        assert_eq!(getprop_value(""), None);
+    }
+
+    #[test]
+    fn parse_abilist_keeps_order_and_skips_unknown() {
+        assert_eq!(
+            parse_abilist("arm64-v8a,armeabi-v7a,armeabi\n"),
+            vec![Abi::Arm64V8a, Abi::ArmV7a]
         );
+        assert_eq!(parse_abilist("x86_64,x86,arm64-v8a"), vec![Abi::X86_64, Abi::X86, Abi::Arm64V8a]);
+        assert!(parse_abilist("").is_empty());
     }
 
-    Ok(())
-}
+    #[test]
+    fn boot_completed_maps_getprop_results() {
+        assert!(boot_completed(Ok(Some("1".to_string()))));
+        assert!(!boot_completed(Ok(Some("0".to_string()))));
+        assert!(!boot_completed(Ok(None)));
+        assert!(!boot_completed(Err(anyhow::anyhow!("getprop failed"))));
+    }
 
-/// Unmount a FUSE mount using fusermount (no root required)
-fn fusermount_unmount(mount_point: &Path) -> Result<()> {
-    // Try fusermount3 first (newer), fall back to fusermount
-    let result = Command::new("fusermount3")
-        .arg("-u")
-        .arg(mount_point)
-        .output();
+    #[test]
+    fn exec_falls_back_to_nsenter_when_setns_fails() {
+        let nsenter_called = std::cell::Cell::new(false);
 
-    let output = match result {
-        Ok(o) if o.status.success() => return Ok(()),
-        _ => {
-            // Fall back to fusermount
-            Command::new("fusermount")
-                .arg("-u")
-                .arg(mount_point)
-                .output()
-                .with_context(|| {
-                    format!(
-                        "Failed to execute fusermount -u {}",
-                        mount_point.display()
-                    )
-                })?
+        let (value, path) = exec_with_fallback(
+            || Err(std::io::Error::from_raw_os_error(nix::libc::EPERM)),
+            || {
+                nsenter_called.set(true);
+                Ok("from nsenter")
+            },
+        )
+        .unwrap();
+        assert_eq!((value, path), ("from nsenter", ExecPath::Nsenter));
+        assert!(nsenter_called.get());
+
+        nsenter_called.set(false);
+        let (value, path) = exec_with_fallback(
+            || Ok("native"),
+            || {
+                nsenter_called.set(true);
+                Ok("from nsenter")
+            },
+        )
+        .unwrap();
+        assert_eq!((value, path), ("native", ExecPath::Setns));
+        assert!(!nsenter_called.get());
+
+        // A missing command isn't a setns problem
+        let result = exec_with_fallback(
+            || Err::<(), _>(std::io::Error::from(std::io::ErrorKind::NotFound)),
+            || {
+                nsenter_called.set(true);
+                Ok(())
+            },
+        );
+        assert!(result.is_err());
+        assert!(!nsenter_called.get());
+    }
+
+    #[tokio::test]
+    async fn start_async_fails_like_start_on_missing_images() {
+        let sync_err = test_container_at(Path::new("/tmp/prefix")).start().unwrap_err();
+        let mut container = test_container_at(Path::new("/tmp/prefix"));
+        let async_err = container.start_async().await.unwrap_err();
+
+        assert_eq!(format!("{:#}", async_err), format!("{:#}", sync_err));
+        assert!(!container.is_running());
+        assert_eq!(
+            container.images.system_img,
+            PathBuf::from("/tmp/nonexistent/system.img")
+        );
+    }
+
+    #[test]
+    fn output_with_timeout_gives_up_after_timeout() {
+        let output = output_with_timeout(
+            Command::new("sh").args(["-c", "echo out; echo err >&2"]),
+            Duration::from_secs(5),
+        )
+        .unwrap()
+        .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+
+        // Returns once the timeout passes instead of after the sleep
+        let start = std::time::Instant::now();
+        let output = output_with_timeout(
+            Command::new("sh").args(["-c", "sleep 10; echo late"]),
+            Duration::from_millis(100),
+        )
+        .unwrap();
+        assert!(output.is_none());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let err = anyhow::Error::from(CoreError::TimedOut {
+            command: "getprop sys.boot_completed".to_string(),
+            timeout: Duration::from_secs(5),
+        });
+        assert_eq!(
+            err.to_string(),
+            "`getprop sys.boot_completed` in the container timed out after 5s"
+        );
+    }
+
+    #[test]
+    fn logcat_lines_yields_lines_and_kills_group_on_drop() {
+        let child = std::os::unix::process::CommandExt::process_group(
+            Command::new("sh").args(["-c", "echo first; echo second; sleep 30 & wait"]),
+            0,
+        )
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+        let mut lines = LogcatLines::new(child).unwrap();
+        let group = Pid::from_raw(lines.process_group() as i32);
+
+        assert_eq!(lines.next().as_deref(), Some("first"));
+        assert_eq!(lines.next().as_deref(), Some("second"));
+        drop(lines);
+
+        // The backgrounded sleep went down with the shell. It may linger as a
+        // zombie until reaped by init, so look for live group members.
+        let group_alive = || {
+            std::fs::read_dir("/proc").unwrap().flatten().any(|entry| {
+                let stat = std::fs::read_to_string(entry.path().join("stat")).unwrap_or_default();
+                let fields: Vec<&str> = stat
+                    .rsplit_once(") ")
+                    .map_or(vec![], |(_, rest)| rest.split(' ').collect());
+                fields.len() > 2
+                    && fields[2] == group.to_string()
+                    && !matches!(fields[0], "Z" | "X")
+            })
+        };
+        let mut alive = group_alive();
+        for _ in 0..50 {
+            if !alive {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+            alive = group_alive();
         }
-    };
+        assert!(!alive, "logcat process group survived the drop");
+    }
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        // Try lazy unmount as last resort
-        let lazy_result = Command::new("fusermount")
-            .arg("-uz")
-            .arg(mount_point)
-            .output();
+    #[test]
+    fn poll_prop_waits_for_expected_value() {
+        let mut outputs = vec![
+            Err(anyhow::anyhow!("getprop not available yet")),
+            Ok(None),
+            Ok(Some("starting".to_string())),
+            Ok(Some("running".to_string())),
+        ]
+        .into_iter();
+        let mut calls = 0;
 
-        if let Ok(lazy_out) = lazy_result
-            && lazy_out.status.success()
-        {
-            warn!(
-                "Used lazy unmount for {} (busy mount)",
-                mount_point.display()
-            );
-            return Ok(());
+        poll_prop(
+            "init.svc.zygote",
+            "running",
+            Duration::from_secs(5),
+            Duration::ZERO,
+            || {
+                calls += 1;
+                outputs.next().unwrap()
+            },
+            || true,
+            || false,
+        )
+        .unwrap();
+        assert_eq!(calls, 4);
+    }
+
+    #[test]
+    fn poll_prop_times_out_with_last_value() {
+        let err = poll_prop(
+            "init.svc.zygote",
+            "running",
+            Duration::ZERO,
+            Duration::ZERO,
+            || Ok(Some("restarting".to_string())),
+            || true,
+            || false,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("init.svc.zygote=running"), "{}", err);
+        assert!(err.to_string().contains("\"restarting\""), "{}", err);
+
+        let err = poll_prop(
+            "sys.boot_completed",
+            "1",
+            Duration::from_secs(5),
+            Duration::ZERO,
+            || Ok(None),
+            || false,
+            || false,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("died"), "{}", err);
+    }
+
+    #[test]
+    fn poll_prop_stops_when_interrupted() {
+        let mut polls = 0;
+        let err = poll_prop(
+            "sys.boot_completed",
+            "1",
+            Duration::from_secs(60),
+            Duration::ZERO,
+            || Ok(None),
+            || true,
+            || {
+                polls += 1;
+                polls > 2
+            },
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Interrupted"), "{}", err);
+        assert_eq!(polls, 3);
+    }
+
+    #[test]
+    fn find_init_checks_known_locations() {
+        let root = std::env::temp_dir().join(format!("rad-find-init-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("system/bin")).unwrap();
+
+        assert!(find_init(&root).is_err());
+
+        std::fs::write(root.join("system/bin/init"), b"").unwrap();
+        assert_eq!(find_init(&root).unwrap(), "/system/bin/init");
+
+        std::fs::write(root.join("init"), b"").unwrap();
+        assert_eq!(find_init(&root).unwrap(), "/init");
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn build_init_script_sets_up_mounts() {
+        let mut container = test_container_at(Path::new("/tmp/prefix"))
+            .with_init_command("/system/bin/init".to_string(), vec![]);
+        container.apex_modules = vec![
+            apex::ApexModule {
+                name: "com.android.runtime".to_string(),
+                dir_name: "com.android.runtime.release".to_string(),
+            },
+            apex::ApexModule {
+                name: "com.android.art".to_string(),
+                dir_name: "com.android.art".to_string(),
+            },
+        ];
+
+        let script = container.build_init_script().unwrap();
+
+        assert!(script.contains("mount -t overlay overlay"));
+        assert!(script.contains(
+            "-o 'lowerdir=/tmp/prefix/.mounts/system,upperdir=/tmp/prefix/.overlay/upper,\
+             workdir=/tmp/prefix/.overlay/work"
+        ));
+        assert!(script.contains("    '/tmp/prefix/rootfs'\n"), "overlay target");
+        assert!(script.contains(
+            "mount --bind '/tmp/prefix/rootfs/system/apex/com.android.runtime.release' \
+             '/tmp/prefix/rootfs/apex/com.android.runtime'\n"
+        ));
+        assert!(script.contains(
+            "mount --bind '/tmp/prefix/rootfs/system/apex/com.android.art' \
+             '/tmp/prefix/rootfs/apex/com.android.art'\n"
+        ));
+        assert!(script.contains(
+            "mount --bind '/tmp/prefix/.mounts/vendor' '/tmp/prefix/rootfs/vendor'"
+        ));
+        assert!(script.contains("mount -t proc proc '/tmp/prefix/rootfs/proc'"));
+        assert!(script.contains(&format!("echo {}", READY_MARKER)));
+    }
+
+    #[test]
+    fn build_init_script_execs_custom_init_command() {
+        let container = test_container_at(Path::new("/tmp/prefix")).with_init_command(
+            "/system/bin/sh".to_string(),
+            vec!["-c".to_string(), "echo it's up; sleep 1000".to_string()],
+        );
+
+        // No system image is mounted, so this only works with a custom command
+        let script = container.build_init_script().unwrap();
+
+        assert!(script.contains(
+            r"exec chroot '/tmp/prefix/rootfs' '/system/bin/sh' '-c' 'echo it'\''s up; sleep 1000'"
+        ));
+        // Mount setup is kept
+        assert!(script.contains("mount -t overlay overlay"));
+    }
+
+    #[test]
+    fn with_init_quotes_path_and_args() {
+        let container = test_container_at(Path::new("/tmp/prefix")).with_init(
+            Some("/system/bin/my init".to_string()),
+            vec!["--second-stage".to_string(), "a b;c".to_string()],
+        );
+        assert_eq!(
+            container.init_target().unwrap(),
+            "'/system/bin/my init' '--second-stage' 'a b;c'"
+        );
+
+        // Without a path, init is still probed in the system mount
+        let root = std::env::temp_dir().join(format!("rad-with-init-{}", std::process::id()));
+        let mut mounts = MountPoints::for_prefix(&root);
+        mounts.system_mount = root.join("system-mount");
+        std::fs::create_dir_all(mounts.system_mount.join("system/bin")).unwrap();
+        std::fs::write(mounts.system_mount.join("system/bin/init"), "").unwrap();
+        let container =
+            Container::new(test_images(), mounts).with_init(None, vec!["--second-stage".to_string()]);
+        assert_eq!(
+            container.init_target().unwrap(),
+            "'/system/bin/init' '--second-stage'"
+        );
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn build_init_script_quotes_prefix_paths() {
+        let prefix = Path::new("/tmp/my prefix/$(x)");
+        let container = test_container_at(prefix)
+            .with_init_command("/system/bin/init".to_string(), vec![]);
+
+        let script = container.build_init_script().unwrap();
+
+        assert!(script.contains("mount -t proc proc '/tmp/my prefix/$(x)/rootfs/proc'"));
+        assert!(script.contains("exec chroot '/tmp/my prefix/$(x)/rootfs' '/system/bin/init'"));
+        // Outside of quotes, nothing of the prefix is left for the shell
+        let commands: Vec<&str> = script.lines().filter(|l| !l.starts_with('#')).collect();
+        let unquoted: String = commands.join("\n").split('\'').step_by(2).collect();
+        assert!(!unquoted.contains("$(x)"));
+        assert!(!unquoted.contains("my prefix"));
+
+        // The shell sees the literal path as the mount target
+        let line = script
+            .lines()
+            .find(|l| l.starts_with("mount -t proc proc"))
+            .unwrap()
+            .replace("mount -t proc proc", "printf %s");
+        let output = Command::new("sh").arg("-c").arg(&line).output().unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "/tmp/my prefix/$(x)/rootfs/proc"
+        );
+    }
+
+    #[test]
+    fn init_env_merges_zygote_env_and_path() {
+        let container = test_container_at(Path::new("/tmp/prefix"));
+
+        let env: std::collections::HashMap<_, _> = container.init_env().into_iter().collect();
+
+        for (key, value) in zygote::build_android_env(Path::new("/tmp/prefix/rootfs")) {
+            assert_eq!(env.get(&key), Some(&value), "{}", key);
         }
+        assert_eq!(env[zygote::ANDROID_RUNTIME_ROOT], "/apex/com.android.runtime");
+        assert!(env[zygote::LD_LIBRARY_PATH].contains("/system/lib64"));
+        assert_eq!(env["PATH"], DEFAULT_INIT_ENV[0].1);
+    }
 
-        anyhow::bail!(
-            "fusermount -u failed for {}: {}",
-            mount_point.display(),
-            stderr.trim()
+    #[test]
+    fn inherit_fds_clears_cloexec_and_dups_to_target() {
+        use std::os::fd::AsRawFd;
+        use std::os::unix::process::CommandExt;
+
+        // std creates both ends with FD_CLOEXEC
+        let (_host, guest) = std::os::unix::net::UnixStream::pair().unwrap();
+        let (_host2, guest2) = std::os::unix::net::UnixStream::pair().unwrap();
+        let same = guest2.as_raw_fd();
+        let fds = vec![(guest.as_raw_fd(), 73), (same, same)];
+
+        // The exec'd shell sees both sockets, at the requested numbers
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg(format!("readlink /proc/$$/fd/73 /proc/$$/fd/{}", same));
+        unsafe {
+            cmd.pre_exec(move || inherit_fds(&fds));
+        }
+        let output = cmd.output().unwrap();
+        assert!(output.status.success(), "{:?}", output);
+
+        let link = |fd: RawFd| std::fs::read_link(format!("/proc/self/fd/{}", fd)).unwrap();
+        let expected = format!(
+            "{}\n{}\n",
+            link(guest.as_raw_fd()).display(),
+            link(same).display()
         );
+        assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
     }
 
-    Ok(())
-}
+    #[test]
+    fn with_inherited_fd_sets_wayland_socket() {
+        let container = test_container_at(Path::new("/tmp/prefix")).with_inherited_fd(10, 3);
 
-/// Check if fuse2fs is available on the system
-pub fn check_fuse2fs() -> bool {
-    Command::new("fuse2fs")
-        .arg("--help")
-        .output()
-        .map(|o| o.status.success() || !o.stderr.is_empty())
-        .unwrap_or(false)
-}
+        assert_eq!(container.inherited_fds, vec![(10, 3)]);
+        let env: std::collections::HashMap<_, _> = container.init_env().into_iter().collect();
+        assert_eq!(env["WAYLAND_SOCKET"], "3");
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::image::{ImagePaths, MountPoints};
+    #[test]
+    fn isolated_networking_unshares_the_network_namespace() {
+        let has_net = |container: &Container| {
+            let unshare = container.unshare_command("true");
+            unshare.get_args().any(|arg| arg == "--net")
+        };
+
+        assert!(!has_net(&test_container()));
+        let slirp = test_container().with_networking(NetworkMode::Slirp);
+        assert!(has_net(&slirp));
+        let none = test_container().with_networking(NetworkMode::None);
+        assert!(has_net(&none));
+    }
 
     #[test]
-    fn container_new_not_running() {
-        let images = ImagePaths {
-            system_img: PathBuf::from("/tmp/nonexistent/system.img"),
-            vendor_img: PathBuf::from("/tmp/nonexistent/vendor.img"),
+    fn with_env_overrides_defaults_in_spawned_command() {
+        let container = test_container_at(Path::new("/tmp/prefix")).with_env(vec![
+            ("ANDROID_DATA".to_string(), "/data2".to_string()),
+            ("ANDROID_LOG_TAGS".to_string(), "*:v".to_string()),
+        ]);
+
+        let cmd = container.unshare_command("true");
+        let envs: std::collections::HashMap<_, _> = cmd
+            .get_envs()
+            .map(|(k, v)| (k.to_str().unwrap(), v.unwrap().to_str().unwrap()))
+            .collect();
+
+        assert_eq!(envs["ANDROID_DATA"], "/data2");
+        assert_eq!(envs["ANDROID_LOG_TAGS"], "*:v");
+        assert_eq!(envs["ANDROID_ROOT"], "/system");
+        assert!(envs["PATH"].starts_with("/system/bin"));
+    }
+
+    #[test]
+    fn overlay_mount_options_for_flag_combinations() {
+        let (lower, upper, work) = (Path::new("/l"), Path::new("/u"), Path::new("/w"));
+        let base = "lowerdir=/l,upperdir=/u,workdir=/w";
+
+        assert_eq!(
+            OverlayOpts::default().mount_options(lower, upper, work),
+            base
+        );
+        let metacopy = OverlayOpts {
+            metacopy: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            metacopy.mount_options(lower, upper, work),
+            format!("{base},metacopy=on")
+        );
+        let all = OverlayOpts {
+            metacopy: true,
+            redirect_dir: true,
+            index: true,
         };
-        let mounts = MountPoints::for_prefix(Path::new("/tmp/nonexistent-prefix"));
-        let container = Container::new(images, mounts);
+        assert_eq!(
+            all.mount_options(lower, upper, work),
+            format!("{base},metacopy=on,redirect_dir=on,index=on")
+        );
+    }
 
-        assert!(!container.is_running());
-        assert!(container.init_pid.is_none());
+    #[test]
+    fn overlay_opts_drop_unsupported_flags() {
+        let all = OverlayOpts {
+            metacopy: true,
+            redirect_dir: true,
+            index: true,
+        };
+
+        let supported = all.supported_with(|name| name != "metacopy");
+
+        assert_eq!(
+            supported,
+            OverlayOpts {
+                metacopy: false,
+                redirect_dir: true,
+                index: true,
+            }
+        );
+        assert_eq!(all.supported_with(|_| true), all);
     }
 
     #[test]
-    fn container_start_fails_without_images() {
-        let images = ImagePaths {
-            system_img: PathBuf::from("/tmp/nonexistent/system.img"),
-            vendor_img: PathBuf::from("/tmp/nonexistent/vendor.img"),
+    fn logcat_opts_to_args() {
+        let opts = LogcatOpts {
+            buffers: vec![LogcatBuffer::Crash],
+            lines: Some(100),
         };
-        let mounts = MountPoints::for_prefix(Path::new("/tmp/nonexistent-prefix"));
-        let mut container = Container::new(images, mounts);
 
-        let result = container.start();
-        assert!(result.is_err());
+        assert_eq!(opts.to_args(false), vec!["-b", "crash", "-t", "100"]);
+        assert_eq!(opts.to_args(true), vec!["-d", "-b", "crash", "-t", "100"]);
+    }
+
+    #[test]
+    fn logcat_opts_default_is_plain_dump() {
+        assert_eq!(LogcatOpts::default().to_args(true), vec!["-d"]);
+    }
+
+    #[test]
+    fn logcat_fails_when_not_running() {
+        let container = test_container();
+
+        assert!(container.logcat(LogcatOpts::default()).is_err());
+    }
+
+    #[test]
+    fn poll_service_bounds_a_hanging_probe() {
+        let timeout = Duration::from_millis(300);
+        let start = std::time::Instant::now();
+        let mut probes = Vec::new();
+
+        let err = poll_service(
+            "package",
+            timeout,
+            Duration::ZERO,
+            |remaining| {
+                // A wedged servicemanager: the probe runs until it is killed
+                probes.push(remaining);
+                std::thread::sleep(remaining);
+                Err(CoreError::TimedOut {
+                    command: "service check package".to_string(),
+                    timeout: remaining,
+                }
+                .into())
+            },
+            || true,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Timeout waiting for service 'package'"), "{}", err);
+        assert!(start.elapsed() < timeout * 3, "{:?}", start.elapsed());
+        assert!(probes.iter().all(|probe| *probe <= timeout), "{:?}", probes);
+
+        let mut checks = 0;
+        poll_service(
+            "package",
+            timeout,
+            Duration::ZERO,
+            |_| {
+                checks += 1;
+                Ok(checks == 2)
+            },
+            || true,
+        )
+        .unwrap();
+        assert_eq!(checks, 2);
+    }
+
+    #[test]
+    fn parse_service_check_output() {
+        assert!(parse_service_check("Service package: found\n"));
+        assert!(!parse_service_check("Service package: not found\n"));
+        assert!(!parse_service_check(""));
+    }
+
+    #[test]
+    fn pm_install_args_with_forced_abi() {
+        assert_eq!(
+            pm_install_args(None),
+            vec!["install", "-r", "/data/local/tmp/install.apk"]
+        );
+        assert_eq!(
+            pm_install_args(Some(&Abi::Arm64V8a)),
+            vec!["install", "-r", "--abi", "arm64-v8a", "/data/local/tmp/install.apk"]
+        );
+    }
+
+    #[test]
+    fn apk_set_puts_base_first() {
+        let base = Path::new("/apks/base.apk");
+        let arch = Path::new("/apks/split_config.x86_64.apk");
+        let density = Path::new("/apks/split_config.xxhdpi.apk");
+
+        assert_eq!(
+            order_apk_set(&[arch, base, density]).unwrap(),
+            vec![base, arch, density]
+        );
+        assert_eq!(order_apk_set(&[base]).unwrap(), vec![base]);
+        assert!(order_apk_set(&[arch, density]).is_err());
+        assert!(order_apk_set(&[base, Path::new("/apks/other.apk")]).is_err());
+        assert!(order_apk_set(&[]).is_err());
+    }
+
+    #[test]
+    fn install_session_id_is_parsed() {
+        assert_eq!(
+            parse_install_session("Success: created install session [1073741824]\n"),
+            Some(1073741824)
+        );
+        assert_eq!(parse_install_session("Error: failed\n"), None);
+        assert_eq!(
+            pm_install_create_args(Some(&Abi::X86_64)),
+            vec!["install-create", "-r", "--abi", "x86_64"]
+        );
+    }
+
+    #[test]
+    fn parse_pm_path_lists_apks() {
+        let output = "package:/data/app/~~Xy==/com.example-Ab==/base.apk\n\
+                      package:/data/app/~~Xy==/com.example-Ab==/split_config.x86_64.apk\n";
+
+        assert_eq!(
+            parse_pm_path(output),
+            vec![
+                "/data/app/~~Xy==/com.example-Ab==/base.apk",
+                "/data/app/~~Xy==/com.example-Ab==/split_config.x86_64.apk",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_pm_path_not_installed() {
+        assert!(parse_pm_path("").is_empty());
+        assert!(parse_pm_path("Error: package com.example not found\n").is_empty());
+    }
+
+    #[test]
+    fn adb_enable_commands_set_port_then_restart() {
+        let commands = adb_enable_commands(5555);
+
+        assert_eq!(commands.len(), 3);
+        assert_eq!(commands[0].0, "sh");
+        assert_eq!(
+            commands[0].1,
+            vec![
+                "-c",
+                "[ -x /apex/com.android.adbd/bin/adbd ] || [ -x /system/bin/adbd ]"
+            ]
+        );
+        assert_eq!(
+            commands[1],
+            ("setprop", vec!["service.adb.tcp.port".to_string(), "5555".to_string()])
+        );
+        assert_eq!(
+            commands[2],
+            ("setprop", vec!["ctl.restart".to_string(), "adbd".to_string()])
+        );
+    }
+
+    #[test]
+    fn with_display_generates_density_and_size_props() {
+        let container = test_container()
+            .with_display(720, 1280, 320)
+            .with_display(1080, 2340, 440);
+
+        assert_eq!(
+            local_prop_content(&container.boot_props),
+            format!(
+                "{}\nro.sf.lcd_density=440\npersist.waydroid.width=1080\npersist.waydroid.height=2340\n",
+                LOCAL_PROP_HEADER
+            )
+        );
+    }
+
+    #[test]
+    fn upper_fs_verdicts_for_known_magics() {
+        // ext4, xfs, btrfs
+        assert_eq!(unsupported_upper_fs(0xef53), None);
+        assert_eq!(unsupported_upper_fs(0x5846_5342), None);
+        assert_eq!(unsupported_upper_fs(0x9123_683e), None);
+
+        assert!(unsupported_upper_fs(0x0102_1994).unwrap().starts_with("tmpfs"));
+        assert!(unsupported_upper_fs(0x794c_7630).unwrap().starts_with("overlayfs"));
+        assert_eq!(unsupported_upper_fs(0x6969), Some("NFS"));
+        // CIFS magic as a sign-extended c_long
+        assert_eq!(unsupported_upper_fs(0xff53_4d42_u32 as i32 as i64), Some("CIFS"));
+    }
+
+    #[test]
+    fn force_stop_args_for_package() {
+        assert_eq!(
+            force_stop_args("org.fdroid.fdroid"),
+            ["force-stop", "org.fdroid.fdroid"]
+        );
+    }
+
+    #[test]
+    fn parse_ps_line_matches_package() {
+        assert_eq!(parse_ps_line("  1234 org.fdroid.fdroid", "org.fdroid.fdroid"), Some(1234));
+        assert_eq!(
+            parse_ps_line("1240 org.fdroid.fdroid:remote", "org.fdroid.fdroid"),
+            Some(1240)
+        );
+        assert_eq!(parse_ps_line("1250 org.fdroid.fdroidx", "org.fdroid.fdroid"), None);
+        assert_eq!(parse_ps_line("  PID NAME", "org.fdroid.fdroid"), None);
+        assert_eq!(parse_ps_line("", "org.fdroid.fdroid"), None);
     }
 
     #[test]
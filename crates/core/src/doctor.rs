@@ -1,30 +1,73 @@
 use std::path::Path;
 
+use crate::image::{ChecksumManifest, ImagePaths};
+
 pub struct DoctorIssue {
     pub name: String,
     pub status: bool,
     pub description: String,
     pub fix: Option<String>,
+    /// Shell command that applies the fix, for users to copy-paste
+    pub fix_command: Option<String>,
 }
 
 pub fn run_doctor() -> Vec<DoctorIssue> {
     vec![
         // Check Binder
         check_binder(),
+        // Check binderfs works without root
+        check_binder_userns(),
         // Check Namespaces
         check_namespaces(),
+        // Check AppArmor/Yama userns restrictions
+        check_userns_restrictions(),
         // Check Cgroups v2
         check_cgroups_v2(),
         // Check OverlayFS
         check_overlayfs(),
+        // Check ARM binaries can run on a non-ARM host
+        check_binfmt(),
+        // Check slirp4netns is available for isolated networking
+        check_slirp4netns(),
     ]
 }
 
-fn check_overlayfs() -> DoctorIssue {
-    let status = Path::new("/proc/filesystems").exists() && {
-        let content = std::fs::read_to_string("/proc/filesystems").unwrap_or_default();
-        content.contains("overlay")
+/// Compare the images with the checksums setup recorded; `None` without a
+/// checksum manifest. Hashes gigabytes, so it isn't part of [`run_doctor`].
+pub fn check_image_checksums(paths: &ImagePaths) -> Option<DoctorIssue> {
+    let manifest = match ChecksumManifest::load(&paths.checksum_manifest_path()) {
+        Ok(Some(manifest)) => manifest,
+        Ok(None) => return None,
+        Err(e) => return Some(image_checksum_issue(Err(e))),
     };
+    Some(image_checksum_issue(paths.verify_checksums(&manifest)))
+}
+
+fn image_checksum_issue(result: anyhow::Result<()>) -> DoctorIssue {
+    let status = result.is_ok();
+    DoctorIssue {
+        name: "Image checksums".to_string(),
+        status,
+        description: match result {
+            Ok(()) => "system.img and vendor.img match the checksums recorded by setup.".to_string(),
+            Err(e) => format!("{:#}", e),
+        },
+        fix: (!status).then(|| "Delete the cached images and re-run 'run-android-app setup'.".to_string()),
+        fix_command: None,
+    }
+}
+
+fn check_overlayfs() -> DoctorIssue {
+    check_overlayfs_with(|path| std::fs::read_to_string(path))
+}
+
+fn check_overlayfs_with<ReadFn>(read_to_string: ReadFn) -> DoctorIssue
+where
+    ReadFn: Fn(&str) -> std::io::Result<String>,
+{
+    let status = read_to_string("/proc/filesystems")
+        .map(|content| content.contains("overlay"))
+        .unwrap_or(false);
 
     DoctorIssue {
         name: "OverlayFS".to_string(),
@@ -35,10 +78,11 @@ fn check_overlayfs() -> DoctorIssue {
             "OverlayFS is NOT supported. Useful for efficient prefix management.".to_string()
         },
         fix: if !status {
-            Some("Ensure overlay module is loaded (`modprobe overlay`).".to_string())
+            Some("Ensure the overlay kernel module is loaded.".to_string())
         } else {
             None
         },
+        fix_command: (!status).then(|| "modprobe overlay".to_string()),
     }
 }
 
@@ -57,7 +101,15 @@ fn check_binder() -> DoctorIssue {
     };
 
     if status.kernel_support && !status.control_exists {
-        description.push_str(" However, /dev/binderfs/binder-control is missing.");
+        description.push_str(&format!(
+            " However, {} is missing.",
+            status.control_path.display()
+        ));
+    } else if let Some(mount_point) = &status.mount_point {
+        description.push_str(&format!(" Mounted at {}.", mount_point.display()));
+        if !status.devices_exist {
+            description.push_str(" Some binder devices are missing (binder, hwbinder, vndbinder).");
+        }
     }
     
     DoctorIssue {
@@ -67,32 +119,228 @@ fn check_binder() -> DoctorIssue {
         fix: if !ok {
             Some("Ensure CONFIG_ANDROID_BINDERFS=y or CONFIG_ANDROID_BINDER_IPC=y in kernel config.".to_string())
         } else if status.kernel_support && !status.control_exists {
-            Some("Mount binderfs: `mkdir /dev/binderfs && mount -t binder binder /dev/binderfs`".to_string())
+            Some("Mount binderfs at /dev/binderfs.".to_string())
+        } else {
+            None
+        },
+        fix_command: if ok && status.kernel_support && !status.control_exists {
+            Some("mkdir -p /dev/binderfs && mount -t binder binder /dev/binderfs".to_string())
         } else {
             None
         },
     }
 }
 
+fn check_binder_userns() -> DoctorIssue {
+    binder_userns_issue(sandbox::doctor::check_binderfs_in_userns())
+}
+
+fn binder_userns_issue(result: sandbox::doctor::CheckResult) -> DoctorIssue {
+    use sandbox::doctor::CheckResult;
+
+    // Without binderfs at all, check_binder already reports the problem
+    let status = !matches!(result, CheckResult::Error(_));
+    let description = match &result {
+        CheckResult::Available => {
+            "Binderfs can be mounted inside an unprivileged user namespace.".to_string()
+        }
+        CheckResult::Disabled => {
+            "Binderfs is not available, skipped the rootless mount probe.".to_string()
+        }
+        CheckResult::Error(reason) => format!(
+            "Binder is present but binderfs can't be mounted rootlessly: {}",
+            reason
+        ),
+    };
+
+    DoctorIssue {
+        name: "Rootless binderfs".to_string(),
+        status,
+        description,
+        fix: (!status).then(|| {
+            "Use a kernel that allows binderfs in user namespaces (Linux 5.x+ with CONFIG_ANDROID_BINDERFS=y), or check that AppArmor/SELinux isn't blocking the mount.".to_string()
+        }),
+        fix_command: None,
+    }
+}
+
+fn check_binfmt() -> DoctorIssue {
+    use crate::binfmt;
+
+    let machine = apk::EM_AARCH64;
+    let handlers = binfmt::handlers_for(Path::new(binfmt::BINFMT_MISC_DIR), machine)
+        .unwrap_or_default();
+    binfmt_issue(
+        binfmt::native_machines().contains(&machine),
+        handlers.first().map(|h| h.interpreter.as_path()),
+    )
+}
+
+/// Only informational: x86 images run fine without emulation, so a missing
+/// handler doesn't fail the doctor (starting an ARM image reports it instead)
+fn binfmt_issue(native: bool, interpreter: Option<&Path>) -> DoctorIssue {
+    let description = match (native, interpreter) {
+        (true, _) => "Host runs ARM binaries natively.".to_string(),
+        (false, Some(interpreter)) => format!(
+            "ARM binaries run through binfmt_misc ({}).",
+            interpreter.display()
+        ),
+        (false, None) => format!(
+            "No binfmt_misc handler for aarch64: only x86 images can boot. {}",
+            crate::binfmt::REGISTER_HINT
+        ),
+    };
+
+    DoctorIssue {
+        name: "ARM emulation".to_string(),
+        status: true,
+        description,
+        fix: None,
+        fix_command: None,
+    }
+}
+
+fn check_slirp4netns() -> DoctorIssue {
+    slirp4netns_issue(crate::net::find_slirp4netns().as_deref())
+}
+
+/// Only informational: containers use the host network unless slirp
+/// networking is asked for, which reports a missing binary itself
+fn slirp4netns_issue(binary: Option<&Path>) -> DoctorIssue {
+    DoctorIssue {
+        name: "slirp4netns".to_string(),
+        status: true,
+        description: match binary {
+            Some(binary) => format!(
+                "{} is installed: containers can use an isolated NAT'd network.",
+                binary.display()
+            ),
+            None => "slirp4netns is not installed: only host networking is available.".to_string(),
+        },
+        fix: binary
+            .is_none()
+            .then(|| "Install slirp4netns to give containers their own network namespace.".to_string()),
+        fix_command: binary
+            .is_none()
+            .then(|| "sudo apt install slirp4netns".to_string()),
+    }
+}
+
 fn check_namespaces() -> DoctorIssue {
-    let user_ns = Path::new("/proc/self/ns/user").exists();
-    
+    check_namespaces_with(
+        |path| Path::new(path).exists(),
+        |path| std::fs::read_to_string(path),
+    )
+}
+
+fn check_namespaces_with<ExistsFn, ReadFn>(exists: ExistsFn, read_to_string: ReadFn) -> DoctorIssue
+where
+    ExistsFn: Fn(&str) -> bool,
+    ReadFn: Fn(&str) -> std::io::Result<String>,
+{
+    const USERNS_CLONE_SYSCTL: &str = "/proc/sys/kernel/unprivileged_userns_clone";
+
+    let user_ns = exists("/proc/self/ns/user");
+    // Debian/Arch-style knob; absent on kernels that always allow it
+    let clone_disabled = read_to_string(USERNS_CLONE_SYSCTL)
+        .map(|content| content.trim() == "0")
+        .unwrap_or(false);
+    let status = user_ns && !clone_disabled;
+
     DoctorIssue {
         name: "User Namespaces".to_string(),
-        status: user_ns,
-        description: if user_ns {
+        status,
+        description: if status {
             "User namespaces are supported by the kernel.".to_string()
+        } else if user_ns {
+            "Unprivileged user namespaces are disabled (unprivileged_userns_clone = 0). Required for rootless execution.".to_string()
         } else {
             "User namespaces are NOT supported or disabled. Required for rootless execution.".to_string()
         },
         fix: if !user_ns {
             Some("Enable user namespaces in your kernel config (CONFIG_USER_NS=y).".to_string())
+        } else if clone_disabled {
+            Some("Allow unprivileged user namespaces via sysctl.".to_string())
+        } else {
+            None
+        },
+        fix_command: if user_ns && clone_disabled {
+            Some("sysctl -w kernel.unprivileged_userns_clone=1".to_string())
         } else {
             None
         },
     }
 }
 
+fn check_userns_restrictions() -> DoctorIssue {
+    check_userns_restrictions_with(|path| std::fs::read_to_string(path))
+}
+
+/// AppArmor (Ubuntu 23.10+) can block unprivileged user namespaces even with
+/// CONFIG_USER_NS=y, and Yama's ptrace_scope >= 2 prevents attaching to
+/// container processes for debugging.
+fn check_userns_restrictions_with<ReadFn>(read_to_string: ReadFn) -> DoctorIssue
+where
+    ReadFn: Fn(&str) -> std::io::Result<String>,
+{
+    const APPARMOR_SYSCTL: &str = "/proc/sys/kernel/apparmor_restrict_unprivileged_userns";
+    const PTRACE_SCOPE_SYSCTL: &str = "/proc/sys/kernel/yama/ptrace_scope";
+
+    let read_value = |path| {
+        read_to_string(path)
+            .ok()
+            .and_then(|content| content.trim().parse::<u32>().ok())
+    };
+
+    let apparmor_restricted = read_value(APPARMOR_SYSCTL).is_some_and(|v| v != 0);
+    let ptrace_scope = read_value(PTRACE_SCOPE_SYSCTL).unwrap_or(0);
+
+    let mut restrictions = Vec::new();
+    if apparmor_restricted {
+        restrictions.push(
+            "AppArmor restricts unprivileged user namespaces (apparmor_restrict_unprivileged_userns = 1)"
+                .to_string(),
+        );
+    }
+    if ptrace_scope >= 2 {
+        restrictions.push(format!(
+            "Yama ptrace_scope = {} blocks attaching to container processes",
+            ptrace_scope
+        ));
+    }
+    let status = restrictions.is_empty();
+
+    let fix = if apparmor_restricted {
+        Some("Relax the AppArmor user namespace restriction (or add an AppArmor profile allowing userns for run-android-app).".to_string())
+    } else if ptrace_scope == 3 {
+        Some("ptrace_scope = 3 cannot be lowered at runtime; set kernel.yama.ptrace_scope=1 in /etc/sysctl.d and reboot.".to_string())
+    } else if ptrace_scope == 2 {
+        Some("Lower Yama ptrace_scope to 1.".to_string())
+    } else {
+        None
+    };
+
+    let fix_command = if apparmor_restricted {
+        Some("sysctl -w kernel.apparmor_restrict_unprivileged_userns=0".to_string())
+    } else if ptrace_scope == 2 {
+        Some("sysctl -w kernel.yama.ptrace_scope=1".to_string())
+    } else {
+        None
+    };
+
+    DoctorIssue {
+        name: "Userns Restrictions".to_string(),
+        status,
+        description: if status {
+            "No AppArmor/Yama restrictions on unprivileged user namespaces.".to_string()
+        } else {
+            format!("{}.", restrictions.join("; "))
+        },
+        fix,
+        fix_command,
+    }
+}
+
 fn check_cgroups_v2() -> DoctorIssue {
     check_cgroups_v2_with(
         |path| Path::new(path).exists(),
@@ -141,6 +389,7 @@ where
         } else {
             None
         },
+        fix_command: None,
     }
 }
 
@@ -153,7 +402,37 @@ fn parse_cgroup_controllers(content: &str) -> Vec<String> {
 
 #[cfg(test)]
 mod tests {
-    use super::{check_cgroups_v2_with, parse_cgroup_controllers, run_doctor};
+    use super::{
+        binder_userns_issue, check_cgroups_v2_with, check_namespaces_with, check_overlayfs_with,
+        check_userns_restrictions_with, image_checksum_issue, parse_cgroup_controllers, run_doctor,
+        slirp4netns_issue,
+    };
+    use std::io;
+    use std::path::Path;
+
+    #[test]
+    fn image_checksum_mismatch_is_reported() {
+        let issue = image_checksum_issue(Err(anyhow::anyhow!("vendor.img: expected 00, got ff")));
+        assert!(!issue.status);
+        assert!(issue.description.contains("vendor.img"));
+        assert!(issue.fix.is_some());
+
+        assert!(image_checksum_issue(Ok(())).status);
+    }
+
+    #[test]
+    fn binder_userns_failure_is_reported() {
+        use sandbox::doctor::CheckResult;
+
+        let issue = binder_userns_issue(CheckResult::Error("EPERM".to_string()));
+        assert!(!issue.status);
+        assert!(issue.description.contains("EPERM"));
+        assert!(issue.fix.is_some());
+
+        // No binderfs is check_binder's problem, not this one's
+        assert!(binder_userns_issue(CheckResult::Disabled).status);
+        assert!(binder_userns_issue(CheckResult::Available).status);
+    }
 
     #[test]
     fn doctor_reports_cgroups_v2_check() {
@@ -174,6 +453,112 @@ mod tests {
         assert!(issue.fix.is_some());
     }
 
+    #[test]
+    fn overlayfs_check_suggests_modprobe() {
+        let issue = check_overlayfs_with(|_| Ok("nodev\tproc\nnodev\ttmpfs\n".to_string()));
+
+        assert!(!issue.status);
+        assert!(issue.fix.is_some());
+        assert_eq!(issue.fix_command.as_deref(), Some("modprobe overlay"));
+    }
+
+    #[test]
+    fn overlayfs_check_passes_without_fix_command() {
+        let issue = check_overlayfs_with(|_| Ok("nodev\toverlay\n".to_string()));
+
+        assert!(issue.status);
+        assert!(issue.fix_command.is_none());
+    }
+
+    #[test]
+    fn namespaces_check_suggests_sysctl_when_userns_clone_disabled() {
+        let issue = check_namespaces_with(|_| true, |_| Ok("0\n".to_string()));
+
+        assert!(!issue.status);
+        assert_eq!(
+            issue.fix_command.as_deref(),
+            Some("sysctl -w kernel.unprivileged_userns_clone=1")
+        );
+    }
+
+    #[test]
+    fn namespaces_check_passes_when_sysctl_missing() {
+        let issue = check_namespaces_with(
+            |_| true,
+            |_| Err(io::Error::from(io::ErrorKind::NotFound)),
+        );
+
+        assert!(issue.status);
+        assert!(issue.fix_command.is_none());
+    }
+
+    #[test]
+    fn userns_restrictions_detects_apparmor() {
+        let issue = check_userns_restrictions_with(|path| {
+            if path.ends_with("apparmor_restrict_unprivileged_userns") {
+                Ok("1\n".to_string())
+            } else {
+                Ok("0\n".to_string())
+            }
+        });
+
+        assert!(!issue.status);
+        assert!(issue.description.contains("AppArmor"));
+        assert_eq!(
+            issue.fix_command.as_deref(),
+            Some("sysctl -w kernel.apparmor_restrict_unprivileged_userns=0")
+        );
+    }
+
+    #[test]
+    fn userns_restrictions_interprets_ptrace_scope() {
+        let with_scope = |scope: &'static str| {
+            move |path: &str| {
+                if path.ends_with("ptrace_scope") {
+                    Ok(scope.to_string())
+                } else {
+                    Err(io::Error::from(io::ErrorKind::NotFound))
+                }
+            }
+        };
+
+        assert!(check_userns_restrictions_with(with_scope("1\n")).status);
+
+        let issue = check_userns_restrictions_with(with_scope("2\n"));
+        assert!(!issue.status);
+        assert_eq!(
+            issue.fix_command.as_deref(),
+            Some("sysctl -w kernel.yama.ptrace_scope=1")
+        );
+
+        // Scope 3 is sticky until reboot, so there is no command to paste
+        let issue = check_userns_restrictions_with(with_scope("3\n"));
+        assert!(!issue.status);
+        assert!(issue.fix.is_some());
+        assert!(issue.fix_command.is_none());
+    }
+
+    #[test]
+    fn userns_restrictions_pass_when_sysctls_missing() {
+        let issue =
+            check_userns_restrictions_with(|_| Err(io::Error::from(io::ErrorKind::NotFound)));
+
+        assert!(issue.status);
+        assert!(issue.fix.is_none());
+    }
+
+    #[test]
+    fn slirp4netns_check_suggests_install_when_missing() {
+        let missing = slirp4netns_issue(None);
+        assert!(missing.status);
+        assert!(missing.description.contains("not installed"));
+        assert_eq!(missing.fix_command.as_deref(), Some("sudo apt install slirp4netns"));
+
+        let found = slirp4netns_issue(Some(Path::new("/usr/bin/slirp4netns")));
+        assert!(found.description.contains("/usr/bin/slirp4netns"));
+        assert!(found.fix.is_none());
+    }
+
     #[test]
     fn parse_cgroup_controllers_splits_whitespace() {
         let controllers = parse_cgroup_controllers("cpu memory\nio  pids\n");
@@ -0,0 +1,138 @@
+//! Typed errors for failures that deserve specific remediation or handling.
+//!
+//! Most of core reports errors through `anyhow`; these are attached to the
+//! chain where a known cause was recognized, so callers can
+//! `downcast_ref::<CoreError>()` and the user gets more than raw stderr.
+
+/// A recognized cause of a container failing to start, or of a command in
+/// it failing
+///
+/// `Display` and `Error` are implemented by hand: `thiserror`'s derive
+/// expands to `::core::…` paths, which resolve to this crate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoreError {
+    UserNamespacesDisabled,
+
+    OverlayInUserNsUnsupported,
+
+    InitMissing,
+
+    /// `Container::exec_command_timeout` killed a command that ran too long
+    TimedOut {
+        command: String,
+        timeout: std::time::Duration,
+    },
+}
+
+impl std::fmt::Display for CoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CoreError::UserNamespacesDisabled => write!(
+                f,
+                "unprivileged user namespaces are disabled. Enable them with \
+                 `sysctl kernel.unprivileged_userns_clone=1` (Debian), \
+                 `sysctl user.max_user_namespaces=15000`, or on Ubuntu 24.04+ \
+                 `sysctl kernel.apparmor_restrict_unprivileged_userns=0`"
+            ),
+            CoreError::OverlayInUserNsUnsupported => write!(
+                f,
+                "the kernel refused to mount overlayfs inside a user namespace. \
+                 Rootless overlay mounts need Linux 5.11 or newer (`uname -r`); \
+                 some distros also disable them via a sysctl or LSM policy"
+            ),
+            CoreError::InitMissing => write!(
+                f,
+                "init was not found in the rootfs. The system image may be \
+                 incomplete or failed to mount; check the prefix's .mounts/system"
+            ),
+            CoreError::TimedOut { command, timeout } => {
+                write!(f, "`{}` in the container timed out after {:?}", command, timeout)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CoreError {}
+
+impl CoreError {
+    /// Recognize a known failure from the stderr of a container that died
+    /// during setup
+    pub fn classify_startup_failure(stderr: &str) -> Option<Self> {
+        let stderr = stderr.to_lowercase();
+        let denied = |line: &str| {
+            line.contains("operation not permitted")
+                || line.contains("permission denied")
+                || line.contains("must be superuser")
+        };
+
+        for line in stderr.lines() {
+            if line.starts_with("unshare:") && denied(line) {
+                return Some(Self::UserNamespacesDisabled);
+            }
+        }
+        for line in stderr.lines() {
+            if line.starts_with("mount:") && denied(line) {
+                return Some(Self::OverlayInUserNsUnsupported);
+            }
+            if line.starts_with("chroot:") && line.contains("no such file") {
+                return Some(Self::InitMissing);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_unshare_denied() {
+        let stderr = "unshare: unshare failed: Operation not permitted\n";
+        assert_eq!(
+            CoreError::classify_startup_failure(stderr),
+            Some(CoreError::UserNamespacesDisabled)
+        );
+        // Even if later commands complain too
+        let stderr = "unshare: write failed /proc/self/uid_map: Operation not permitted\n\
+                      mount: permission denied (are you root?)\n";
+        assert_eq!(
+            CoreError::classify_startup_failure(stderr),
+            Some(CoreError::UserNamespacesDisabled)
+        );
+    }
+
+    #[test]
+    fn classifies_overlay_mount_denied() {
+        for stderr in [
+            "mount: permission denied (are you root?)\n",
+            "mount: /tmp/prefix/rootfs: permission denied.\n",
+            "mount: /tmp/prefix/rootfs: must be superuser to use mount.\n",
+        ] {
+            assert_eq!(
+                CoreError::classify_startup_failure(stderr),
+                Some(CoreError::OverlayInUserNsUnsupported),
+                "{}",
+                stderr
+            );
+        }
+    }
+
+    #[test]
+    fn classifies_missing_init() {
+        let stderr = "chroot: failed to run command '/init': No such file or directory\n";
+        assert_eq!(
+            CoreError::classify_startup_failure(stderr),
+            Some(CoreError::InitMissing)
+        );
+    }
+
+    #[test]
+    fn unknown_failures_are_not_classified() {
+        assert_eq!(CoreError::classify_startup_failure(""), None);
+        assert_eq!(
+            CoreError::classify_startup_failure("mknod: /dev/null: File exists\n"),
+            None
+        );
+    }
+}
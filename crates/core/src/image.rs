@@ -3,14 +3,228 @@
 //! Handles locating, validating, and mounting system.img/vendor.img
 //! from the RAD data directory (~/.local/share/rad/).
 
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
-use tracing::info;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
 
-/// Default data directory for RAD images
-const RAD_DATA_DIR: &str = ".local/share/rad";
+/// Overrides the image cache directory
+pub const IMAGE_DIR_ENV: &str = "RAD_IMAGE_DIR";
+
+/// Data directory under `$XDG_DATA_HOME`
+const RAD_DATA_SUBDIR: &str = "rad";
+/// `$XDG_DATA_HOME` default, relative to `$HOME`
+const XDG_DATA_HOME_DEFAULT: &str = ".local/share";
 const CACHE_SUBDIR: &str = "cache";
+const IMAGES_SUBDIR: &str = "images";
+
+const MB: u64 = 1024 * 1024;
+
+/// A LineageOS system.img smaller than this is probably not a full image
+const MIN_SYSTEM_IMAGE_BYTES: u64 = 500 * MB;
+
+/// The ext4 superblock starts 1024 bytes into the filesystem
+const EXT4_SUPERBLOCK_OFFSET: u64 = 1024;
+/// `s_magic`, at 0x38 in the superblock (0x438 in the image)
+const EXT4_MAGIC: u16 = 0xEF53;
+/// `INCOMPAT_64BIT`: the block count has a high 32-bit half
+const EXT4_FEATURE_INCOMPAT_64BIT: u32 = 0x80;
+
+/// Android sparse image header magic
+const SPARSE_HEADER_MAGIC: u32 = 0xED26_FF3A;
+/// Sparse chunk holding raw data
+const SPARSE_CHUNK_RAW: u16 = 0xCAC1;
+
+/// `sha256sum` output written next to the images by setup
+pub const CHECKSUMS_FILE: &str = "checksums.txt";
+
+/// Expected SHA-256 digests of the images, by file name
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChecksumManifest {
+    digests: BTreeMap<String, String>,
+}
+
+impl ChecksumManifest {
+    /// Parse `sha256sum` output (`<hex>  <name>`, or `<hex> *<name>` in
+    /// binary mode); blank lines and `#` comments are ignored
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut digests = BTreeMap::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (digest, name) = line
+                .split_once(char::is_whitespace)
+                .with_context(|| format!("Malformed checksum line {}: {}", number + 1, line))?;
+            if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+                anyhow::bail!("Malformed SHA-256 digest on line {}: {}", number + 1, digest);
+            }
+            let name = name.trim_start();
+            let name = name.strip_prefix('*').unwrap_or(name);
+            // Only the file name matters, the images are looked up next to it
+            let name = Path::new(name)
+                .file_name()
+                .with_context(|| format!("Malformed checksum line {}: {}", number + 1, line))?;
+            digests.insert(name.to_string_lossy().into_owned(), digest.to_lowercase());
+        }
+        Ok(Self { digests })
+    }
+
+    /// Read a manifest, `None` if the file doesn't exist
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content)
+                .with_context(|| format!("Invalid checksum file {}", path.display()))
+                .map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Expected lowercase hex digest of `file_name`
+    pub fn get(&self, file_name: &str) -> Option<&str> {
+        self.digests.get(file_name).map(String::as_str)
+    }
+}
+
+/// Lowercase hex SHA-256 of a file, read in chunks
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Compressed copy of an image (`system.img` -> `system.img.xz`)
+fn xz_path(image: &Path) -> PathBuf {
+    let mut path = image.as_os_str().to_owned();
+    path.push(".xz");
+    PathBuf::from(path)
+}
+
+/// Stream-decompress an `.xz` file to `output`
+///
+/// Writes to a `.partial` file renamed into place when done, so an
+/// interrupted run never leaves a truncated image behind. Memory use is
+/// bounded by the xz dictionary size, not the image size.
+pub fn decompress_xz(input: &Path, output: &Path) -> Result<()> {
+    let mut partial = output.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+
+    let result = (|| -> Result<()> {
+        let file = std::fs::File::open(input)
+            .with_context(|| format!("Failed to open {}", input.display()))?;
+        let mut reader = std::io::BufReader::with_capacity(1024 * 1024, file);
+        let mut writer = std::io::BufWriter::with_capacity(
+            1024 * 1024,
+            std::fs::File::create(&partial)
+                .with_context(|| format!("Failed to create {}", partial.display()))?,
+        );
+        lzma_rs::xz_decompress(&mut reader, &mut writer)
+            .map_err(|e| anyhow::anyhow!("{} is not a valid xz file: {}", input.display(), e))?;
+        std::io::Write::flush(&mut writer)?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e);
+    }
+    std::fs::rename(&partial, output)
+        .with_context(|| format!("Failed to move {} into place", output.display()))
+}
+
+/// Expected sizes of one image fetched by `setup`
+#[derive(Debug, Clone, Copy)]
+pub struct ManifestEntry {
+    /// Image name ("system" -> system.zip, system.img, images/system/)
+    pub name: &'static str,
+    /// Size of the downloaded zip
+    pub download_bytes: u64,
+    /// Size of the .img inside the zip (also used for the extracted tree)
+    pub image_bytes: u64,
+}
+
+/// Images downloaded by scripts/setup-image.sh, with rounded-up sizes
+pub const SETUP_MANIFEST: &[ManifestEntry] = &[
+    ManifestEntry {
+        name: "system",
+        download_bytes: 900 * MB,
+        image_bytes: 3072 * MB,
+    },
+    ManifestEntry {
+        name: "vendor",
+        download_bytes: 200 * MB,
+        image_bytes: 512 * MB,
+    },
+];
+
+/// Default cache directory holding the downloaded zips and .img files
+///
+/// In order of precedence:
+/// 1. `$RAD_IMAGE_DIR`, used as is (e.g. a shared read-only volume)
+/// 2. `$XDG_DATA_HOME/rad/cache`
+/// 3. `$HOME/.local/share/rad/cache`
+///
+/// Empty variables count as unset, as does a relative `$XDG_DATA_HOME`
+/// (which the XDG spec says to ignore).
+pub fn default_cache_dir() -> Result<PathBuf> {
+    cache_dir_from(
+        std::env::var_os(IMAGE_DIR_ENV),
+        std::env::var_os("XDG_DATA_HOME"),
+        std::env::var_os("HOME"),
+    )
+}
+
+fn cache_dir_from(
+    image_dir: Option<OsString>,
+    xdg_data_home: Option<OsString>,
+    home: Option<OsString>,
+) -> Result<PathBuf> {
+    if let Some(dir) = image_dir.filter(|v| !v.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    Ok(data_dir_from(xdg_data_home, home)?.join(CACHE_SUBDIR))
+}
+
+/// The RAD data directory: `$XDG_DATA_HOME/rad`, or `$HOME/.local/share/rad`
+///
+/// Empty variables count as unset, as does a relative `$XDG_DATA_HOME`.
+pub fn data_dir() -> Result<PathBuf> {
+    data_dir_from(std::env::var_os("XDG_DATA_HOME"), std::env::var_os("HOME"))
+}
+
+fn data_dir_from(xdg_data_home: Option<OsString>, home: Option<OsString>) -> Result<PathBuf> {
+    let set = |var: Option<OsString>| var.filter(|v| !v.is_empty()).map(PathBuf::from);
+
+    let data_home = match set(xdg_data_home).filter(|p| p.is_absolute()) {
+        Some(dir) => dir,
+        None => set(home)
+            .context("HOME environment variable not set")?
+            .join(XDG_DATA_HOME_DEFAULT),
+    };
+    Ok(data_home.join(RAD_DATA_SUBDIR))
+}
 
 /// Paths to the Waydroid container images
 #[derive(Debug, Clone)]
@@ -24,9 +238,7 @@ pub struct ImagePaths {
 impl ImagePaths {
     /// Locate images in the default RAD data directory
     pub fn default_location() -> Result<Self> {
-        let home = std::env::var("HOME").context("HOME environment variable not set")?;
-        let data_dir = PathBuf::from(home).join(RAD_DATA_DIR).join(CACHE_SUBDIR);
-        Self::from_dir(&data_dir)
+        Self::from_dir(&default_cache_dir()?)
     }
 
     /// Locate images in a specific directory
@@ -40,6 +252,90 @@ impl ImagePaths {
         })
     }
 
+    /// The checksum manifest setup writes next to the images
+    pub fn checksum_manifest_path(&self) -> PathBuf {
+        self.system_img
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join(CHECKSUMS_FILE)
+    }
+
+    /// Compare the images' SHA-256 with `manifest`
+    ///
+    /// Images the manifest has no entry for are skipped; all mismatches are
+    /// reported together.
+    pub fn verify_checksums(&self, manifest: &ChecksumManifest) -> Result<()> {
+        let mut mismatches = Vec::new();
+        for image in [&self.system_img, &self.vendor_img] {
+            let name = image
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let Some(expected) = manifest.get(&name) else {
+                continue;
+            };
+            let actual = sha256_file(image)?;
+            if actual != expected {
+                mismatches.push(format!("{}: expected {}, got {}", name, expected, actual));
+            }
+        }
+
+        if !mismatches.is_empty() {
+            anyhow::bail!(
+                "Image checksum mismatch, the images are corrupted:\n  {}\nDelete them and re-run 'run-android-app setup'.",
+                mismatches.join("\n  ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Images with an xz-compressed copy (`system.img.xz`) that is missing or
+    /// newer than the image itself, as (compressed, image) pairs
+    pub fn pending_decompression(&self) -> Vec<(PathBuf, PathBuf)> {
+        [&self.system_img, &self.vendor_img]
+            .into_iter()
+            .filter_map(|image| {
+                let compressed = xz_path(image);
+                let xz_modified = std::fs::metadata(&compressed).ok()?.modified().ok()?;
+                let stale = match std::fs::metadata(image).and_then(|m| m.modified()) {
+                    Ok(image_modified) => image_modified < xz_modified,
+                    Err(_) => true,
+                };
+                stale.then(|| (compressed, image.clone()))
+            })
+            .collect()
+    }
+
+    /// Decompress the images listed by
+    /// [`pending_decompression`](Self::pending_decompression)
+    pub fn decompress_pending(&self) -> Result<()> {
+        for (compressed, image) in self.pending_decompression() {
+            info!("Decompressing {} (first run)...", compressed.display());
+            decompress_xz(&compressed, &image)?;
+        }
+        Ok(())
+    }
+
+    /// Validate the images that are not waiting on decompression, so a pending
+    /// system.img.xz doesn't hide a missing or broken vendor.img
+    pub fn validate_decompressed(&self) -> Result<()> {
+        let pending = self.pending_decompression();
+        for (image, name) in [(&self.system_img, "system.img"), (&self.vendor_img, "vendor.img")] {
+            if pending.iter().any(|(_, p)| p == image) {
+                continue;
+            }
+            if !image.exists() {
+                anyhow::bail!(
+                    "{} not found: {}\nRun 'run-android-app setup' or './scripts/setup-image.sh' to download images.",
+                    name,
+                    image.display()
+                );
+            }
+            check_ext4_image(image, name)?;
+        }
+        Ok(())
+    }
+
     /// Validate that both images exist and are ext4 filesystems
     pub fn validate(&self) -> Result<()> {
         if !self.system_img.exists() {
@@ -56,27 +352,151 @@ impl ImagePaths {
             );
         }
 
-        // Basic size check - system.img should be at least 500MB
+        // The superblock tells truncated downloads and saved error pages apart
+        let system_fs = check_ext4_image(&self.system_img, "system.img")?;
+        let vendor_fs = check_ext4_image(&self.vendor_img, "vendor.img")?;
+
+        // A valid but tiny filesystem is still suspicious
         let system_size = std::fs::metadata(&self.system_img)
             .context("Failed to read system.img metadata")?
             .len();
-        if system_size < 500 * 1024 * 1024 {
-            anyhow::bail!(
-                "System image too small ({} bytes), expected at least 500MB. May be corrupted.",
+        if system_size < MIN_SYSTEM_IMAGE_BYTES {
+            warn!(
+                "system.img is only {} bytes, expected at least 500MB; it may be incomplete",
                 system_size
             );
         }
 
         info!(
-            "Images validated: system.img ({:.0} MB), vendor.img ({:.0} MB)",
+            "Images validated: system.img ({:.0} MB, ext4 {} x {} B blocks), vendor.img ({:.0} MB, ext4 {} x {} B blocks)",
             system_size as f64 / (1024.0 * 1024.0),
-            std::fs::metadata(&self.vendor_img)?.len() as f64 / (1024.0 * 1024.0)
+            system_fs.block_count,
+            system_fs.block_size,
+            std::fs::metadata(&self.vendor_img)?.len() as f64 / (1024.0 * 1024.0),
+            vendor_fs.block_count,
+            vendor_fs.block_size
         );
 
         Ok(())
     }
 }
 
+/// Geometry read from an ext4 superblock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ext4Info {
+    pub block_count: u64,
+    pub block_size: u64,
+}
+
+/// Whether `path` holds an ext4 filesystem (raw, or inside an Android sparse image)
+pub fn is_ext4(path: &Path) -> Result<bool> {
+    Ok(read_ext4_info(path)?.is_some())
+}
+
+/// Read the ext4 superblock of `path`, or None if it isn't ext4
+///
+/// For Android sparse images the superblock is looked up in the first chunk,
+/// which holds the start of the filesystem.
+pub fn read_ext4_info(path: &Path) -> Result<Option<Ext4Info>> {
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let fs_start = if is_sparse_image(&mut file)? {
+        match sparse_first_raw_offset(&mut file)? {
+            Some(offset) => offset,
+            None => return Ok(None),
+        }
+    } else {
+        0
+    };
+
+    let mut superblock = [0u8; 0x100];
+    if !read_at(&mut file, fs_start + EXT4_SUPERBLOCK_OFFSET, &mut superblock)? {
+        return Ok(None);
+    }
+    if u16::from_le_bytes([superblock[0x38], superblock[0x39]]) != EXT4_MAGIC {
+        return Ok(None);
+    }
+
+    let le32 = |at: usize| {
+        u32::from_le_bytes([
+            superblock[at],
+            superblock[at + 1],
+            superblock[at + 2],
+            superblock[at + 3],
+        ])
+    };
+    let mut block_count = le32(0x04) as u64;
+    if le32(0x60) & EXT4_FEATURE_INCOMPAT_64BIT != 0 {
+        block_count |= (le32(0x150) as u64) << 32;
+    }
+    let block_size = 1024u64 << le32(0x18).min(16);
+
+    Ok(Some(Ext4Info {
+        block_count,
+        block_size,
+    }))
+}
+
+/// Fail with a distinct message unless `path` is a raw ext4 image fuse2fs can mount
+fn check_ext4_image(path: &Path, name: &str) -> Result<Ext4Info> {
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    if is_sparse_image(&mut file)? {
+        anyhow::bail!(
+            "{} is an Android sparse image: {}\nConvert it with simg2img or re-run 'run-android-app setup'.",
+            name,
+            path.display()
+        );
+    }
+
+    read_ext4_info(path)?.with_context(|| {
+        format!(
+            "{} is not a valid ext4 filesystem: {}\nRe-run 'run-android-app setup' to download it again.",
+            name,
+            path.display()
+        )
+    })
+}
+
+fn is_sparse_image(file: &mut std::fs::File) -> Result<bool> {
+    let mut magic = [0u8; 4];
+    Ok(read_at(file, 0, &mut magic)? && u32::from_le_bytes(magic) == SPARSE_HEADER_MAGIC)
+}
+
+/// File offset of the data of the first sparse chunk, if it is a raw chunk
+fn sparse_first_raw_offset(file: &mut std::fs::File) -> Result<Option<u64>> {
+    // sparse_header: magic, major, minor, file_hdr_sz (u16 at 8), chunk_hdr_sz (u16 at 10), ...
+    let mut header = [0u8; 28];
+    if !read_at(file, 0, &mut header)? {
+        return Ok(None);
+    }
+    let file_hdr_sz = u16::from_le_bytes([header[8], header[9]]) as u64;
+    let chunk_hdr_sz = u16::from_le_bytes([header[10], header[11]]) as u64;
+
+    // chunk_header: chunk_type (u16), reserved, chunk_sz, total_sz
+    let mut chunk = [0u8; 12];
+    if !read_at(file, file_hdr_sz, &mut chunk)? {
+        return Ok(None);
+    }
+    if u16::from_le_bytes([chunk[0], chunk[1]]) != SPARSE_CHUNK_RAW {
+        return Ok(None);
+    }
+    Ok(Some(file_hdr_sz + chunk_hdr_sz))
+}
+
+/// Fill `buf` from `offset`; false if the file is too short
+fn read_at(file: &mut std::fs::File, offset: u64, buf: &mut [u8]) -> Result<bool> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    file.seek(SeekFrom::Start(offset))?;
+    match file.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
 /// Mount points for the container runtime.
 ///
 /// These are temporary mount points used during container setup.
@@ -94,6 +514,8 @@ pub struct MountPoints {
     pub overlay_upper: PathBuf,
     /// OverlayFS work directory
     pub overlay_work: PathBuf,
+    /// Saved copies of the upper layer, one directory per snapshot
+    pub snapshots: PathBuf,
 }
 
 impl MountPoints {
@@ -105,6 +527,7 @@ impl MountPoints {
             rootfs: prefix_root.join("rootfs"),
             overlay_upper: prefix_root.join(".overlay/upper"),
             overlay_work: prefix_root.join(".overlay/work"),
+            snapshots: prefix_root.join(".snapshots"),
         }
     }
 
@@ -124,6 +547,133 @@ impl MountPoints {
     }
 }
 
+/// Bytes `setup` still has to write into `cache_dir`
+///
+/// Artifacts already present (zip downloaded, .img unzipped, contents
+/// extracted next to the cache) are not counted again.
+pub fn required_setup_bytes(cache_dir: &Path) -> u64 {
+    let images_dir = cache_dir
+        .parent()
+        .unwrap_or(cache_dir)
+        .join(IMAGES_SUBDIR);
+
+    SETUP_MANIFEST
+        .iter()
+        .map(|entry| {
+            let mut bytes = 0;
+            if !cache_dir.join(format!("{}.zip", entry.name)).exists() {
+                bytes += entry.download_bytes;
+            }
+            if !cache_dir.join(format!("{}.img", entry.name)).exists() {
+                bytes += entry.image_bytes;
+            }
+            if !images_dir.join(entry.name).join(".extracted").exists() {
+                bytes += entry.image_bytes;
+            }
+            bytes
+        })
+        .sum()
+}
+
+/// Fail with a readable message if `free_bytes` can't hold `required_bytes`
+pub fn check_free_space(free_bytes: u64, required_bytes: u64) -> Result<()> {
+    if free_bytes < required_bytes {
+        anyhow::bail!(
+            "Not enough free disk space for setup: {} MB required, {} MB available.\nFree up {} MB and run setup again.",
+            required_bytes.div_ceil(MB),
+            free_bytes / MB,
+            (required_bytes - free_bytes).div_ceil(MB)
+        );
+    }
+    Ok(())
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`
+pub fn free_bytes(path: &Path) -> Result<u64> {
+    let stat = nix::sys::statvfs::statvfs(path)
+        .with_context(|| format!("Failed to statvfs {}", path.display()))?;
+    Ok(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+}
+
+/// Remove leftovers of an interrupted setup run from `cache_dir`
+///
+/// Deletes `*.raw.img` files from an aborted sparse conversion, zips that
+/// can't be opened (truncated download) and .img files whose size doesn't
+/// match the entry in their zip (interrupted unzip). Returns what was removed.
+pub fn clean_partial_images(cache_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    if !cache_dir.exists() {
+        return Ok(removed);
+    }
+
+    for entry in std::fs::read_dir(cache_dir)
+        .with_context(|| format!("Failed to read {}", cache_dir.display()))?
+    {
+        let path = entry?.path();
+        if path.to_string_lossy().ends_with(".raw.img") {
+            remove_partial(&path, &mut removed)?;
+        }
+    }
+
+    for entry in SETUP_MANIFEST {
+        let zip_path = cache_dir.join(format!("{}.zip", entry.name));
+        let img_name = format!("{}.img", entry.name);
+        let img_path = cache_dir.join(&img_name);
+
+        if !zip_path.exists() {
+            continue;
+        }
+        let Some(expected) = zip_entry_size(&zip_path, &img_name) else {
+            remove_partial(&zip_path, &mut removed)?;
+            continue;
+        };
+
+        if let Ok(meta) = std::fs::metadata(&img_path)
+            && meta.len() != expected
+        {
+            remove_partial(&img_path, &mut removed)?;
+        }
+    }
+
+    Ok(removed)
+}
+
+fn remove_partial(path: &Path, removed: &mut Vec<PathBuf>) -> Result<()> {
+    warn!("Removing partial file from a previous setup: {}", path.display());
+    std::fs::remove_file(path)
+        .with_context(|| format!("Failed to remove {}", path.display()))?;
+    removed.push(path.to_path_buf());
+    Ok(())
+}
+
+/// Uncompressed size of `name` in the zip at `zip_path`, or None if the zip
+/// is unreadable or doesn't contain it
+fn zip_entry_size(zip_path: &Path, name: &str) -> Option<u64> {
+    let file = std::fs::File::open(zip_path).ok()?;
+    let mut archive = zip::ZipArchive::new(file).ok()?;
+    let entry = archive.by_name(name).ok()?;
+    Some(entry.size())
+}
+
+/// Check that setup can run in `cache_dir`: clean partial files from a previous
+/// run, then make sure the remaining downloads and extraction fit on disk
+pub fn preflight_setup(cache_dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(cache_dir)
+        .with_context(|| format!("Failed to create directory: {}", cache_dir.display()))?;
+
+    clean_partial_images(cache_dir)?;
+
+    let required = required_setup_bytes(cache_dir);
+    let free = free_bytes(cache_dir)?;
+    info!(
+        "Setup needs {} MB, {} MB free in {}",
+        required.div_ceil(MB),
+        free / MB,
+        cache_dir.display()
+    );
+    check_free_space(free, required)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -136,6 +686,66 @@ mod tests {
         assert_eq!(paths.vendor_img, PathBuf::from("/tmp/test-cache/vendor.img"));
     }
 
+    /// Serializes the tests that change the process environment
+    static ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    /// Set (`Some`) or clear (`None`) an environment variable
+    fn set_env(name: &str, value: Option<&str>) {
+        // SAFETY: callers hold ENV_LOCK, and no other test reads these variables
+        unsafe {
+            match value {
+                Some(value) => std::env::set_var(name, value),
+                None => std::env::remove_var(name),
+            }
+        }
+    }
+
+    #[test]
+    fn default_cache_dir_precedence() {
+        let _lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let saved = [IMAGE_DIR_ENV, "XDG_DATA_HOME"].map(|name| (name, std::env::var(name).ok()));
+        let home = PathBuf::from(std::env::var("HOME").unwrap());
+
+        set_env(IMAGE_DIR_ENV, Some("/srv/rad-images"));
+        set_env("XDG_DATA_HOME", Some("/xdg/data"));
+        assert_eq!(default_cache_dir().unwrap(), PathBuf::from("/srv/rad-images"));
+        assert_eq!(
+            ImagePaths::default_location().unwrap().system_img,
+            PathBuf::from("/srv/rad-images/system.img")
+        );
+
+        set_env(IMAGE_DIR_ENV, None);
+        assert_eq!(default_cache_dir().unwrap(), PathBuf::from("/xdg/data/rad/cache"));
+
+        set_env(IMAGE_DIR_ENV, Some(""));
+        set_env("XDG_DATA_HOME", None);
+        assert_eq!(default_cache_dir().unwrap(), home.join(".local/share/rad/cache"));
+
+        for (name, value) in saved {
+            set_env(name, value.as_deref());
+        }
+    }
+
+    #[test]
+    fn cache_dir_ignores_relative_xdg_data_home() {
+        let dir = cache_dir_from(None, Some("relative".into()), Some("/home/u".into())).unwrap();
+        assert_eq!(dir, PathBuf::from("/home/u/.local/share/rad/cache"));
+        assert!(cache_dir_from(None, None, None).is_err());
+    }
+
+    #[test]
+    fn data_dir_follows_xdg_data_home() {
+        let home = Some("/home/u".into());
+        assert_eq!(
+            data_dir_from(Some("/xdg/data".into()), home.clone()).unwrap(),
+            PathBuf::from("/xdg/data/rad")
+        );
+        assert_eq!(
+            data_dir_from(Some("".into()), home).unwrap(),
+            PathBuf::from("/home/u/.local/share/rad")
+        );
+    }
+
     #[test]
     fn validate_fails_when_images_missing() {
         let dir = PathBuf::from("/tmp/nonexistent-rad-images");
@@ -145,6 +755,262 @@ mod tests {
         assert!(result.unwrap_err().to_string().contains("System image not found"));
     }
 
+    fn make_temp_dir(label: &str) -> PathBuf {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("rad-image-{label}-{nanos}"));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_xz(path: &Path, data: &[u8]) {
+        let mut compressed = Vec::new();
+        lzma_rs::xz_compress(&mut &data[..], &mut compressed).unwrap();
+        std::fs::write(path, compressed).unwrap();
+    }
+
+    #[test]
+    fn xz_images_are_decompressed_once() {
+        let dir = make_temp_dir("xz");
+        let paths = ImagePaths::from_dir(&dir).unwrap();
+        let system_xz = dir.join("system.img.xz");
+        write_xz(&system_xz, &ext4_bytes(1000));
+
+        assert_eq!(
+            paths.pending_decompression(),
+            vec![(system_xz.clone(), paths.system_img.clone())]
+        );
+        paths.decompress_pending().unwrap();
+        assert_eq!(std::fs::read(&paths.system_img).unwrap(), ext4_bytes(1000));
+        assert!(!dir.join("system.img.partial").exists());
+        assert!(paths.pending_decompression().is_empty());
+
+        // A newer compressed copy replaces the image
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(&paths.system_img)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        write_xz(&system_xz, &ext4_bytes(2000));
+        paths.decompress_pending().unwrap();
+        assert_eq!(std::fs::read(&paths.system_img).unwrap(), ext4_bytes(2000));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn corrupt_xz_leaves_no_image() {
+        let dir = make_temp_dir("xz-corrupt");
+        let paths = ImagePaths::from_dir(&dir).unwrap();
+        std::fs::write(dir.join("vendor.img.xz"), b"<html>404</html>").unwrap();
+
+        let err = paths.decompress_pending().unwrap_err().to_string();
+        assert!(err.contains("not a valid xz file"), "{}", err);
+        assert!(!paths.vendor_img.exists());
+        assert!(!dir.join("vendor.img.partial").exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn validate_decompressed_skips_only_pending_images() {
+        let dir = make_temp_dir("validate-pending");
+        let paths = ImagePaths::from_dir(&dir).unwrap();
+        write_xz(&dir.join("system.img.xz"), &ext4_bytes(1000));
+
+        let err = paths.validate_decompressed().unwrap_err().to_string();
+        assert!(err.contains("vendor.img not found"), "{}", err);
+
+        std::fs::write(&paths.vendor_img, ext4_bytes(1000)).unwrap();
+        paths.validate_decompressed().unwrap();
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn validate_checks_ext4_magic_before_size() {
+        let dir = make_temp_dir("validate");
+        let paths = ImagePaths::from_dir(&dir).unwrap();
+        std::fs::write(&paths.vendor_img, ext4_bytes(1000)).unwrap();
+
+        std::fs::write(&paths.system_img, b"<html>502 Bad Gateway</html>").unwrap();
+        let err = paths.validate().unwrap_err().to_string();
+        assert!(err.contains("system.img is not a valid ext4 filesystem"), "{}", err);
+
+        // Small but valid only warns
+        std::fs::write(&paths.system_img, ext4_bytes(1000)).unwrap();
+        paths.validate().unwrap();
+
+        std::fs::write(&paths.vendor_img, vec![0u8; 4096]).unwrap();
+        let err = paths.validate().unwrap_err().to_string();
+        assert!(err.contains("vendor.img is not a valid ext4 filesystem"), "{}", err);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn checksum_manifest_parses_sha256sum_output() {
+        let system = "a".repeat(64);
+        let vendor = "B".repeat(64);
+        let content = format!(
+            "# written by setup\n{}  system.img\n\n{} */cache/vendor.img\n",
+            system, vendor
+        );
+        let manifest = ChecksumManifest::parse(&content).unwrap();
+        assert_eq!(manifest.get("system.img"), Some(system.as_str()));
+        assert_eq!(manifest.get("vendor.img"), Some("b".repeat(64).as_str()));
+        assert_eq!(manifest.get("other.img"), None);
+
+        assert!(ChecksumManifest::parse("abc  system.img\n").is_err());
+        assert!(ChecksumManifest::parse(&"a".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn verify_checksums_reports_mismatches() {
+        let dir = make_temp_dir("checksums");
+        let paths = ImagePaths::from_dir(&dir).unwrap();
+        std::fs::write(&paths.system_img, b"system").unwrap();
+        std::fs::write(&paths.vendor_img, b"vendor").unwrap();
+
+        // sha256("system"), and a digest vendor.img doesn't have
+        let system = "bbc5e661e106c6dcd8dc6dd186454c2fcba3c710fb4d8e71a60c93eaf077f073";
+        let vendor = "0".repeat(64);
+        assert_eq!(sha256_file(&paths.system_img).unwrap(), system);
+
+        let good = ChecksumManifest::parse(&format!("{}  system.img\n", system)).unwrap();
+        paths.verify_checksums(&good).unwrap();
+
+        let bad = ChecksumManifest::parse(&format!("{}  system.img\n{}  vendor.img\n", system, vendor))
+            .unwrap();
+        let err = paths.verify_checksums(&bad).unwrap_err().to_string();
+        assert!(err.contains("vendor.img: expected"), "{}", err);
+        assert!(!err.contains("system.img"), "{}", err);
+
+        std::fs::write(paths.checksum_manifest_path(), format!("{}  system.img\n", system)).unwrap();
+        assert_eq!(
+            ChecksumManifest::load(&paths.checksum_manifest_path()).unwrap(),
+            Some(good)
+        );
+        assert_eq!(ChecksumManifest::load(&dir.join("missing.txt")).unwrap(), None);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// A minimal "filesystem": zeros with an ext4 superblock of `blocks` 4K blocks
+    fn ext4_bytes(blocks: u32) -> Vec<u8> {
+        let mut data = vec![0u8; 4096];
+        data[0x404..0x408].copy_from_slice(&blocks.to_le_bytes());
+        data[0x418..0x41c].copy_from_slice(&2u32.to_le_bytes());
+        data[0x438..0x43a].copy_from_slice(&EXT4_MAGIC.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn is_ext4_detects_superblock_magic() {
+        let dir = make_temp_dir("ext4");
+        let ext4 = dir.join("ext4.img");
+        let other = dir.join("other.img");
+        let tiny = dir.join("tiny.img");
+        std::fs::write(&ext4, ext4_bytes(1000)).unwrap();
+        std::fs::write(&other, vec![0u8; 4096]).unwrap();
+        std::fs::write(&tiny, b"<html>404</html>").unwrap();
+
+        assert!(is_ext4(&ext4).unwrap());
+        assert_eq!(
+            read_ext4_info(&ext4).unwrap(),
+            Some(Ext4Info {
+                block_count: 1000,
+                block_size: 4096,
+            })
+        );
+        assert!(!is_ext4(&other).unwrap());
+        assert!(!is_ext4(&tiny).unwrap());
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn is_ext4_looks_inside_sparse_images() {
+        let dir = make_temp_dir("sparse");
+        let path = dir.join("sparse.img");
+
+        let mut data = Vec::new();
+        data.extend_from_slice(&SPARSE_HEADER_MAGIC.to_le_bytes());
+        data.extend_from_slice(&[1, 0, 0, 0]); // version 1.0
+        data.extend_from_slice(&28u16.to_le_bytes()); // file_hdr_sz
+        data.extend_from_slice(&12u16.to_le_bytes()); // chunk_hdr_sz
+        data.resize(28, 0);
+        data.extend_from_slice(&SPARSE_CHUNK_RAW.to_le_bytes());
+        data.resize(40, 0);
+        data.extend_from_slice(&ext4_bytes(8));
+        std::fs::write(&path, data).unwrap();
+
+        assert!(is_ext4(&path).unwrap());
+        let err = check_ext4_image(&path, "system.img").unwrap_err();
+        assert!(err.to_string().contains("sparse"));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn check_free_space_compares_free_and_required() {
+        assert!(check_free_space(2000 * MB, 1000 * MB).is_ok());
+        assert!(check_free_space(1000 * MB, 1000 * MB).is_ok());
+
+        let err = check_free_space(200 * MB, 1000 * MB).unwrap_err().to_string();
+        assert!(err.contains("1000 MB required"), "{}", err);
+        assert!(err.contains("200 MB available"), "{}", err);
+        assert!(err.contains("Free up 800 MB"), "{}", err);
+    }
+
+    #[test]
+    fn required_setup_bytes_skips_present_artifacts() {
+        let root = make_temp_dir("required");
+        let cache = root.join("cache");
+        std::fs::create_dir_all(&cache).unwrap();
+
+        let full: u64 = SETUP_MANIFEST
+            .iter()
+            .map(|e| e.download_bytes + 2 * e.image_bytes)
+            .sum();
+        assert_eq!(required_setup_bytes(&cache), full);
+
+        std::fs::write(cache.join("system.zip"), b"").unwrap();
+        std::fs::create_dir_all(root.join("images/system")).unwrap();
+        std::fs::write(root.join("images/system/.extracted"), b"").unwrap();
+        let system = SETUP_MANIFEST[0];
+        assert_eq!(
+            required_setup_bytes(&cache),
+            full - system.download_bytes - system.image_bytes
+        );
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn clean_partial_images_removes_leftovers() {
+        let cache = make_temp_dir("partial");
+        std::fs::write(cache.join("system.raw.img"), b"half").unwrap();
+        // A truncated download is not a readable zip
+        std::fs::write(cache.join("vendor.zip"), b"PK\x03\x04trunc").unwrap();
+        std::fs::write(cache.join("notes.txt"), b"keep").unwrap();
+
+        let mut removed = clean_partial_images(&cache).unwrap();
+        removed.sort();
+
+        assert_eq!(
+            removed,
+            vec![cache.join("system.raw.img"), cache.join("vendor.zip")]
+        );
+        assert!(cache.join("notes.txt").exists());
+
+        let _ = std::fs::remove_dir_all(cache);
+    }
+
     #[test]
     fn mount_points_for_prefix() {
         let prefix = PathBuf::from("/tmp/test-prefix");
@@ -1,5 +1,15 @@
+pub mod apex;
+pub mod binfmt;
+pub mod cgroup;
+pub mod config;
 pub mod container;
 pub mod doctor;
+pub mod error;
 pub mod image;
+pub mod net;
+pub mod overlay;
+pub mod paths;
 pub mod prefix;
+pub mod registry;
+pub mod snapshot;
 pub mod zygote;
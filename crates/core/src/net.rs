@@ -0,0 +1,278 @@
+//! Container networking.
+//!
+//! By default containers share the host network namespace. With
+//! [`NetworkMode::None`] or [`NetworkMode::Slirp`] they get their own
+//! (`CLONE_NEWNET`); in `Slirp` mode a `slirp4netns` process attached to the
+//! container's init provides a tap device with NAT'd outbound connectivity,
+//! without root.
+
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+/// The user-mode network stack binary
+pub const SLIRP4NETNS: &str = "slirp4netns";
+
+/// Tap device slirp4netns creates in the container
+pub const TAP_DEVICE: &str = "tap0";
+
+/// File next to the container's PID file recording the slirp4netns PID, so
+/// a later handle can stop it
+pub const SLIRP_PID_FILE: &str = "slirp4netns.pid";
+
+/// MTU recommended by slirp4netns for throughput
+const SLIRP_MTU: u32 = 65520;
+
+/// How long `Slirp::stop` waits after SIGTERM before SIGKILL
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How the container is connected to the network
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkMode {
+    /// Share the host network namespace (every host port is visible)
+    #[default]
+    Host,
+    /// Own network namespace with only a loopback device
+    None,
+    /// Own network namespace with outbound NAT through slirp4netns
+    Slirp,
+}
+
+impl NetworkMode {
+    /// Whether the container gets its own network namespace
+    pub fn isolated(&self) -> bool {
+        !matches!(self, NetworkMode::Host)
+    }
+}
+
+impl std::str::FromStr for NetworkMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "host" => Ok(NetworkMode::Host),
+            "none" => Ok(NetworkMode::None),
+            "slirp" | "slirp4netns" => Ok(NetworkMode::Slirp),
+            _ => anyhow::bail!(
+                "Unknown network mode {:?} (expected host, none or slirp)",
+                s
+            ),
+        }
+    }
+}
+
+/// Locate `slirp4netns` in `PATH`
+pub fn find_slirp4netns() -> Option<PathBuf> {
+    find_in_path(SLIRP4NETNS, &std::env::var_os("PATH")?)
+}
+
+/// First executable `name` in the `PATH`-style list `path`
+pub(crate) fn find_in_path(name: &str, path: &std::ffi::OsStr) -> Option<PathBuf> {
+    std::env::split_paths(path)
+        .map(|dir| dir.join(name))
+        .find(|candidate| is_executable(candidate))
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+/// Arguments for a slirp4netns attached to the network namespace of `pid`
+fn slirp_args(pid: u32) -> Vec<String> {
+    vec![
+        "--configure".to_string(),
+        format!("--mtu={}", SLIRP_MTU),
+        "--disable-host-loopback".to_string(),
+        pid.to_string(),
+        TAP_DEVICE.to_string(),
+    ]
+}
+
+/// A running slirp4netns process
+#[derive(Debug)]
+pub struct Slirp {
+    child: Child,
+}
+
+impl Slirp {
+    /// Attach slirp4netns to the network namespace of `pid`, the
+    /// container's init
+    ///
+    /// slirp4netns joins the namespace itself (through `/proc/<pid>/ns`),
+    /// so `pid` must already have unshared it.
+    pub fn spawn(pid: u32) -> Result<Self> {
+        let binary = find_slirp4netns()
+            .context("slirp4netns not found in PATH (install it to use slirp networking)")?;
+        let child = Command::new(&binary)
+            .args(slirp_args(pid))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Failed to spawn {}", binary.display()))?;
+        info!(
+            "slirp4netns attached to PID {} (PID {}, device {})",
+            pid,
+            child.id(),
+            TAP_DEVICE
+        );
+        Ok(Self { child })
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Terminate slirp4netns: SIGTERM, then SIGKILL if it doesn't exit in time
+    pub fn stop(mut self) -> Result<()> {
+        if self.child.try_wait()?.is_some() {
+            return Ok(());
+        }
+        let pid = Pid::from_raw(self.child.id() as i32);
+        let _ = signal::kill(pid, Signal::SIGTERM);
+
+        let deadline = std::time::Instant::now() + STOP_TIMEOUT;
+        while std::time::Instant::now() < deadline {
+            if self.child.try_wait()?.is_some() {
+                return Ok(());
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        warn!("slirp4netns did not exit after SIGTERM; killing it");
+        self.child.kill().context("Failed to kill slirp4netns")?;
+        self.child.wait().context("Failed to reap slirp4netns")?;
+        Ok(())
+    }
+
+    /// Terminate a slirp4netns started by another handle, by PID
+    ///
+    /// Does nothing if `pid` is gone or has been reused by something other
+    /// than slirp4netns.
+    pub fn stop_pid(pid: u32) -> Result<()> {
+        if !is_slirp(pid) {
+            return Ok(());
+        }
+        let pid = Pid::from_raw(pid as i32);
+        let _ = signal::kill(pid, Signal::SIGTERM);
+
+        let deadline = std::time::Instant::now() + STOP_TIMEOUT;
+        while std::time::Instant::now() < deadline {
+            if !is_slirp(pid.as_raw() as u32) {
+                return Ok(());
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        warn!("slirp4netns did not exit after SIGTERM; killing it");
+        signal::kill(pid, Signal::SIGKILL).context("Failed to kill slirp4netns")?;
+        Ok(())
+    }
+}
+
+/// Whether `pid` is a live (not zombie) slirp4netns
+fn is_slirp(pid: u32) -> bool {
+    let comm = std::fs::read_to_string(format!("/proc/{}/comm", pid)).unwrap_or_default();
+    let state = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap_or_default();
+    let alive = state
+        .rsplit_once(')')
+        .and_then(|(_, rest)| rest.split_whitespace().next())
+        .is_some_and(|state| state != "Z" && state != "X");
+    comm.trim_end() == SLIRP4NETNS && alive
+}
+
+/// Whether `pid` is in a different network namespace than this process,
+/// or None if that can't be read
+pub fn has_own_netns(pid: u32) -> Option<bool> {
+    let theirs = std::fs::read_link(format!("/proc/{}/ns/net", pid)).ok()?;
+    let ours = std::fs::read_link("/proc/self/ns/net").ok()?;
+    Some(theirs != ours)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_mode_parses() {
+        assert_eq!("host".parse::<NetworkMode>().unwrap(), NetworkMode::Host);
+        assert_eq!("none".parse::<NetworkMode>().unwrap(), NetworkMode::None);
+        assert_eq!("slirp".parse::<NetworkMode>().unwrap(), NetworkMode::Slirp);
+        assert!("bridge".parse::<NetworkMode>().is_err());
+        assert!(!NetworkMode::default().isolated());
+        assert!(NetworkMode::Slirp.isolated());
+    }
+
+    #[test]
+    fn slirp_attaches_to_pid_with_tap_device() {
+        assert_eq!(
+            slirp_args(4242).join(" "),
+            "--configure --mtu=65520 --disable-host-loopback 4242 tap0"
+        );
+    }
+
+    #[test]
+    fn own_netns_compares_with_ours() {
+        assert_eq!(has_own_netns(std::process::id()), Some(false));
+        assert_eq!(has_own_netns(u32::MAX), None);
+    }
+
+    #[test]
+    fn stop_pid_only_stops_slirp4netns() {
+        let dir = std::env::temp_dir().join(format!("rad-net-stop-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // A stand-in that shows up as slirp4netns in /proc/<pid>/comm
+        let fake = dir.join(SLIRP4NETNS);
+        std::fs::copy(find_in_path("sleep", &std::env::var_os("PATH").unwrap()).unwrap(), &fake)
+            .unwrap();
+
+        let mut other = Command::new("sleep").arg("30").spawn().unwrap();
+        Slirp::stop_pid(other.id()).unwrap();
+        assert!(other.try_wait().unwrap().is_none(), "killed a process that isn't slirp4netns");
+        let _ = other.kill();
+        let _ = other.wait();
+
+        let mut slirp = Command::new(&fake).arg("30").spawn().unwrap();
+        // Wait for exec, so comm is the new name
+        while !is_slirp(slirp.id()) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        Slirp::stop_pid(slirp.id()).unwrap();
+        assert!(!is_slirp(slirp.id()), "slirp4netns still running");
+        let _ = slirp.wait();
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn find_in_path_skips_non_executables() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("rad-net-path-{}", std::process::id()));
+        let (plain, bin) = (dir.join("plain"), dir.join("bin"));
+        std::fs::create_dir_all(&plain).unwrap();
+        std::fs::create_dir_all(&bin).unwrap();
+        std::fs::write(plain.join(SLIRP4NETNS), "").unwrap();
+        let exe = bin.join(SLIRP4NETNS);
+        std::fs::write(&exe, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let path = std::env::join_paths([&plain, &bin]).unwrap();
+        assert_eq!(find_in_path(SLIRP4NETNS, &path), Some(exe));
+        let path = std::env::join_paths([&plain]).unwrap();
+        assert_eq!(find_in_path(SLIRP4NETNS, &path), None);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
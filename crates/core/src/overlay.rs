@@ -0,0 +1,192 @@
+//! Editing the overlay upper layer from the host.
+//!
+//! Removing a file from the upper dir only drops our copy: if the read-only
+//! lower layer (the system image) has the same path, it shows through again
+//! in the merged view. Overlayfs hides a lower file with a whiteout, a 0/0
+//! character device at the same path in the upper dir.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use nix::sys::stat::{Mode, SFlag, makedev, mknod};
+
+use crate::paths;
+
+/// Whether `path` is an overlay whiteout
+pub fn is_whiteout(path: &Path) -> bool {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    path.symlink_metadata()
+        .is_ok_and(|m| m.file_type().is_char_device() && m.rdev() == 0)
+}
+
+/// Mask `rel` (a container path, leading `/` optional) in the merged view
+/// with a whiteout in `upper`
+///
+/// Replaces whatever `upper` has at `rel`; missing parent directories are
+/// created (overlayfs merges them with the lower ones).
+pub fn whiteout(upper: &Path, rel: &str) -> Result<()> {
+    let target = resolve_upper(upper, rel)?;
+    if target == upper.canonicalize()? {
+        anyhow::bail!("Cannot white out the overlay root");
+    }
+    remove_upper(&target)?;
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    mknod(&target, SFlag::S_IFCHR, Mode::empty(), makedev(0, 0))
+        .with_context(|| format!("Failed to create whiteout {}", target.display()))?;
+    Ok(())
+}
+
+/// Delete `rel` from the merged view
+///
+/// `lower` is the lower layer if it is available to check: the upper copy is
+/// removed, and a whiteout is added only if `lower` has the path too. With
+/// `lower` unknown (None) a whiteout is always created, which is harmless if
+/// nothing was there.
+pub fn remove(upper: &Path, lower: Option<&Path>, rel: &str) -> Result<()> {
+    let in_lower = match lower {
+        Some(lower) => {
+            let rel = paths::normalize_relative(rel)?;
+            lower.join(rel).symlink_metadata().is_ok()
+        }
+        None => true,
+    };
+
+    if in_lower {
+        whiteout(upper, rel)
+    } else {
+        remove_upper(&resolve_upper(upper, rel)?)
+    }
+}
+
+/// `rel` inside `upper`, refusing `..` and symlink escapes
+///
+/// Only the parent directory is resolved: the last component is the entry
+/// itself, so a symlink there is acted on rather than followed.
+fn resolve_upper(upper: &Path, rel: &str) -> Result<std::path::PathBuf> {
+    let rel = paths::normalize_relative(rel)?;
+    let Some(name) = rel.file_name() else {
+        return Ok(upper.canonicalize()?);
+    };
+    let parent = rel.parent().unwrap_or(Path::new(""));
+    Ok(paths::resolve_within(upper, &parent.to_string_lossy())?.join(name))
+}
+
+fn remove_upper(target: &Path) -> Result<()> {
+    match target.symlink_metadata() {
+        Ok(m) if m.is_dir() => std::fs::remove_dir_all(target)?,
+        Ok(_) => std::fs::remove_file(target)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_layers(label: &str) -> (PathBuf, PathBuf, PathBuf) {
+        let root =
+            std::env::temp_dir().join(format!("rad-overlay-{}-{}", label, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let (lower, upper) = (root.join("lower"), root.join("upper"));
+        std::fs::create_dir_all(lower.join("etc")).unwrap();
+        std::fs::create_dir_all(&upper).unwrap();
+        (root, lower, upper)
+    }
+
+    #[test]
+    fn remove_upper_only_file_leaves_no_whiteout() {
+        let (root, lower, upper) = temp_layers("upper-only");
+        std::fs::create_dir_all(upper.join("data")).unwrap();
+        std::fs::write(upper.join("data/file"), b"x").unwrap();
+
+        remove(&upper, Some(&lower), "/data/file").unwrap();
+        assert!(upper.join("data/file").symlink_metadata().is_err());
+        assert!(remove(&upper, Some(&lower), "../escape").is_err());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    /// An upper dir with `data/file`, a relative link `data/rel` to it, and
+    /// an absolute link `data/abs` to `outside` (next to `upper`)
+    fn upper_with_links(label: &str) -> (PathBuf, PathBuf, PathBuf) {
+        let (root, lower, upper) = temp_layers(label);
+        std::fs::create_dir_all(upper.join("data")).unwrap();
+        std::fs::write(upper.join("data/file"), b"x").unwrap();
+        std::fs::write(root.join("outside"), b"host").unwrap();
+        std::os::unix::fs::symlink("file", upper.join("data/rel")).unwrap();
+        std::os::unix::fs::symlink(root.join("outside"), upper.join("data/abs")).unwrap();
+        (root, lower, upper)
+    }
+
+    #[test]
+    fn remove_acts_on_symlinks_not_their_targets() {
+        let (root, lower, upper) = upper_with_links("remove-links");
+
+        remove(&upper, Some(&lower), "/data/rel").unwrap();
+        remove(&upper, Some(&lower), "/data/abs").unwrap();
+        assert!(upper.join("data/rel").symlink_metadata().is_err());
+        assert!(upper.join("data/abs").symlink_metadata().is_err());
+        assert_eq!(std::fs::read(upper.join("data/file")).unwrap(), b"x");
+        assert_eq!(std::fs::read(root.join("outside")).unwrap(), b"host");
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    #[ignore = "needs mknod (root or a user namespace)"]
+    fn whiteout_replaces_symlinks_not_their_targets() {
+        let (root, _, upper) = upper_with_links("whiteout-links");
+
+        whiteout(&upper, "/data/rel").unwrap();
+        whiteout(&upper, "/data/abs").unwrap();
+        assert!(is_whiteout(&upper.join("data/rel")));
+        assert!(is_whiteout(&upper.join("data/abs")));
+        assert_eq!(std::fs::read(upper.join("data/file")).unwrap(), b"x");
+        assert_eq!(std::fs::read(root.join("outside")).unwrap(), b"host");
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    #[ignore = "needs mknod and overlayfs mounting (root or a user namespace)"]
+    fn whiteout_hides_lower_file_in_merged_view() {
+        let (root, lower, upper) = temp_layers("whiteout");
+        let (work, merged) = (root.join("work"), root.join("merged"));
+        std::fs::create_dir_all(&work).unwrap();
+        std::fs::create_dir_all(&merged).unwrap();
+        std::fs::write(lower.join("etc/hosts"), b"127.0.0.1 localhost\n").unwrap();
+
+        remove(&upper, Some(&lower), "/etc/hosts").unwrap();
+        assert!(is_whiteout(&upper.join("etc/hosts")));
+
+        let options = format!(
+            "lowerdir={},upperdir={},workdir={}",
+            lower.display(),
+            upper.display(),
+            work.display()
+        );
+        nix::mount::mount(
+            Some("overlay"),
+            &merged,
+            Some("overlay"),
+            nix::mount::MsFlags::empty(),
+            Some(options.as_str()),
+        )
+        .unwrap();
+
+        let hidden = merged.join("etc/hosts").exists();
+        let etc_visible = merged.join("etc").is_dir();
+        let _ = nix::mount::umount(&merged);
+        assert!(!hidden, "lower file still visible");
+        assert!(etc_visible);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,152 @@
+//! Safe path handling for files written into the prefix/overlay.
+//!
+//! Package names, APK entry names and user-supplied destinations end up
+//! joined onto directories we own; [`resolve_within`] makes sure none of them
+//! can point outside of that directory.
+
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Join `rel` onto `base`, rejecting anything that would resolve outside `base`
+///
+/// `base` must exist. `rel` must be relative; `..` components are resolved
+/// lexically and symlinks in the existing part of the path are followed, so
+/// both `../` escapes and symlinks pointing out of `base` are rejected. The
+/// returned path is absolute and may not exist yet.
+pub fn resolve_within(base: &Path, rel: &str) -> Result<PathBuf> {
+    let base = base
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve base directory {}", base.display()))?;
+
+    if Path::new(rel).is_absolute() {
+        anyhow::bail!("Path must be relative: {}", rel);
+    }
+    let normalized = normalize_relative(rel)
+        .with_context(|| format!("Path escapes {}: {}", base.display(), rel))?;
+
+    // Follow symlinks through the part of the path that already exists
+    let mut resolved = base.clone();
+    let mut remaining = normalized.components();
+    for part in remaining.by_ref() {
+        let next = resolved.join(part);
+        if next.symlink_metadata().is_err() {
+            resolved = next;
+            break;
+        }
+        resolved = next
+            .canonicalize()
+            .with_context(|| format!("Failed to resolve {}", next.display()))?;
+        if !resolved.starts_with(&base) {
+            anyhow::bail!("Path escapes {} via symlink: {}", base.display(), rel);
+        }
+    }
+    resolved.extend(remaining);
+
+    Ok(resolved)
+}
+
+/// Lexically normalize `rel`, rejecting `..` components that climb above it
+///
+/// Leading `/` are ignored, so container paths like `/data/media` normalize
+/// to `data/media`. Nothing is read from disk; use this for paths inside a
+/// rootfs that isn't mounted yet.
+pub fn normalize_relative(rel: &str) -> Result<PathBuf> {
+    let mut parts: Vec<&std::ffi::OsStr> = Vec::new();
+    for component in Path::new(rel).components() {
+        match component {
+            Component::Normal(part) => parts.push(part),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir => {
+                if parts.pop().is_none() {
+                    anyhow::bail!("Path climbs above its base: {}", rel);
+                }
+            }
+            Component::Prefix(_) => anyhow::bail!("Unsupported path prefix: {}", rel),
+        }
+    }
+    Ok(parts.iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn make_temp_base(label: &str) -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let path = std::env::temp_dir().join(format!("rad-paths-{label}-{nanos}"));
+        fs::create_dir_all(&path).unwrap();
+        path.canonicalize().unwrap()
+    }
+
+    #[test]
+    fn nested_paths_resolve_under_base() {
+        let base = make_temp_base("nested");
+        fs::create_dir_all(base.join("data/app")).unwrap();
+
+        assert_eq!(
+            resolve_within(&base, "data/app/com.example").unwrap(),
+            base.join("data/app/com.example")
+        );
+        assert_eq!(
+            resolve_within(&base, "./data/../data/local/tmp").unwrap(),
+            base.join("data/local/tmp")
+        );
+
+        let _ = fs::remove_dir_all(base);
+    }
+
+    #[test]
+    fn parent_dir_escape_is_rejected() {
+        let base = make_temp_base("dotdot");
+
+        assert!(resolve_within(&base, "../outside").is_err());
+        assert!(resolve_within(&base, "data/../../outside").is_err());
+
+        let _ = fs::remove_dir_all(base);
+    }
+
+    #[test]
+    fn normalize_relative_strips_root_and_dots() {
+        assert_eq!(
+            normalize_relative("/data/./media/../shared").unwrap(),
+            PathBuf::from("data/shared")
+        );
+        assert_eq!(normalize_relative("/").unwrap(), PathBuf::new());
+        assert!(normalize_relative("/data/../../etc").is_err());
+    }
+
+    #[test]
+    fn absolute_path_is_rejected() {
+        let base = make_temp_base("absolute");
+
+        assert!(resolve_within(&base, "/etc/passwd").is_err());
+
+        let _ = fs::remove_dir_all(base);
+    }
+
+    #[test]
+    fn symlink_escape_is_rejected() {
+        let base = make_temp_base("symlink");
+        let outside = make_temp_base("symlink-target");
+        std::os::unix::fs::symlink(&outside, base.join("link")).unwrap();
+
+        assert!(resolve_within(&base, "link/file").is_err());
+
+        // Symlinks that stay inside base are fine
+        fs::create_dir_all(base.join("real")).unwrap();
+        std::os::unix::fs::symlink(base.join("real"), base.join("inner")).unwrap();
+        assert_eq!(
+            resolve_within(&base, "inner/file").unwrap(),
+            base.join("real/file")
+        );
+
+        let _ = fs::remove_dir_all(base);
+        let _ = fs::remove_dir_all(outside);
+    }
+}
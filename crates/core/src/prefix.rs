@@ -1,13 +1,18 @@
 use std::path::{Path, PathBuf};
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::fs;
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 use sandbox;
 use apk::{ApkInfo, ApkInspector, Abi};
-use tracing::info;
+use sandbox::SeccompProfile;
+use tracing::{info, warn};
 use nix::unistd::{fork, ForkResult};
+use nix::sched::CloneFlags;
 use nix::sys::wait::{waitpid, WaitStatus};
+use crate::image::MountPoints;
+use crate::paths;
+use crate::snapshot;
 use crate::zygote;
 
 const DEFAULT_PREFIX_DIRS: &[&str] = &[
@@ -24,19 +29,524 @@ const DEFAULT_PREFIX_DIRS: &[&str] = &[
     "logs",
 ];
 
+/// File (relative to the prefix root) holding the running container's init PID
+pub const CONTAINER_PID_FILE: &str = ".container_pid";
+
+/// ext4 image (relative to the prefix root) backing the overlay dirs under a quota
+pub const QUOTA_IMAGE_FILE: &str = ".overlay.img";
+
+/// Smallest quota image; ext4 needs room for its metadata and journal
+pub const MIN_QUOTA_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Quota images are sized in whole ext4 blocks
+const QUOTA_BLOCK_SIZE: u64 = 4096;
+
+/// Host tools needed to create and mount a quota image
+const QUOTA_TOOLS: &[&str] = &["mkfs.ext4", "fuse2fs"];
+
+/// Size of the quota image for a quota of `bytes`
+pub fn quota_image_size(bytes: u64) -> u64 {
+    bytes.max(MIN_QUOTA_BYTES).div_ceil(QUOTA_BLOCK_SIZE) * QUOTA_BLOCK_SIZE
+}
+
+/// How the overlay dirs get their writable space
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuotaPlan {
+    /// Plain directories on the host filesystem
+    Unlimited,
+    /// An ext4 image of this size, FUSE-mounted over the overlay dirs
+    Image { size_bytes: u64 },
+    /// A quota was asked for but these tools are missing
+    Unavailable { missing: Vec<&'static str> },
+}
+
+impl QuotaPlan {
+    /// Decide for a requested quota, given which host tools exist
+    fn new(quota: Option<u64>, has_tool: impl Fn(&str) -> bool) -> Self {
+        let Some(bytes) = quota else {
+            return Self::Unlimited;
+        };
+        let missing: Vec<&'static str> =
+            QUOTA_TOOLS.iter().copied().filter(|tool| !has_tool(tool)).collect();
+        if missing.is_empty() {
+            Self::Image {
+                size_bytes: quota_image_size(bytes),
+            }
+        } else {
+            Self::Unavailable { missing }
+        }
+    }
+}
+
+/// Whether an executable named `tool` is in `PATH`
+fn in_path(tool: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|path| crate::net::find_in_path(tool, &path).is_some())
+}
+
+/// Log directory relative to the prefix root, unless overridden
+const DEFAULT_LOG_DIR: &str = "logs";
+
+/// Name of the sandboxed app's log file in the log directory
+pub const APP_LOG_FILE: &str = "app.log";
+
+/// When to rotate `app.log` and how many old logs to keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRotation {
+    /// Rotate once the log has reached this size
+    pub max_bytes: u64,
+    /// Rotated logs kept as `app.log.1` (newest) .. `app.log.N`; 0 keeps none
+    pub generations: u32,
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        Self {
+            max_bytes: 10 * 1024 * 1024,
+            generations: 3,
+        }
+    }
+}
+
+impl LogRotation {
+    /// Rotate `log` if it has reached `max_bytes`; returns whether it did
+    ///
+    /// `log.N-1` becomes `log.N` (dropping the old `log.N`), ..., `log` becomes
+    /// `log.1`, so the next open starts a fresh file.
+    pub fn rotate(&self, log: &Path) -> Result<bool> {
+        let size = match fs::metadata(log) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        };
+        if size < self.max_bytes {
+            return Ok(false);
+        }
+
+        if self.generations == 0 {
+            fs::remove_file(log)?;
+            return Ok(true);
+        }
+
+        let _ = fs::remove_file(generation_path(log, self.generations));
+        for generation in (1..self.generations).rev() {
+            let from = generation_path(log, generation);
+            if from.exists() {
+                fs::rename(&from, generation_path(log, generation + 1))?;
+            }
+        }
+        fs::rename(log, generation_path(log, 1))?;
+        Ok(true)
+    }
+}
+
+/// `app.log` -> `app.log.<generation>`
+fn generation_path(log: &Path, generation: u32) -> PathBuf {
+    let mut name = log.as_os_str().to_owned();
+    name.push(format!(".{}", generation));
+    PathBuf::from(name)
+}
+
+/// Resolve the ABI to install: the forced one if the APK ships it, otherwise
+/// the best one for the host ([`apk::preferred_abi`])
+pub fn select_abi(info: &ApkInfo, forced: Option<&Abi>) -> Result<Option<Abi>> {
+    select_abi_for_host(info, forced, Abi::host())
+}
+
+fn select_abi_for_host(info: &ApkInfo, forced: Option<&Abi>, host: Option<Abi>) -> Result<Option<Abi>> {
+    let Some(forced) = forced else {
+        let Some(host) = host else {
+            return Ok(Abi::best_abi(&info.supported_abis));
+        };
+        if let Some(abi) = apk::preferred_abi(&info.supported_abis, host.clone()) {
+            return Ok(Some(abi));
+        }
+        // Only a native bridge (libhoudini/libndk_translation) in the image
+        // can run these, but that beats installing no libraries at all
+        let fallback = Abi::best_abi(&info.supported_abis);
+        if let Some(abi) = &fallback {
+            warn!(
+                "{} has no native libraries for {}; installing {}, which needs a native bridge",
+                info.package_name,
+                host.as_str(),
+                abi.as_str()
+            );
+        }
+        return Ok(fallback);
+    };
+
+    if info.supported_abis.is_empty() {
+        warn!(
+            "{} has no native libraries; ignoring forced ABI {}",
+            info.package_name,
+            forced.as_str()
+        );
+        return Ok(None);
+    }
+
+    if !info.supported_abis.contains(forced) {
+        let available: Vec<&str> = info.supported_abis.iter().map(|a| a.as_str()).collect();
+        anyhow::bail!(
+            "ABI {} is not supported by {} (available: {})",
+            forced.as_str(),
+            info.package_name,
+            available.join(", ")
+        );
+    }
+
+    Ok(Some(forced.clone()))
+}
+
+/// A saved copy of the overlay upper layer (see `Container::snapshot`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    pub name: String,
+    /// Total size of the files in the snapshot
+    pub size_bytes: u64,
+    /// When the snapshot was taken (modification time of its directory)
+    pub created: std::time::SystemTime,
+}
+
+/// An APK installed into a prefix by `Prefix::install_apk`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstalledApp {
+    pub package_name: String,
+    pub version_code: Option<i32>,
+    pub version_name: Option<String>,
+    /// `data/app/<package>/base.apk`
+    pub apk_path: PathBuf,
+}
+
 pub struct Prefix {
     pub root: PathBuf,
+    /// Where `app.log` goes instead of `<root>/logs`
+    log_dir: Option<PathBuf>,
+    log_rotation: LogRotation,
+    /// Cap on the overlay upper+work dirs, in bytes
+    quota: Option<u64>,
+    seccomp: SeccompProfile,
 }
 
 impl Prefix {
     pub fn new<P: AsRef<Path>>(root: P) -> Self {
         Self {
             root: root.as_ref().to_path_buf(),
+            log_dir: None,
+            log_rotation: LogRotation::default(),
+            quota: None,
+            seccomp: SeccompProfile::default(),
         }
     }
 
+    /// Choose the seccomp filter for sandboxed commands (enforcing by
+    /// default; permissive logs denied syscalls instead, to debug an app)
+    pub fn with_seccomp(mut self, profile: SeccompProfile) -> Self {
+        self.seccomp = profile;
+        self
+    }
+
+    /// Cap the app's writable space (the overlay upper and work dirs) at
+    /// `bytes`, see [`Prefix::mount_quota`]
+    pub fn with_quota(mut self, bytes: u64) -> Self {
+        self.quota = Some(bytes);
+        self
+    }
+
+    /// Where the overlay upper and work dirs live
+    fn overlay_dir(&self) -> PathBuf {
+        MountPoints::for_prefix(&self.root)
+            .overlay_upper
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| self.root.clone())
+    }
+
+    pub fn quota_image_path(&self) -> PathBuf {
+        self.root.join(QUOTA_IMAGE_FILE)
+    }
+
+    /// Back the overlay dirs with a size-capped ext4 image, if a quota is set
+    ///
+    /// Call before starting the container. The image is created on first
+    /// use as a sparse file, seeded with whatever the overlay dirs already
+    /// hold, and FUSE-mounted over them. An existing image is not resized
+    /// when the quota changes; a warning says so. Without mkfs.ext4 or
+    /// fuse2fs the prefix runs without a quota and a warning is logged.
+    /// Returns whether the quota is in effect.
+    pub fn mount_quota(&self) -> Result<bool> {
+        let size = match QuotaPlan::new(self.quota, in_path) {
+            QuotaPlan::Unlimited => return Ok(false),
+            QuotaPlan::Unavailable { missing } => {
+                warn!(
+                    "Cannot enforce the storage quota for {} ({} not found); running without one",
+                    self.root.display(),
+                    missing.join(", ")
+                );
+                return Ok(false);
+            }
+            QuotaPlan::Image { size_bytes } => size_bytes,
+        };
+
+        let overlay_dir = self.overlay_dir();
+        let image = self.quota_image_path();
+        fs::create_dir_all(&overlay_dir)?;
+        if !image.exists() {
+            info!(
+                "Creating {} MiB quota image {}...",
+                size / (1024 * 1024),
+                image.display()
+            );
+            crate::container::create_storage_image(&image, size, &overlay_dir)?;
+        } else {
+            let current = fs::metadata(&image)
+                .with_context(|| format!("Failed to stat {}", image.display()))?
+                .len();
+            if current != size {
+                warn!(
+                    "Quota image {} is {} MiB but the quota is now {} MiB; the old size stays \
+                     in effect. Back up the app's data, delete the image and run again to apply it.",
+                    image.display(),
+                    current / (1024 * 1024),
+                    size / (1024 * 1024)
+                );
+            }
+        }
+
+        let _ = crate::container::fusermount_unmount(&overlay_dir);
+        crate::container::fuse2fs_mount(&image, &overlay_dir, false)
+            .context("Failed to FUSE-mount the quota image")?;
+
+        let mounts = MountPoints::for_prefix(&self.root);
+        fs::create_dir_all(&mounts.overlay_upper)?;
+        fs::create_dir_all(&mounts.overlay_work)?;
+        info!(
+            "Overlay dirs of {} capped at {} MiB",
+            self.root.display(),
+            size / (1024 * 1024)
+        );
+        Ok(true)
+    }
+
+    /// Unmount the quota image mounted by `mount_quota`, if any
+    pub fn unmount_quota(&self) -> Result<()> {
+        let overlay_dir = self.overlay_dir();
+        let mounted = sandbox::mountinfo::read_self()
+            .unwrap_or_default()
+            .iter()
+            .any(|m| m.mount_point == overlay_dir);
+        if mounted {
+            crate::container::fusermount_unmount(&overlay_dir)?;
+        }
+        Ok(())
+    }
+
+    /// Write `app.log` to `dir` instead of `<root>/logs`
+    pub fn with_log_dir(mut self, dir: PathBuf) -> Self {
+        self.log_dir = Some(dir);
+        self
+    }
+
+    /// Rotate `app.log` per `rotation` instead of the defaults
+    pub fn with_log_rotation(mut self, rotation: LogRotation) -> Self {
+        self.log_rotation = rotation;
+        self
+    }
+
+    pub fn log_dir(&self) -> PathBuf {
+        self.log_dir
+            .clone()
+            .unwrap_or_else(|| self.root.join(DEFAULT_LOG_DIR))
+    }
+
+    /// The sandboxed app's stdout/stderr log
+    pub fn log_path(&self) -> PathBuf {
+        self.log_dir().join(APP_LOG_FILE)
+    }
+
     pub fn initialize(&self) -> Result<()> {
-        self.initialize_with_layout(DEFAULT_PREFIX_DIRS)
+        if self.root.exists() {
+            let added = self.migrate()?;
+            if !added.is_empty() {
+                info!("Migrated prefix {}: added {}", self.root.display(), added.join(", "));
+            }
+            return Ok(());
+        }
+
+        self.initialize_with_layout(&Self::expected_layout())
+    }
+
+    /// Bring an existing prefix up to the current layout
+    ///
+    /// Creates the directories a prefix made by an older version lacks,
+    /// without touching anything already there, and returns the ones added.
+    pub fn migrate(&self) -> Result<Vec<String>> {
+        let mut added = Vec::new();
+        for dir in Self::expected_layout() {
+            if !self.root.join(&dir).exists() {
+                self.ensure_directory(&dir)?;
+                added.push(dir);
+            }
+        }
+        Ok(added)
+    }
+
+    /// Directories every prefix has: [`DEFAULT_PREFIX_DIRS`] plus the overlay dirs
+    fn expected_layout() -> Vec<String> {
+        let mounts = MountPoints::for_prefix(Path::new(""));
+        DEFAULT_PREFIX_DIRS
+            .iter()
+            .map(|dir| dir.to_string())
+            .chain(
+                [mounts.overlay_upper, mounts.overlay_work]
+                    .iter()
+                    .map(|dir| dir.to_string_lossy().into_owned()),
+            )
+            .collect()
+    }
+
+    /// Snapshots of this prefix, oldest first
+    pub fn list_snapshots(&self) -> Result<Vec<SnapshotInfo>> {
+        let dir = MountPoints::for_prefix(&self.root).snapshots;
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut snapshots = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            snapshots.push(SnapshotInfo {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                size_bytes: snapshot::tree_size(&entry.path())?,
+                created: entry.metadata()?.modified()?,
+            });
+        }
+        snapshots.sort_by(|a, b| a.created.cmp(&b.created).then(a.name.cmp(&b.name)));
+        Ok(snapshots)
+    }
+
+    /// Remove the prefix and everything in it, app data and snapshots included
+    ///
+    /// Refuses while a live PID file says the container is running, unless
+    /// `force` is set, and always while anything is still mounted inside the
+    /// prefix (deleting through a mount would reach into the image or host),
+    /// or when `/proc/self/mountinfo` can't be read to check.
+    pub fn delete(&self, force: bool) -> Result<()> {
+        if !self.root.exists() {
+            return Ok(());
+        }
+
+        if !force && self.pid_file().exists() && self.stale_pid()?.is_none() {
+            anyhow::bail!(
+                "The container for {} is still running; stop it first",
+                self.root.display()
+            );
+        }
+
+        // Without mountinfo there's no telling what is mounted, so don't guess
+        let mounted: Vec<PathBuf> = sandbox::mountinfo::read_self()
+            .context("Failed to read /proc/self/mountinfo to check for mounts in the prefix")?
+            .into_iter()
+            .map(|m| m.mount_point)
+            .filter(|m| m.starts_with(&self.root))
+            .collect();
+        if !mounted.is_empty() {
+            let list: Vec<String> = mounted.iter().map(|m| m.display().to_string()).collect();
+            anyhow::bail!(
+                "Refusing to delete {}: still mounted: {}",
+                self.root.display(),
+                list.join(", ")
+            );
+        }
+
+        fs::remove_dir_all(&self.root)?;
+        info!("Deleted prefix {}", self.root.display());
+        Ok(())
+    }
+
+    /// Per-package run settings (see `RunConfig`)
+    pub fn config_path(&self) -> PathBuf {
+        self.root.join(crate::config::CONFIG_FILE)
+    }
+
+    /// Where the container init PID is persisted while it runs
+    pub fn pid_file(&self) -> PathBuf {
+        self.root.join(CONTAINER_PID_FILE)
+    }
+
+    /// Check that the prefix is fully initialized and not left in a broken state
+    ///
+    /// Verifies the default layout exists, the overlay upper/work dirs are
+    /// writable, and no PID file points at a container that is no longer running.
+    pub fn validate(&self) -> Result<()> {
+        if !self.root.is_dir() {
+            anyhow::bail!("Prefix {} does not exist", self.root.display());
+        }
+
+        let missing: Vec<&str> = DEFAULT_PREFIX_DIRS
+            .iter()
+            .copied()
+            .filter(|dir| !self.root.join(dir).is_dir())
+            .collect();
+        if !missing.is_empty() {
+            anyhow::bail!(
+                "Prefix {} is missing directories: {}",
+                self.root.display(),
+                missing.join(", ")
+            );
+        }
+
+        let mounts = MountPoints::for_prefix(&self.root);
+        for dir in [&mounts.overlay_upper, &mounts.overlay_work] {
+            if !dir.is_dir() {
+                anyhow::bail!("Overlay directory is missing: {}", dir.display());
+            }
+            if nix::unistd::access(dir.as_path(), nix::unistd::AccessFlags::W_OK).is_err() {
+                anyhow::bail!("Overlay directory is not writable: {}", dir.display());
+            }
+        }
+
+        if let Some(pid) = self.stale_pid()? {
+            anyhow::bail!(
+                "Stale PID file {} (container PID {} is not running)",
+                self.pid_file().display(),
+                pid
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Fix what [`validate`](Self::validate) complains about: drop a stale PID
+    /// file and recreate missing directories. App data is kept.
+    pub fn repair(&self) -> Result<()> {
+        if self.stale_pid()?.is_some() {
+            fs::remove_file(self.pid_file())?;
+        }
+        self.initialize()
+    }
+
+    /// PID recorded in the PID file if that process is gone
+    fn stale_pid(&self) -> Result<Option<u32>> {
+        let pid_file = self.pid_file();
+        if !pid_file.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&pid_file)?;
+        let Ok(pid) = content.trim().parse::<u32>() else {
+            // Unparseable contents can't refer to a live container
+            return Ok(Some(0));
+        };
+        let alive = match nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid as i32), None) {
+            Ok(()) => true,
+            Err(nix::errno::Errno::ESRCH) => false,
+            Err(_) => true,
+        };
+        Ok((!alive).then_some(pid))
     }
 
     pub fn initialize_with_layout<S: AsRef<str>>(&self, dirs: &[S]) -> Result<()> {
@@ -51,28 +561,44 @@ impl Prefix {
         if data_dir.exists() {
             fs::remove_dir_all(&data_dir)?;
         }
-        let logs_dir = self.root.join("logs");
+        let logs_dir = self.root.join(DEFAULT_LOG_DIR);
         if logs_dir.exists() {
             fs::remove_dir_all(&logs_dir)?;
         }
+        if self.log_dir.is_some() {
+            // Only our own files: a custom log dir may be shared
+            let log = self.log_path();
+            let _ = fs::remove_file(&log);
+            for generation in 1..=self.log_rotation.generations {
+                let _ = fs::remove_file(generation_path(&log, generation));
+            }
+        }
         self.initialize()?;
         Ok(())
     }
 
     pub fn install_apk(&self, apk_path: &Path, info: &ApkInfo) -> Result<()> {
+        self.install_apk_with_abi(apk_path, info, None)
+    }
+
+    /// Install an APK, extracting native libs for `abi` instead of the
+    /// automatically selected one
+    pub fn install_apk_with_abi(
+        &self,
+        apk_path: &Path,
+        info: &ApkInfo,
+        abi: Option<&Abi>,
+    ) -> Result<()> {
         let pkg_name = &info.package_name;
-        
-        let app_dir = self.root.join("data/app").join(pkg_name);
+        let abi = select_abi(info, abi)?;
+
+        // The package name comes from the APK, so keep it inside the prefix
+        let app_dir = paths::resolve_within(&self.root, &format!("data/app/{}", pkg_name))?;
         fs::create_dir_all(&app_dir)?;
         let target_apk = app_dir.join("base.apk");
         fs::copy(apk_path, &target_apk)?;
         info!("Copied APK to {}", target_apk.display());
 
-        let abi = info.supported_abis.iter()
-            .find(|a| matches!(a, Abi::X86_64))
-            .or_else(|| info.supported_abis.first())
-            .cloned();
-
         if let Some(abi) = abi {
             let lib_dir = app_dir.join("lib").join(abi.as_str());
             fs::create_dir_all(&lib_dir)?;
@@ -81,12 +607,60 @@ impl Prefix {
             info!("Extracted libs for {} to {}", abi.as_str(), lib_dir.display());
         }
 
-        let data_dir = self.root.join("data/data").join(pkg_name);
+        let data_dir = paths::resolve_within(&self.root, &format!("data/data/{}", pkg_name))?;
         fs::create_dir_all(&data_dir)?;
 
         Ok(())
     }
 
+    /// APKs installed in this prefix, sorted by package name, read from
+    /// `data/app/*/base.apk` without booting the container
+    ///
+    /// APKs whose manifest can't be parsed, or whose package doesn't match
+    /// the directory they were installed under, are skipped with a warning.
+    pub fn installed_packages(&self) -> Result<Vec<InstalledApp>> {
+        let app_root = self.root.join("data/app");
+        let entries = match fs::read_dir(&app_root) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut apps = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let apk_path = entry.path().join("base.apk");
+            if !apk_path.is_file() {
+                continue;
+            }
+
+            let manifest = match ApkInspector::new(&apk_path).parse_manifest() {
+                Ok(manifest) => manifest,
+                Err(e) => {
+                    warn!("Skipping unreadable APK {}: {}", apk_path.display(), e);
+                    continue;
+                }
+            };
+            if *manifest.package_name != *entry.file_name() {
+                warn!(
+                    "Skipping {}: it contains package {}",
+                    apk_path.display(),
+                    manifest.package_name
+                );
+                continue;
+            }
+
+            apps.push(InstalledApp {
+                package_name: manifest.package_name,
+                version_code: manifest.version_code,
+                version_name: manifest.version_name,
+                apk_path,
+            });
+        }
+        apps.sort_by(|a, b| a.package_name.cmp(&b.package_name));
+        Ok(apps)
+    }
+
     pub fn enter_shell(&self, payload_path: &Path) -> Result<()> {
         let (command, args) = self.resolve_shell_command()?;
         self.run_in_sandbox_with_env(payload_path, &command, &args, &[], false)
@@ -129,10 +703,8 @@ impl Prefix {
             ForkResult::Parent { child } => {
                 // Parent process: wait for child
                 match waitpid(child, None)? {
-                    WaitStatus::Exited(_, code) => {
-                        if code != 0 {
-                            return Err(anyhow::anyhow!("Child process exited with code {}", code));
-                        }
+                    WaitStatus::Exited(_, code) if code != 0 => {
+                        return Err(anyhow::anyhow!("Child process exited with code {}", code));
                     }
                     WaitStatus::Signaled(_, signal, _) => {
                         return Err(anyhow::anyhow!("Child process killed by signal {:?}", signal));
@@ -167,22 +739,36 @@ impl Prefix {
         env: &[(String, String)],
         redirect: bool,
     ) -> Result<()> {
-        let log_path = self.root.join("logs/app.log");
-        
-        // Enter namespaces (safe in child process)
-        sandbox::enter_namespaces()?;
+        let log_path = self.log_path();
+        if redirect {
+            fs::create_dir_all(self.log_dir())?;
+            if self.log_rotation.rotate(&log_path)? {
+                info!("Rotated {}", log_path.display());
+            }
+        }
+
+        // Enter namespaces (safe in child process), and become PID 1 of the
+        // new PID namespace so /proc can be mounted for init
+        sandbox::enter_namespaces_with(CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWPID)?;
+        sandbox::fork_pid_namespace_init()?;
 
         // Setup mounts inside the new mount namespace
         self.setup_sandbox_mounts(payload_path)?;
 
         if redirect {
-            let log_file = fs::File::create(&log_path)?;
+            let log_file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&log_path)?;
             sandbox::redirect_stdio(&log_file)?;
         }
 
         // Chroot into the prefix root
         sandbox::chroot(&self.root)?;
 
+        // Last, as it denies mount and friends
+        sandbox::apply_seccomp(self.seccomp)?;
+
         // Exec the command (never returns if successful)
         let mut exec_args = Vec::with_capacity(args.len() + 1);
         exec_args.push(command.to_string());
@@ -278,7 +864,11 @@ impl Prefix {
 
 #[cfg(test)]
 mod tests {
-    use super::Prefix;
+    use super::{
+        quota_image_size, select_abi, select_abi_for_host, LogRotation, Prefix, QuotaPlan, MIN_QUOTA_BYTES,
+    };
+    use crate::image::MountPoints;
+    use apk::{Abi, ApkInfo};
     use std::fs;
     #[cfg(unix)]
     use std::os::unix::fs::PermissionsExt;
@@ -301,6 +891,195 @@ mod tests {
         let _ = fs::remove_dir_all(root);
     }
 
+    #[test]
+    fn quota_image_size_rounds_to_blocks_with_minimum() {
+        assert_eq!(quota_image_size(1), MIN_QUOTA_BYTES);
+        assert_eq!(quota_image_size(512 * 1024 * 1024), 512 * 1024 * 1024);
+        assert_eq!(quota_image_size(MIN_QUOTA_BYTES + 1), MIN_QUOTA_BYTES + 4096);
+        assert_eq!(quota_image_size(MIN_QUOTA_BYTES + 4096), MIN_QUOTA_BYTES + 4096);
+    }
+
+    #[test]
+    #[ignore = "needs mkfs.ext4"]
+    fn quota_image_is_sparse_ext4_of_requested_size() {
+        use std::os::unix::fs::MetadataExt;
+
+        let root = make_temp_prefix_root("quota-image");
+        let seed = root.join(".overlay/upper");
+        fs::create_dir_all(&seed).unwrap();
+        fs::write(seed.join("kept"), b"x").unwrap();
+        let image = root.join(super::QUOTA_IMAGE_FILE);
+
+        let size = quota_image_size(20 * 1024 * 1024 + 1);
+        crate::container::create_storage_image(&image, size, &root.join(".overlay")).unwrap();
+
+        let meta = fs::metadata(&image).unwrap();
+        assert_eq!(meta.len(), size);
+        assert!(meta.blocks() * 512 < size, "image is not sparse");
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn quota_falls_back_without_tools() {
+        assert_eq!(QuotaPlan::new(None, |_| true), QuotaPlan::Unlimited);
+        assert_eq!(
+            QuotaPlan::new(Some(64 * 1024 * 1024), |_| true),
+            QuotaPlan::Image {
+                size_bytes: 64 * 1024 * 1024
+            }
+        );
+        assert_eq!(
+            QuotaPlan::new(Some(64 * 1024 * 1024), |tool| tool != "fuse2fs"),
+            QuotaPlan::Unavailable {
+                missing: vec!["fuse2fs"]
+            }
+        );
+
+        assert_eq!(
+            QuotaPlan::new(Some(1), |_| false),
+            QuotaPlan::Unavailable {
+                missing: vec!["mkfs.ext4", "fuse2fs"]
+            }
+        );
+    }
+
+    #[test]
+    fn list_snapshots_reports_names_and_sizes() {
+        let root = make_temp_prefix_root("list-snapshots");
+        let prefix = Prefix::new(&root);
+        assert!(prefix.list_snapshots().unwrap().is_empty());
+
+        let snapshots = MountPoints::for_prefix(&root).snapshots;
+        fs::create_dir_all(snapshots.join("first/data")).unwrap();
+        fs::write(snapshots.join("first/data/a"), vec![0u8; 100]).unwrap();
+        fs::write(snapshots.join("first/b"), vec![0u8; 20]).unwrap();
+        fs::create_dir_all(snapshots.join("second")).unwrap();
+        fs::write(snapshots.join("stray-file"), b"").unwrap();
+
+        let mut listed = prefix.list_snapshots().unwrap();
+        listed.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let summary: Vec<(&str, u64)> =
+            listed.iter().map(|s| (s.name.as_str(), s.size_bytes)).collect();
+        assert_eq!(summary, vec![("first", 120), ("second", 0)]);
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn delete_refuses_live_pid_file_without_force() {
+        let root = make_temp_prefix_root("delete");
+        let prefix = Prefix::new(&root);
+        prefix.initialize().unwrap();
+        // Our own PID is certainly alive
+        fs::write(prefix.pid_file(), std::process::id().to_string()).unwrap();
+
+        let err = prefix.delete(false).unwrap_err();
+        assert!(err.to_string().contains("still running"));
+        assert!(root.exists());
+
+        prefix.delete(true).unwrap();
+        assert!(!root.exists());
+    }
+
+    #[test]
+    fn log_rotation_shifts_generations_when_full() {
+        let root = make_temp_prefix_root("log-rotation");
+        let prefix = Prefix::new(&root).with_log_dir(root.join("custom-logs"));
+        let rotation = LogRotation {
+            max_bytes: 1024,
+            generations: 2,
+        };
+        fs::create_dir_all(prefix.log_dir()).unwrap();
+        let log = prefix.log_path();
+        assert_eq!(log, root.join("custom-logs/app.log"));
+
+        // Below the threshold nothing moves
+        fs::write(&log, vec![b'a'; 100]).unwrap();
+        assert!(!rotation.rotate(&log).unwrap());
+        assert!(log.exists());
+
+        fs::write(&log, vec![b'b'; 1024]).unwrap();
+        fs::write(root.join("custom-logs/app.log.1"), b"older").unwrap();
+        fs::write(root.join("custom-logs/app.log.2"), b"oldest").unwrap();
+        assert!(rotation.rotate(&log).unwrap());
+
+        assert!(!log.exists());
+        assert_eq!(fs::read(root.join("custom-logs/app.log.1")).unwrap().len(), 1024);
+        assert_eq!(fs::read(root.join("custom-logs/app.log.2")).unwrap(), b"older");
+        assert!(!root.join("custom-logs/app.log.3").exists());
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn migrate_adds_missing_dirs_and_keeps_data() {
+        let root = make_temp_prefix_root("migrate");
+        let prefix = Prefix::new(&root);
+        prefix.initialize().unwrap();
+        fs::write(root.join("data/data/app.db"), b"user data").unwrap();
+        // A prefix from before apex/ and logs/ were part of the layout
+        fs::remove_dir_all(root.join("apex")).unwrap();
+        fs::remove_dir_all(root.join("logs")).unwrap();
+
+        let added = prefix.migrate().unwrap();
+
+        assert_eq!(added, vec!["apex".to_string(), "logs".to_string()]);
+        assert!(root.join("apex").is_dir());
+        assert!(root.join("logs").is_dir());
+        assert_eq!(fs::read(root.join("data/data/app.db")).unwrap(), b"user data");
+        assert!(prefix.migrate().unwrap().is_empty());
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn validate_accepts_initialized_prefix() {
+        let root = make_temp_prefix_root("validate-ok");
+        let prefix = Prefix::new(&root);
+        prefix.initialize().unwrap();
+
+        prefix.validate().unwrap();
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn validate_names_missing_data_dir() {
+        let root = make_temp_prefix_root("validate-missing");
+        let prefix = Prefix::new(&root);
+        prefix.initialize().unwrap();
+        fs::remove_dir_all(root.join("data")).unwrap();
+
+        let err = prefix.validate().unwrap_err().to_string();
+
+        assert!(err.contains("missing directories: data, data/app, data/data"), "{}", err);
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn validate_rejects_stale_pid_and_repair_fixes_it() {
+        let root = make_temp_prefix_root("validate-stale");
+        let prefix = Prefix::new(&root);
+        prefix.initialize().unwrap();
+
+        // A reaped child's PID is dead (and unlikely to be reused right away)
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        fs::write(prefix.pid_file(), pid.to_string()).unwrap();
+
+        let err = prefix.validate().unwrap_err().to_string();
+        assert!(err.contains("Stale PID file"), "{}", err);
+
+        prefix.repair().unwrap();
+        prefix.validate().unwrap();
+        assert!(!prefix.pid_file().exists());
+
+        let _ = fs::remove_dir_all(root);
+    }
+
     #[test]
     #[cfg(unix)]
     fn initialize_sets_0755_permissions() {
@@ -385,6 +1164,196 @@ mod tests {
         let _ = fs::remove_dir_all(payload);
     }
 
+    fn multi_abi_info() -> ApkInfo {
+        ApkInfo {
+            package_name: "com.example.app".to_string(),
+            supported_abis: vec![Abi::Arm64V8a, Abi::X86_64],
+        }
+    }
+
+    #[test]
+    fn select_abi_honors_supported_forced_abi() {
+        let info = multi_abi_info();
+
+        assert_eq!(
+            select_abi_for_host(&info, None, Some(Abi::X86_64)).unwrap(),
+            Some(Abi::X86_64)
+        );
+        assert_eq!(
+            select_abi_for_host(&info, None, Some(Abi::Arm64V8a)).unwrap(),
+            Some(Abi::Arm64V8a)
+        );
+        assert_eq!(
+            select_abi(&info, Some(&Abi::Arm64V8a)).unwrap(),
+            Some(Abi::Arm64V8a)
+        );
+    }
+
+    #[test]
+    fn select_abi_rejects_unsupported_forced_abi() {
+        let err = select_abi(&multi_abi_info(), Some(&Abi::ArmV7a)).unwrap_err();
+
+        let message = err.to_string();
+        assert!(message.contains("armeabi-v7a"));
+        assert!(message.contains("arm64-v8a, x86_64"));
+    }
+
+    #[test]
+    fn install_apk_with_abi_rejects_before_copying() {
+        let root = make_temp_prefix_root("forced-abi");
+        let prefix = Prefix::new(&root);
+
+        let result = prefix.install_apk_with_abi(
+            &root.join("missing.apk"),
+            &multi_abi_info(),
+            Some(&Abi::X86),
+        );
+
+        assert!(result.unwrap_err().to_string().contains("ABI x86 is not supported"));
+        assert!(!root.join("data/app/com.example.app").exists());
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    /// Minimal binary AndroidManifest.xml: `<manifest package=..
+    /// android:versionCode=.. android:versionName=..>`
+    fn binary_manifest(package: &str, version_code: u32, version_name: &str) -> Vec<u8> {
+        fn u16le(out: &mut Vec<u8>, v: u16) {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        fn u32le(out: &mut Vec<u8>, v: u32) {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+        const NONE: u32 = u32::MAX;
+        let strings = [
+            "android",
+            "http://schemas.android.com/apk/res/android",
+            "manifest",
+            "package",
+            "versionCode",
+            "versionName",
+            package,
+            version_name,
+        ];
+
+        // UTF-8 string pool
+        let mut data = Vec::new();
+        let mut offsets = Vec::new();
+        for s in strings {
+            offsets.push(data.len() as u32);
+            data.extend_from_slice(&[s.len() as u8, s.len() as u8]);
+            data.extend_from_slice(s.as_bytes());
+            data.push(0);
+        }
+        data.resize(data.len().div_ceil(4) * 4, 0);
+        let mut pool = Vec::new();
+        let strings_start = 28 + 4 * strings.len() as u32;
+        u16le(&mut pool, 0x0001);
+        u16le(&mut pool, 28);
+        u32le(&mut pool, strings_start + data.len() as u32);
+        u32le(&mut pool, strings.len() as u32);
+        u32le(&mut pool, 0);
+        u32le(&mut pool, 1 << 8);
+        u32le(&mut pool, strings_start);
+        u32le(&mut pool, 0);
+        offsets.iter().for_each(|o| u32le(&mut pool, *o));
+        pool.extend_from_slice(&data);
+
+        let node = |out: &mut Vec<u8>, typ: u16, body: &[u8]| {
+            u16le(out, typ);
+            u16le(out, 16);
+            u32le(out, 16 + body.len() as u32);
+            u32le(out, 1);
+            u32le(out, NONE);
+            out.extend_from_slice(body);
+        };
+        let attr = |out: &mut Vec<u8>, ns: u32, name: u32, string: Option<u32>, value: u32| {
+            u32le(out, ns);
+            u32le(out, name);
+            u32le(out, string.unwrap_or(NONE));
+            u16le(out, 8);
+            out.push(0);
+            out.push(if string.is_some() { 0x03 } else { 0x10 });
+            u32le(out, value);
+        };
+
+        let mut nodes = Vec::new();
+        let namespace = [0u32.to_le_bytes(), 1u32.to_le_bytes()].concat();
+        node(&mut nodes, 0x0100, &namespace);
+        let mut element = Vec::new();
+        u32le(&mut element, NONE);
+        u32le(&mut element, 2);
+        for v in [20u16, 20, 3, 0, 0, 0] {
+            u16le(&mut element, v);
+        }
+        attr(&mut element, NONE, 3, Some(6), 6);
+        attr(&mut element, 1, 4, None, version_code);
+        attr(&mut element, 1, 5, Some(7), 7);
+        node(&mut nodes, 0x0102, &element);
+        node(&mut nodes, 0x0103, &[NONE.to_le_bytes(), 2u32.to_le_bytes()].concat());
+        node(&mut nodes, 0x0101, &namespace);
+
+        // Empty resource map
+        let resource_map = [0x80u8, 0x01, 8, 0, 8, 0, 0, 0];
+        let mut doc = Vec::new();
+        u16le(&mut doc, 0x0003);
+        u16le(&mut doc, 8);
+        u32le(&mut doc, (8 + pool.len() + resource_map.len() + nodes.len()) as u32);
+        doc.extend_from_slice(&pool);
+        doc.extend_from_slice(&resource_map);
+        doc.extend_from_slice(&nodes);
+        doc
+    }
+
+    fn write_fake_apk(path: &std::path::Path, package: &str, version_code: u32) {
+        use std::io::Write;
+
+        let mut zip = zip::ZipWriter::new(fs::File::create(path).unwrap());
+        zip.start_file("AndroidManifest.xml", zip::write::FileOptions::default())
+            .unwrap();
+        zip.write_all(&binary_manifest(package, version_code, "1.0"))
+            .unwrap();
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn installed_packages_lists_installed_apks() {
+        let root = make_temp_prefix_root("installed");
+        let prefix = Prefix::new(&root);
+        prefix.initialize().unwrap();
+        assert!(prefix.installed_packages().unwrap().is_empty());
+
+        for (package, version) in [("org.example.notes", 7), ("com.example.game", 42)] {
+            let apk = root.join(format!("{package}.apk"));
+            write_fake_apk(&apk, package, version);
+            let info = ApkInfo {
+                package_name: package.to_string(),
+                supported_abis: Vec::new(),
+            };
+            prefix.install_apk(&apk, &info).unwrap();
+        }
+        // Not an APK at all
+        fs::create_dir_all(root.join("data/app/com.example.broken")).unwrap();
+        fs::write(root.join("data/app/com.example.broken/base.apk"), b"junk").unwrap();
+
+        let apps = prefix.installed_packages().unwrap();
+        let summary: Vec<(&str, Option<i32>)> = apps
+            .iter()
+            .map(|a| (a.package_name.as_str(), a.version_code))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![("com.example.game", Some(42)), ("org.example.notes", Some(7))]
+        );
+        assert_eq!(apps[0].version_name.as_deref(), Some("1.0"));
+        assert_eq!(
+            apps[0].apk_path,
+            root.join("data/app/com.example.game/base.apk")
+        );
+
+        let _ = fs::remove_dir_all(root);
+    }
+
     fn make_temp_prefix_root(label: &str) -> PathBuf {
         let nanos = SystemTime::now()
             .duration_since(UNIX_EPOCH)
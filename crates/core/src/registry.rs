@@ -0,0 +1,249 @@
+//! Tool-wide table of running containers.
+//!
+//! Every `run`/`shell`/`stop` invocation records its container here
+//! (package → init PID and start time), so separate invocations share one view
+//! of what is running. The table is a small JSON file under the RAD data dir;
+//! all read-modify-write cycles hold an exclusive `flock` on a sidecar lock
+//! file so concurrent invocations can't lose each other's updates.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use nix::fcntl::{flock, FlockArg};
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+
+/// A running container as recorded in the table
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerEntry {
+    /// Outer PID of the container init process
+    pub pid: u32,
+    /// Start time in seconds since the Unix epoch
+    pub started_at: u64,
+}
+
+pub type Entries = BTreeMap<String, ContainerEntry>;
+
+/// Name of the table file in the RAD data dir
+const TABLE_FILE: &str = "containers.json";
+
+pub struct ContainerTable {
+    path: PathBuf,
+}
+
+impl ContainerTable {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Default table location: `containers.json` in the RAD data dir (see
+    /// [`image::data_dir`](crate::image::data_dir)), next to the images
+    pub fn default_location() -> Result<Self> {
+        Ok(Self::new(crate::image::data_dir()?.join(TABLE_FILE)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Record `package` as running with init `pid`, replacing any previous entry
+    pub fn add(&self, package: &str, pid: u32) -> Result<()> {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        self.with_locked(|entries| {
+            entries.insert(package.to_string(), ContainerEntry { pid, started_at });
+        })
+    }
+
+    /// Forget `package`, returning its entry if there was one
+    pub fn remove(&self, package: &str) -> Result<Option<ContainerEntry>> {
+        self.with_locked(|entries| entries.remove(package))
+    }
+
+    /// Look up `package` (without checking that its PID is alive)
+    pub fn get(&self, package: &str) -> Result<Option<ContainerEntry>> {
+        self.with_locked(|entries| entries.get(package).cloned())
+    }
+
+    /// All running containers, after pruning entries whose PID is dead
+    pub fn list(&self) -> Result<Entries> {
+        self.prune()?;
+        self.with_locked(|entries| entries.clone())
+    }
+
+    /// Drop entries whose init process no longer exists, returning their packages
+    pub fn prune(&self) -> Result<Vec<String>> {
+        self.prune_with(pid_alive)
+    }
+
+    fn prune_with<AliveFn>(&self, alive: AliveFn) -> Result<Vec<String>>
+    where
+        AliveFn: Fn(u32) -> bool,
+    {
+        self.with_locked(|entries| {
+            let dead: Vec<String> = entries
+                .iter()
+                .filter(|(_, entry)| !alive(entry.pid))
+                .map(|(package, _)| package.clone())
+                .collect();
+            for package in &dead {
+                entries.remove(package);
+            }
+            dead
+        })
+    }
+
+    /// Run `f` on the table while holding the lock, then persist the result
+    fn with_locked<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Entries) -> T,
+    {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        let lock_path = self.path.with_extension("lock");
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("Failed to open {}", lock_path.display()))?;
+        flock(lock.as_raw_fd(), FlockArg::LockExclusive)
+            .with_context(|| format!("Failed to lock {}", lock_path.display()))?;
+
+        let mut entries = self.read_entries()?;
+        let before = entries.clone();
+        let result = f(&mut entries);
+        if entries != before {
+            self.write_entries(&entries)?;
+        }
+
+        // The lock is released when `lock` is closed
+        drop(lock);
+        Ok(result)
+    }
+
+    fn read_entries(&self) -> Result<Entries> {
+        match File::open(&self.path) {
+            Ok(file) => serde_json::from_reader(file)
+                .with_context(|| format!("Failed to parse {}", self.path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Entries::new()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", self.path.display())),
+        }
+    }
+
+    fn write_entries(&self, entries: &Entries) -> Result<()> {
+        // Write to a temp file and rename so readers never see a partial table
+        let tmp_path = self.path.with_extension("json.tmp");
+        let content = serde_json::to_string_pretty(entries)?;
+        fs::write(&tmp_path, content)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Failed to update {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+fn pid_alive(pid: u32) -> bool {
+    nix::sys::signal::kill(Pid::from_raw(pid as i32), None).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_temp_table(label: &str) -> (PathBuf, ContainerTable) {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("rad-registry-{label}-{nanos}"));
+        let table = ContainerTable::new(dir.join("containers.json"));
+        (dir, table)
+    }
+
+    #[test]
+    fn add_get_remove_roundtrip() {
+        let (dir, table) = make_temp_table("roundtrip");
+
+        table.add("com.example", 1234).unwrap();
+        assert_eq!(table.get("com.example").unwrap().unwrap().pid, 1234);
+
+        let removed = table.remove("com.example").unwrap();
+        assert_eq!(removed.map(|e| e.pid), Some(1234));
+        assert!(table.get("com.example").unwrap().is_none());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn concurrent_add_and_remove_keep_every_update() {
+        let (dir, table) = make_temp_table("concurrent");
+        let path = table.path().to_path_buf();
+
+        let handles: Vec<_> = (0..16)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let table = ContainerTable::new(&path);
+                    table.add(&format!("pkg.{i}"), 1000 + i).unwrap();
+                    // Odd packages stop again right away
+                    if i % 2 == 1 {
+                        table.remove(&format!("pkg.{i}")).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let entries = table.with_locked(|entries| entries.clone()).unwrap();
+        let expected: Vec<String> = (0..16)
+            .filter(|i| i % 2 == 0)
+            .map(|i| format!("pkg.{i}"))
+            .collect();
+        assert_eq!(entries.len(), expected.len());
+        for package in expected {
+            assert!(entries.contains_key(&package), "missing {}", package);
+        }
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn prune_drops_dead_pids() {
+        let (dir, table) = make_temp_table("prune");
+        table.add("alive", 100).unwrap();
+        table.add("dead", 200).unwrap();
+
+        let pruned = table.prune_with(|pid| pid == 100).unwrap();
+
+        assert_eq!(pruned, vec!["dead".to_string()]);
+        assert!(table.get("alive").unwrap().is_some());
+        assert!(table.get("dead").unwrap().is_none());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn list_keeps_own_process() {
+        let (dir, table) = make_temp_table("list");
+        table.add("self", std::process::id()).unwrap();
+
+        assert!(table.list().unwrap().contains_key("self"));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
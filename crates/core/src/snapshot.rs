@@ -0,0 +1,206 @@
+//! Copies of the overlay upper layer, for checkpointing a stopped container.
+//!
+//! Regular files are copied (overlayfs writes to copied-up files in place, so
+//! a hardlink would change along with the live layer). Whiteouts and other
+//! special files are immutable and get hardlinked, since an unprivileged
+//! process may not be able to mknod them; across filesystems (e.g. with the
+//! quota image mounted over the upper dir) they are recreated with mknod.
+//! Overlay xattrs (opaque dirs, redirects) are kept.
+
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{PermissionsExt, symlink};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use nix::libc;
+
+/// Check a snapshot name is usable as a single directory name
+pub fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\0') {
+        anyhow::bail!("Invalid snapshot name: {:?}", name);
+    }
+    Ok(())
+}
+
+/// Copy the tree at `src` to `dst`, which must not exist yet
+pub fn copy_tree(src: &Path, dst: &Path) -> Result<()> {
+    let metadata =
+        fs::symlink_metadata(src).with_context(|| format!("Failed to stat {}", src.display()))?;
+    let file_type = metadata.file_type();
+
+    if file_type.is_dir() {
+        fs::create_dir(dst).with_context(|| format!("Failed to create {}", dst.display()))?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_tree(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+        fs::set_permissions(
+            dst,
+            fs::Permissions::from_mode(metadata.permissions().mode()),
+        )?;
+    } else if file_type.is_symlink() {
+        symlink(fs::read_link(src)?, dst)?;
+    } else if file_type.is_file() {
+        fs::copy(src, dst).with_context(|| format!("Failed to copy {}", src.display()))?;
+    } else {
+        match fs::hard_link(src, dst) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.raw_os_error() == Some(libc::EXDEV) => recreate_special(&metadata, dst)
+                .with_context(|| format!("Failed to recreate special file {}", src.display()))?,
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to link special file {}", src.display()));
+            }
+        }
+    }
+
+    copy_overlay_xattrs(src, dst);
+    Ok(())
+}
+
+/// Total size of the files in a tree, without following symlinks
+pub fn tree_size(path: &Path) -> Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Ok(metadata.len());
+    }
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        size += tree_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+/// Replace the tree at `live` with a copy of `snapshot`
+///
+/// The copy is made next to `live` first, so a failed copy leaves `live` untouched.
+pub fn restore_tree(snapshot: &Path, live: &Path) -> Result<()> {
+    let staging = sibling(live, "restore");
+    let old = sibling(live, "old");
+    for leftover in [&staging, &old] {
+        if leftover.exists() {
+            fs::remove_dir_all(leftover)?;
+        }
+    }
+
+    if let Err(e) = copy_tree(snapshot, &staging) {
+        let _ = fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+    if live.exists() {
+        fs::rename(live, &old)?;
+    }
+    fs::rename(&staging, live)?;
+    if old.exists() {
+        fs::remove_dir_all(&old)?;
+    }
+    Ok(())
+}
+
+/// `<dir>.<suffix>` next to `path`
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", suffix));
+    PathBuf::from(name)
+}
+
+/// mknod a device, FIFO or socket like the one `metadata` describes at `dst`
+fn recreate_special(metadata: &fs::Metadata, dst: &Path) -> Result<()> {
+    use nix::sys::stat::{Mode, SFlag, mknod};
+    use std::os::unix::fs::MetadataExt;
+
+    let mode = metadata.mode();
+    mknod(
+        dst,
+        SFlag::from_bits_truncate(mode & libc::S_IFMT),
+        Mode::from_bits_truncate(mode & 0o7777),
+        metadata.rdev(),
+    )?;
+    Ok(())
+}
+
+/// Copy `trusted.overlay.*`/`user.overlay.*` xattrs, best effort
+fn copy_overlay_xattrs(src: &Path, dst: &Path) {
+    let (Ok(src), Ok(dst)) = (
+        CString::new(src.as_os_str().as_bytes()),
+        CString::new(dst.as_os_str().as_bytes()),
+    ) else {
+        return;
+    };
+
+    let mut names = vec![0u8; 4096];
+    // SAFETY: `src` is NUL-terminated and `names` is writable for its length
+    let len = unsafe { libc::llistxattr(src.as_ptr(), names.as_mut_ptr().cast(), names.len()) };
+    if len <= 0 {
+        return;
+    }
+    names.truncate(len as usize);
+
+    for name in names.split(|b| *b == 0).filter(|n| !n.is_empty()) {
+        if !(name.starts_with(b"trusted.overlay.") || name.starts_with(b"user.overlay.")) {
+            continue;
+        }
+        let Ok(name) = CString::new(name) else {
+            continue;
+        };
+        let mut value = vec![0u8; 4096];
+        // SAFETY: `src` and `name` are NUL-terminated and `value` is writable
+        // for its length
+        let len = unsafe {
+            libc::lgetxattr(
+                src.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        if len < 0 {
+            continue;
+        }
+        // SAFETY: `dst` and `name` are NUL-terminated and `value` holds `len`
+        // initialized bytes
+        unsafe {
+            libc::lsetxattr(
+                dst.as_ptr(),
+                name.as_ptr(),
+                value.as_ptr().cast(),
+                len as usize,
+                0,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_name_rejects_paths() {
+        assert!(validate_name("clean-install").is_ok());
+        for name in ["", ".", "..", "a/b", "../up"] {
+            assert!(validate_name(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn recreate_special_makes_a_matching_fifo() {
+        use std::os::unix::fs::FileTypeExt;
+
+        let dir = std::env::temp_dir().join(format!("rad-snapshot-fifo-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("src");
+        nix::unistd::mkfifo(&src, nix::sys::stat::Mode::from_bits_truncate(0o640)).unwrap();
+
+        let dst = dir.join("dst");
+        recreate_special(&fs::symlink_metadata(&src).unwrap(), &dst).unwrap();
+        let metadata = fs::symlink_metadata(&dst).unwrap();
+        assert!(metadata.file_type().is_fifo());
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o640);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
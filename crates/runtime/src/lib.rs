@@ -8,14 +8,19 @@
 //! The runtime provides minimal stub services that Android apps expect:
 //! - ActivityManager: App lifecycle and permission checks
 //! - PackageManager: Package and component queries
+//! - ServiceManager: Lookup of the other services by name
 //!
 //! These stubs prevent apps from crashing when they try to access
 //! system services via Binder IPC.
 
+pub mod parcel;
+
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock, Weak};
 use tracing::{info, warn, debug};
 
+use crate::parcel::{no_exception_reply, null_binder_reply, Parcel};
+
 /// Result type for runtime operations
 pub type Result<T> = std::result::Result<T, RuntimeError>;
 
@@ -26,6 +31,7 @@ pub enum RuntimeError {
     ServiceAlreadyRegistered(String),
     InvalidRequest(String),
     NotImplemented(String),
+    InterfaceMismatch { expected: String, found: String },
 }
 
 impl std::fmt::Display for RuntimeError {
@@ -35,6 +41,9 @@ impl std::fmt::Display for RuntimeError {
             RuntimeError::ServiceAlreadyRegistered(name) => write!(f, "Service already registered: {}", name),
             RuntimeError::InvalidRequest(msg) => write!(f, "Invalid request: {}", msg),
             RuntimeError::NotImplemented(feature) => write!(f, "Not implemented: {}", feature),
+            RuntimeError::InterfaceMismatch { expected, found } => {
+                write!(f, "Interface mismatch: expected {}, got {}", expected, found)
+            }
         }
     }
 }
@@ -51,6 +60,50 @@ pub trait StubService: Send + Sync {
     /// Get the service interface descriptor
     fn interface_descriptor(&self) -> &str;
     
+    /// Interface layouts this stub can be pinned to, oldest first
+    ///
+    /// Empty for stubs whose descriptor doesn't vary across releases.
+    fn supported_interface_versions(&self) -> &[InterfaceVersion] {
+        &[]
+    }
+    
+    /// The layout whose transaction codes `handle_transaction` understands
+    ///
+    /// Defaults to the newest supported one.
+    fn active_interface_version(&self) -> Option<&InterfaceVersion> {
+        self.supported_interface_versions().last()
+    }
+    
+    /// Handle a raw binder transaction
+    ///
+    /// The default answers the `IBinder` meta transactions itself and maps
+    /// other codes to a method name through
+    /// [`active_interface_version`](Self::active_interface_version), then
+    /// calls [`handle_call`](Self::handle_call) with the unchanged parcel.
+    fn handle_transaction(&self, code: u32, data: &[u8]) -> Result<Vec<u8>> {
+        match code {
+            INTERFACE_TRANSACTION => {
+                let mut reply = Parcel::new();
+                reply.write_string16(Some(self.interface_descriptor()));
+                Ok(reply.into_bytes())
+            }
+            PING_TRANSACTION => Ok(Vec::new()),
+            _ => {
+                let method = self
+                    .active_interface_version()
+                    .and_then(|version| version.method_name(code))
+                    .ok_or_else(|| {
+                        RuntimeError::NotImplemented(format!(
+                            "transaction {} on {}",
+                            code,
+                            self.interface_descriptor()
+                        ))
+                    })?;
+                self.handle_call(method, data)
+            }
+        }
+    }
+    
     /// Handle a method call
     /// 
     /// # Arguments
@@ -61,8 +114,135 @@ pub trait StubService: Send + Sync {
     /// Serialized response data
     fn handle_call(&self, method: &str, _args: &[u8]) -> Result<Vec<u8>> {
         debug!("Stub service '{}' received call to '{}'", self.service_name(), method);
-        // Default implementation returns empty success
-        Ok(Vec::new())
+        // Default implementation returns success with no return value
+        Ok(no_exception_reply())
+    }
+}
+
+/// The descriptor and transaction codes of an interface from one API level on
+///
+/// Binder calls are identified by code, not name, and the codes follow the
+/// method order of the interface, which moves between releases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterfaceVersion {
+    /// First API level with this layout
+    pub min_sdk: i32,
+    pub descriptor: &'static str,
+    /// Method name to transaction code
+    pub codes: &'static [(&'static str, u32)],
+}
+
+impl InterfaceVersion {
+    /// The entry of `versions` (sorted by `min_sdk`) that applies to `sdk`
+    pub fn select(versions: &[InterfaceVersion], sdk: i32) -> Option<&InterfaceVersion> {
+        versions.iter().rev().find(|v| v.min_sdk <= sdk)
+    }
+
+    pub fn transaction_code(&self, method: &str) -> Option<u32> {
+        self.codes.iter().find(|(name, _)| *name == method).map(|(_, code)| *code)
+    }
+
+    pub fn method_name(&self, code: u32) -> Option<&'static str> {
+        self.codes.iter().find(|(_, c)| *c == code).map(|(name, _)| *name)
+    }
+}
+
+/// `PackageManager.PERMISSION_GRANTED`
+pub const PERMISSION_GRANTED: i32 = 0;
+
+/// `IBinder.FIRST_CALL_TRANSACTION`
+pub const FIRST_CALL_TRANSACTION: u32 = 1;
+
+/// `IBinder.PING_TRANSACTION` (`'_PNG'`)
+pub const PING_TRANSACTION: u32 = 0x5f50_4e47;
+
+/// `IBinder.INTERFACE_TRANSACTION` (`'_NTF'`), answered with the descriptor
+pub const INTERFACE_TRANSACTION: u32 = 0x5f4e_5446;
+
+/// Transaction codes of `android.app.IActivityManager`
+pub struct IActivityManager;
+
+impl IActivityManager {
+    pub const DESCRIPTOR: &'static str = "android.app.IActivityManager";
+    /// Generated from IActivityManager.aidl since Oreo
+    pub const CHECK_PERMISSION_TRANSACTION: u32 = FIRST_CALL_TRANSACTION + 8;
+    /// Hand-written ActivityManagerNative before Oreo
+    pub const LEGACY_CHECK_PERMISSION_TRANSACTION: u32 = FIRST_CALL_TRANSACTION + 53;
+}
+
+/// Transaction codes of `android.content.pm.IPackageManager`
+pub struct IPackageManager;
+
+impl IPackageManager {
+    pub const DESCRIPTOR: &'static str = "android.content.pm.IPackageManager";
+    /// Third method of IPackageManager.aidl, after checkPackageStartable and
+    /// isPackageAvailable
+    pub const GET_PACKAGE_INFO_TRANSACTION: u32 = FIRST_CALL_TRANSACTION + 2;
+}
+
+/// Transaction codes of `android.os.IServiceManager`
+///
+/// The same before and after the interface moved to AIDL in Android 11.
+pub struct IServiceManager;
+
+impl IServiceManager {
+    pub const DESCRIPTOR: &'static str = "android.os.IServiceManager";
+    pub const GET_SERVICE_TRANSACTION: u32 = FIRST_CALL_TRANSACTION;
+    pub const CHECK_SERVICE_TRANSACTION: u32 = FIRST_CALL_TRANSACTION + 1;
+}
+
+/// `IActivityManager` layouts, oldest first
+const ACTIVITY_MANAGER_VERSIONS: &[InterfaceVersion] = &[
+    InterfaceVersion {
+        min_sdk: 1,
+        descriptor: IActivityManager::DESCRIPTOR,
+        codes: &[(
+            "checkPermission",
+            IActivityManager::LEGACY_CHECK_PERMISSION_TRANSACTION,
+        )],
+    },
+    InterfaceVersion {
+        min_sdk: 26,
+        descriptor: IActivityManager::DESCRIPTOR,
+        codes: &[("checkPermission", IActivityManager::CHECK_PERMISSION_TRANSACTION)],
+    },
+];
+
+/// `IServiceManager` layouts, oldest first
+const SERVICE_MANAGER_VERSIONS: &[InterfaceVersion] = &[InterfaceVersion {
+    min_sdk: 1,
+    descriptor: IServiceManager::DESCRIPTOR,
+    codes: &[
+        ("getService", IServiceManager::GET_SERVICE_TRANSACTION),
+        ("checkService", IServiceManager::CHECK_SERVICE_TRANSACTION),
+    ],
+}];
+
+/// `IPackageManager` layouts, oldest first
+const PACKAGE_MANAGER_VERSIONS: &[InterfaceVersion] = &[InterfaceVersion {
+    min_sdk: 24,
+    descriptor: IPackageManager::DESCRIPTOR,
+    codes: &[("getPackageInfo", IPackageManager::GET_PACKAGE_INFO_TRANSACTION)],
+}];
+
+/// Arguments of `IActivityManager.checkPermission(String permission, int pid, int uid)`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckPermissionArgs {
+    pub permission: String,
+    pub pid: i32,
+    pub uid: i32,
+}
+
+impl CheckPermissionArgs {
+    /// Read the arguments that follow the interface token
+    pub fn read_from(parcel: &mut Parcel) -> Result<Self> {
+        let permission = parcel.read_string16()?.ok_or_else(|| {
+            RuntimeError::InvalidRequest("checkPermission: null permission".to_string())
+        })?;
+        let pid = parcel.read_i32()?;
+        let uid = parcel.read_i32()?;
+
+        Ok(Self { permission, pid, uid })
     }
 }
 
@@ -72,16 +252,33 @@ pub trait StubService: Send + Sync {
 /// - Permission checks (always allow)
 /// - App ops queries (always allow)
 /// - Process management (no-op)
+///
+/// Answers with the newest known interface layout unless pinned to an API
+/// level with [`ActivityManagerStub::with_sdk`].
 pub struct ActivityManagerStub {
     name: String,
+    version: &'static InterfaceVersion,
 }
 
 impl ActivityManagerStub {
     pub fn new() -> Self {
         Self {
             name: "activity".to_string(),
+            version: ACTIVITY_MANAGER_VERSIONS.last().expect("no IActivityManager layouts"),
         }
     }
+
+    /// Use the descriptor and transaction codes of API level `sdk`
+    pub fn with_sdk(mut self, sdk: i32) -> Self {
+        self.version = InterfaceVersion::select(ACTIVITY_MANAGER_VERSIONS, sdk)
+            .unwrap_or(&ACTIVITY_MANAGER_VERSIONS[0]);
+        self
+    }
+
+    /// The interface layout this stub answers with
+    pub fn interface_version(&self) -> &InterfaceVersion {
+        self.version
+    }
 }
 
 impl Default for ActivityManagerStub {
@@ -96,18 +293,39 @@ impl StubService for ActivityManagerStub {
     }
     
     fn interface_descriptor(&self) -> &str {
-        "android.app.IActivityManager"
+        self.version.descriptor
     }
     
-    fn handle_call(&self, method: &str, _args: &[u8]) -> Result<Vec<u8>> {
+    fn supported_interface_versions(&self) -> &[InterfaceVersion] {
+        ACTIVITY_MANAGER_VERSIONS
+    }
+    
+    fn active_interface_version(&self) -> Option<&InterfaceVersion> {
+        Some(self.version)
+    }
+    
+    fn handle_call(&self, method: &str, args: &[u8]) -> Result<Vec<u8>> {
         match method {
             "checkPermission" => {
-                // Return permission granted (1 = PERMISSION_GRANTED)
-                Ok(vec![1, 0, 0, 0]) // i32 as little-endian bytes
+                // Callers that don't pass a parcel get the blanket grant
+                if !args.is_empty() {
+                    let mut parcel = Parcel::from_bytes(args);
+                    parcel.enforce_interface(self.interface_descriptor())?;
+                    let request = CheckPermissionArgs::read_from(&mut parcel)?;
+                    debug!(
+                        "checkPermission({}, pid={}, uid={})",
+                        request.permission, request.pid, request.uid
+                    );
+                }
+                // Always granted, after the AIDL reply header
+                let mut reply = Parcel::new();
+                reply.write_no_exception();
+                reply.write_i32(PERMISSION_GRANTED);
+                Ok(reply.into_bytes())
             }
             "getAppOpsService" => {
-                // Return empty (null service reference)
-                Ok(Vec::new())
+                // Return a null service reference
+                Ok(null_binder_reply())
             }
             _ => {
                 warn!("ActivityManager method '{}' not implemented in stub", method);
@@ -117,19 +335,86 @@ impl StubService for ActivityManagerStub {
     }
 }
 
+/// The package a [`PackageManagerStub`] reports as installed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageInfo {
+    pub package_name: String,
+    pub version_code: i32,
+    /// Linux uid the app runs as
+    pub uid: i32,
+    /// Private data directory, e.g. "/data/data/com.example.app"
+    pub data_dir: String,
+}
+
+impl PackageInfo {
+    /// Write this as the `PackageInfo` parcelable returned by `getPackageInfo`
+    ///
+    /// This is a minimal stub layout, not AOSP's: the head mirrors the start
+    /// of `PackageInfo.writeToParcel` with everything the stub doesn't track
+    /// written as null or 0, but the ApplicationInfo that follows carries only
+    /// the four fields below instead of `ApplicationInfo.writeToParcel`. A
+    /// framework client reading past `sharedUserLabel` won't parse it.
+    ///
+    /// | Field                         | Encoding                    |
+    /// |-------------------------------|-----------------------------|
+    /// | (non-null marker)             | i32 1                       |
+    /// | packageName                   | String16                    |
+    /// | splitNames                    | i32 -1 (null array)         |
+    /// | versionCode                   | i32                         |
+    /// | versionCodeMajor              | i32 0                       |
+    /// | versionName                   | String16 null (i32 -1)      |
+    /// | baseRevisionCode              | i32 0                       |
+    /// | splitRevisionCodes            | i32 -1 (null array)         |
+    /// | sharedUserId                  | String16 null (i32 -1)      |
+    /// | sharedUserLabel               | i32 0                       |
+    /// | (applicationInfo flag)        | i32 1                       |
+    /// | applicationInfo.name          | String16 (the package name) |
+    /// | applicationInfo.packageName   | String16                    |
+    /// | applicationInfo.uid           | i32                         |
+    /// | applicationInfo.dataDir       | String16                    |
+    pub fn write_to(&self, parcel: &mut Parcel) {
+        parcel.write_presence(true);
+        parcel.write_string16(Some(&self.package_name));
+        parcel.write_null_array();
+        parcel.write_i32(self.version_code);
+        parcel.write_i32(0);
+        parcel.write_string16(None);
+        parcel.write_i32(0);
+        parcel.write_null_array();
+        parcel.write_string16(None);
+        parcel.write_i32(0);
+
+        parcel.write_presence(true);
+        parcel.write_string16(Some(&self.package_name));
+        parcel.write_string16(Some(&self.package_name));
+        parcel.write_i32(self.uid);
+        parcel.write_string16(Some(&self.data_dir));
+    }
+}
+
 /// Stub implementation of PackageManager service
 ///
-/// Provides minimal responses for package queries
+/// Provides minimal responses for package queries. `getPackageInfo` knows
+/// only the package set with [`PackageManagerStub::with_package`]; any
+/// other name gets a null `PackageInfo`.
 pub struct PackageManagerStub {
     name: String,
+    package: Option<PackageInfo>,
 }
 
 impl PackageManagerStub {
     pub fn new() -> Self {
         Self {
             name: "package".to_string(),
+            package: None,
         }
     }
+
+    /// Report `package` as the installed app
+    pub fn with_package(mut self, package: PackageInfo) -> Self {
+        self.package = Some(package);
+        self
+    }
 }
 
 impl Default for PackageManagerStub {
@@ -144,60 +429,242 @@ impl StubService for PackageManagerStub {
     }
     
     fn interface_descriptor(&self) -> &str {
-        "android.content.pm.IPackageManager"
+        IPackageManager::DESCRIPTOR
+    }
+    
+    fn supported_interface_versions(&self) -> &[InterfaceVersion] {
+        PACKAGE_MANAGER_VERSIONS
+    }
+    
+    fn handle_call(&self, method: &str, args: &[u8]) -> Result<Vec<u8>> {
+        match method {
+            "getPackageInfo" => {
+                // getPackageInfo(String packageName, int/long flags, int userId);
+                // only the name matters here. Callers that don't pass a
+                // parcel didn't name a package, so they get null
+                let requested = if args.is_empty() {
+                    None
+                } else {
+                    let mut parcel = Parcel::from_bytes(args);
+                    parcel.enforce_interface(self.interface_descriptor())?;
+                    Some(parcel.read_string16()?.ok_or_else(|| {
+                        RuntimeError::InvalidRequest("getPackageInfo: null package name".to_string())
+                    })?)
+                };
+                let package = self
+                    .package
+                    .as_ref()
+                    .filter(|p| requested.as_deref() == Some(p.package_name.as_str()));
+                debug!("getPackageInfo({:?}) -> {}", requested, package.is_some());
+
+                let mut reply = Parcel::new();
+                reply.write_no_exception();
+                match package {
+                    Some(package) => package.write_to(&mut reply),
+                    None => reply.write_presence(false),
+                }
+                Ok(reply.into_bytes())
+            }
+            _ => {
+                debug!("Stub service '{}' received call to '{}'", self.name, method);
+                Ok(no_exception_reply())
+            }
+        }
+    }
+}
+
+/// Stub of `servicemanager`, the context manager (handle 0) apps ask for
+/// every other service
+///
+/// `getService` and `checkService` answer with the binder handle of the
+/// named service in the [`ServiceRegistry`] it was created from (see
+/// [`ServiceRegistry::as_service_manager`]), or null if there is none.
+pub struct ServiceManagerStub {
+    name: String,
+    registry: Weak<ServiceRegistry>,
+}
+
+impl ServiceManagerStub {
+    fn new(registry: Weak<ServiceRegistry>) -> Self {
+        Self {
+            name: "manager".to_string(),
+            registry,
+        }
+    }
+}
+
+impl StubService for ServiceManagerStub {
+    fn service_name(&self) -> &str {
+        &self.name
+    }
+    
+    fn interface_descriptor(&self) -> &str {
+        IServiceManager::DESCRIPTOR
+    }
+    
+    fn supported_interface_versions(&self) -> &[InterfaceVersion] {
+        SERVICE_MANAGER_VERSIONS
+    }
+    
+    fn handle_call(&self, method: &str, args: &[u8]) -> Result<Vec<u8>> {
+        match method {
+            "getService" | "checkService" => {
+                let mut parcel = Parcel::from_bytes(args);
+                parcel.enforce_interface(self.interface_descriptor())?;
+                let name = parcel.read_string16()?.ok_or_else(|| {
+                    RuntimeError::InvalidRequest(format!("{}: null service name", method))
+                })?;
+                let registry = self
+                    .registry
+                    .upgrade()
+                    .ok_or_else(|| RuntimeError::ServiceNotFound(name.clone()))?;
+                let handle = registry.handle_of(&name);
+                debug!("{}({}) -> {:?}", method, name, handle);
+                
+                let mut reply = Parcel::new();
+                reply.write_no_exception();
+                match handle {
+                    Some(handle) => reply.write_strong_binder_handle(handle),
+                    None => reply.write_strong_binder_null(),
+                }
+                Ok(reply.into_bytes())
+            }
+            _ => {
+                warn!("ServiceManager method '{}' not implemented in stub", method);
+                Ok(no_exception_reply())
+            }
+        }
     }
 }
 
+/// Binder handle of the context manager (`servicemanager`)
+pub const CONTEXT_MANAGER_HANDLE: u32 = 0;
+
 /// Service registry for managing stub services
 ///
 /// Maintains a registry of all available stub services and provides
-/// lookup by service name.
+/// lookup by service name. Each service gets a binder handle when it is
+/// registered: the context manager (the service answering for
+/// `IServiceManager`) always gets handle 0, the others count up from 1.
+///
+/// Registration only needs `&self`, so services can still be added once the
+/// registry is shared, e.g. the [`ServiceManagerStub`] that refers back to it.
 pub struct ServiceRegistry {
-    services: HashMap<String, Arc<dyn StubService>>,
+    services: RwLock<HashMap<String, RegisteredService>>,
+}
+
+struct RegisteredService {
+    handle: u32,
+    service: Arc<dyn StubService>,
 }
 
 impl ServiceRegistry {
     /// Create a new empty service registry
     pub fn new() -> Self {
         Self {
-            services: HashMap::new(),
+            services: RwLock::new(HashMap::new()),
         }
     }
     
     /// Register a stub service
     ///
     /// # Errors
-    /// Returns error if a service with the same name is already registered
-    pub fn register(&mut self, service: Arc<dyn StubService>) -> Result<()> {
+    /// Returns error if a service with the same name, or a second context
+    /// manager, is already registered
+    pub fn register(&self, service: Arc<dyn StubService>) -> Result<()> {
         let name = service.service_name().to_string();
+        let mut services = self.services.write().unwrap_or_else(|e| e.into_inner());
         
-        if self.services.contains_key(&name) {
+        if services.contains_key(&name) {
             return Err(RuntimeError::ServiceAlreadyRegistered(name));
         }
         
-        info!("Registering stub service: {}", name);
-        self.services.insert(name, service);
+        let has_manager = services.values().any(|entry| entry.handle == CONTEXT_MANAGER_HANDLE);
+        let handle = if service.interface_descriptor() == IServiceManager::DESCRIPTOR {
+            if has_manager {
+                return Err(RuntimeError::ServiceAlreadyRegistered(name));
+            }
+            CONTEXT_MANAGER_HANDLE
+        } else {
+            (services.len() - usize::from(has_manager)) as u32 + 1
+        };
+        info!("Registering stub service: {} (handle {})", name, handle);
+        services.insert(name, RegisteredService { handle, service });
         Ok(())
     }
     
     /// Lookup a service by name
     pub fn get_service(&self, name: &str) -> Option<Arc<dyn StubService>> {
-        self.services.get(name).cloned()
+        self.read().get(name).map(|entry| entry.service.clone())
+    }
+    
+    /// The binder handle assigned to service `name`
+    pub fn handle_of(&self, name: &str) -> Option<u32> {
+        self.read().get(name).map(|entry| entry.handle)
+    }
+    
+    /// Find the service implementing interface `descriptor`
+    ///
+    /// Matches the descriptor a service currently answers with as well as
+    /// those of the other layouts it supports.
+    pub fn get_service_by_descriptor(&self, descriptor: &str) -> Option<Arc<dyn StubService>> {
+        self.read()
+            .values()
+            .map(|entry| &entry.service)
+            .find(|service| {
+                service.interface_descriptor() == descriptor
+                    || service
+                        .supported_interface_versions()
+                        .iter()
+                        .any(|v| v.descriptor == descriptor)
+            })
+            .cloned()
+    }
+    
+    /// Route a call to the service named by the parcel's interface token
+    pub fn dispatch(&self, method: &str, data: &[u8]) -> Result<Vec<u8>> {
+        let token = Parcel::from_bytes(data).read_interface_token()?;
+        let service = self
+            .get_service_by_descriptor(&token.descriptor)
+            .ok_or(RuntimeError::ServiceNotFound(token.descriptor))?;
+        service.handle_call(method, data)
+    }
+    
+    /// Route a raw binder transaction to the service named by the parcel's
+    /// interface token
+    pub fn dispatch_transaction(&self, code: u32, data: &[u8]) -> Result<Vec<u8>> {
+        let token = Parcel::from_bytes(data).read_interface_token()?;
+        let service = self
+            .get_service_by_descriptor(&token.descriptor)
+            .ok_or(RuntimeError::ServiceNotFound(token.descriptor))?;
+        service.handle_transaction(code, data)
     }
     
     /// Get the number of registered services
     pub fn service_count(&self) -> usize {
-        self.services.len()
+        self.read().len()
     }
     
     /// Check if a service is registered
     pub fn has_service(&self, name: &str) -> bool {
-        self.services.contains_key(name)
+        self.read().contains_key(name)
     }
     
     /// Get list of all registered service names
     pub fn list_services(&self) -> Vec<String> {
-        self.services.keys().cloned().collect()
+        self.read().keys().cloned().collect()
+    }
+    
+    /// A `servicemanager` stub that looks services up in this registry
+    ///
+    /// The stub only holds a weak reference, so registering it here doesn't
+    /// keep the registry alive.
+    pub fn as_service_manager(self: Arc<Self>) -> Arc<ServiceManagerStub> {
+        Arc::new(ServiceManagerStub::new(Arc::downgrade(&self)))
+    }
+    
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<String, RegisteredService>> {
+        self.services.read().unwrap_or_else(|e| e.into_inner())
     }
 }
 
@@ -215,6 +682,7 @@ impl Default for ServiceRegistry {
 /// # Services Registered
 /// - activity: ActivityManagerStub
 /// - package: PackageManagerStub
+/// - manager: ServiceManagerStub, resolving the others
 ///
 /// # Example
 /// ```
@@ -223,10 +691,10 @@ impl Default for ServiceRegistry {
 /// let registry = init_minimal_services().expect("Failed to init services");
 /// assert!(registry.has_service("activity"));
 /// ```
-pub fn init_minimal_services() -> Result<ServiceRegistry> {
+pub fn init_minimal_services() -> Result<Arc<ServiceRegistry>> {
     info!("Initializing minimal Android system services");
     
-    let mut registry = ServiceRegistry::new();
+    let registry = Arc::new(ServiceRegistry::new());
     
     // Register ActivityManager stub
     let activity_manager = Arc::new(ActivityManagerStub::new());
@@ -236,6 +704,10 @@ pub fn init_minimal_services() -> Result<ServiceRegistry> {
     let package_manager = Arc::new(PackageManagerStub::new());
     registry.register(package_manager)?;
     
+    // Register the service manager last, so it can resolve the others
+    let service_manager = registry.clone().as_service_manager();
+    registry.register(service_manager)?;
+    
     info!("Registered {} minimal services", registry.service_count());
     info!("Services: {:?}", registry.list_services());
     
@@ -265,7 +737,7 @@ mod tests {
     // RED: Test that we can register services
     #[test]
     fn test_register_service() {
-        let mut registry = ServiceRegistry::new();
+        let registry = ServiceRegistry::new();
         let service = Arc::new(ActivityManagerStub::new());
         
         registry.register(service).expect("Failed to register service");
@@ -278,7 +750,7 @@ mod tests {
     // RED: Test that duplicate registration fails
     #[test]
     fn test_duplicate_registration_fails() {
-        let mut registry = ServiceRegistry::new();
+        let registry = ServiceRegistry::new();
         let service1 = Arc::new(ActivityManagerStub::new());
         let service2 = Arc::new(ActivityManagerStub::new());
         
@@ -294,6 +766,18 @@ mod tests {
         }
     }
     
+    #[test]
+    fn test_context_manager_gets_handle_zero() {
+        let registry = Arc::new(ServiceRegistry::new());
+        registry.register(registry.clone().as_service_manager()).unwrap();
+        registry.register(Arc::new(ActivityManagerStub::new())).unwrap();
+        registry.register(Arc::new(PackageManagerStub::new())).unwrap();
+        
+        assert_eq!(registry.handle_of("manager"), Some(CONTEXT_MANAGER_HANDLE));
+        assert_eq!(registry.handle_of("activity"), Some(1));
+        assert_eq!(registry.handle_of("package"), Some(2));
+    }
+    
     // RED: Test service lookup returns None for non-existent service
     #[test]
     fn test_service_not_found() {
@@ -307,10 +791,63 @@ mod tests {
     fn test_init_minimal_services() {
         let registry = init_minimal_services().expect("Failed to init services");
         
-        // Should have ActivityManager and PackageManager
-        assert_eq!(registry.service_count(), 2);
+        // Should have ActivityManager, PackageManager and the ServiceManager
+        assert_eq!(registry.service_count(), 3);
         assert!(registry.has_service("activity"));
         assert!(registry.has_service("package"));
+        assert!(registry.has_service("manager"));
+    }
+    
+    fn get_service_parcel(name: &str) -> Vec<u8> {
+        let mut parcel = Parcel::new();
+        parcel.write_interface_token(IServiceManager::DESCRIPTOR);
+        parcel.write_string16(Some(name));
+        parcel.into_bytes()
+    }
+    
+    #[test]
+    fn test_service_manager_resolves_registered_services() {
+        let registry = init_minimal_services().expect("Failed to init");
+        assert_eq!(registry.handle_of("manager"), Some(CONTEXT_MANAGER_HANDLE));
+        let package = registry.handle_of("package").expect("no handle");
+        assert_ne!(package, CONTEXT_MANAGER_HANDLE);
+        assert_ne!(registry.handle_of("activity"), Some(package));
+        
+        for code in [
+            IServiceManager::GET_SERVICE_TRANSACTION,
+            IServiceManager::CHECK_SERVICE_TRANSACTION,
+        ] {
+            let reply = registry
+                .dispatch_transaction(code, &get_service_parcel("package"))
+                .expect("dispatch failed");
+            let mut reply = Parcel::from_bytes(&reply);
+            assert_eq!(reply.read_i32().unwrap(), 0, "no exception");
+            assert_eq!(
+                reply.read_strong_binder().unwrap(),
+                Some(parcel::StrongBinder::Handle(package))
+            );
+            assert_eq!(reply.remaining(), 0);
+        }
+        
+        let reply = registry
+            .dispatch("getService", &get_service_parcel("window"))
+            .expect("dispatch failed");
+        let mut reply = Parcel::from_bytes(&reply);
+        assert_eq!(reply.read_i32().unwrap(), 0);
+        assert_eq!(reply.read_strong_binder().unwrap(), None);
+    }
+    
+    #[test]
+    fn test_service_manager_does_not_keep_registry_alive() {
+        let registry = Arc::new(ServiceRegistry::new());
+        let manager = registry.clone().as_service_manager();
+        registry.register(manager.clone()).unwrap();
+        drop(registry);
+        
+        assert!(matches!(
+            manager.handle_call("getService", &get_service_parcel("package")),
+            Err(RuntimeError::ServiceNotFound(_))
+        ));
     }
     
     // RED: Test ActivityManager handle_call for checkPermission
@@ -325,6 +862,141 @@ mod tests {
         assert!(!result.is_empty(), "checkPermission should return a response");
     }
     
+    fn check_permission_parcel(permission: &str, pid: i32, uid: i32) -> Vec<u8> {
+        let mut parcel = Parcel::new();
+        parcel.write_interface_token("android.app.IActivityManager");
+        parcel.write_string16(Some(permission));
+        parcel.write_i32(pid);
+        parcel.write_i32(uid);
+        parcel.into_bytes()
+    }
+    
+    #[test]
+    fn test_decode_check_permission_args() {
+        let data = check_permission_parcel("android.permission.INTERNET", 1234, 10057);
+        
+        let mut parcel = Parcel::from_bytes(&data);
+        parcel.enforce_interface("android.app.IActivityManager").expect("Bad token");
+        let args = CheckPermissionArgs::read_from(&mut parcel).expect("Failed to decode");
+        
+        assert_eq!(args.permission, "android.permission.INTERNET");
+        assert_eq!(args.pid, 1234);
+        assert_eq!(args.uid, 10057);
+    }
+    
+    #[test]
+    fn test_check_permission_with_parcel_args() {
+        let am = ActivityManagerStub::new();
+        let data = check_permission_parcel("android.permission.CAMERA", 1, 10000);
+        
+        assert!(am.handle_call("checkPermission", &data).is_ok());
+        // A truncated parcel is rejected instead of silently granted
+        assert!(am.handle_call("checkPermission", &data[..data.len() - 4]).is_err());
+    }
+    
+    #[test]
+    fn test_check_permission_rejects_wrong_interface() {
+        let am = ActivityManagerStub::new();
+        let mut parcel = Parcel::new();
+        parcel.write_interface_token("android.content.pm.IPackageManager");
+        parcel.write_string16(Some("android.permission.CAMERA"));
+        parcel.write_i32(1);
+        parcel.write_i32(10000);
+        
+        let result = am.handle_call("checkPermission", parcel.as_bytes());
+        
+        assert!(matches!(result, Err(RuntimeError::InterfaceMismatch { .. })));
+    }
+    
+    #[test]
+    fn test_sdk_levels_select_code_tables() {
+        let nougat = ActivityManagerStub::new().with_sdk(25);
+        let pie = ActivityManagerStub::new().with_sdk(28);
+        
+        let old = nougat.interface_version().transaction_code("checkPermission");
+        let new = pie.interface_version().transaction_code("checkPermission");
+        assert!(old.is_some() && new.is_some());
+        assert_ne!(old, new);
+        assert_eq!(nougat.interface_version().method_name(old.unwrap()), Some("checkPermission"));
+        
+        // Unpinned stubs use the newest layout
+        assert_eq!(ActivityManagerStub::new().interface_version(), pie.interface_version());
+        assert_eq!(pie.supported_interface_versions().len(), 2);
+    }
+    
+    #[test]
+    fn test_registry_dispatches_on_descriptor() {
+        let registry = init_minimal_services().expect("Failed to init");
+        let data = check_permission_parcel("android.permission.INTERNET", 1, 10000);
+        
+        let reply = registry.dispatch("checkPermission", &data).expect("dispatch failed");
+        let mut reply = Parcel::from_bytes(&reply);
+        assert_eq!(reply.read_i32().unwrap(), 0, "no exception");
+        assert_eq!(reply.read_i32().unwrap(), PERMISSION_GRANTED);
+        assert_eq!(reply.remaining(), 0);
+        
+        let mut parcel = Parcel::new();
+        parcel.write_interface_token("android.os.IUnknown");
+        assert!(matches!(
+            registry.dispatch("checkPermission", parcel.as_bytes()),
+            Err(RuntimeError::ServiceNotFound(_))
+        ));
+    }
+    
+    #[test]
+    fn test_transactions_map_codes_to_methods() {
+        let data = check_permission_parcel("android.permission.INTERNET", 1, 10000);
+        let by_name = ActivityManagerStub::new()
+            .handle_call("checkPermission", &data)
+            .unwrap();
+        
+        let am = ActivityManagerStub::new();
+        assert_eq!(
+            am.handle_transaction(IActivityManager::CHECK_PERMISSION_TRANSACTION, &data)
+                .unwrap(),
+            by_name
+        );
+        // Codes follow the pinned layout
+        let nougat = ActivityManagerStub::new().with_sdk(25);
+        assert_eq!(
+            nougat
+                .handle_transaction(IActivityManager::LEGACY_CHECK_PERMISSION_TRANSACTION, &data)
+                .unwrap(),
+            by_name
+        );
+        assert!(matches!(
+            nougat.handle_transaction(IActivityManager::CHECK_PERMISSION_TRANSACTION, &data),
+            Err(RuntimeError::NotImplemented(_))
+        ));
+    }
+    
+    #[test]
+    fn test_meta_transactions() {
+        let pm = PackageManagerStub::new();
+        let reply = pm.handle_transaction(INTERFACE_TRANSACTION, &[]).unwrap();
+        assert_eq!(
+            Parcel::from_bytes(&reply).read_string16().unwrap().as_deref(),
+            Some(IPackageManager::DESCRIPTOR)
+        );
+        assert!(pm.handle_transaction(PING_TRANSACTION, &[]).unwrap().is_empty());
+    }
+    
+    #[test]
+    fn test_registry_dispatches_transactions() {
+        let registry = ServiceRegistry::new();
+        registry
+            .register(Arc::new(PackageManagerStub::new().with_package(sample_package())))
+            .unwrap();
+        
+        let data = get_package_info_parcel("com.example.app");
+        let reply = registry
+            .dispatch_transaction(IPackageManager::GET_PACKAGE_INFO_TRANSACTION, &data)
+            .expect("dispatch failed");
+        let mut reply = Parcel::from_bytes(&reply);
+        assert_eq!(reply.read_i32().unwrap(), 0);
+        assert_eq!(reply.read_i32().unwrap(), 1, "non-null PackageInfo");
+    }
+    
     // RED: Test that unimplemented methods return empty response (no crash)
     #[test]
     fn test_unimplemented_method_returns_empty() {
@@ -344,14 +1016,90 @@ mod tests {
         assert_eq!(pm.interface_descriptor(), "android.content.pm.IPackageManager");
     }
     
+    #[test]
+    fn test_default_handle_call_returns_no_exception() {
+        let pm = PackageManagerStub::new();
+        
+        let result = pm.handle_call("getInstalledPackages", &[])
+            .expect("Default handler should not fail");
+        
+        assert_eq!(result, vec![0, 0, 0, 0]);
+    }
+    
+    fn sample_package() -> PackageInfo {
+        PackageInfo {
+            package_name: "com.example.app".to_string(),
+            version_code: 42,
+            uid: 10057,
+            data_dir: "/data/data/com.example.app".to_string(),
+        }
+    }
+    
+    fn get_package_info_parcel(package: &str) -> Vec<u8> {
+        let mut parcel = Parcel::new();
+        parcel.write_interface_token("android.content.pm.IPackageManager");
+        parcel.write_string16(Some(package));
+        parcel.write_i32(0);
+        parcel.write_i32(0);
+        parcel.into_bytes()
+    }
+    
+    #[test]
+    fn test_get_package_info_layout() {
+        let pm = PackageManagerStub::new().with_package(sample_package());
+        let reply = pm
+            .handle_call("getPackageInfo", &get_package_info_parcel("com.example.app"))
+            .expect("getPackageInfo failed");
+        
+        let mut parcel = Parcel::from_bytes(&reply);
+        assert_eq!(parcel.read_i32().unwrap(), 0, "no exception");
+        assert_eq!(parcel.read_i32().unwrap(), 1, "non-null PackageInfo");
+        assert_eq!(parcel.read_string16().unwrap().as_deref(), Some("com.example.app"));
+        assert_eq!(parcel.read_i32().unwrap(), -1, "splitNames");
+        assert_eq!(parcel.read_i32().unwrap(), 42, "versionCode");
+        assert_eq!(parcel.read_i32().unwrap(), 0, "versionCodeMajor");
+        assert_eq!(parcel.read_string16().unwrap(), None, "versionName");
+        assert_eq!(parcel.read_i32().unwrap(), 0, "baseRevisionCode");
+        assert_eq!(parcel.read_i32().unwrap(), -1, "splitRevisionCodes");
+        assert_eq!(parcel.read_string16().unwrap(), None, "sharedUserId");
+        assert_eq!(parcel.read_i32().unwrap(), 0, "sharedUserLabel");
+        assert_eq!(parcel.read_i32().unwrap(), 1, "non-null ApplicationInfo");
+        assert_eq!(parcel.read_string16().unwrap().as_deref(), Some("com.example.app"));
+        assert_eq!(parcel.read_string16().unwrap().as_deref(), Some("com.example.app"));
+        assert_eq!(parcel.read_i32().unwrap(), 10057, "uid");
+        assert_eq!(
+            parcel.read_string16().unwrap().as_deref(),
+            Some("/data/data/com.example.app")
+        );
+        assert_eq!(parcel.remaining(), 0);
+    }
+    
+    #[test]
+    fn test_get_package_info_unknown_package_is_null() {
+        let pm = PackageManagerStub::new().with_package(sample_package());
+        let reply = pm
+            .handle_call("getPackageInfo", &get_package_info_parcel("com.other.app"))
+            .expect("getPackageInfo failed");
+        assert_eq!(reply, vec![0, 0, 0, 0, 0, 0, 0, 0]);
+        
+        let reply = pm
+            .handle_call("getPackageInfo", &[])
+            .expect("getPackageInfo failed");
+        assert_eq!(reply, vec![0, 0, 0, 0, 0, 0, 0, 0]);
+        
+        let data = get_package_info_parcel("com.example.app");
+        assert!(pm.handle_call("getPackageInfo", &data[..data.len() - 12]).is_err());
+    }
+    
     // RED: Test service list
     #[test]
     fn test_list_services() {
         let registry = init_minimal_services().expect("Failed to init");
         let services = registry.list_services();
         
-        assert_eq!(services.len(), 2);
+        assert_eq!(services.len(), 3);
         assert!(services.contains(&"activity".to_string()));
         assert!(services.contains(&"package".to_string()));
+        assert!(services.contains(&"manager".to_string()));
     }
 }
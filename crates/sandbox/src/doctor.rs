@@ -103,6 +103,73 @@ pub fn check_user_namespaces_support() -> Result<(), UserNamespaceError> {
     }
 }
 
+/// Check that binderfs can be mounted inside an unprivileged user namespace
+///
+/// Kernel support alone isn't enough: many kernels refuse binderfs mounts from
+/// a non-initial user namespace. A forked child enters a user+mount namespace
+/// and creates a `BinderfsInstance` on a temporary path, which only lives in
+/// the child's mount namespace.
+///
+/// Returns `Disabled` if the kernel has no binderfs at all.
+pub fn check_binderfs_in_userns() -> CheckResult {
+    let kernel_support = fs::read_to_string("/proc/filesystems")
+        .map(|c| crate::parse_proc_filesystems(&c))
+        .unwrap_or(false);
+    if !kernel_support {
+        return CheckResult::Disabled;
+    }
+
+    let probe_dir = std::env::temp_dir().join(format!("rad-binderfs-probe-{}", std::process::id()));
+    let result = probe_binderfs_mount(&probe_dir);
+    let _ = fs::remove_dir_all(&probe_dir);
+    result
+}
+
+fn probe_binderfs_mount(probe_dir: &std::path::Path) -> CheckResult {
+    use std::io::{Read, Write};
+
+    let (read_fd, write_fd) = match nix::unistd::pipe() {
+        Ok(fds) => fds,
+        Err(e) => return CheckResult::Error(format!("pipe() failed: {}", e)),
+    };
+    // SAFETY: both ends are fresh descriptors owned only by us
+    let (mut reader, mut writer) = unsafe {
+        use std::os::unix::io::FromRawFd;
+        (fs::File::from_raw_fd(read_fd), fs::File::from_raw_fd(write_fd))
+    };
+
+    match unsafe { fork() } {
+        Ok(ForkResult::Parent { child }) => {
+            drop(writer);
+            let mut reason = String::new();
+            let _ = reader.read_to_string(&mut reason);
+            match waitpid(child, None) {
+                Ok(WaitStatus::Exited(_, 0)) => CheckResult::Available,
+                Ok(_) if !reason.is_empty() => CheckResult::Error(reason),
+                Ok(status) => CheckResult::Error(format!("Probe process failed: {:?}", status)),
+                Err(e) => CheckResult::Error(format!("waitpid failed: {}", e)),
+            }
+        }
+        Ok(ForkResult::Child) => {
+            drop(reader);
+            let code = match crate::enter_namespaces()
+                .map_err(|e| format!("Cannot enter a user+mount namespace: {}", e))
+                .and_then(|()| {
+                    crate::BinderfsInstance::new(probe_dir.join("binderfs"))
+                        .map_err(|e| format!("{:#}", e))
+                }) {
+                Ok(_instance) => 0,
+                Err(reason) => {
+                    let _ = writer.write_all(reason.as_bytes());
+                    1
+                }
+            };
+            std::process::exit(code);
+        }
+        Err(e) => CheckResult::Error(format!("fork() failed: {}", e)),
+    }
+}
+
 /// Public API for checking user namespace support
 /// Returns a CheckResult for easy consumption by the doctor command
 pub fn check_user_namespaces() -> CheckResult {
@@ -170,6 +237,13 @@ mod tests {
         }
     }
     
+    /// Mounts binderfs in a user namespace; needs a kernel that allows it
+    #[test]
+    #[ignore]
+    fn test_binderfs_mountable_in_userns() {
+        assert_eq!(check_binderfs_in_userns(), CheckResult::Available);
+    }
+
     #[test]
     fn test_check_result_variants() {
         // Test CheckResult equality and is_available
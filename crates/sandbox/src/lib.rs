@@ -8,9 +8,13 @@ use std::ffi::CString;
 
 pub mod doctor;
 pub mod binderfs;
+pub mod mountinfo;
+pub mod seccomp;
+pub mod uinput;
 
 // Re-export key binderfs types for convenience
 pub use binderfs::{BinderfsInstance, setup_binderfs_in_sandbox};
+pub use seccomp::{apply_seccomp, SeccompProfile};
 
 pub struct SandboxConfig {
     pub rootfs: std::path::PathBuf,
@@ -22,6 +26,16 @@ pub struct SandboxConfig {
 /// or after fork(). Calling unshare(CLONE_NEWUSER) in a multi-threaded process will fail
 /// with EINVAL.
 pub fn enter_namespaces() -> Result<()> {
+    enter_namespaces_with(CloneFlags::CLONE_NEWNS)
+}
+
+/// Enter a user namespace, then the namespaces in `flags` (e.g.
+/// `CLONE_NEWNS | CLONE_NEWPID`)
+///
+/// Same threading rules as [`enter_namespaces`]. With `CLONE_NEWPID` only
+/// children join the new PID namespace: call [`fork_pid_namespace_init`]
+/// afterwards so that `setup_mounts` runs as its PID 1 and can mount /proc.
+pub fn enter_namespaces_with(flags: CloneFlags) -> Result<()> {
     use tracing::info;
     
     // Get original uid/gid BEFORE entering namespace
@@ -35,13 +49,59 @@ pub fn enter_namespaces() -> Result<()> {
     // Setup uid/gid mapping immediately after entering user namespace
     setup_uid_gid_mapping(uid, gid)?;
     
-    // Now we can enter mount namespace (needs to be root in user ns)
-    info!("Entering mount namespace");
-    unshare(CloneFlags::CLONE_NEWNS)?;
+    // Now we can enter the other namespaces (needs to be root in user ns)
+    let flags = flags - CloneFlags::CLONE_NEWUSER;
+    info!("Entering namespaces: {:?}", flags);
+    unshare(flags)?;
     
     Ok(())
 }
 
+/// Fork into the PID namespace created by `enter_namespaces_with`
+///
+/// Returns in the child, which is PID 1 of the namespace. The parent waits
+/// for it and exits with its status, so the caller's process stands in for
+/// the whole namespace. The parent never returns, even if waiting fails:
+/// otherwise two processes would carry on with the caller's code.
+pub fn fork_pid_namespace_init() -> Result<()> {
+    use nix::errno::Errno;
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::{fork, ForkResult};
+
+    match unsafe { fork()? } {
+        ForkResult::Child => Ok(()),
+        ForkResult::Parent { child } => {
+            let code = loop {
+                match waitpid(child, None) {
+                    Ok(WaitStatus::Exited(_, code)) => break code,
+                    Ok(WaitStatus::Signaled(_, signal, _)) => break 128 + signal as i32,
+                    // Stopped/continued: the child is still around
+                    Ok(_) | Err(Errno::EINTR) => continue,
+                    Err(_) => break 1,
+                }
+            };
+            unsafe { libc::_exit(code) }
+        }
+    }
+}
+
+/// Whether this process is PID 1 of its own PID namespace
+pub fn in_new_pid_namespace() -> bool {
+    nix::unistd::getpid().as_raw() == 1
+}
+
+/// Mount a fresh procfs, which shows the current PID namespace
+pub fn mount_proc<P: AsRef<Path>>(target: P) -> Result<()> {
+    mount(
+        Some("proc"),
+        target.as_ref(),
+        Some("proc"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+        None::<&Path>,
+    )?;
+    Ok(())
+}
+
 pub fn bind_mount<P: AsRef<Path>, Q: AsRef<Path>>(source: P, target: Q) -> Result<()> {
     mount(
         Some(source.as_ref()),
@@ -93,9 +153,15 @@ pub fn setup_mounts(rootfs: &Path) -> Result<()> {
     std::fs::create_dir_all(&dev_path)?;
     std::fs::create_dir_all(&tmp_path)?;
     
-    // Mount proc - requires PID namespace, so we skip it for now
-    // TODO(task/021): Add PID namespace support
-    warn!("Skipping /proc mount (requires PID namespace)");
+    // Mount proc - only meaningful (and only permitted) as the init of a PID
+    // namespace we created; otherwise it would show the host's processes
+    if !in_new_pid_namespace() {
+        warn!("Skipping /proc mount (not in a new PID namespace)");
+    } else if let Err(e) = mount_proc(&proc_path) {
+        warn!("Failed to mount proc on /proc: {}", e);
+    } else {
+        info!("Mounted proc on /proc");
+    }
     
     // Mount sysfs - may also fail without proper namespace
     warn!("Skipping /sys mount (may require additional namespaces)");
@@ -180,19 +246,58 @@ pub fn exec_with_env<P: AsRef<Path>>(path: P, args: &[String], env: &[(String, S
     Err(anyhow::anyhow!("execve failed"))
 }
 
+/// Where binderfs is mounted when nothing else is found
+pub const DEFAULT_BINDERFS_MOUNT: &str = "/dev/binderfs";
+
+/// Devices an Android container needs in its binderfs
+pub const BINDER_DEVICES: &[&str] = &["binder", "hwbinder", "vndbinder"];
+
 pub struct BinderfsStatus {
     pub kernel_support: bool,
+    /// Where binderfs is mounted, if it is mounted anywhere
+    pub mount_point: Option<std::path::PathBuf>,
+    /// binder-control under the mount point (or under /dev/binderfs if unmounted)
+    pub control_path: std::path::PathBuf,
     pub control_exists: bool,
+    /// Whether binder, hwbinder and vndbinder all exist next to binder-control
+    pub devices_exist: bool,
 }
 
 pub fn check_binderfs() -> BinderfsStatus {
     let kernel_support = std::fs::read_to_string("/proc/filesystems")
         .map(|c| parse_proc_filesystems(&c))
         .unwrap_or(false);
-    
-    let control_exists = std::path::Path::new("/dev/binderfs/binder-control").exists();
-    
-    BinderfsStatus { kernel_support, control_exists }
+
+    let mount_point = mountinfo::read_self()
+        .ok()
+        .and_then(|mounts| find_binderfs_mount(&mounts));
+    let dir = mount_point
+        .clone()
+        .unwrap_or_else(|| std::path::PathBuf::from(DEFAULT_BINDERFS_MOUNT));
+
+    let control_path = dir.join("binder-control");
+    let control_exists = control_path.exists();
+    let devices_exist = BINDER_DEVICES.iter().all(|dev| dir.join(dev).exists());
+
+    BinderfsStatus {
+        kernel_support,
+        mount_point,
+        control_path,
+        control_exists,
+        devices_exist,
+    }
+}
+
+/// The first binderfs mount, preferring the conventional /dev/binderfs
+fn find_binderfs_mount(mounts: &[mountinfo::MountEntry]) -> Option<std::path::PathBuf> {
+    let binder_mounts: Vec<&mountinfo::MountEntry> =
+        mounts.iter().filter(|m| m.fs_type == "binder").collect();
+
+    binder_mounts
+        .iter()
+        .find(|m| m.mount_point == Path::new(DEFAULT_BINDERFS_MOUNT))
+        .or(binder_mounts.first())
+        .map(|m| m.mount_point.clone())
 }
 
 fn parse_proc_filesystems(content: &str) -> bool {
@@ -213,6 +318,22 @@ mod tests {
         assert!(!parse_proc_filesystems(fail_content));
     }
 
+    #[test]
+    fn test_find_binderfs_mount_at_custom_path() {
+        let content = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+31 25 0:27 / /dev/shm rw,nosuid,nodev shared:4 - tmpfs tmpfs rw
+87 22 0:52 / /var/lib/waydroid/binder rw,relatime shared:40 - binder binder rw,max=1048576
+";
+        let mounts = mountinfo::parse(content);
+
+        assert_eq!(
+            find_binderfs_mount(&mounts),
+            Some(std::path::PathBuf::from("/var/lib/waydroid/binder"))
+        );
+        assert_eq!(find_binderfs_mount(&mounts[..2]), None);
+    }
+
     #[test]
     fn test_check_binderfs_logic() {
         // We can't easily mock the FS for check_binderfs() without changing its signature,
@@ -220,7 +341,12 @@ mod tests {
         // This is a sanity check for the current host.
         let status = check_binderfs();
         println!("Binderfs kernel support: {}", status.kernel_support);
-        println!("Binderfs control exists: {}", status.control_exists);
+        println!("Binderfs mount point: {:?}", status.mount_point);
+        println!(
+            "Binderfs control exists: {} ({})",
+            status.control_exists,
+            status.control_path.display()
+        );
     }
 
     /// Test that enters a user namespace
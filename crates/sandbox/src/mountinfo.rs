@@ -0,0 +1,87 @@
+//! Parsing of `/proc/<pid>/mountinfo`.
+//!
+//! Each line looks like
+//! `36 35 98:0 /mnt1 /mnt/parent rw,noatime master:1 - ext3 /dev/root rw`:
+//! the mount point is the 5th field and the filesystem type follows the `-`
+//! separator that ends the optional fields.
+
+use std::path::PathBuf;
+
+/// One mount from a mountinfo dump
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountEntry {
+    /// Where it is mounted
+    pub mount_point: PathBuf,
+    /// Filesystem type (e.g. "ext4", "binder", "fuse.fuse2fs")
+    pub fs_type: String,
+    /// Mount source (device or fs-specific string)
+    pub source: String,
+}
+
+/// Parse a mountinfo dump, skipping malformed lines
+pub fn parse(content: &str) -> Vec<MountEntry> {
+    content.lines().filter_map(parse_line).collect()
+}
+
+/// Read and parse `/proc/self/mountinfo`
+pub fn read_self() -> std::io::Result<Vec<MountEntry>> {
+    std::fs::read_to_string("/proc/self/mountinfo").map(|content| parse(&content))
+}
+
+fn parse_line(line: &str) -> Option<MountEntry> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let mount_point = fields.get(4)?;
+    let separator = fields.iter().skip(6).position(|f| *f == "-")? + 6;
+
+    Some(MountEntry {
+        mount_point: PathBuf::from(unescape(mount_point)),
+        fs_type: unescape(fields.get(separator + 1)?),
+        source: unescape(fields.get(separator + 2).unwrap_or(&"")),
+    })
+}
+
+/// Undo the octal escaping (`\040` for space etc.) used in mountinfo fields
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && let Some(digits) = bytes.get(i + 1..i + 4)
+            && let Ok(digits) = std::str::from_utf8(digits)
+            && let Ok(code) = u8::from_str_radix(digits, 8)
+        {
+            out.push(code);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_mount_point_and_type_after_optional_fields() {
+        let content = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+45 22 0:40 / /home/user/my\\040prefix/.mounts/system ro - fuse.fuse2fs system.img ro
+garbage
+";
+        let mounts = parse(content);
+
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[0].mount_point, PathBuf::from("/"));
+        assert_eq!(mounts[0].fs_type, "ext4");
+        assert_eq!(
+            mounts[1].mount_point,
+            PathBuf::from("/home/user/my prefix/.mounts/system")
+        );
+        assert_eq!(mounts[1].fs_type, "fuse.fuse2fs");
+        assert_eq!(mounts[1].source, "system.img");
+    }
+}
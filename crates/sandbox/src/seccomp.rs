@@ -0,0 +1,409 @@
+//! seccomp-bpf syscall filter for sandboxed apps
+//!
+//! A denylist: everything is allowed except syscalls an app has no business
+//! making inside the sandbox (kernel keyrings, ptrace, mounts, module
+//! loading, ...). The filter is a classic BPF program checking
+//! `seccomp_data.arch` first, so 32-bit processes (x86 on x86_64, arm on
+//! arm64) are matched against their own syscall numbers.
+//!
+//! With [`SeccompProfile::Permissive`] the syscalls are only logged by the
+//! kernel (`type=1326` audit records, see `dmesg` or `journalctl -k`), to
+//! find out what an app needs before enforcing.
+
+use std::io;
+
+use nix::libc;
+use thiserror::Error;
+use tracing::info;
+
+/// How the sandbox treats denied syscalls
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SeccompProfile {
+    /// No filter
+    Disabled,
+    /// Allow denied syscalls, but have the kernel log them
+    Permissive,
+    /// Fail denied syscalls with EPERM
+    #[default]
+    Enforcing,
+}
+
+#[derive(Debug, Error)]
+pub enum SeccompError {
+    #[error("seccomp filters are not supported on this architecture")]
+    UnsupportedArch,
+    #[error("prctl({0}) failed: {1}")]
+    Prctl(&'static str, io::Error),
+}
+
+/// Syscall names denied by the filter
+pub const DENIED_SYSCALLS: &[&str] = &[
+    "acct",
+    "add_key",
+    "bpf",
+    "delete_module",
+    "finit_module",
+    "init_module",
+    "kexec_file_load",
+    "kexec_load",
+    "keyctl",
+    "mount",
+    "open_by_handle_at",
+    "perf_event_open",
+    "pivot_root",
+    "ptrace",
+    "reboot",
+    "request_key",
+    "swapoff",
+    "swapon",
+    // The one-argument umount only exists on i386; elsewhere umount(2) is
+    // a libc wrapper over umount2
+    "umount",
+    "umount2",
+    "userfaultfd",
+];
+
+// Classic BPF opcodes (linux/bpf_common.h)
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JEQ_K: u16 = 0x15;
+const BPF_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+
+// linux/seccomp.h
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+
+/// Offsets in `struct seccomp_data`
+const DATA_NR: u32 = 0;
+const DATA_ARCH: u32 = 4;
+
+// linux/audit.h
+const AUDIT_ARCH_X86_64: u32 = 0xc000_003e;
+const AUDIT_ARCH_I386: u32 = 0x4000_0003;
+const AUDIT_ARCH_AARCH64: u32 = 0xc000_00b7;
+const AUDIT_ARCH_ARM: u32 = 0x4000_0028;
+
+/// x32 syscalls share the x86_64 arch, with this bit set in the number
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// Syscall numbers of one ABI, by name
+type SyscallTable = &'static [(&'static str, u32)];
+
+const X86_64_SYSCALLS: SyscallTable = &[
+    ("acct", 163),
+    ("add_key", 248),
+    ("bpf", 321),
+    ("delete_module", 176),
+    ("finit_module", 313),
+    ("init_module", 175),
+    ("kexec_file_load", 320),
+    ("kexec_load", 246),
+    ("keyctl", 250),
+    ("mount", 165),
+    ("open_by_handle_at", 304),
+    ("perf_event_open", 298),
+    ("pivot_root", 155),
+    ("ptrace", 101),
+    ("reboot", 169),
+    ("request_key", 249),
+    ("swapoff", 168),
+    ("swapon", 167),
+    ("umount2", 166),
+    ("userfaultfd", 323),
+];
+
+/// i386 has no kexec_file_load, but keeps the old umount (oldumount)
+const I386_SYSCALLS: SyscallTable = &[
+    ("acct", 51),
+    ("add_key", 286),
+    ("bpf", 357),
+    ("delete_module", 129),
+    ("finit_module", 350),
+    ("init_module", 128),
+    ("kexec_load", 283),
+    ("keyctl", 288),
+    ("mount", 21),
+    ("open_by_handle_at", 342),
+    ("perf_event_open", 336),
+    ("pivot_root", 217),
+    ("ptrace", 26),
+    ("reboot", 88),
+    ("request_key", 287),
+    ("swapoff", 115),
+    ("swapon", 87),
+    ("umount", 22),
+    ("umount2", 52),
+    ("userfaultfd", 374),
+];
+
+const AARCH64_SYSCALLS: SyscallTable = &[
+    ("acct", 89),
+    ("add_key", 217),
+    ("bpf", 280),
+    ("delete_module", 106),
+    ("finit_module", 273),
+    ("init_module", 105),
+    ("kexec_file_load", 294),
+    ("kexec_load", 104),
+    ("keyctl", 219),
+    ("mount", 40),
+    ("open_by_handle_at", 265),
+    ("perf_event_open", 241),
+    ("pivot_root", 41),
+    ("ptrace", 117),
+    ("reboot", 142),
+    ("request_key", 218),
+    ("swapoff", 225),
+    ("swapon", 224),
+    ("umount2", 39),
+    ("userfaultfd", 282),
+];
+
+/// ARM EABI
+const ARM_SYSCALLS: SyscallTable = &[
+    ("acct", 51),
+    ("add_key", 309),
+    ("bpf", 386),
+    ("delete_module", 129),
+    ("finit_module", 379),
+    ("init_module", 128),
+    ("kexec_file_load", 401),
+    ("kexec_load", 347),
+    ("keyctl", 311),
+    ("mount", 21),
+    ("open_by_handle_at", 371),
+    ("perf_event_open", 364),
+    ("pivot_root", 218),
+    ("ptrace", 26),
+    ("reboot", 88),
+    ("request_key", 310),
+    ("swapoff", 115),
+    ("swapon", 87),
+    ("umount2", 52),
+    ("userfaultfd", 388),
+];
+
+/// The native and compat (32-bit) ABIs of the host: (arch, syscalls)
+fn host_abis() -> Option<[(u32, SyscallTable); 2]> {
+    if cfg!(target_arch = "x86_64") {
+        Some([
+            (AUDIT_ARCH_X86_64, X86_64_SYSCALLS),
+            (AUDIT_ARCH_I386, I386_SYSCALLS),
+        ])
+    } else if cfg!(target_arch = "aarch64") {
+        Some([
+            (AUDIT_ARCH_AARCH64, AARCH64_SYSCALLS),
+            (AUDIT_ARCH_ARM, ARM_SYSCALLS),
+        ])
+    } else {
+        None
+    }
+}
+
+fn stmt(code: u16, k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter { code, jt, jf, k }
+}
+
+/// Instructions matching one ABI's syscall number (already loaded) against
+/// `table`: each match jumps to the final `RET action`
+fn abi_block(table: SyscallTable, x32: bool, action: u32) -> Vec<libc::sock_filter> {
+    let numbers: Vec<u32> = DENIED_SYSCALLS
+        .iter()
+        .filter_map(|name| table.iter().find(|(n, _)| n == name).map(|&(_, nr)| nr))
+        .collect();
+    let checks = numbers.len() + usize::from(x32);
+
+    let mut block = vec![stmt(BPF_LD_W_ABS, DATA_NR)];
+    if x32 {
+        // jt: skip the other checks and RET ALLOW
+        block.push(jump(BPF_JGE_K, X32_SYSCALL_BIT, checks as u8, 0));
+    }
+    for (i, nr) in numbers.iter().enumerate() {
+        let remaining = checks - (i + usize::from(x32)) - 1;
+        block.push(jump(BPF_JEQ_K, *nr, (remaining + 1) as u8, 0));
+    }
+    block.push(stmt(BPF_RET_K, SECCOMP_RET_ALLOW));
+    block.push(stmt(BPF_RET_K, action));
+    block
+}
+
+/// The BPF program for `profile`, `None` for [`SeccompProfile::Disabled`]
+pub fn build_filter(
+    profile: SeccompProfile,
+) -> Result<Option<Vec<libc::sock_filter>>, SeccompError> {
+    let action = match profile {
+        SeccompProfile::Disabled => return Ok(None),
+        SeccompProfile::Permissive => SECCOMP_RET_LOG,
+        SeccompProfile::Enforcing => SECCOMP_RET_ERRNO | libc::EPERM as u32,
+    };
+    let [(native_arch, native), (compat_arch, compat)] =
+        host_abis().ok_or(SeccompError::UnsupportedArch)?;
+
+    let native_block = abi_block(native, native_arch == AUDIT_ARCH_X86_64, action);
+    let compat_block = abi_block(compat, false, action);
+
+    let mut program = vec![stmt(BPF_LD_W_ABS, DATA_ARCH)];
+    program.push(jump(BPF_JEQ_K, native_arch, 0, native_block.len() as u8));
+    program.extend(native_block);
+    program.push(jump(BPF_JEQ_K, compat_arch, 0, compat_block.len() as u8));
+    program.extend(compat_block);
+    // Any other arch: the profile's action for everything
+    program.push(stmt(BPF_RET_K, action));
+    Ok(Some(program))
+}
+
+/// Install the filter for `profile` on the calling thread (inherited across
+/// fork and exec)
+///
+/// Sets `no_new_privs` first, which lets unprivileged processes install
+/// filters. Call it last before exec: the filter can't be removed.
+pub fn apply_seccomp(profile: SeccompProfile) -> Result<(), SeccompError> {
+    let Some(mut filter) = build_filter(profile)? else {
+        return Ok(());
+    };
+    let program = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_mut_ptr(),
+    };
+
+    // SAFETY: plain prctl calls; the kernel copies the program
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(SeccompError::Prctl(
+                "PR_SET_NO_NEW_PRIVS",
+                io::Error::last_os_error(),
+            ));
+        }
+        if libc::prctl(
+            libc::PR_SET_SECCOMP,
+            libc::SECCOMP_MODE_FILTER,
+            &program as *const libc::sock_fprog,
+        ) != 0
+        {
+            return Err(SeccompError::Prctl(
+                "PR_SET_SECCOMP",
+                io::Error::last_os_error(),
+            ));
+        }
+    }
+    info!("Installed {:?} seccomp filter", profile);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::wait::{WaitStatus, waitpid};
+    use nix::unistd::{ForkResult, fork};
+
+    #[test]
+    fn tables_cover_denied_syscalls() {
+        // Only i386 has umount, and it lacks kexec_file_load
+        for table in [X86_64_SYSCALLS, I386_SYSCALLS, AARCH64_SYSCALLS, ARM_SYSCALLS] {
+            assert_eq!(table.len(), DENIED_SYSCALLS.len() - 1);
+        }
+        assert!(I386_SYSCALLS.contains(&("umount", 22)));
+
+        #[cfg(target_arch = "x86_64")]
+        for (name, nr) in [
+            ("keyctl", libc::SYS_keyctl),
+            ("ptrace", libc::SYS_ptrace),
+            ("mount", libc::SYS_mount),
+        ] {
+            assert!(X86_64_SYSCALLS.contains(&(name, nr as u32)));
+        }
+    }
+
+    #[test]
+    fn disabled_profile_has_no_filter() {
+        assert!(build_filter(SeccompProfile::Disabled).unwrap().is_none());
+    }
+
+    #[test]
+    fn jumps_land_on_the_deny_action() {
+        let program = build_filter(SeccompProfile::Enforcing).unwrap().unwrap();
+        let deny = SECCOMP_RET_ERRNO | libc::EPERM as u32;
+
+        for (i, insn) in program.iter().enumerate() {
+            if insn.code == BPF_JEQ_K && insn.jt > 0 {
+                let target = &program[i + 1 + insn.jt as usize];
+                assert_eq!((target.code, target.k), (BPF_RET_K, deny), "insn {}", i);
+            }
+            if insn.code == BPF_JEQ_K && insn.jf > 0 {
+                // Arch mismatch skips to the next arch check or the final RET
+                let target = &program[i + 1 + insn.jf as usize];
+                assert!(
+                    target.code == BPF_JEQ_K || target.code == BPF_RET_K,
+                    "insn {}",
+                    i
+                );
+            }
+        }
+        assert_eq!(program.last().map(|i| i.k), Some(deny));
+    }
+
+    /// errno of `umount2("/", <invalid flag>)`, normally EINVAL: the flags
+    /// are checked before privileges
+    fn probe_umount() -> Option<i32> {
+        let ret = unsafe { libc::umount2(c"/".as_ptr(), 0x100) };
+        (ret == -1)
+            .then(|| io::Error::last_os_error().raw_os_error())
+            .flatten()
+    }
+
+    /// errno of the probe before and after applying `profile`, in a child
+    fn probe_in_filtered_child(profile: SeccompProfile) -> (Option<i32>, Option<i32>) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Parent { child } => {
+                unsafe { libc::close(fds[1]) };
+                let mut buf = [0u8; 8];
+                let read = unsafe { libc::read(fds[0], buf.as_mut_ptr().cast(), buf.len()) };
+                unsafe { libc::close(fds[0]) };
+                let status = waitpid(child, None).unwrap();
+                assert!(matches!(status, WaitStatus::Exited(_, 0)), "{:?}", status);
+                assert_eq!(read, 8);
+                let errno = |b: [u8; 4]| Some(i32::from_ne_bytes(b)).filter(|&e| e != 0);
+                (
+                    errno(buf[..4].try_into().unwrap()),
+                    errno(buf[4..].try_into().unwrap()),
+                )
+            }
+            ForkResult::Child => {
+                let before = probe_umount().unwrap_or(0);
+                let code = i32::from(apply_seccomp(profile).is_err());
+                let after = probe_umount().unwrap_or(0);
+                let mut buf = [0u8; 8];
+                buf[..4].copy_from_slice(&before.to_ne_bytes());
+                buf[4..].copy_from_slice(&after.to_ne_bytes());
+                unsafe {
+                    libc::write(fds[1], buf.as_ptr().cast(), buf.len());
+                    libc::_exit(code)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn enforcing_profile_denies_umount() {
+        let (_, after) = probe_in_filtered_child(SeccompProfile::Enforcing);
+        assert_eq!(after, Some(libc::EPERM));
+    }
+
+    #[test]
+    fn permissive_profile_only_logs() {
+        let (before, after) = probe_in_filtered_child(SeccompProfile::Permissive);
+        assert_eq!(before, after);
+    }
+}
@@ -0,0 +1,459 @@
+//! Virtual Input Device (uinput)
+//!
+//! Creates a `/dev/uinput`-backed keyboard + touchscreen inside the container
+//! and injects host input captured by the wayland crate as evdev events.
+//!
+//! Pointer input is presented as a single-touch screen (left button = finger
+//! down), which is what Android apps expect; the right and middle buttons map
+//! to the Android BACK and HOME keys.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use nix::libc;
+use thiserror::Error;
+use tracing::info;
+use wayland::InputEvent;
+
+/// Default uinput control device
+pub const UINPUT_PATH: &str = "/dev/uinput";
+
+// Constants for uinput ioctl (linux/uinput.h)
+const UI_DEV_CREATE: u64 = 0x5501; // _IO('U', 1)
+const UI_DEV_DESTROY: u64 = 0x5502; // _IO('U', 2)
+const UI_DEV_SETUP: u64 = 0x405C_5503; // _IOW('U', 3, struct uinput_setup)
+const UI_ABS_SETUP: u64 = 0x401C_5504; // _IOW('U', 4, struct uinput_abs_setup)
+const UI_SET_EVBIT: u64 = 0x4004_5564; // _IOW('U', 100, int)
+const UI_SET_KEYBIT: u64 = 0x4004_5565; // _IOW('U', 101, int)
+const UI_SET_ABSBIT: u64 = 0x4004_5567; // _IOW('U', 103, int)
+const UI_SET_PROPBIT: u64 = 0x4004_556E; // _IOW('U', 110, int)
+
+// Event types and codes (linux/input-event-codes.h)
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const ABS_MT_SLOT: u16 = 0x2f;
+const ABS_MT_POSITION_X: u16 = 0x35;
+const ABS_MT_POSITION_Y: u16 = 0x36;
+const ABS_MT_TRACKING_ID: u16 = 0x39;
+const INPUT_PROP_DIRECT: u16 = 0x01;
+const BUS_VIRTUAL: u16 = 0x06;
+
+pub const KEY_BACK: u16 = 158;
+pub const KEY_HOMEPAGE: u16 = 172;
+pub const BTN_LEFT: u16 = 0x110;
+pub const BTN_RIGHT: u16 = 0x111;
+pub const BTN_MIDDLE: u16 = 0x112;
+pub const BTN_TOUCH: u16 = 0x14a;
+
+/// Highest keyboard key code we declare (KEY_MICMUTE)
+const MAX_KEYBOARD_CODE: u16 = 248;
+
+/// Pointer buttons that map to Android navigation keys
+const BUTTON_KEY_MAP: &[(u16, u16)] = &[(BTN_RIGHT, KEY_BACK), (BTN_MIDDLE, KEY_HOMEPAGE)];
+
+#[derive(Debug, Error)]
+pub enum UinputError {
+    #[error(
+        "{path} is not accessible ({source}). Inside a user namespace uinput must be \
+         bind-mounted from the host and writable by the mapped user \
+         (e.g. `sudo setfacl -m u:$USER:rw /dev/uinput`)"
+    )]
+    NotAccessible {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("uinput ioctl {name} failed: {source}")]
+    Ioctl {
+        name: &'static str,
+        source: std::io::Error,
+    },
+
+    #[error("Failed to write input event: {0}")]
+    Write(std::io::Error),
+}
+
+/// One evdev event to be written to the device (type, code, value)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvdevEvent {
+    pub type_: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+impl EvdevEvent {
+    const fn new(type_: u16, code: u16, value: i32) -> Self {
+        Self { type_, code, value }
+    }
+
+    const fn sync() -> Self {
+        Self::new(EV_SYN, SYN_REPORT, 0)
+    }
+}
+
+/// Map a keyboard key code from an [`InputEvent`] to the evdev code we emit
+///
+/// Wayland already reports evdev codes, so keyboard keys pass through;
+/// codes outside the declared keyboard range are dropped.
+pub fn evdev_key_code(keycode: u32) -> Option<u16> {
+    u16::try_from(keycode)
+        .ok()
+        .filter(|code| (1..=MAX_KEYBOARD_CODE).contains(code))
+}
+
+/// Map a pointer button to the key it produces, if it isn't the touch button
+pub fn button_key_code(button: u32) -> Option<u16> {
+    BUTTON_KEY_MAP
+        .iter()
+        .find(|(btn, _)| u32::from(*btn) == button)
+        .map(|(_, key)| *key)
+}
+
+/// Touch state needed to turn pointer events into touchscreen events
+#[derive(Debug, Default)]
+struct TouchState {
+    x: i32,
+    y: i32,
+    down: bool,
+    next_tracking_id: i32,
+}
+
+impl TouchState {
+    /// Translate one [`InputEvent`] into the evdev events to write
+    fn translate(&mut self, event: InputEvent, width: i32, height: i32) -> Vec<EvdevEvent> {
+        let mut events = Vec::new();
+
+        match event {
+            InputEvent::KeyDown { keycode } | InputEvent::KeyUp { keycode } => {
+                if let Some(code) = evdev_key_code(keycode) {
+                    let value = matches!(event, InputEvent::KeyDown { .. }) as i32;
+                    events.push(EvdevEvent::new(EV_KEY, code, value));
+                }
+            }
+            InputEvent::PointerMotion { x, y } => {
+                self.x = (x as i32).clamp(0, width - 1);
+                self.y = (y as i32).clamp(0, height - 1);
+                events.push(EvdevEvent::new(EV_ABS, ABS_X, self.x));
+                events.push(EvdevEvent::new(EV_ABS, ABS_Y, self.y));
+                if self.down {
+                    events.push(EvdevEvent::new(EV_ABS, ABS_MT_SLOT, 0));
+                    events.push(EvdevEvent::new(EV_ABS, ABS_MT_POSITION_X, self.x));
+                    events.push(EvdevEvent::new(EV_ABS, ABS_MT_POSITION_Y, self.y));
+                }
+            }
+            InputEvent::PointerButton { button, pressed } => {
+                if let Some(code) = button_key_code(button) {
+                    events.push(EvdevEvent::new(EV_KEY, code, pressed as i32));
+                } else if button == u32::from(BTN_LEFT) && pressed != self.down {
+                    self.down = pressed;
+                    let tracking_id = if pressed {
+                        self.next_tracking_id += 1;
+                        self.next_tracking_id
+                    } else {
+                        -1
+                    };
+                    events.push(EvdevEvent::new(EV_ABS, ABS_MT_SLOT, 0));
+                    events.push(EvdevEvent::new(EV_ABS, ABS_MT_TRACKING_ID, tracking_id));
+                    if pressed {
+                        events.push(EvdevEvent::new(EV_ABS, ABS_MT_POSITION_X, self.x));
+                        events.push(EvdevEvent::new(EV_ABS, ABS_MT_POSITION_Y, self.y));
+                    }
+                    events.push(EvdevEvent::new(EV_KEY, BTN_TOUCH, pressed as i32));
+                }
+            }
+        }
+
+        if !events.is_empty() {
+            events.push(EvdevEvent::sync());
+        }
+        events
+    }
+}
+
+/// A virtual keyboard + touchscreen backed by uinput
+pub struct UinputDevice {
+    file: File,
+    width: i32,
+    height: i32,
+    touch: TouchState,
+}
+
+impl UinputDevice {
+    /// Create a virtual device sized to the given screen resolution
+    pub fn new(width: i32, height: i32) -> Result<Self, UinputError> {
+        Self::with_path(UINPUT_PATH, width, height)
+    }
+
+    /// Create a virtual device using a specific uinput control node
+    pub fn with_path<P: AsRef<Path>>(
+        path: P,
+        width: i32,
+        height: i32,
+    ) -> Result<Self, UinputError> {
+        let path = path.as_ref();
+        info!(
+            "Creating uinput device via {} ({}x{})",
+            path.display(),
+            width,
+            height
+        );
+
+        let file = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .map_err(|source| UinputError::NotAccessible {
+                path: path.to_path_buf(),
+                source,
+            })?;
+
+        let device = Self {
+            file,
+            width: width.max(1),
+            height: height.max(1),
+            touch: TouchState::default(),
+        };
+        device.configure()?;
+
+        info!("uinput device created");
+        Ok(device)
+    }
+
+    /// Inject a host input event into the container
+    pub fn inject(&mut self, event: InputEvent) -> Result<(), UinputError> {
+        let events = self.touch.translate(event, self.width, self.height);
+        self.write_events(&events)
+    }
+
+    fn configure(&self) -> Result<(), UinputError> {
+        self.ioctl_int("UI_SET_EVBIT", UI_SET_EVBIT, EV_KEY)?;
+        self.ioctl_int("UI_SET_EVBIT", UI_SET_EVBIT, EV_ABS)?;
+        self.ioctl_int("UI_SET_PROPBIT", UI_SET_PROPBIT, INPUT_PROP_DIRECT)?;
+
+        for code in 1..=MAX_KEYBOARD_CODE {
+            self.ioctl_int("UI_SET_KEYBIT", UI_SET_KEYBIT, code)?;
+        }
+        self.ioctl_int("UI_SET_KEYBIT", UI_SET_KEYBIT, BTN_TOUCH)?;
+
+        for (code, max) in [
+            (ABS_X, self.width - 1),
+            (ABS_Y, self.height - 1),
+            (ABS_MT_SLOT, 0),
+            (ABS_MT_POSITION_X, self.width - 1),
+            (ABS_MT_POSITION_Y, self.height - 1),
+            (ABS_MT_TRACKING_ID, i32::from(u16::MAX)),
+        ] {
+            self.ioctl_int("UI_SET_ABSBIT", UI_SET_ABSBIT, code)?;
+            let setup = libc::uinput_abs_setup {
+                code,
+                absinfo: libc::input_absinfo {
+                    value: 0,
+                    minimum: 0,
+                    maximum: max,
+                    fuzz: 0,
+                    flat: 0,
+                    resolution: 0,
+                },
+            };
+            self.ioctl_ptr("UI_ABS_SETUP", UI_ABS_SETUP, &setup)?;
+        }
+
+        let mut setup = libc::uinput_setup {
+            id: libc::input_id {
+                bustype: BUS_VIRTUAL,
+                vendor: 0x1d6b,
+                product: 0x0104,
+                version: 1,
+            },
+            name: [0; libc::UINPUT_MAX_NAME_SIZE],
+            ff_effects_max: 0,
+        };
+        for (dst, src) in setup.name.iter_mut().zip(b"rad virtual input") {
+            *dst = *src as libc::c_char;
+        }
+        self.ioctl_ptr("UI_DEV_SETUP", UI_DEV_SETUP, &setup)?;
+
+        let result = unsafe { libc::ioctl(self.file.as_raw_fd(), UI_DEV_CREATE as _) };
+        if result < 0 {
+            return Err(UinputError::Ioctl {
+                name: "UI_DEV_CREATE",
+                source: std::io::Error::last_os_error(),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn ioctl_int(&self, name: &'static str, request: u64, value: u16) -> Result<(), UinputError> {
+        let result = unsafe {
+            libc::ioctl(
+                self.file.as_raw_fd(),
+                request as _,
+                libc::c_int::from(value),
+            )
+        };
+        if result < 0 {
+            return Err(UinputError::Ioctl {
+                name,
+                source: std::io::Error::last_os_error(),
+            });
+        }
+        Ok(())
+    }
+
+    fn ioctl_ptr<T>(&self, name: &'static str, request: u64, arg: &T) -> Result<(), UinputError> {
+        let result = unsafe { libc::ioctl(self.file.as_raw_fd(), request as _, arg as *const T) };
+        if result < 0 {
+            return Err(UinputError::Ioctl {
+                name,
+                source: std::io::Error::last_os_error(),
+            });
+        }
+        Ok(())
+    }
+
+    fn write_events(&mut self, events: &[EvdevEvent]) -> Result<(), UinputError> {
+        for event in events {
+            let raw = libc::input_event {
+                time: libc::timeval {
+                    tv_sec: 0,
+                    tv_usec: 0,
+                },
+                type_: event.type_,
+                code: event.code,
+                value: event.value,
+            };
+            let bytes = unsafe {
+                std::slice::from_raw_parts(
+                    &raw as *const libc::input_event as *const u8,
+                    std::mem::size_of::<libc::input_event>(),
+                )
+            };
+            self.file.write_all(bytes).map_err(UinputError::Write)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for UinputDevice {
+    fn drop(&mut self) {
+        unsafe {
+            libc::ioctl(self.file.as_raw_fd(), UI_DEV_DESTROY as _);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_ESC: u32 = 1;
+    const KEY_A: u32 = 30;
+
+    #[test]
+    fn test_keyboard_codes_pass_through() {
+        assert_eq!(evdev_key_code(KEY_ESC), Some(1));
+        assert_eq!(evdev_key_code(KEY_A), Some(30));
+        assert_eq!(
+            evdev_key_code(u32::from(MAX_KEYBOARD_CODE)),
+            Some(MAX_KEYBOARD_CODE)
+        );
+    }
+
+    #[test]
+    fn test_keyboard_codes_outside_range_are_dropped() {
+        assert_eq!(evdev_key_code(0), None);
+        assert_eq!(evdev_key_code(u32::from(BTN_LEFT)), None);
+        assert_eq!(evdev_key_code(0x1_0000), None);
+    }
+
+    #[test]
+    fn test_button_map_to_android_navigation_keys() {
+        assert_eq!(button_key_code(u32::from(BTN_RIGHT)), Some(KEY_BACK));
+        assert_eq!(button_key_code(u32::from(BTN_MIDDLE)), Some(KEY_HOMEPAGE));
+        assert_eq!(button_key_code(u32::from(BTN_LEFT)), None);
+    }
+
+    #[test]
+    fn test_key_events_translate_to_ev_key() {
+        let mut touch = TouchState::default();
+
+        let down = touch.translate(InputEvent::KeyDown { keycode: KEY_A }, 1080, 1920);
+        let up = touch.translate(InputEvent::KeyUp { keycode: KEY_A }, 1080, 1920);
+
+        assert_eq!(
+            down,
+            vec![EvdevEvent::new(EV_KEY, 30, 1), EvdevEvent::sync()]
+        );
+        assert_eq!(up, vec![EvdevEvent::new(EV_KEY, 30, 0), EvdevEvent::sync()]);
+    }
+
+    #[test]
+    fn test_left_button_becomes_touch() {
+        let mut touch = TouchState::default();
+        touch.translate(
+            InputEvent::PointerMotion {
+                x: 100.0,
+                y: 5000.0,
+            },
+            1080,
+            1920,
+        );
+
+        let down = touch.translate(
+            InputEvent::PointerButton {
+                button: u32::from(BTN_LEFT),
+                pressed: true,
+            },
+            1080,
+            1920,
+        );
+
+        assert!(down.contains(&EvdevEvent::new(EV_KEY, BTN_TOUCH, 1)));
+        assert!(down.contains(&EvdevEvent::new(EV_ABS, ABS_MT_TRACKING_ID, 1)));
+        // Motion is clamped to the screen bounds
+        assert!(down.contains(&EvdevEvent::new(EV_ABS, ABS_MT_POSITION_Y, 1919)));
+
+        let up = touch.translate(
+            InputEvent::PointerButton {
+                button: u32::from(BTN_LEFT),
+                pressed: false,
+            },
+            1080,
+            1920,
+        );
+        assert!(up.contains(&EvdevEvent::new(EV_ABS, ABS_MT_TRACKING_ID, -1)));
+        assert!(up.contains(&EvdevEvent::new(EV_KEY, BTN_TOUCH, 0)));
+    }
+
+    #[test]
+    fn test_missing_uinput_reports_not_accessible() {
+        let result = UinputDevice::with_path("/nonexistent/uinput", 1080, 1920);
+
+        match result {
+            Err(UinputError::NotAccessible { path, .. }) => {
+                assert_eq!(path, PathBuf::from("/nonexistent/uinput"));
+            }
+            _ => panic!("Expected NotAccessible error"),
+        }
+    }
+
+    /// Requires write access to /dev/uinput
+    #[test]
+    #[ignore]
+    fn test_create_uinput_device() {
+        let mut device = UinputDevice::new(1080, 1920).expect("Failed to create uinput device");
+
+        device
+            .inject(InputEvent::KeyDown { keycode: KEY_A })
+            .expect("Failed to inject key down");
+        device
+            .inject(InputEvent::KeyUp { keycode: KEY_A })
+            .expect("Failed to inject key up");
+    }
+}
@@ -34,6 +34,46 @@ fn test_setup_mounts_creates_dirs() {
     assert!(rootfs.join("tmp").exists());
 }
 
+/// In a new PID namespace setup_mounts mounts a procfs showing only the
+/// namespace's processes
+#[test]
+#[ignore = "Requires unprivileged user namespaces to be enabled and uses fork()"]
+fn test_setup_mounts_mounts_proc_in_pid_namespace() {
+    use nix::sched::CloneFlags;
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::{fork, ForkResult};
+
+    let tmp = tempdir().expect("Failed to create tempdir");
+    let rootfs = tmp.path();
+
+    match unsafe { fork() }.expect("Fork failed") {
+        ForkResult::Parent { child } => {
+            let status = waitpid(child, None).expect("Failed to wait for child");
+            assert!(matches!(status, WaitStatus::Exited(_, 0)), "{:?}", status);
+        }
+        ForkResult::Child => {
+            let code = (|| -> anyhow::Result<i32> {
+                sandbox::enter_namespaces_with(CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWPID)?;
+                sandbox::fork_pid_namespace_init()?;
+                if !sandbox::in_new_pid_namespace() {
+                    return Ok(2);
+                }
+                sandbox::setup_mounts(rootfs)?;
+                // Our own PID 1 entry, and no host processes
+                let pids: Vec<_> = std::fs::read_dir(rootfs.join("proc"))?
+                    .filter_map(|e| e.ok()?.file_name().to_str()?.parse::<u32>().ok())
+                    .collect();
+                Ok(if pids == vec![1] { 0 } else { 3 })
+            })()
+            .unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                1
+            });
+            std::process::exit(code);
+        }
+    }
+}
+
 /// Test that validates SandboxConfig can be created
 #[test]
 fn test_sandbox_config() {
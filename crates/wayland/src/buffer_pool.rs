@@ -0,0 +1,181 @@
+use std::os::fd::AsFd;
+
+use rustix::fs::{MemfdFlags, ftruncate, memfd_create};
+
+use crate::{SurfaceflingerError, VirtualBuffer};
+
+/// Bytes per pixel of the buffers the pool allocates (32-bit formats such as
+/// ARGB8888/XRGB8888)
+const BYTES_PER_PIXEL: u32 = 4;
+
+/// Idle buffers kept for reuse unless configured otherwise
+const DEFAULT_MAX_IDLE: usize = 4;
+
+/// Recycles memfd-backed `VirtualBuffer`s across frames
+///
+/// `release` keeps the buffer (and its memfd) around so the next `acquire`
+/// with the same geometry reuses it instead of allocating a new one.
+#[derive(Debug)]
+pub struct BufferPool {
+    idle: Vec<VirtualBuffer>,
+    max_idle: usize,
+    outstanding: usize,
+    high_water: usize,
+    allocations: usize,
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BufferPool {
+    pub fn new() -> Self {
+        Self {
+            idle: Vec::new(),
+            max_idle: DEFAULT_MAX_IDLE,
+            outstanding: 0,
+            high_water: 0,
+            allocations: 0,
+        }
+    }
+
+    /// Keep at most `max_idle` released buffers; extra ones are closed
+    pub fn with_max_idle(mut self, max_idle: usize) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    /// Hand out an idle buffer of this geometry, or allocate a new one
+    pub fn acquire(
+        &mut self,
+        width: u32,
+        height: u32,
+        format: u32,
+    ) -> Result<VirtualBuffer, SurfaceflingerError> {
+        let reusable = self
+            .idle
+            .iter()
+            .position(|b| b.width() == width && b.height() == height && b.format() == format);
+        let buffer = match reusable {
+            Some(index) => self.idle.swap_remove(index),
+            None => self.allocate(width, height, format)?,
+        };
+
+        self.outstanding += 1;
+        self.high_water = self.high_water.max(self.outstanding);
+        Ok(buffer)
+    }
+
+    /// Return a buffer acquired from this pool for reuse
+    pub fn release(&mut self, buffer: VirtualBuffer) {
+        self.outstanding = self.outstanding.saturating_sub(1);
+        if self.idle.len() < self.max_idle {
+            self.idle.push(buffer);
+        } else {
+            tracing::debug!(
+                "Buffer pool full ({} idle), closing {}x{} buffer",
+                self.idle.len(),
+                buffer.width(),
+                buffer.height()
+            );
+        }
+    }
+
+    /// Buffers currently handed out
+    pub fn outstanding(&self) -> usize {
+        self.outstanding
+    }
+
+    /// Released buffers waiting for reuse
+    pub fn idle(&self) -> usize {
+        self.idle.len()
+    }
+
+    /// Most buffers ever handed out at once
+    pub fn high_water_mark(&self) -> usize {
+        self.high_water
+    }
+
+    /// Memfds allocated over the pool's lifetime
+    pub fn allocations(&self) -> usize {
+        self.allocations
+    }
+
+    fn allocate(
+        &mut self,
+        width: u32,
+        height: u32,
+        format: u32,
+    ) -> Result<VirtualBuffer, SurfaceflingerError> {
+        let stride = width.checked_mul(BYTES_PER_PIXEL).ok_or(
+            SurfaceflingerError::BufferAllocationFailed(anyhow::anyhow!(
+                "buffer width {} is too large",
+                width
+            )),
+        )?;
+        let size = u64::from(stride) * u64::from(height);
+
+        let fd = memfd_create("rad-buffer", MemfdFlags::CLOEXEC)
+            .map_err(|e| SurfaceflingerError::BufferAllocationFailed(e.into()))?;
+        ftruncate(fd.as_fd(), size)
+            .map_err(|e| SurfaceflingerError::BufferAllocationFailed(e.into()))?;
+
+        self.allocations += 1;
+        VirtualBuffer::new(fd, width, height, stride, format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::AsRawFd;
+
+    const XRGB8888: u32 = 0x34325258;
+
+    #[test]
+    fn release_then_acquire_reuses_the_memfd() {
+        let mut pool = BufferPool::new();
+
+        let buffer = pool.acquire(64, 32, XRGB8888).unwrap();
+        let fd = buffer.as_fd().as_raw_fd();
+        assert_eq!(buffer.stride(), 256);
+        pool.release(buffer);
+
+        let buffer = pool.acquire(64, 32, XRGB8888).unwrap();
+        assert_eq!(buffer.as_fd().as_raw_fd(), fd);
+        assert_eq!(pool.allocations(), 1);
+        pool.release(buffer);
+    }
+
+    #[test]
+    fn different_geometry_allocates() {
+        let mut pool = BufferPool::new();
+
+        let small = pool.acquire(64, 32, XRGB8888).unwrap();
+        pool.release(small);
+        let large = pool.acquire(128, 64, XRGB8888).unwrap();
+
+        assert_eq!(pool.allocations(), 2);
+        assert_eq!(pool.idle(), 1);
+        pool.release(large);
+    }
+
+    #[test]
+    fn tracks_high_water_mark_and_caps_idle() {
+        let mut pool = BufferPool::new().with_max_idle(1);
+
+        let buffers: Vec<_> = (0..3)
+            .map(|_| pool.acquire(16, 16, XRGB8888).unwrap())
+            .collect();
+        assert_eq!(pool.outstanding(), 3);
+        for buffer in buffers {
+            pool.release(buffer);
+        }
+
+        assert_eq!(pool.high_water_mark(), 3);
+        assert_eq!(pool.outstanding(), 0);
+        assert_eq!(pool.idle(), 1);
+    }
+}
@@ -0,0 +1,545 @@
+//! Wayland client connection to the host compositor.
+//!
+//! Binds the globals the display pipeline needs (`wl_compositor`, `wl_shm`,
+//! `xdg_wm_base`) and tracks every `wl_output` advertised by the compositor,
+//! including outputs that are hotplugged after the connection is made.
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::env;
+use std::io;
+use std::os::unix::io::BorrowedFd;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+
+use wayland_client::protocol::wl_compositor::WlCompositor;
+use wayland_client::protocol::wl_output::{self, WlOutput};
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::protocol::wl_seat::WlSeat;
+use wayland_client::protocol::wl_shm::WlShm;
+use wayland_client::{Attached, Display, EventQueue, GlobalEvent, GlobalManager, Main};
+use wayland_protocols::xdg_shell::client::xdg_wm_base::{self, XdgWmBase};
+
+use crate::input::{self, InputEvent};
+use crate::window::{self, Window, WindowState};
+
+/// Highest `wl_output` version we know how to handle
+const WL_OUTPUT_VERSION: u32 = 4;
+
+/// Roundtrips to wait for the first configure of a new window
+const WINDOW_CONFIGURE_ATTEMPTS: usize = 3;
+
+/// How long a roundtrip may take before the compositor is considered stalled
+pub const DEFAULT_ROUNDTRIP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Geometry, mode and scale reported by a single `wl_output`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputInfo {
+    /// Registry name of the `wl_output` global
+    pub id: u32,
+    /// Connector name (e.g. "HDMI-A-1"), only sent by `wl_output` v4+
+    pub name: Option<String>,
+    pub make: String,
+    pub model: String,
+    /// Current mode width in pixels
+    pub width: i32,
+    /// Current mode height in pixels
+    pub height: i32,
+    /// Current mode refresh rate in mHz
+    pub refresh_mhz: i32,
+    /// Physical width in millimeters (0 if unknown)
+    pub physical_width_mm: i32,
+    /// Physical height in millimeters (0 if unknown)
+    pub physical_height_mm: i32,
+    /// Integer scale factor (1 unless the compositor says otherwise)
+    pub scale: i32,
+}
+
+impl OutputInfo {
+    fn new(id: u32) -> Self {
+        Self {
+            id,
+            name: None,
+            make: String::new(),
+            model: String::new(),
+            width: 0,
+            height: 0,
+            refresh_mhz: 0,
+            physical_width_mm: 0,
+            physical_height_mm: 0,
+            scale: 1,
+        }
+    }
+
+    /// Whether the compositor has reported a current mode for this output
+    pub fn has_mode(&self) -> bool {
+        self.width > 0 && self.height > 0
+    }
+
+    /// Apply a single `wl_output` event to this output's state
+    fn apply(&mut self, event: wl_output::Event) {
+        match event {
+            wl_output::Event::Geometry {
+                physical_width,
+                physical_height,
+                make,
+                model,
+                ..
+            } => {
+                self.physical_width_mm = physical_width;
+                self.physical_height_mm = physical_height;
+                self.make = make;
+                self.model = model;
+            }
+            // Non-current modes are deprecated; only track the active one
+            wl_output::Event::Mode {
+                flags,
+                width,
+                height,
+                refresh,
+            } if flags.contains(wl_output::Mode::Current) => {
+                self.width = width;
+                self.height = height;
+                self.refresh_mhz = refresh;
+            }
+            wl_output::Event::Scale { factor } => {
+                self.scale = factor;
+            }
+            wl_output::Event::Name { name } => {
+                self.name = Some(name);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A bound `wl_output` together with the state collected from its events
+struct TrackedOutput {
+    proxy: Main<WlOutput>,
+    info: OutputInfo,
+}
+
+type OutputMap = Rc<RefCell<BTreeMap<u32, TrackedOutput>>>;
+
+pub struct WaylandConnection {
+    event_queue: EventQueue,
+    globals: GlobalManager,
+    compositor: Option<WlCompositor>,
+    shm: Option<WlShm>,
+    wm_base: Option<XdgWmBase>,
+    seat: Option<WlSeat>,
+    outputs: OutputMap,
+}
+
+impl WaylandConnection {
+    pub fn new() -> Result<Self, String> {
+        let display_name = display_name_from_env(env::var("WAYLAND_DISPLAY"))?;
+
+        // Connect to Wayland display
+        let display = Display::connect_to_name(&display_name).map_err(|e| {
+            format!("Failed to connect to Wayland display '{}': {}", display_name, e)
+        })?;
+
+        let mut event_queue = display.create_event_queue();
+        let attached_display = (*display).clone().attach(event_queue.token());
+
+        // Track outputs through the registry callback so hotplugged outputs
+        // (and removed ones) are reflected after any later roundtrip.
+        let outputs: OutputMap = Rc::new(RefCell::new(BTreeMap::new()));
+        let tracker = outputs.clone();
+        let globals = GlobalManager::new_with_cb(&attached_display, move |event, registry, _| {
+            handle_global_event(&tracker, event, &registry);
+        });
+
+        // Roundtrip to get initial globals
+        roundtrip_with_timeout(&mut event_queue, DEFAULT_ROUNDTRIP_TIMEOUT)?;
+
+        let mut connection = WaylandConnection {
+            event_queue,
+            globals,
+            compositor: None,
+            shm: None,
+            wm_base: None,
+            seat: None,
+            outputs,
+        };
+
+        connection.setup_globals();
+
+        // Second roundtrip delivers the initial geometry/mode/scale of each output
+        connection.roundtrip()?;
+
+        Ok(connection)
+    }
+
+    fn setup_globals(&mut self) {
+        // Check for compositor
+        if let Ok(compositor) = self.globals.instantiate_range::<WlCompositor>(1, 4) {
+            self.compositor = Some(compositor.detach());
+        }
+
+        // Check for SHM
+        if let Ok(shm) = self.globals.instantiate_exact::<WlShm>(1) {
+            self.shm = Some(shm.detach());
+        }
+
+        // Check for xdg_wm_base (must answer pings or the compositor kills us)
+        if let Ok(wm_base) = self.globals.instantiate_exact::<XdgWmBase>(1) {
+            wm_base.quick_assign(|wm_base, event, _| {
+                if let xdg_wm_base::Event::Ping { serial } = event {
+                    wm_base.pong(serial);
+                }
+            });
+            self.wm_base = Some(wm_base.detach());
+        }
+    }
+
+    pub fn compositor(&self) -> Option<&WlCompositor> {
+        self.compositor.as_ref()
+    }
+
+    pub fn shm(&self) -> Option<&WlShm> {
+        self.shm.as_ref()
+    }
+
+    pub fn wm_base(&self) -> Option<&XdgWmBase> {
+        self.wm_base.as_ref()
+    }
+
+    /// Snapshot of every output currently advertised by the compositor,
+    /// ordered by registry name (the first entry is the primary output).
+    ///
+    /// Call [`roundtrip`](Self::roundtrip) to pick up hotplug changes.
+    pub fn outputs(&self) -> Vec<OutputInfo> {
+        self.outputs
+            .borrow()
+            .values()
+            .map(|output| output.info.clone())
+            .collect()
+    }
+
+    /// Create a top-level window and complete the initial configure handshake
+    ///
+    /// The returned window's surface has been acked and can receive buffers.
+    pub fn create_window(&mut self, title: &str, app_id: &str) -> Result<Window, String> {
+        let compositor = self
+            .compositor
+            .as_ref()
+            .ok_or_else(|| "Compositor does not advertise wl_compositor".to_string())?;
+        let wm_base = self
+            .wm_base
+            .as_ref()
+            .ok_or_else(|| "Compositor does not advertise xdg_wm_base".to_string())?;
+
+        let state = Rc::new(WindowState::default());
+
+        let surface = compositor.create_surface();
+        let xdg_surface = wm_base.get_xdg_surface(&surface);
+        let surface_state = state.clone();
+        xdg_surface.quick_assign(move |xdg_surface, event, _| {
+            window::handle_xdg_surface_event(&xdg_surface, event, &surface_state);
+        });
+
+        let toplevel = xdg_surface.get_toplevel();
+        let toplevel_state = state.clone();
+        toplevel.quick_assign(move |_, event, _| {
+            window::handle_toplevel_event(event, &toplevel_state);
+        });
+        toplevel.set_title(title.to_string());
+        toplevel.set_app_id(app_id.to_string());
+
+        // Initial commit without a buffer asks the compositor to configure us
+        surface.commit();
+
+        let window = Window::new(
+            surface.detach(),
+            xdg_surface.detach(),
+            toplevel.detach(),
+            state,
+        );
+
+        for _ in 0..WINDOW_CONFIGURE_ATTEMPTS {
+            self.roundtrip()?;
+            if window.is_configured() {
+                return Ok(window);
+            }
+        }
+
+        Err(format!(
+            "Compositor did not configure window '{}' after {} roundtrips",
+            title, WINDOW_CONFIGURE_ATTEMPTS
+        ))
+    }
+
+    /// Bind the compositor's seat and start forwarding keyboard/pointer input
+    ///
+    /// Events are delivered while the event queue is dispatched (e.g. by
+    /// [`roundtrip`](Self::roundtrip)). Only one seat is bound per connection.
+    pub fn bind_input(&mut self) -> Result<Receiver<InputEvent>, String> {
+        if self.seat.is_some() {
+            return Err("Input seat is already bound on this connection".to_string());
+        }
+
+        let seat = self
+            .globals
+            .instantiate_range::<WlSeat>(1, 5)
+            .map_err(|e| format!("Failed to bind wl_seat: {}", e))?;
+
+        let (sender, receiver) = mpsc::channel();
+        input::assign_seat(&seat, sender);
+        self.seat = Some(seat.detach());
+
+        // Receive the seat capabilities so keyboard/pointer get bound
+        self.roundtrip()?;
+
+        Ok(receiver)
+    }
+
+    /// Roundtrip with the compositor using [`DEFAULT_ROUNDTRIP_TIMEOUT`]
+    pub fn roundtrip(&mut self) -> Result<(), String> {
+        self.roundtrip_timeout(DEFAULT_ROUNDTRIP_TIMEOUT)
+    }
+
+    /// Roundtrip with the compositor, failing if it does not answer within `dur`
+    pub fn roundtrip_timeout(&mut self, dur: Duration) -> Result<(), String> {
+        roundtrip_with_timeout(&mut self.event_queue, dur)
+    }
+}
+
+/// Send a `wl_display.sync` and dispatch events until its callback fires
+///
+/// Unlike `EventQueue::sync_roundtrip`, the socket is polled with a deadline
+/// so a stalled compositor produces an error instead of blocking forever.
+fn roundtrip_with_timeout(event_queue: &mut EventQueue, dur: Duration) -> Result<(), String> {
+    let deadline = Instant::now() + dur;
+    let roundtrip_err = |e: io::Error| format!("Failed to roundtrip with Wayland display: {}", e);
+    let timeout_err = || format!("Wayland roundtrip timed out after {:?}", dur);
+
+    let done = Rc::new(Cell::new(false));
+    let callback = (**event_queue.display())
+        .clone()
+        .attach(event_queue.token())
+        .sync();
+    let flag = done.clone();
+    callback.quick_assign(move |_, _, _| flag.set(true));
+
+    loop {
+        event_queue
+            .dispatch_pending(&mut (), |_, _, _| {})
+            .map_err(roundtrip_err)?;
+        if done.get() {
+            return Ok(());
+        }
+
+        match event_queue.display().flush() {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(roundtrip_err(e)),
+        }
+
+        // Events already queued: dispatch them before reading more
+        let Some(guard) = event_queue.prepare_read() else {
+            continue;
+        };
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            guard.cancel();
+            return Err(timeout_err());
+        }
+
+        let fd = event_queue.display().get_connection_fd();
+        // Safety: the fd is owned by the display, which outlives this call
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+        let mut fds = [PollFd::new(&fd, PollFlags::POLLIN)];
+        let timeout_ms = remaining.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+
+        match poll(&mut fds, timeout_ms) {
+            // Nothing arrived; the deadline check above ends the loop
+            Ok(0) | Err(Errno::EINTR) => guard.cancel(),
+            Ok(_) => match guard.read_events() {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(roundtrip_err(e)),
+            },
+            Err(e) => {
+                guard.cancel();
+                return Err(roundtrip_err(e.into()));
+            }
+        }
+    }
+}
+
+/// Registry callback: bind new outputs and forget removed ones
+fn handle_global_event(outputs: &OutputMap, event: GlobalEvent, registry: &Attached<WlRegistry>) {
+    match event {
+        GlobalEvent::New {
+            id,
+            interface,
+            version,
+        } if interface == "wl_output" => {
+            let proxy = registry.bind::<WlOutput>(version.min(WL_OUTPUT_VERSION), id);
+            let state = outputs.clone();
+            proxy.quick_assign(move |_, event, _| {
+                if let Some(output) = state.borrow_mut().get_mut(&id) {
+                    output.info.apply(event);
+                }
+            });
+            outputs.borrow_mut().insert(
+                id,
+                TrackedOutput {
+                    proxy,
+                    info: OutputInfo::new(id),
+                },
+            );
+        }
+        GlobalEvent::Removed { id, interface } if interface == "wl_output" => {
+            if let Some(output) = outputs.borrow_mut().remove(&id)
+                && output.proxy.as_ref().version() >= 3
+            {
+                output.proxy.release();
+            }
+        }
+        _ => {}
+    }
+}
+
+fn display_name_from_env(value: Result<String, env::VarError>) -> Result<String, String> {
+    // Check if WAYLAND_DISPLAY environment variable is set
+    let display_name =
+        value.map_err(|_| "WAYLAND_DISPLAY environment variable not set".to_string())?;
+
+    if display_name.is_empty() {
+        return Err("WAYLAND_DISPLAY environment variable is empty".to_string());
+    }
+
+    Ok(display_name)
+}
+
+pub fn create_wayland_connection() -> Result<WaylandConnection, String> {
+    WaylandConnection::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that talk to a real compositor only run when one is available
+    fn compositor_available() -> bool {
+        env::var("WAYLAND_DISPLAY").is_ok_and(|name| !name.is_empty())
+    }
+
+    #[test]
+    fn test_wayland_connection_fails_without_display() {
+        let result = display_name_from_env(Err(env::VarError::NotPresent));
+
+        assert!(result.is_err());
+        assert_eq!(result.err().unwrap(), "WAYLAND_DISPLAY environment variable not set");
+    }
+
+    #[test]
+    fn test_wayland_connection_fails_with_empty_display() {
+        let result = display_name_from_env(Ok(String::new()));
+
+        assert!(result.is_err());
+        assert_eq!(result.err().unwrap(), "WAYLAND_DISPLAY environment variable is empty");
+    }
+
+    #[test]
+    fn test_output_info_tracks_current_mode_and_scale() {
+        let mut info = OutputInfo::new(7);
+        assert_eq!(info.scale, 1);
+        assert!(!info.has_mode());
+
+        // A non-current mode must not overwrite the active one
+        info.apply(wl_output::Event::Mode {
+            flags: wl_output::Mode::Current | wl_output::Mode::Preferred,
+            width: 2560,
+            height: 1440,
+            refresh: 144_000,
+        });
+        info.apply(wl_output::Event::Mode {
+            flags: wl_output::Mode::empty(),
+            width: 1024,
+            height: 768,
+            refresh: 60_000,
+        });
+        info.apply(wl_output::Event::Scale { factor: 2 });
+
+        assert!(info.has_mode());
+        assert_eq!((info.width, info.height, info.refresh_mhz), (2560, 1440, 144_000));
+        assert_eq!(info.scale, 2);
+    }
+
+    #[test]
+    fn test_roundtrip_times_out_when_peer_never_responds() {
+        use std::os::unix::io::IntoRawFd;
+        use std::os::unix::net::UnixStream;
+
+        // Keep the peer open but silent so the sync callback never arrives
+        let (client, _peer) = UnixStream::pair().expect("socketpair failed");
+        let display =
+            unsafe { Display::from_fd(client.into_raw_fd()) }.expect("failed to wrap socket");
+        let mut event_queue = display.create_event_queue();
+
+        let start = Instant::now();
+        let result = roundtrip_with_timeout(&mut event_queue, Duration::from_millis(100));
+
+        let err = result.expect_err("roundtrip should time out");
+        assert!(err.contains("timed out"), "unexpected error: {}", err);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_create_window_against_compositor() {
+        if !compositor_available() {
+            eprintln!("WAYLAND_DISPLAY not set, skipping");
+            return;
+        }
+
+        let mut connection = create_wayland_connection().expect("failed to connect to compositor");
+        let window = connection
+            .create_window("rad test", "dev.rad.test")
+            .expect("failed to create window");
+
+        assert!(window.is_configured());
+    }
+
+    #[test]
+    fn test_bind_input_creates_event_channel() {
+        if !compositor_available() {
+            eprintln!("WAYLAND_DISPLAY not set, skipping");
+            return;
+        }
+
+        let mut connection = create_wayland_connection().expect("failed to connect to compositor");
+        let receiver = connection.bind_input().expect("failed to bind seat");
+
+        // No input is expected during the test, but the channel must be live
+        assert!(matches!(
+            receiver.try_recv(),
+            Ok(_) | Err(mpsc::TryRecvError::Empty)
+        ));
+        assert!(connection.bind_input().is_err(), "seat must only be bound once");
+    }
+
+    #[test]
+    fn test_outputs_populated_after_roundtrip() {
+        if !compositor_available() {
+            eprintln!("WAYLAND_DISPLAY not set, skipping");
+            return;
+        }
+
+        let mut connection = create_wayland_connection().expect("failed to connect to compositor");
+        connection.roundtrip().expect("roundtrip failed");
+
+        let outputs = connection.outputs();
+        assert!(!outputs.is_empty(), "compositor should advertise at least one output");
+        assert!(outputs[0].has_mode(), "primary output should report its current mode");
+    }
+}
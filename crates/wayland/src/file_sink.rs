@@ -0,0 +1,303 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::{FrameSink, SurfaceflingerError, VirtualBuffer};
+
+/// DRM fourcc codes the PNG encoder understands (both little-endian B,G,R,A/X)
+const DRM_FORMAT_XRGB8888: u32 = 0x34325258;
+const DRM_FORMAT_ARGB8888: u32 = 0x34325241;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Largest payload of a stored (uncompressed) deflate block
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+/// How `FileFrameSink` writes each frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FrameFileFormat {
+    /// The buffer contents as-is (`stride * height` bytes)
+    #[default]
+    Raw,
+    /// RGBA PNG; needs an XRGB8888 or ARGB8888 buffer
+    Png,
+}
+
+impl FrameFileFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            FrameFileFormat::Raw => "raw",
+            FrameFileFormat::Png => "png",
+        }
+    }
+}
+
+/// Headless presentation target that dumps frames into a directory
+///
+/// Frames are written as `frame-000000.<ext>`, `frame-000001.<ext>`, ...
+/// With `with_fps`, frames arriving sooner than `1/fps` after the last
+/// written one are dropped.
+#[derive(Debug)]
+pub struct FileFrameSink {
+    dir: PathBuf,
+    format: FrameFileFormat,
+    min_interval: Option<Duration>,
+    last_written: Option<Instant>,
+    written: u64,
+}
+
+impl FileFrameSink {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            format: FrameFileFormat::default(),
+            min_interval: None,
+            last_written: None,
+            written: 0,
+        }
+    }
+
+    pub fn with_format(mut self, format: FrameFileFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Write at most `fps` frames per second (0 disables throttling)
+    pub fn with_fps(mut self, fps: u32) -> Self {
+        self.min_interval = (fps > 0).then(|| Duration::from_secs(1) / fps);
+        self
+    }
+
+    /// Frames written so far
+    pub fn frames_written(&self) -> u64 {
+        self.written
+    }
+
+    fn frame_path(&self) -> PathBuf {
+        self.dir.join(format!(
+            "frame-{:06}.{}",
+            self.written,
+            self.format.extension()
+        ))
+    }
+}
+
+impl FrameSink for FileFrameSink {
+    fn present(&mut self, buffer: &VirtualBuffer) -> Result<bool, SurfaceflingerError> {
+        let now = Instant::now();
+        if let (Some(interval), Some(last)) = (self.min_interval, self.last_written)
+            && now.duration_since(last) < interval
+        {
+            return Ok(false);
+        }
+
+        let data = read_buffer(buffer)?;
+        let content = match self.format {
+            FrameFileFormat::Raw => data,
+            FrameFileFormat::Png => encode_png(buffer, &data)?,
+        };
+
+        std::fs::create_dir_all(&self.dir)
+            .and_then(|()| std::fs::write(self.frame_path(), content))
+            .map_err(|e| SurfaceflingerError::FrameWriteFailed(e.into()))?;
+
+        self.written += 1;
+        self.last_written = Some(now);
+        Ok(true)
+    }
+}
+
+/// Copy the `stride * height` bytes of a buffer's backing file
+fn read_buffer(buffer: &VirtualBuffer) -> Result<Vec<u8>, SurfaceflingerError> {
+    let len = buffer.stride() as usize * buffer.height() as usize;
+    let mut data = vec![0u8; len];
+    let mut filled = 0;
+    while filled < len {
+        let n = rustix::io::pread(buffer, &mut data[filled..], filled as u64)
+            .map_err(|e| SurfaceflingerError::FrameWriteFailed(e.into()))?;
+        if n == 0 {
+            return Err(SurfaceflingerError::FrameWriteFailed(anyhow::anyhow!(
+                "buffer backing is {} bytes, expected {}",
+                filled,
+                len
+            )));
+        }
+        filled += n;
+    }
+    Ok(data)
+}
+
+fn encode_png(buffer: &VirtualBuffer, data: &[u8]) -> Result<Vec<u8>, SurfaceflingerError> {
+    let has_alpha = match buffer.format() {
+        DRM_FORMAT_ARGB8888 => true,
+        DRM_FORMAT_XRGB8888 => false,
+        other => {
+            return Err(SurfaceflingerError::FrameWriteFailed(anyhow::anyhow!(
+                "cannot encode format {:#010x} as PNG",
+                other
+            )));
+        }
+    };
+
+    let (width, height, stride) = (
+        buffer.width() as usize,
+        buffer.height() as usize,
+        buffer.stride() as usize,
+    );
+    // One filter byte (0 = none) per scanline, then RGBA pixels
+    let mut raw = Vec::with_capacity(height * (1 + width * 4));
+    for row in data.chunks(stride).take(height) {
+        raw.push(0);
+        for pixel in row.chunks_exact(4).take(width) {
+            let alpha = if has_alpha { pixel[3] } else { 0xff };
+            raw.extend_from_slice(&[pixel[2], pixel[1], pixel[0], alpha]);
+        }
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&buffer.width().to_be_bytes());
+    ihdr.extend_from_slice(&buffer.height().to_be_bytes());
+    // 8-bit depth, colour type 6 (RGBA), default compression/filter, no interlace
+    ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &ihdr);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    Ok(png)
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// zlib stream of uncompressed deflate blocks; frames are throwaway, so
+/// speed and simplicity matter more than size
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BufferPool;
+    use std::os::fd::AsFd;
+
+    fn temp_dir(label: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rad-frames-{label}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn frame(pool: &mut BufferPool, fill: u8) -> VirtualBuffer {
+        let buffer = pool.acquire(8, 4, DRM_FORMAT_XRGB8888).unwrap();
+        let data = vec![fill; (buffer.stride() * buffer.height()) as usize];
+        rustix::io::pwrite(buffer.as_fd(), &data, 0).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn writes_one_raw_file_per_frame() {
+        let dir = temp_dir("raw");
+        let mut pool = BufferPool::new().with_max_idle(0);
+        let mut sink = FileFrameSink::new(dir.clone());
+
+        for fill in [0x11, 0x22] {
+            let buffer = frame(&mut pool, fill);
+            assert!(sink.present(&buffer).unwrap());
+            pool.release(buffer);
+        }
+
+        assert_eq!(sink.frames_written(), 2);
+        for (i, fill) in [0x11u8, 0x22].iter().enumerate() {
+            let data = std::fs::read(dir.join(format!("frame-{:06}.raw", i))).unwrap();
+            assert_eq!(data.len(), 8 * 4 * 4);
+            assert!(data.iter().all(|b| b == fill));
+        }
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn fps_limit_drops_frames_arriving_too_soon() {
+        let dir = temp_dir("fps");
+        let mut pool = BufferPool::new();
+        let mut sink = FileFrameSink::new(dir.clone()).with_fps(1);
+
+        let buffer = frame(&mut pool, 0);
+        assert!(sink.present(&buffer).unwrap());
+        assert!(!sink.present(&buffer).unwrap());
+
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        pool.release(buffer);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn png_has_header_and_dimensions() {
+        let dir = temp_dir("png");
+        let mut pool = BufferPool::new();
+        let mut sink = FileFrameSink::new(dir.clone()).with_format(FrameFileFormat::Png);
+
+        let buffer = frame(&mut pool, 0x80);
+        sink.present(&buffer).unwrap();
+
+        let png = std::fs::read(dir.join("frame-000000.png")).unwrap();
+        assert!(png.starts_with(PNG_SIGNATURE));
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(u32::from_be_bytes(png[16..20].try_into().unwrap()), 8);
+        assert_eq!(u32::from_be_bytes(png[20..24].try_into().unwrap()), 4);
+        assert!(png.ends_with(&[0xae, 0x42, 0x60, 0x82]), "IEND CRC");
+
+        pool.release(buffer);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+}
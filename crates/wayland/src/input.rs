@@ -0,0 +1,183 @@
+//! Host input capture (`wl_seat` → `wl_keyboard` / `wl_pointer`).
+//!
+//! Keyboard and pointer events received from the compositor are translated
+//! into [`InputEvent`]s and sent through a channel. This is the producer
+//! side; a consumer inside the container injects them as evdev events.
+//!
+//! Input is opt-in via [`WaylandConnection::bind_input`](crate::WaylandConnection::bind_input),
+//! so display-only consumers never bind a seat.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::mpsc::Sender;
+
+use wayland_client::protocol::wl_keyboard::{self, WlKeyboard};
+use wayland_client::protocol::wl_pointer::{self, WlPointer};
+use wayland_client::protocol::wl_seat::{self, WlSeat};
+use wayland_client::Main;
+
+/// Input event forwarded to the Android side
+///
+/// Key codes are Linux evdev codes (`KEY_*`), which is what `wl_keyboard`
+/// reports, and button codes are evdev `BTN_*` codes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputEvent {
+    KeyDown { keycode: u32 },
+    KeyUp { keycode: u32 },
+    /// Pointer position in surface-local coordinates
+    PointerMotion { x: f64, y: f64 },
+    PointerButton { button: u32, pressed: bool },
+}
+
+/// Devices obtained from the seat, kept so they can be released on
+/// capability loss
+#[derive(Default)]
+struct SeatDevices {
+    keyboard: Option<Main<WlKeyboard>>,
+    pointer: Option<Main<WlPointer>>,
+}
+
+/// Assign handlers to a freshly bound seat, forwarding events to `sender`
+pub(crate) fn assign_seat(seat: &Main<WlSeat>, sender: Sender<InputEvent>) {
+    let devices = Rc::new(RefCell::new(SeatDevices::default()));
+
+    seat.quick_assign(move |seat, event, _| {
+        let wl_seat::Event::Capabilities { capabilities } = event else {
+            return;
+        };
+        let mut devices = devices.borrow_mut();
+
+        let has_keyboard = capabilities.contains(wl_seat::Capability::Keyboard);
+        if has_keyboard && devices.keyboard.is_none() {
+            let keyboard = seat.get_keyboard();
+            let sender = sender.clone();
+            keyboard.quick_assign(move |_, event, _| {
+                if let Some(input) = translate_keyboard_event(event) {
+                    let _ = sender.send(input);
+                }
+            });
+            devices.keyboard = Some(keyboard);
+        } else if !has_keyboard && let Some(keyboard) = devices.keyboard.take() {
+            release_keyboard(keyboard);
+        }
+
+        let has_pointer = capabilities.contains(wl_seat::Capability::Pointer);
+        if has_pointer && devices.pointer.is_none() {
+            let pointer = seat.get_pointer();
+            let sender = sender.clone();
+            pointer.quick_assign(move |_, event, _| {
+                if let Some(input) = translate_pointer_event(event) {
+                    let _ = sender.send(input);
+                }
+            });
+            devices.pointer = Some(pointer);
+        } else if !has_pointer && let Some(pointer) = devices.pointer.take() {
+            release_pointer(pointer);
+        }
+    });
+}
+
+// `release` requests only exist since wl_seat v3; older devices are just dropped
+fn release_keyboard(keyboard: Main<WlKeyboard>) {
+    if keyboard.as_ref().version() >= 3 {
+        keyboard.release();
+    }
+}
+
+fn release_pointer(pointer: Main<WlPointer>) {
+    if pointer.as_ref().version() >= 3 {
+        pointer.release();
+    }
+}
+
+fn translate_keyboard_event(event: wl_keyboard::Event) -> Option<InputEvent> {
+    match event {
+        wl_keyboard::Event::Key { key, state, .. } => Some(match state {
+            wl_keyboard::KeyState::Pressed => InputEvent::KeyDown { keycode: key },
+            _ => InputEvent::KeyUp { keycode: key },
+        }),
+        wl_keyboard::Event::Keymap { fd, .. } => {
+            // We forward raw evdev codes and never need the XKB keymap,
+            // but the fd is ours and must not leak.
+            let _ = nix::unistd::close(fd);
+            None
+        }
+        _ => None,
+    }
+}
+
+fn translate_pointer_event(event: wl_pointer::Event) -> Option<InputEvent> {
+    match event {
+        wl_pointer::Event::Enter {
+            surface_x,
+            surface_y,
+            ..
+        }
+        | wl_pointer::Event::Motion {
+            surface_x,
+            surface_y,
+            ..
+        } => Some(InputEvent::PointerMotion {
+            x: surface_x,
+            y: surface_y,
+        }),
+        wl_pointer::Event::Button { button, state, .. } => Some(InputEvent::PointerButton {
+            button,
+            pressed: state == wl_pointer::ButtonState::Pressed,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_A: u32 = 30;
+    const BTN_LEFT: u32 = 0x110;
+
+    #[test]
+    fn test_keyboard_key_events_translate_to_key_down_up() {
+        let down = translate_keyboard_event(wl_keyboard::Event::Key {
+            serial: 1,
+            time: 0,
+            key: KEY_A,
+            state: wl_keyboard::KeyState::Pressed,
+        });
+        let up = translate_keyboard_event(wl_keyboard::Event::Key {
+            serial: 2,
+            time: 1,
+            key: KEY_A,
+            state: wl_keyboard::KeyState::Released,
+        });
+
+        assert_eq!(down, Some(InputEvent::KeyDown { keycode: KEY_A }));
+        assert_eq!(up, Some(InputEvent::KeyUp { keycode: KEY_A }));
+    }
+
+    #[test]
+    fn test_pointer_events_translate_to_motion_and_button() {
+        let motion = translate_pointer_event(wl_pointer::Event::Motion {
+            time: 0,
+            surface_x: 12.5,
+            surface_y: 40.0,
+        });
+        let button = translate_pointer_event(wl_pointer::Event::Button {
+            serial: 1,
+            time: 0,
+            button: BTN_LEFT,
+            state: wl_pointer::ButtonState::Pressed,
+        });
+        let frame = translate_pointer_event(wl_pointer::Event::Frame);
+
+        assert_eq!(motion, Some(InputEvent::PointerMotion { x: 12.5, y: 40.0 }));
+        assert_eq!(
+            button,
+            Some(InputEvent::PointerButton {
+                button: BTN_LEFT,
+                pressed: true
+            })
+        );
+        assert_eq!(frame, None);
+    }
+}
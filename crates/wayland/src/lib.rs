@@ -1,8 +1,18 @@
+mod buffer_pool;
+mod connection;
 mod dmabuf;
+mod file_sink;
+mod input;
+mod window;
 
+pub use buffer_pool::BufferPool;
+pub use connection::{create_wayland_connection, OutputInfo, WaylandConnection};
 pub use dmabuf::{DmabufBuffer, DmabufError, SurfaceDmabufManager};
+pub use file_sink::{FileFrameSink, FrameFileFormat};
+pub use input::InputEvent;
+pub use window::Window;
 
-use std::os::fd::{OwnedFd, AsFd, AsRawFd, RawFd};
+use std::os::fd::{OwnedFd, AsFd, AsRawFd, BorrowedFd, RawFd};
 
 #[derive(Debug, thiserror::Error)]
 pub enum SurfaceflingerError {
@@ -14,6 +24,12 @@ pub enum SurfaceflingerError {
     
     #[error("frame notification failed: {0}")]
     FrameNotificationFailed(anyhow::Error),
+
+    #[error("failed to allocate buffer: {0}")]
+    BufferAllocationFailed(anyhow::Error),
+
+    #[error("failed to write frame: {0}")]
+    FrameWriteFailed(anyhow::Error),
 }
 
 #[derive(Debug)]
@@ -68,12 +84,24 @@ impl VirtualBuffer {
     }
 }
 
+impl AsFd for VirtualBuffer {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
 pub trait FrameProvider: Send + Sync {
     fn acquire_frame(&self) -> Result<VirtualBuffer, SurfaceflingerError>;
     fn release_frame(&self, buffer: VirtualBuffer) -> Result<(), SurfaceflingerError>;
     fn on_frame_ready(&self) -> tokio::sync::watch::Receiver<()>;
 }
 
+/// Consumer of the frames a `FrameProvider` hands out
+pub trait FrameSink {
+    /// Show (or record) `buffer`; returns false if the frame was skipped
+    fn present(&mut self, buffer: &VirtualBuffer) -> Result<bool, SurfaceflingerError>;
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
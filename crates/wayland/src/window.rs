@@ -0,0 +1,124 @@
+//! Top-level window (`wl_surface` + `xdg_surface` + `xdg_toplevel`).
+//!
+//! Created through [`WaylandConnection::create_window`](crate::WaylandConnection::create_window),
+//! which performs the initial commit/configure handshake so the surface is
+//! ready for buffer attachment as soon as it is returned.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use wayland_client::protocol::wl_surface::WlSurface;
+use wayland_protocols::xdg_shell::client::xdg_surface::{self, XdgSurface};
+use wayland_protocols::xdg_shell::client::xdg_toplevel::{self, XdgToplevel};
+
+/// Configure state shared with the xdg event handlers
+#[derive(Debug, Default)]
+pub(crate) struct WindowState {
+    /// Whether at least one `xdg_surface.configure` has been acked
+    pub(crate) configured: Cell<bool>,
+    /// Size suggested by the last `xdg_toplevel.configure` (0 = client decides)
+    pub(crate) size: Cell<(i32, i32)>,
+    /// Set when the compositor asks us to close the window
+    pub(crate) close_requested: Cell<bool>,
+}
+
+pub struct Window {
+    surface: WlSurface,
+    xdg_surface: XdgSurface,
+    toplevel: XdgToplevel,
+    state: Rc<WindowState>,
+}
+
+impl Window {
+    pub(crate) fn new(
+        surface: WlSurface,
+        xdg_surface: XdgSurface,
+        toplevel: XdgToplevel,
+        state: Rc<WindowState>,
+    ) -> Self {
+        Self {
+            surface,
+            xdg_surface,
+            toplevel,
+            state,
+        }
+    }
+
+    /// The underlying `wl_surface`, for attaching buffers and committing
+    pub fn surface(&self) -> &WlSurface {
+        &self.surface
+    }
+
+    /// Size suggested by the compositor; `(0, 0)` means we pick the size
+    pub fn size(&self) -> (i32, i32) {
+        self.state.size.get()
+    }
+
+    /// Whether the compositor has configured this window
+    pub fn is_configured(&self) -> bool {
+        self.state.configured.get()
+    }
+
+    /// Whether the compositor asked to close this window
+    pub fn close_requested(&self) -> bool {
+        self.state.close_requested.get()
+    }
+}
+
+impl Drop for Window {
+    fn drop(&mut self) {
+        // Destroy in reverse creation order, as required by xdg-shell
+        self.toplevel.destroy();
+        self.xdg_surface.destroy();
+        self.surface.destroy();
+    }
+}
+
+/// Handle `xdg_surface` events: ack every configure
+pub(crate) fn handle_xdg_surface_event(
+    xdg_surface: &XdgSurface,
+    event: xdg_surface::Event,
+    state: &WindowState,
+) {
+    if let xdg_surface::Event::Configure { serial } = event {
+        xdg_surface.ack_configure(serial);
+        state.configured.set(true);
+    }
+}
+
+/// Handle `xdg_toplevel` events: record suggested size and close requests
+pub(crate) fn handle_toplevel_event(event: xdg_toplevel::Event, state: &WindowState) {
+    match event {
+        xdg_toplevel::Event::Configure { width, height, .. } => {
+            state.size.set((width, height));
+        }
+        xdg_toplevel::Event::Close => {
+            state.close_requested.set(true);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toplevel_events_update_state() {
+        let state = WindowState::default();
+
+        handle_toplevel_event(
+            xdg_toplevel::Event::Configure {
+                width: 1280,
+                height: 720,
+                states: Vec::new(),
+            },
+            &state,
+        );
+        assert_eq!(state.size.get(), (1280, 720));
+        assert!(!state.close_requested.get());
+
+        handle_toplevel_event(xdg_toplevel::Event::Close, &state);
+        assert!(state.close_requested.get());
+    }
+}
@@ -16,9 +16,11 @@
 set -euo pipefail
 
 # --- Configuration ---
-RAD_DATA_DIR="${RAD_DATA_DIR:-$HOME/.local/share/rad}"
+# Same precedence as ImagePaths::default_location: RAD_IMAGE_DIR, then
+# $XDG_DATA_HOME/rad/cache, then ~/.local/share/rad/cache
+RAD_DATA_DIR="${RAD_DATA_DIR:-${XDG_DATA_HOME:-$HOME/.local/share}/rad}"
 IMAGES_DIR="$RAD_DATA_DIR/images"
-CACHE_DIR="$RAD_DATA_DIR/cache"
+CACHE_DIR="${RAD_IMAGE_DIR:-$RAD_DATA_DIR/cache}"
 SYSTEM_DIR="$IMAGES_DIR/system"
 VENDOR_DIR="$IMAGES_DIR/vendor"
 
@@ -223,6 +225,7 @@ main() {
 
     if [[ ! -f "$CACHE_DIR/system.img" ]]; then
         info "Unzipping system.zip..."
+        rm -f "$CACHE_DIR/checksums.txt"
         unzip -o -q "$CACHE_DIR/system.zip" -d "$CACHE_DIR/"
         info "Unzipped system.img ($(du -h "$CACHE_DIR/system.img" | cut -f1))"
     else
@@ -231,12 +234,19 @@ main() {
 
     if [[ ! -f "$CACHE_DIR/vendor.img" ]]; then
         info "Unzipping vendor.zip..."
+        rm -f "$CACHE_DIR/checksums.txt"
         unzip -o -q "$CACHE_DIR/vendor.zip" -d "$CACHE_DIR/"
         info "Unzipped vendor.img ($(du -h "$CACHE_DIR/vendor.img" | cut -f1))"
     else
         info "vendor.img already extracted from zip"
     fi
 
+    # Record checksums so 'doctor' can detect later corruption
+    if [[ ! -f "$CACHE_DIR/checksums.txt" ]]; then
+        info "Recording image checksums..."
+        (cd "$CACHE_DIR" && sha256sum system.img vendor.img > checksums.txt)
+    fi
+
     # Extract image contents
     info ""
     info "--- Extracting image contents ---"